@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+
+use codemap::CodeMap;
+use codemap_diagnostic::{Level, SpanStyle};
+use gamma_parser::ast::{self, Expression, Statement, AST};
+use gamma_parser::data::{self, DataTypes};
+use gamma_parser::format::format_expression;
+use gamma_parser::macros::Macros;
+use gamma_parser::parser::Parser;
+use gamma_parser::reduce::Reducer;
+use gamma_parser::resolve::{self, Resolution, Symbol, SymbolKind};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, DocumentSymbol, Location, NumberOrString,
+    Position, Range, Url,
+};
+
+/// Number of beta reductions after which hover gives up looking for a normal
+/// form.
+const HOVER_REDUCTION_LIMIT: usize = 10_000;
+
+/// An open `.gm` file and everything the server knows about it.
+pub struct Document {
+    pub uri: Url,
+    pub text: String,
+    pub ast: AST,
+    pub resolution: Resolution,
+    pub diagnostics: Vec<lsp_types::Diagnostic>,
+}
+
+impl Document {
+    pub fn new(uri: Url, text: String) -> Self {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(&text, uri.as_str(), &mut codemap);
+        let ast = parser.parse();
+        let file_span = parser.file_span;
+        let mut diagnostics = std::mem::take(&mut parser.diagnostics);
+        drop(parser);
+
+        let span = |span: &ast::Span| file_span.subspan(span.start as u64, span.end as u64);
+        let mut definitions = HashMap::new();
+        for statement in &ast {
+            if let Statement::Let {
+                name,
+                name_span,
+                expression_span,
+                ..
+            } = statement
+            {
+                match definitions.get(name) {
+                    Some(previous) => diagnostics.extend(resolve::redefinition(
+                        name,
+                        span(name_span),
+                        span(expression_span),
+                        Some(span(previous)),
+                    )),
+                    None => {
+                        definitions.insert(name, expression_span.clone());
+                    }
+                }
+            }
+        }
+
+        let resolution = resolve::resolve(&ast);
+        let mut document = Self {
+            uri,
+            text,
+            ast,
+            resolution,
+            diagnostics: vec![],
+        };
+
+        for diagnostic in diagnostics {
+            document.add_diagnostic(file_span, diagnostic);
+        }
+
+        document
+    }
+
+    /// Converts a diagnostic reported against `file_span`. Notes are attached
+    /// to the diagnostic before them as related information.
+    fn add_diagnostic(
+        &mut self,
+        file_span: codemap::Span,
+        diagnostic: codemap_diagnostic::Diagnostic,
+    ) {
+        let range = |span: codemap::Span| {
+            let start = (span.low() - file_span.low()) as usize;
+            let end = (span.high() - file_span.low()) as usize;
+            self.range(&(start..end))
+        };
+
+        let primary = diagnostic
+            .spans
+            .iter()
+            .find(|label| label.style == SpanStyle::Primary)
+            .or_else(|| diagnostic.spans.first());
+        let message = match primary.and_then(|label| label.label.as_ref()) {
+            Some(label) => format!("{}: {}", diagnostic.message, label),
+            None => diagnostic.message.clone(),
+        };
+
+        let primary_range = primary.map(|label| range(label.span));
+        let related: Vec<_> = diagnostic
+            .spans
+            .iter()
+            .filter(|label| label.style != SpanStyle::Primary)
+            .map(|label| DiagnosticRelatedInformation {
+                location: Location::new(self.uri.clone(), range(label.span)),
+                message: label.label.clone().unwrap_or_default(),
+            })
+            .collect();
+
+        if diagnostic.level == Level::Note {
+            let location = primary_range.map(|range| Location::new(self.uri.clone(), range));
+            if let (Some(previous), Some(location)) = (self.diagnostics.last_mut(), location) {
+                previous
+                    .related_information
+                    .get_or_insert_with(Vec::new)
+                    .push(DiagnosticRelatedInformation { location, message });
+                return;
+            }
+        }
+
+        let lsp_diagnostic = lsp_types::Diagnostic {
+            range: primary_range.unwrap_or_default(),
+            severity: Some(match diagnostic.level {
+                Level::Bug | Level::Error => DiagnosticSeverity::ERROR,
+                Level::Warning => DiagnosticSeverity::WARNING,
+                Level::Note => DiagnosticSeverity::INFORMATION,
+                Level::Help => DiagnosticSeverity::HINT,
+            }),
+            code: diagnostic.code.map(NumberOrString::String),
+            source: Some("gamma".to_owned()),
+            message,
+            related_information: if related.is_empty() {
+                None
+            } else {
+                Some(related)
+            },
+            ..Default::default()
+        };
+
+        self.diagnostics.push(lsp_diagnostic);
+    }
+
+    /// Byte offset of an LSP position, which counts UTF-16 code units.
+    pub fn offset(&self, position: Position) -> usize {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            match self.text[line_start..].find('\n') {
+                Some(newline) => line_start += newline + 1,
+                None => return self.text.len(),
+            }
+        }
+
+        let mut units = 0;
+        for (offset, c) in self.text[line_start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return line_start + offset;
+            }
+
+            units += c.len_utf16();
+        }
+
+        self.text.len()
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        Position::new(
+            before.matches('\n').count() as u32,
+            before[line_start..].encode_utf16().count() as u32,
+        )
+    }
+
+    pub fn range(&self, span: &ast::Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    pub fn location(&self, span: &ast::Span) -> Location {
+        Location::new(self.uri.clone(), self.range(span))
+    }
+
+    pub fn symbol_at(&self, position: Position) -> Option<&Symbol> {
+        self.resolution.symbol_at(self.offset(position))
+    }
+
+    /// Normal form of the `let` binding or constructor defined at
+    /// `definition`, computed with the bindings, syntax rules and data types
+    /// defined before it.
+    pub fn normal_form(&self, definition: &ast::Span) -> Option<String> {
+        let file_span = CodeMap::new()
+            .add_file(self.uri.to_string(), self.text.clone())
+            .span;
+        let mut macros = Macros::new();
+        let mut data_types = DataTypes::new();
+        let mut bindings = HashMap::new();
+        for statement in &self.ast {
+            match statement {
+                Statement::Let {
+                    name,
+                    name_span,
+                    expression,
+                    expression_span,
+                    ..
+                } => {
+                    let expanded = macros
+                        .expand(expression, expression_span)
+                        .ok()
+                        .and_then(|(expression, _)| data_types.desugar(&expression).ok());
+                    if name_span == definition {
+                        return Reducer::new(&bindings, HOVER_REDUCTION_LIMIT)
+                            .normalize(&expanded?)
+                            .ok()
+                            .map(|expression| format_expression(&expression));
+                    }
+
+                    if let Some(expression) = expanded {
+                        bindings.entry(name.clone()).or_insert(expression);
+                    }
+                }
+                Statement::Data { .. } => {
+                    for generated in data::definitions(statement).unwrap_or_default() {
+                        if let Statement::Let {
+                            name,
+                            name_span,
+                            expression,
+                            ..
+                        } = generated
+                        {
+                            if name_span == *definition {
+                                return Some(format_expression(&expression));
+                            }
+
+                            bindings.entry(name).or_insert(expression);
+                        }
+                    }
+
+                    data_types.declare(statement);
+                }
+                // Rules with errors are left out, like `let`s whose uses of
+                // rules do not expand.
+                Statement::Syntax { .. } => {
+                    let _ = macros.define(statement, file_span);
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Top level `let` statements.
+    pub fn symbols(&self) -> Vec<DocumentSymbol> {
+        self.resolution
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.kind == SymbolKind::Let)
+            .map(|symbol| {
+                let is_function = self.ast.iter().any(|statement| {
+                    matches!(
+                        statement,
+                        Statement::Let {
+                            name_span,
+                            expression: Expression::Abstraction { .. },
+                            ..
+                        } if *name_span == symbol.definition
+                    )
+                });
+
+                #[allow(deprecated)]
+                DocumentSymbol {
+                    name: symbol.name.clone(),
+                    detail: None,
+                    kind: if is_function {
+                        lsp_types::SymbolKind::FUNCTION
+                    } else {
+                        lsp_types::SymbolKind::VARIABLE
+                    },
+                    tags: None,
+                    deprecated: None,
+                    range: self.range(&symbol.scope),
+                    selection_range: self.range(&symbol.definition),
+                    children: None,
+                }
+            })
+            .collect()
+    }
+}
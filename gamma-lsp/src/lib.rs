@@ -0,0 +1,292 @@
+//! Language server for Gamma, speaking LSP over stdio.
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    DocumentSymbolRequest, GotoDefinition, HoverRequest, References, Rename, Request as _,
+};
+use lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse,
+    Hover, HoverContents, HoverParams, HoverProviderCapability, MarkupContent, MarkupKind, OneOf,
+    PublishDiagnosticsParams, ReferenceParams, RenameParams, ServerCapabilities,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+    WorkspaceEdit,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+mod document;
+
+use gamma_parser::rename::{self, RenameError};
+use gamma_parser::resolve::{Symbol, SymbolKind};
+
+pub use document::Document;
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Why a request failed, as the error of its response.
+type RequestError = (ErrorCode, String);
+
+/// Runs the server on stdin and stdout until the client asks it to exit.
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Performs the initialization handshake and handles messages from
+/// `connection` until shutdown.
+pub fn serve(connection: &Connection) -> Result<()> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+    };
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+
+                server.request(request)?;
+            }
+            Message::Notification(notification) => server.notification(notification)?,
+            Message::Response(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    documents: HashMap<Url, Document>,
+}
+
+impl<'a> Server<'a> {
+    /// Handles a notification. Notifications have no response, so one with
+    /// malformed parameters is logged to stderr and skipped.
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        let Notification { method, params } = notification;
+        match method.as_str() {
+            DidOpenTextDocument::METHOD => match notification_params(&method, params) {
+                Some(DidOpenTextDocumentParams { text_document }) => {
+                    self.open(text_document.uri, text_document.text)
+                }
+                None => Ok(()),
+            },
+            DidChangeTextDocument::METHOD => {
+                match notification_params::<DidChangeTextDocumentParams>(&method, params) {
+                    Some(params) => match params.content_changes.into_iter().last() {
+                        Some(change) => self.open(params.text_document.uri, change.text),
+                        None => Ok(()),
+                    },
+                    None => Ok(()),
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Some(params) =
+                    notification_params::<DidCloseTextDocumentParams>(&method, params)
+                {
+                    self.documents.remove(&params.text_document.uri);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Analyses the new contents of a document and publishes its diagnostics.
+    fn open(&mut self, uri: Url, text: String) -> Result<()> {
+        let document = Document::new(uri.clone(), text);
+        let params = PublishDiagnosticsParams::new(uri.clone(), document.diagnostics.clone(), None);
+        self.documents.insert(uri, document);
+
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                PublishDiagnostics::METHOD.to_owned(),
+                params,
+            )))?;
+        Ok(())
+    }
+
+    /// Answers a request, with an error response if it fails.
+    fn request(&mut self, request: Request) -> Result<()> {
+        let Request { id, method, params } = request;
+        let response = match self.result(&method, params) {
+            Ok(result) => Response::new_ok(id, result.unwrap_or(Value::Null)),
+            Err((code, message)) => Response::new_err(id, code as i32, message),
+        };
+
+        self.connection.sender.send(Message::Response(response))?;
+        Ok(())
+    }
+
+    fn result(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> std::result::Result<Option<Value>, RequestError> {
+        let result = match method {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = request_params(params)?;
+                let position = params.text_document_position_params;
+                self.with_symbol(&position, |document, symbol| {
+                    GotoDefinitionResponse::Scalar(document.location(&symbol.definition))
+                })
+                .map(serde_json::to_value)
+            }
+            References::METHOD => {
+                let params: ReferenceParams = request_params(params)?;
+                let include_declaration = params.context.include_declaration;
+                self.with_symbol(&params.text_document_position, |document, symbol| {
+                    let mut spans = vec![];
+                    if include_declaration {
+                        spans.push(&symbol.definition);
+                    }
+                    spans.extend(&symbol.references);
+
+                    spans
+                        .into_iter()
+                        .map(|span| document.location(span))
+                        .collect::<Vec<_>>()
+                })
+                .map(serde_json::to_value)
+            }
+            HoverRequest::METHOD => {
+                let params: HoverParams = request_params(params)?;
+                self.hover(&params.text_document_position_params)
+                    .map(serde_json::to_value)
+            }
+            DocumentSymbolRequest::METHOD => {
+                let params: DocumentSymbolParams = request_params(params)?;
+                self.documents
+                    .get(&params.text_document.uri)
+                    .map(|document| DocumentSymbolResponse::Nested(document.symbols()))
+                    .map(serde_json::to_value)
+            }
+            Rename::METHOD => {
+                let params: RenameParams = request_params(params)?;
+                self.rename(params)
+                    .map_err(|error| (ErrorCode::RequestFailed, error.to_string()))?
+                    .map(serde_json::to_value)
+            }
+            _ => {
+                return Err((
+                    ErrorCode::MethodNotFound,
+                    format!("unsupported request: {}", method),
+                ))
+            }
+        };
+
+        result
+            .transpose()
+            .map_err(|error| (ErrorCode::InternalError, error.to_string()))
+    }
+
+    fn with_symbol<T>(
+        &self,
+        position: &TextDocumentPositionParams,
+        f: impl FnOnce(&Document, &Symbol) -> T,
+    ) -> Option<T> {
+        let document = self.documents.get(&position.text_document.uri)?;
+        let symbol = document.symbol_at(position.position)?;
+        Some(f(document, symbol))
+    }
+
+    fn rename(
+        &self,
+        params: RenameParams,
+    ) -> std::result::Result<Option<WorkspaceEdit>, RenameError> {
+        let position = params.text_document_position;
+        let document = match self.documents.get(&position.text_document.uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+
+        let edits = rename::rename(
+            &document.ast,
+            document.offset(position.position),
+            &params.new_name,
+        )?
+        .into_iter()
+        .map(|edit| TextEdit::new(document.range(&edit.span), edit.text))
+        .collect();
+
+        Ok(Some(WorkspaceEdit::new(HashMap::from([(
+            document.uri.clone(),
+            edits,
+        )]))))
+    }
+
+    /// Shows the normal form of `let` bindings.
+    fn hover(&self, position: &TextDocumentPositionParams) -> Option<Hover> {
+        let document = self.documents.get(&position.text_document.uri)?;
+        let symbol = document.symbol_at(position.position)?;
+        if symbol.kind != SymbolKind::Let {
+            return None;
+        }
+
+        let value = match document.normal_form(&symbol.definition) {
+            Some(normal_form) => format!("```gamma\n{} = {}\n```", symbol.name, normal_form),
+            None => format!(
+                "`{}` has no normal form within the reduction and size limits",
+                symbol.name
+            ),
+        };
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+}
+
+fn request_params<P: DeserializeOwned>(params: Value) -> std::result::Result<P, RequestError> {
+    serde_json::from_value(params).map_err(|error| {
+        (
+            ErrorCode::InvalidParams,
+            format!("invalid parameters: {}", error),
+        )
+    })
+}
+
+/// The parameters of a notification, or `None` after logging why they are
+/// malformed.
+fn notification_params<P: DeserializeOwned>(method: &str, params: Value) -> Option<P> {
+    match serde_json::from_value(params) {
+        Ok(params) => Some(params),
+        Err(error) => {
+            eprintln!("ignoring malformed {} notification: {}", method, error);
+            None
+        }
+    }
+}
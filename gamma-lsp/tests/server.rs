@@ -0,0 +1,217 @@
+//! Drives the server with a scripted client over an in-memory connection.
+
+use std::thread;
+
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///booleans.gm";
+
+const SOURCE: &str = "let true = \\x => \\y => x;
+let false = \\x => \\y => y;
+let not = \\b => b false true;
+let nt = not true;
+let true = \\x . x;
+";
+
+const LARGE: &str = "let mult = \\m => \\n => \\f => m (n f);
+let ten = \\f => \\x => f (f (f (f (f (f (f (f (f (f x)))))))));
+let hundred = mult ten ten;
+let big = mult hundred hundred;
+";
+
+struct Client {
+    connection: Connection,
+    next_id: i32,
+}
+
+impl Client {
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let request = Request::new(self.next_id.into(), method.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Request(request))
+            .unwrap();
+
+        match self.connection.receiver.recv().unwrap() {
+            Message::Response(Response {
+                result: Some(result),
+                ..
+            }) => result,
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    fn request_error(&mut self, method: &str, params: Value) -> String {
+        self.next_id += 1;
+        let request = Request::new(self.next_id.into(), method.to_owned(), params);
+        self.connection
+            .sender
+            .send(Message::Request(request))
+            .unwrap();
+
+        match self.connection.receiver.recv().unwrap() {
+            Message::Response(Response {
+                error: Some(error), ..
+            }) => error.message,
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    fn notify(&self, method: &str, params: Value) {
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                method.to_owned(),
+                params,
+            )))
+            .unwrap();
+    }
+
+    fn notification(&self) -> Notification {
+        match self.connection.receiver.recv().unwrap() {
+            Message::Notification(notification) => notification,
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    fn at(&mut self, method: &str, line: u32, character: u32) -> Value {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": false },
+            }),
+        )
+    }
+}
+
+#[test]
+fn scripted_session() {
+    let (server, connection) = Connection::memory();
+    let server = thread::spawn(move || gamma_lsp::serve(&server).unwrap());
+    let mut client = Client {
+        connection,
+        next_id: 0,
+    };
+
+    let initialized = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(initialized["capabilities"]["hoverProvider"], json!(true));
+    client.notify("initialized", json!({}));
+
+    // Malformed messages are answered or skipped without stopping the server.
+    client.notify("textDocument/didOpen", json!({ "textDocument": URI }));
+    let error = client.request_error(
+        "textDocument/hover",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    assert!(error.starts_with("invalid parameters: "), "{}", error);
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({
+            "textDocument": { "uri": URI, "languageId": "gamma", "version": 1, "text": SOURCE }
+        }),
+    );
+
+    let published = client.notification();
+    assert_eq!(published.method, "textDocument/publishDiagnostics");
+    let codes: Vec<_> = published.params["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| diagnostic["code"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(codes, vec!["W002", "E003"]);
+
+    // `true` in the body of `not` is defined on the first line.
+    let definition = client.at("textDocument/definition", 2, 26);
+    assert_eq!(
+        definition["range"]["start"],
+        json!({ "line": 0, "character": 4 })
+    );
+
+    let references = client.at("textDocument/references", 0, 5);
+    let lines: Vec<_> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| location["range"]["start"]["line"].clone())
+        .collect();
+    assert_eq!(lines, vec![json!(2), json!(3)]);
+
+    // The parameter `b` is used once in the body.
+    let references = client.at("textDocument/references", 2, 11);
+    assert_eq!(references.as_array().unwrap().len(), 1);
+
+    let hover = client.at("textDocument/hover", 3, 5);
+    assert_eq!(
+        hover["contents"]["value"],
+        json!("```gamma\nnt = \\x => \\y => y\n```")
+    );
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    let names: Vec<_> = symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| symbol["name"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(names, vec!["true", "false", "not", "nt", "true"]);
+
+    let rename = client.request(
+        "textDocument/rename",
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": 2, "character": 11 },
+            "newName": "p",
+        }),
+    );
+    let edits = rename["changes"][URI].as_array().unwrap();
+    assert_eq!(edits.len(), 2);
+    assert!(edits.iter().all(|edit| edit["newText"] == json!("p")));
+
+    // Renaming `b` to `false` would capture the reference to the `let`.
+    let error = client.request_error(
+        "textDocument/rename",
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": 2, "character": 11 },
+            "newName": "false",
+        }),
+    );
+    assert_eq!(error, "renaming would capture the variable `false`");
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "let id = \\x => x;\n" }]
+        }),
+    );
+    assert_eq!(client.notification().params["diagnostics"], json!([]));
+
+    // Hover gives up on normal forms too large to show.
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 3 },
+            "contentChanges": [{ "text": LARGE }]
+        }),
+    );
+    client.notification();
+    let hover = client.at("textDocument/hover", 3, 5);
+    assert_eq!(
+        hover["contents"]["value"],
+        json!("`big` has no normal form within the reduction and size limits")
+    );
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    server.join().unwrap();
+}
@@ -0,0 +1,18 @@
+//! `cargo fuzz run parse`: the parser must accept any UTF-8 input without
+//! panicking, and its concrete syntax tree must give back the input.
+
+#![no_main]
+
+use codemap::CodeMap;
+use gamma_parser::{cst, parser::Parser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let mut codemap = CodeMap::new();
+    let mut parser = Parser::new(source, "<fuzz>", &mut codemap);
+    let tree = parser.parse_cst();
+    assert_eq!(tree.text(), source);
+    cst::lower(&tree);
+
+    Parser::new(source, "<fuzz>", &mut CodeMap::new()).parse_single_expression();
+});
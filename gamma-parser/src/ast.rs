@@ -1,3 +1,5 @@
+use std::fmt;
+
 pub type Span = std::ops::Range<usize>;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -12,6 +14,14 @@ pub enum Expression {
         rhs: Box<Expression>,
         rhs_span: Span,
     },
+    /// `f $ g x`, an application written with `$`. It means the same as
+    /// [`Expression::Apply`] and is only kept apart for printing.
+    Dollar {
+        lhs: Box<Expression>,
+        lhs_span: Span,
+        rhs: Box<Expression>,
+        rhs_span: Span,
+    },
     Paren {
         expression: Box<Expression>,
         expression_span: Span,
@@ -19,9 +29,43 @@ pub enum Expression {
     Abstraction {
         name: String,
         name_span: Span,
+        /// Type of the parameter, as in `\x: A => x`.
+        annotation: Option<Type>,
         expression: Box<Expression>,
         expression_span: Span,
     },
+    /// `/\a => e`, abstraction over the type variable `a` (System F).
+    TypeAbstraction {
+        name: String,
+        name_span: Span,
+        expression: Box<Expression>,
+        expression_span: Span,
+    },
+    /// `e [T]`, instantiation of a polymorphic expression (System F).
+    TypeApply {
+        expression: Box<Expression>,
+        expression_span: Span,
+        argument: Type,
+    },
+    /// `if c then a else b`, which applies `c` to `a` and `b` once
+    /// desugared, see [`crate::data`].
+    If {
+        condition: Box<Expression>,
+        condition_span: Span,
+        then: Box<Expression>,
+        then_span: Span,
+        otherwise: Box<Expression>,
+        otherwise_span: Span,
+    },
+    /// `match e { Nil => a; Cons h t => b }`, which takes apart a value of a
+    /// data type. It is desugared once the data type is known, see
+    /// [`crate::data`].
+    Match {
+        keyword_span: Span,
+        scrutinee: Box<Expression>,
+        scrutinee_span: Span,
+        arms: Vec<Arm>,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -34,10 +78,163 @@ pub enum Statement {
     Let {
         name: String,
         name_span: Span,
+        /// Declared type, as in `let f : A -> A = \x: A => x;`.
+        annotation: Option<Type>,
+        /// Set for operators, which are defined with their fixity, as in
+        /// `infixl 6 + = add;`.
+        fixity: Option<Fixity>,
+        expression: Expression,
+        expression_span: Span,
+        span: Span,
+    },
+    /// `syntax [do otherwise] when c do a otherwise b = c a b;`, a rule that
+    /// rewrites the applications of `when` that match the pattern into the
+    /// template.
+    Syntax {
+        name: String,
+        name_span: Span,
+        /// Names that uses must repeat as they are, like `then`.
+        keywords: Vec<(String, Span)>,
+        /// Names of the pattern after `name`. Those that are not keywords
+        /// stand for the arguments of the use.
+        pattern: Vec<(String, Span)>,
+        template: Expression,
+        template_span: Span,
+        span: Span,
+    },
+    /// `data List = Nil | Cons h t;`, a type whose values are made by its
+    /// constructors and taken apart by `match`.
+    Data {
+        name: String,
+        name_span: Span,
+        constructors: Vec<Constructor>,
+        span: Span,
+    },
+    /// `assert e;`, which fails unless `e` reduces to Church `true`.
+    Assert {
         expression: Expression,
         expression_span: Span,
         span: Span,
     },
+    /// `assert_eq a b;`, which fails unless `a` and `b` have the same normal
+    /// form up to the names of bound variables.
+    AssertEq {
+        lhs: Expression,
+        lhs_span: Span,
+        rhs: Expression,
+        rhs_span: Span,
+        span: Span,
+    },
+}
+
+/// `Cons h t`, a constructor of a `data` declaration and the names of its
+/// fields.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constructor {
+    pub name: String,
+    pub name_span: Span,
+    pub fields: Vec<(String, Span)>,
+    pub span: Span,
+}
+
+/// `Cons h t => b`, an arm of a `match`. The constructor `_` matches the
+/// values of every constructor without an arm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arm {
+    pub constructor: String,
+    pub constructor_span: Span,
+    /// Names of the fields, which are bound in the body.
+    pub fields: Vec<(String, Span)>,
+    pub body: Expression,
+    pub body_span: Span,
+}
+
+impl Arm {
+    pub fn span(&self) -> Span {
+        self.constructor_span.start..self.body_span.end
+    }
+}
+
+/// How an operator groups with its neighbours. Operators that bind tighter
+/// have a higher precedence, from 0 to 9.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixity {
+    pub associativity: Associativity,
+    pub precedence: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Associativity {
+    /// `infixl`: `a - b - c` is `(a - b) - c`.
+    Left,
+    /// `infixr`: `a ^ b ^ c` is `a ^ (b ^ c)`.
+    Right,
+    /// `infix`: `a == b == c` is an error.
+    None,
+}
+
+impl Fixity {
+    /// Fixity of operators without a declaration.
+    pub const DEFAULT: Fixity = Fixity {
+        associativity: Associativity::Left,
+        precedence: 9,
+    };
+}
+
+/// Type annotation. Arrows are right associative: `A -> B -> C` is
+/// `A -> (B -> C)`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Var {
+        name: String,
+        span: Span,
+    },
+    Arrow {
+        lhs: Box<Type>,
+        rhs: Box<Type>,
+        span: Span,
+    },
+    /// `forall a. T` (System F). The body extends as far right as possible.
+    Forall {
+        name: String,
+        name_span: Span,
+        body: Box<Type>,
+        span: Span,
+    },
+}
+
+impl Type {
+    pub fn span(&self) -> Span {
+        match self {
+            Type::Var { span, .. } | Type::Arrow { span, .. } | Type::Forall { span, .. } => {
+                span.clone()
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&crate::format::format_expression(self))
+    }
+}
+
+/// `infixl 6`, as in the declaration.
+impl fmt::Display for Fixity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keyword = match self.associativity {
+            Associativity::Left => "infixl",
+            Associativity::Right => "infixr",
+            Associativity::None => "infix",
+        };
+        write!(f, "{} {}", keyword, self.precedence)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&crate::format::format_type(self))
+    }
 }
 
 pub type AST = Vec<Statement>;
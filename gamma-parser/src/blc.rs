@@ -0,0 +1,479 @@
+//! Tromp's binary lambda calculus (BLC), a bit-level encoding of closed
+//! lambda terms.
+//!
+//! Variables are written as de Bruijn indices: `\x => \y => x` is `λ λ 2`.
+//! An abstraction is encoded as `00` followed by its body, an application
+//! as `01` followed by both sides and the variable with index `n` as `n`
+//! ones followed by a zero. Packed into bytes, the first bit is the most
+//! significant one and the last byte is padded with zeros.
+//!
+//! Programs read and write lists of bits. A list is built from pairs
+//! `\s => s head tail` and ends with `\x => \y => y`; the bit `0` is
+//! `\x => \y => x` and `1` is `\x => \y => y`.
+
+use std::fmt;
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Expression, Span};
+
+/// A lambda term with de Bruijn indices, starting at 1 for the innermost
+/// binder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    Var(usize),
+    Abstraction(Box<Term>),
+    Apply(Box<Term>, Box<Term>),
+}
+
+/// The variable at `span` is free, so the expression has no encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FreeVariable {
+    pub name: String,
+    pub span: Span,
+}
+
+impl fmt::Display for FreeVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "free variable `{}` cannot be encoded", self.name)
+    }
+}
+
+impl FreeVariable {
+    pub fn diagnostic(&self, file_span: codemap::Span) -> Diagnostic {
+        Diagnostic {
+            level: Level::Error,
+            message: self.to_string(),
+            code: Some("E010".to_owned()),
+            spans: vec![SpanLabel {
+                span: file_span.subspan(self.span.start as u64, self.span.end as u64),
+                style: SpanStyle::Primary,
+                label: Some("not defined by any `let` or parameter".to_owned()),
+            }],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ends in the middle of a term.
+    UnexpectedEnd,
+    /// The variable encoded at bit `position` is not bound.
+    UnboundIndex { index: usize, position: usize },
+    /// A character of a bit string at byte `position` is neither `0` nor `1`.
+    InvalidCharacter { character: char, position: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of the bits"),
+            DecodeError::UnboundIndex { index, position } => {
+                write!(f, "unbound variable {} at bit {}", index, position)
+            }
+            DecodeError::InvalidCharacter {
+                character,
+                position,
+            } => write!(f, "invalid bit `{}` at byte {}", character, position),
+        }
+    }
+}
+
+impl Term {
+    /// Translates a closed expression. Types are erased.
+    pub fn from_expression(expression: &Expression) -> Result<Term, FreeVariable> {
+        Self::from_expression_in(expression, &mut vec![])
+    }
+
+    fn from_expression_in<'e>(
+        expression: &'e Expression,
+        binders: &mut Vec<&'e str>,
+    ) -> Result<Term, FreeVariable> {
+        match expression {
+            Expression::Var { name, name_span } => binders
+                .iter()
+                .rev()
+                .position(|binder| binder == name)
+                .map(|index| Term::Var(index + 1))
+                .ok_or_else(|| FreeVariable {
+                    name: name.clone(),
+                    span: name_span.clone(),
+                }),
+            Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+                Ok(Term::Apply(
+                    Box::new(Self::from_expression_in(lhs, binders)?),
+                    Box::new(Self::from_expression_in(rhs, binders)?),
+                ))
+            }
+            Expression::Paren { expression, .. }
+            | Expression::TypeAbstraction { expression, .. }
+            | Expression::TypeApply { expression, .. } => {
+                Self::from_expression_in(expression, binders)
+            }
+            Expression::Abstraction {
+                name, expression, ..
+            } => {
+                binders.push(name);
+                let body = Self::from_expression_in(expression, binders);
+                binders.pop();
+                Ok(Term::Abstraction(Box::new(body?)))
+            }
+            Expression::If { .. } | Expression::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before encoding")
+            }
+        }
+    }
+
+    /// Translates the term back, naming the binders `a`, `b`, ... by their
+    /// depth.
+    pub fn to_expression(&self) -> Expression {
+        self.to_expression_at(0)
+    }
+
+    fn to_expression_at(&self, depth: usize) -> Expression {
+        match self {
+            Term::Var(index) => Expression::Var {
+                name: binder_name(depth - index),
+                name_span: 0..0,
+            },
+            Term::Abstraction(body) => Expression::Abstraction {
+                name: binder_name(depth),
+                name_span: 0..0,
+                annotation: None,
+                expression: Box::new(body.to_expression_at(depth + 1)),
+                expression_span: 0..0,
+            },
+            Term::Apply(lhs, rhs) => Expression::Apply {
+                lhs: Box::new(lhs.to_expression_at(depth)),
+                lhs_span: 0..0,
+                rhs: Box::new(rhs.to_expression_at(depth)),
+                rhs_span: 0..0,
+            },
+        }
+    }
+
+    /// Appends the encoding of the term to `bits`.
+    pub fn encode(&self, bits: &mut Vec<bool>) {
+        match self {
+            Term::Var(index) => {
+                bits.extend(std::iter::repeat_n(true, *index));
+                bits.push(false);
+            }
+            Term::Abstraction(body) => {
+                bits.extend([false, false]);
+                body.encode(bits);
+            }
+            Term::Apply(lhs, rhs) => {
+                bits.extend([false, true]);
+                lhs.encode(bits);
+                rhs.encode(bits);
+            }
+        }
+    }
+
+    /// Decodes the closed term at the start of `bits` and returns it with
+    /// the number of bits it takes.
+    pub fn decode(bits: &[bool]) -> Result<(Term, usize), DecodeError> {
+        let mut position = 0;
+        let term = decode_at(bits, &mut position, 0)?;
+        Ok((term, position))
+    }
+}
+
+fn decode_at(bits: &[bool], position: &mut usize, depth: usize) -> Result<Term, DecodeError> {
+    let next = |position: &mut usize| {
+        let bit = bits.get(*position).copied();
+        *position += 1;
+        bit.ok_or(DecodeError::UnexpectedEnd)
+    };
+
+    match (next(position)?, next(position)?) {
+        (false, false) => Ok(Term::Abstraction(Box::new(decode_at(
+            bits,
+            position,
+            depth + 1,
+        )?))),
+        (false, true) => {
+            let lhs = decode_at(bits, position, depth)?;
+            let rhs = decode_at(bits, position, depth)?;
+            Ok(Term::Apply(Box::new(lhs), Box::new(rhs)))
+        }
+        (true, second) => {
+            let start = *position - 2;
+            let mut index = 1;
+            let mut bit = second;
+            while bit {
+                index += 1;
+                bit = next(position)?;
+            }
+
+            match index <= depth {
+                true => Ok(Term::Var(index)),
+                false => Err(DecodeError::UnboundIndex {
+                    index,
+                    position: start,
+                }),
+            }
+        }
+    }
+}
+
+fn binder_name(depth: usize) -> String {
+    let letter = ((b'a' + (depth % 26) as u8) as char).to_string();
+    match depth / 26 {
+        0 => letter,
+        n => format!("{}{}", letter, n),
+    }
+}
+
+/// Encodes a closed expression.
+pub fn encode(expression: &Expression) -> Result<Vec<bool>, FreeVariable> {
+    let mut bits = vec![];
+    Term::from_expression(expression)?.encode(&mut bits);
+    Ok(bits)
+}
+
+/// Decodes the closed term at the start of `bits`, returning it with the
+/// number of bits it takes.
+pub fn decode(bits: &[bool]) -> Result<(Expression, usize), DecodeError> {
+    Term::decode(bits).map(|(term, length)| (term.to_expression(), length))
+}
+
+/// Writes bits as a string of `0` and `1`.
+pub fn format_bits(bits: &[bool]) -> String {
+    bits.iter()
+        .map(|&bit| if bit { '1' } else { '0' })
+        .collect()
+}
+
+/// Reads a string of `0` and `1`, ignoring whitespace.
+pub fn parse_bits(text: &str) -> Result<Vec<bool>, DecodeError> {
+    text.char_indices()
+        .filter(|(_, character)| !character.is_whitespace())
+        .map(|(position, character)| match character {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(DecodeError::InvalidCharacter {
+                character,
+                position,
+            }),
+        })
+        .collect()
+}
+
+/// Packs bits into bytes, most significant bit first, padding the last
+/// byte with zeros.
+pub fn pack(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (i, &bit)| byte | (u8::from(bit) << (7 - i)))
+        })
+        .collect()
+}
+
+pub fn unpack(bytes: &[u8]) -> Vec<bool> {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| byte & (1 << i) != 0))
+        .collect()
+}
+
+/// The list of `bits` as a closed expression.
+pub fn bit_list(bits: &[bool]) -> Expression {
+    let boolean = |bit: bool| {
+        Term::Abstraction(Box::new(Term::Abstraction(Box::new(Term::Var(if bit {
+            1
+        } else {
+            2
+        })))))
+    };
+
+    bits.iter()
+        .rev()
+        .fold(boolean(true), |tail, &bit| {
+            // \s => s bit tail, where bit and tail are closed.
+            Term::Abstraction(Box::new(Term::Apply(
+                Box::new(Term::Apply(Box::new(Term::Var(1)), Box::new(boolean(bit)))),
+                Box::new(tail),
+            )))
+        })
+        .to_expression()
+}
+
+/// The bits of `expression` if it is a list of bits in normal form.
+pub fn read_bit_list(expression: &Expression) -> Option<Vec<bool>> {
+    let mut term = Term::from_expression(expression).ok()?;
+    let mut bits = vec![];
+    loop {
+        term = match term {
+            Term::Abstraction(body) => match *body {
+                Term::Abstraction(body) if *body == Term::Var(1) => return Some(bits),
+                Term::Apply(lhs, tail) => match *lhs {
+                    Term::Apply(pair, head) if *pair == Term::Var(1) => {
+                        bits.push(read_bit(&head)?);
+                        shift_out(*tail)?
+                    }
+                    _ => return None,
+                },
+                _ => return None,
+            },
+            _ => return None,
+        }
+    }
+}
+
+fn read_bit(term: &Term) -> Option<bool> {
+    match term {
+        Term::Abstraction(body) => match &**body {
+            Term::Abstraction(body) => match **body {
+                Term::Var(2) => Some(false),
+                Term::Var(1) => Some(true),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Removes the innermost binder of the context of `term`, which must not use
+/// it.
+fn shift_out(term: Term) -> Option<Term> {
+    fn shift(term: Term, depth: usize) -> Option<Term> {
+        match term {
+            Term::Var(index) if index <= depth => Some(Term::Var(index)),
+            Term::Var(index) if index == depth + 1 => None,
+            Term::Var(index) => Some(Term::Var(index - 1)),
+            Term::Abstraction(body) => Some(Term::Abstraction(Box::new(shift(*body, depth + 1)?))),
+            Term::Apply(lhs, rhs) => Some(Term::Apply(
+                Box::new(shift(*lhs, depth)?),
+                Box::new(shift(*rhs, depth)?),
+            )),
+        }
+    }
+
+    shift(term, 0)
+}
+
+#[cfg(test)]
+mod blc_tests {
+    use std::collections::HashMap;
+
+    use super::{
+        bit_list, decode, encode, format_bits, pack, parse_bits, read_bit_list, unpack,
+        DecodeError, Term,
+    };
+    use crate::parser::Parser;
+    use crate::reduce::Reducer;
+    use codemap::CodeMap;
+
+    /// Tromp's self-interpreter: applied to a continuation and a list that
+    /// starts with an encoded program, it passes the decoded program and the
+    /// rest of the list to the continuation.
+    const SELF_INTERPRETER: &str =
+        "(λ 1 1) (λ λ λ 1 (λ λ λ λ 3 (λ 5 (3 (λ 2 (3 (λ λ 3 (λ 1 2 3))) \
+         (4 (λ 4 (λ 3 1 (2 1)))))) (1 (2 (λ 1 2)) (λ 4 (λ 4 (λ 2 (1 4))) 5)))) (3 3) 2)";
+
+    /// Parses de Bruijn notation like `λ λ 2`.
+    fn de_bruijn(source: &str) -> Term {
+        fn term<'a>(tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>) -> Term {
+            let mut terms = vec![];
+            while let Some(token) = tokens.next_if(|&token| token != ")") {
+                terms.push(match token {
+                    "λ" => Term::Abstraction(Box::new(term(tokens))),
+                    "(" => {
+                        let inner = term(tokens);
+                        tokens.next();
+                        inner
+                    }
+                    index => Term::Var(index.parse().unwrap()),
+                });
+            }
+
+            let mut terms = terms.into_iter();
+            let first = terms.next().unwrap();
+            terms.fold(first, |lhs, rhs| Term::Apply(Box::new(lhs), Box::new(rhs)))
+        }
+
+        let source = source.replace('(', " ( ").replace(')', " ) ");
+        term(&mut source.split_whitespace().peekable())
+    }
+
+    fn expression(source: &str) -> crate::ast::Expression {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+        parser.parse_single_expression().unwrap()
+    }
+
+    #[test]
+    fn encoding() {
+        let bits = encode(&expression("\\x => \\y => x")).unwrap();
+        assert_eq!(format_bits(&bits), "0000110");
+        let bits = encode(&expression("\\x => x x")).unwrap();
+        assert_eq!(format_bits(&bits), "00011010");
+        assert_eq!(encode(&expression("\\x => y")).unwrap_err().span, 6..7);
+
+        assert_eq!(pack(&parse_bits("0000110 1").unwrap()), vec![0b0000_1101]);
+        assert_eq!(pack(&parse_bits("0000110").unwrap()), vec![0b0000_1100]);
+        assert_eq!(unpack(&[0b0000_1101]), parse_bits("00001101").unwrap());
+    }
+
+    #[test]
+    fn decoding() {
+        let bits = parse_bits("0100011010 0010").unwrap();
+        let (term, length) = decode(&bits).unwrap();
+        assert_eq!(term.to_string(), "(\\a => a a) (\\a => a)");
+        assert_eq!(length, bits.len());
+
+        assert_eq!(decode(&bits[..5]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(
+            decode(&parse_bits("00110").unwrap()),
+            Err(DecodeError::UnboundIndex {
+                index: 2,
+                position: 2
+            })
+        );
+        assert!(matches!(
+            parse_bits("0120"),
+            Err(DecodeError::InvalidCharacter { character: '2', .. })
+        ));
+    }
+
+    #[test]
+    fn self_interpreter() {
+        let interpreter = de_bruijn(SELF_INTERPRETER);
+        let mut bits = vec![];
+        interpreter.encode(&mut bits);
+        assert_eq!(bits.len(), 206);
+        assert_eq!(Term::decode(&bits).unwrap(), (interpreter.clone(), 206));
+
+        // The universal machine runs the program at the start of its input
+        // on the rest. The decoded program expects an environment first,
+        // which it ignores because it is closed.
+        let universal = Term::Apply(
+            Box::new(interpreter),
+            Box::new(de_bruijn("λ 1 ((λ 1 1) (λ 1 1))")),
+        );
+        let mut bits = vec![];
+        universal.encode(&mut bits);
+        assert_eq!(bits.len(), 232);
+
+        // `\l => \s => s (\x => \y => y) l` prepends a 1 to its input.
+        let mut input = encode(&expression("\\l => \\s => s (\\x => \\y => y) l")).unwrap();
+        input.push(false);
+        let run = crate::ast::Expression::Apply {
+            lhs: Box::new(universal.to_expression()),
+            lhs_span: 0..0,
+            rhs: Box::new(bit_list(&input)),
+            rhs_span: 0..0,
+        };
+
+        let output = Reducer::new(&HashMap::new(), 100_000)
+            .normalize(&run)
+            .unwrap();
+        assert_eq!(read_bit_list(&output), Some(vec![true, false]));
+    }
+}
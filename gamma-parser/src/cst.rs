@@ -0,0 +1,699 @@
+//! Lossless concrete syntax tree.
+//!
+//! Every byte of the source, including whitespace and comments ("trivia"),
+//! belongs to exactly one token of the tree, so `node.text()` of the root
+//! reproduces the parsed file. The [`crate::ast`] is derived from it with
+//! [`lower`].
+
+use crate::ast::{self, Associativity, Expression, Fixity, Statement, Type, AST};
+use crate::lexer::{RawToken, Token};
+use crate::reduce;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Program,
+    Let,
+    ExpressionStatement,
+    Assert,
+    AssertEq,
+    /// `infixl 6 + = add;`, which defines an operator.
+    Infix,
+    /// `syntax [do otherwise] when c do a otherwise b = c a b;`, a syntax rule.
+    Syntax,
+    /// `data List = Nil | Cons h t;`, a data type.
+    Data,
+    /// `Cons h t`, a constructor of a data type and its fields.
+    Constructor,
+    Var,
+    Apply,
+    Paren,
+    /// `$ e`, the argument of a low-precedence application `f $ e`.
+    Dollar,
+    /// `a + b`, an operator applied to two operands.
+    Operation,
+    /// `(+)`, an operator used as a function.
+    OperatorName,
+    /// `(a +)`, an operator applied to its left operand only.
+    LeftSection,
+    /// `(+ b)`, an operator applied to its right operand only.
+    RightSection,
+    Abstraction,
+    TypeAbstraction,
+    /// `if c then a else b`.
+    If,
+    /// `match e { Nil => a; Cons h t => b }`.
+    Match,
+    /// `Cons h t => b`, an arm of a `match`.
+    Arm,
+    TypeApply,
+    TypeVar,
+    TypeArrow,
+    TypeParen,
+    TypeForall,
+    /// Statement or expression that failed to parse. It still owns the tokens
+    /// that were consumed while parsing it.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Element {
+    Node(Node),
+    Token(Token),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+}
+
+impl NodeKind {
+    /// Nodes of type annotations.
+    pub fn is_type(self) -> bool {
+        matches!(
+            self,
+            NodeKind::TypeVar | NodeKind::TypeArrow | NodeKind::TypeParen | NodeKind::TypeForall
+        )
+    }
+}
+
+impl RawToken {
+    /// Whitespace, comments and unknown characters, which the parser skips.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self,
+            RawToken::Whitespace | RawToken::Comment | RawToken::Error
+        )
+    }
+}
+
+impl Element {
+    pub fn text(&self) -> String {
+        match self {
+            Element::Node(node) => node.text(),
+            Element::Token(token) => token.literal.clone(),
+        }
+    }
+}
+
+impl Node {
+    pub fn new(kind: NodeKind) -> Self {
+        Self {
+            kind,
+            children: vec![],
+        }
+    }
+
+    /// Source text of the node, trivia included.
+    pub fn text(&self) -> String {
+        self.tokens().map(|token| token.literal.as_str()).collect()
+    }
+
+    /// All tokens of the node in source order, trivia included.
+    pub fn tokens(&self) -> Tokens<'_> {
+        Tokens {
+            stack: vec![self.children.iter()],
+            backwards: false,
+        }
+    }
+
+    /// All tokens of the node from the last one, trivia included.
+    fn tokens_backwards(&self) -> Tokens<'_> {
+        Tokens {
+            stack: vec![self.children.iter()],
+            backwards: true,
+        }
+    }
+
+    /// Tokens of the node that are not trivia.
+    pub fn significant_tokens(&self) -> impl Iterator<Item = &Token> {
+        self.tokens().filter(|token| !token.raw.is_trivia())
+    }
+
+    /// Direct child nodes.
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) => Some(node),
+            Element::Token(_) => None,
+        })
+    }
+
+    /// First direct child token of the given kind.
+    pub fn token(&self, raw: RawToken) -> Option<&Token> {
+        self.children.iter().find_map(|child| match child {
+            Element::Token(token) if token.raw == raw => Some(token),
+            _ => None,
+        })
+    }
+
+    /// Span of the node without leading and trailing trivia. Only the
+    /// tokens at its edges are visited, so lowering a tree, which takes the
+    /// span of every node, stays fast on deep trees.
+    pub fn span(&self) -> ast::Span {
+        let significant = |token: &&Token| !token.raw.is_trivia();
+        match self.significant_tokens().next() {
+            Some(first) => {
+                let last = self.tokens_backwards().find(significant).unwrap_or(first);
+                first.span.start..last.span.end
+            }
+            None => self.full_span(),
+        }
+    }
+
+    /// Span of the node including its trivia.
+    pub fn full_span(&self) -> ast::Span {
+        match (self.tokens().next(), self.tokens_backwards().next()) {
+            (Some(first), Some(last)) => first.span.start..last.span.end,
+            _ => 0..0,
+        }
+    }
+
+    /// Token containing the byte `offset`.
+    pub fn token_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens()
+            .find(|token| token.span.start <= offset && offset < token.span.end)
+    }
+}
+
+/// Iterator over the tokens of a node, with a stack of the children left to
+/// visit at every level rather than nested iterators.
+pub struct Tokens<'a> {
+    stack: Vec<std::slice::Iter<'a, Element>>,
+    /// Whether tokens come from the last one.
+    backwards: bool,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<&'a Token> {
+        while let Some(children) = self.stack.last_mut() {
+            let child = match self.backwards {
+                true => children.next_back(),
+                false => children.next(),
+            };
+            match child {
+                Some(Element::Token(token)) => return Some(token),
+                Some(Element::Node(node)) => self.stack.push(node.children.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Derives the abstract syntax tree from a `Program` node. Statements that
+/// failed to parse are left out.
+pub fn lower(program: &Node) -> AST {
+    program.nodes().filter_map(lower_statement).collect()
+}
+
+pub fn lower_statement(node: &Node) -> Option<Statement> {
+    match node.kind {
+        NodeKind::Let => {
+            let name = node.token(RawToken::Identifier)?;
+            let expression = expression_child(node)?;
+            Some(Statement::Let {
+                name: name.literal.clone(),
+                name_span: name.span.clone(),
+                annotation: lower_annotation(node)?,
+                fixity: None,
+                expression: lower_expression(expression)?,
+                expression_span: expression.span(),
+                span: node.span(),
+            })
+        }
+        NodeKind::Infix => {
+            let operator = node.token(RawToken::Operator)?;
+            let expression = node.nodes().next()?;
+            Some(Statement::Let {
+                name: operator.literal.clone(),
+                name_span: operator.span.clone(),
+                annotation: None,
+                fixity: Some(declared_fixity(node)?),
+                expression: lower_expression(expression)?,
+                expression_span: expression.span(),
+                span: node.span(),
+            })
+        }
+        NodeKind::Syntax => {
+            let mut keywords = vec![];
+            let mut pattern = vec![];
+            let mut in_keywords = false;
+            for child in &node.children {
+                match child {
+                    Element::Token(token) => match token.raw {
+                        RawToken::Lbracket => in_keywords = true,
+                        RawToken::Rbracket => in_keywords = false,
+                        RawToken::Identifier if in_keywords => {
+                            keywords.push((token.literal.clone(), token.span.clone()))
+                        }
+                        RawToken::Identifier => {
+                            pattern.push((token.literal.clone(), token.span.clone()))
+                        }
+                        _ => {}
+                    },
+                    Element::Node(_) => break,
+                }
+            }
+
+            if pattern.is_empty() {
+                return None;
+            }
+            let (name, name_span) = pattern.remove(0);
+            let template = node.nodes().next()?;
+            Some(Statement::Syntax {
+                name,
+                name_span,
+                keywords,
+                pattern,
+                template: lower_expression(template)?,
+                template_span: template.span(),
+                span: node.span(),
+            })
+        }
+        NodeKind::Data => {
+            let name = node.token(RawToken::Identifier)?;
+            Some(Statement::Data {
+                name: name.literal.clone(),
+                name_span: name.span.clone(),
+                constructors: node.nodes().map(lower_constructor).collect::<Option<_>>()?,
+                span: node.span(),
+            })
+        }
+        NodeKind::ExpressionStatement => {
+            let expression = node.nodes().next()?;
+            Some(Statement::Expression {
+                expression: lower_expression(expression)?,
+                expression_span: expression.span(),
+                span: node.span(),
+            })
+        }
+        NodeKind::Assert => {
+            let expression = node.nodes().next()?;
+            Some(Statement::Assert {
+                expression: lower_expression(expression)?,
+                expression_span: expression.span(),
+                span: node.span(),
+            })
+        }
+        NodeKind::AssertEq => {
+            let mut nodes = node.nodes();
+            let (lhs, rhs) = (nodes.next()?, nodes.next()?);
+            Some(Statement::AssertEq {
+                lhs: lower_expression(lhs)?,
+                lhs_span: lhs.span(),
+                rhs: lower_expression(rhs)?,
+                rhs_span: rhs.span(),
+                span: node.span(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Each kind of node is lowered by a function of its own, so that the
+/// recursion through nested expressions only keeps small frames.
+pub fn lower_expression(node: &Node) -> Option<Expression> {
+    match node.kind {
+        NodeKind::Var => {
+            let name = node.token(RawToken::Identifier)?;
+            Some(Expression::Var {
+                name: name.literal.clone(),
+                name_span: name.span.clone(),
+            })
+        }
+        NodeKind::Apply => lower_application(node),
+        NodeKind::Paren => lower_paren(node),
+        NodeKind::Operation => lower_operation(node),
+        NodeKind::OperatorName => Some(operator_var(node.token(RawToken::Operator)?)),
+        NodeKind::LeftSection => lower_left_section(node),
+        NodeKind::RightSection => lower_right_section(node),
+        NodeKind::Abstraction => lower_abstraction(node),
+        NodeKind::TypeAbstraction => lower_type_abstraction(node),
+        NodeKind::If => lower_if(node),
+        NodeKind::Match => lower_match(node),
+        NodeKind::TypeApply => lower_type_apply(node),
+        _ => None,
+    }
+}
+
+/// `f a b` is `Apply(Apply(f, a), b)` and `f $ x` is `Dollar(f, x)`. The
+/// applications nested on the left are lowered in a loop rather than
+/// recursively, so that long applications neither use up the stack nor look
+/// for the start of `f` again and again.
+fn lower_application(node: &Node) -> Option<Expression> {
+    let mut arguments = vec![];
+    let mut function = node;
+    while function.kind == NodeKind::Apply {
+        let mut nodes = function.nodes();
+        let (lhs, rhs) = (nodes.next()?, nodes.next()?);
+        arguments.push(rhs);
+        function = lhs;
+    }
+
+    let mut lhs_span = function.span();
+    let start = lhs_span.start;
+    let mut expression = lower_expression(function)?;
+    for argument in arguments.into_iter().rev() {
+        let dollar = argument.kind == NodeKind::Dollar;
+        let argument = match dollar {
+            true => argument.nodes().next()?,
+            false => argument,
+        };
+
+        let rhs_span = argument.span();
+        let (lhs, rhs) = (Box::new(expression), Box::new(lower_expression(argument)?));
+        expression = match dollar {
+            true => Expression::Dollar {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span: rhs_span.clone(),
+            },
+            false => Expression::Apply {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span: rhs_span.clone(),
+            },
+        };
+        lhs_span = start..rhs_span.end;
+    }
+
+    Some(expression)
+}
+
+fn lower_paren(node: &Node) -> Option<Expression> {
+    let expression = node.nodes().next()?;
+    Some(Expression::Paren {
+        expression: Box::new(lower_expression(expression)?),
+        expression_span: expression.span(),
+    })
+}
+
+/// `a + b` is `((+) a) b`.
+fn lower_operation(node: &Node) -> Option<Expression> {
+    let operator = node.token(RawToken::Operator)?;
+    let mut nodes = node.nodes();
+    let (lhs, rhs) = (nodes.next()?, nodes.next()?);
+    Some(Expression::Apply {
+        lhs: Box::new(Expression::Apply {
+            lhs: Box::new(operator_var(operator)),
+            lhs_span: operator.span.clone(),
+            rhs: Box::new(lower_expression(lhs)?),
+            rhs_span: lhs.span(),
+        }),
+        lhs_span: lhs.span().start..operator.span.end,
+        rhs: Box::new(lower_expression(rhs)?),
+        rhs_span: rhs.span(),
+    })
+}
+
+fn lower_left_section(node: &Node) -> Option<Expression> {
+    let operator = node.token(RawToken::Operator)?;
+    let lhs = node.nodes().next()?;
+    Some(Expression::Apply {
+        lhs: Box::new(operator_var(operator)),
+        lhs_span: operator.span.clone(),
+        rhs: Box::new(lower_expression(lhs)?),
+        rhs_span: lhs.span(),
+    })
+}
+
+/// `(+ b)` is `\x => x + b`, for a name `x` that is not free in `b`. The
+/// parameter is not in the source, so its spans are empty.
+fn lower_right_section(node: &Node) -> Option<Expression> {
+    let operator = node.token(RawToken::Operator)?;
+    let rhs = node.nodes().next()?;
+    let operand = lower_expression(rhs)?;
+
+    let free = reduce::free_variables(&operand);
+    let name = match free.contains("x") {
+        true => reduce::fresh_name("x", |name| free.contains(name)),
+        false => "x".to_owned(),
+    };
+    let span = node.span().start..node.span().start;
+    let parameter = Expression::Var {
+        name: name.clone(),
+        name_span: span.clone(),
+    };
+
+    Some(Expression::Abstraction {
+        name,
+        name_span: span.clone(),
+        annotation: None,
+        expression: Box::new(Expression::Apply {
+            lhs: Box::new(Expression::Apply {
+                lhs: Box::new(operator_var(operator)),
+                lhs_span: operator.span.clone(),
+                rhs: Box::new(parameter),
+                rhs_span: span,
+            }),
+            lhs_span: operator.span.clone(),
+            rhs: Box::new(operand),
+            rhs_span: rhs.span(),
+        }),
+        expression_span: node.span(),
+    })
+}
+
+fn lower_abstraction(node: &Node) -> Option<Expression> {
+    let name = node.token(RawToken::Identifier)?;
+    let expression = expression_child(node)?;
+    Some(Expression::Abstraction {
+        name: name.literal.clone(),
+        name_span: name.span.clone(),
+        annotation: lower_annotation(node)?,
+        expression: Box::new(lower_expression(expression)?),
+        expression_span: expression.span(),
+    })
+}
+
+fn lower_type_abstraction(node: &Node) -> Option<Expression> {
+    let name = node.token(RawToken::Identifier)?;
+    let expression = node.nodes().next()?;
+    Some(Expression::TypeAbstraction {
+        name: name.literal.clone(),
+        name_span: name.span.clone(),
+        expression: Box::new(lower_expression(expression)?),
+        expression_span: expression.span(),
+    })
+}
+
+fn lower_if(node: &Node) -> Option<Expression> {
+    let mut nodes = node.nodes();
+    let (condition, then, otherwise) = (nodes.next()?, nodes.next()?, nodes.next()?);
+    Some(Expression::If {
+        condition: Box::new(lower_expression(condition)?),
+        condition_span: condition.span(),
+        then: Box::new(lower_expression(then)?),
+        then_span: then.span(),
+        otherwise: Box::new(lower_expression(otherwise)?),
+        otherwise_span: otherwise.span(),
+    })
+}
+
+fn lower_match(node: &Node) -> Option<Expression> {
+    let keyword = node.token(RawToken::Match)?;
+    let mut nodes = node.nodes();
+    let scrutinee = nodes.next()?;
+    Some(Expression::Match {
+        keyword_span: keyword.span.clone(),
+        scrutinee: Box::new(lower_expression(scrutinee)?),
+        scrutinee_span: scrutinee.span(),
+        arms: nodes.map(lower_arm).collect::<Option<_>>()?,
+    })
+}
+
+fn lower_type_apply(node: &Node) -> Option<Expression> {
+    let expression = expression_child(node)?;
+    let argument = node.nodes().find(|child| child.kind.is_type())?;
+    Some(Expression::TypeApply {
+        expression: Box::new(lower_expression(expression)?),
+        expression_span: expression.span(),
+        argument: lower_type(argument)?,
+    })
+}
+
+pub fn lower_type(node: &Node) -> Option<Type> {
+    match node.kind {
+        NodeKind::TypeVar => {
+            let name = node.token(RawToken::Identifier)?;
+            Some(Type::Var {
+                name: name.literal.clone(),
+                span: name.span.clone(),
+            })
+        }
+        NodeKind::TypeArrow => {
+            let mut nodes = node.nodes();
+            let (lhs, rhs) = (nodes.next()?, nodes.next()?);
+            Some(Type::Arrow {
+                lhs: Box::new(lower_type(lhs)?),
+                rhs: Box::new(lower_type(rhs)?),
+                span: node.span(),
+            })
+        }
+        NodeKind::TypeParen => lower_type(node.nodes().next()?),
+        NodeKind::TypeForall => {
+            let name = node.token(RawToken::Identifier)?;
+            Some(Type::Forall {
+                name: name.literal.clone(),
+                name_span: name.span.clone(),
+                body: Box::new(lower_type(node.nodes().next()?)?),
+                span: node.span(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Fixity declared by an `Infix` node.
+pub fn declared_fixity(node: &Node) -> Option<Fixity> {
+    let associativity = node.children.iter().find_map(|child| match child {
+        Element::Token(token) => match token.raw {
+            RawToken::InfixLeft => Some(Associativity::Left),
+            RawToken::InfixRight => Some(Associativity::Right),
+            RawToken::Infix => Some(Associativity::None),
+            _ => None,
+        },
+        Element::Node(_) => None,
+    })?;
+    let precedence = node.token(RawToken::Identifier)?.literal.parse().ok()?;
+
+    Some(Fixity {
+        associativity,
+        precedence,
+    })
+}
+
+fn lower_constructor(node: &Node) -> Option<ast::Constructor> {
+    if node.kind != NodeKind::Constructor {
+        return None;
+    }
+
+    let mut names = identifiers(node);
+    let name = names.next()?;
+    Some(ast::Constructor {
+        name: name.literal.clone(),
+        name_span: name.span.clone(),
+        fields: names
+            .map(|field| (field.literal.clone(), field.span.clone()))
+            .collect(),
+        span: node.span(),
+    })
+}
+
+fn lower_arm(node: &Node) -> Option<ast::Arm> {
+    if node.kind != NodeKind::Arm {
+        return None;
+    }
+
+    let mut names = identifiers(node);
+    let constructor = names.next()?;
+    let body = node.nodes().next()?;
+    Some(ast::Arm {
+        constructor: constructor.literal.clone(),
+        constructor_span: constructor.span.clone(),
+        fields: names
+            .map(|field| (field.literal.clone(), field.span.clone()))
+            .collect(),
+        body: lower_expression(body)?,
+        body_span: body.span(),
+    })
+}
+
+/// Identifiers among the direct child tokens.
+fn identifiers(node: &Node) -> impl Iterator<Item = &Token> {
+    node.children.iter().filter_map(|child| match child {
+        Element::Token(token) if token.raw == RawToken::Identifier => Some(token),
+        _ => None,
+    })
+}
+
+fn operator_var(operator: &Token) -> Expression {
+    Expression::Var {
+        name: operator.literal.clone(),
+        name_span: operator.span.clone(),
+    }
+}
+
+/// The expression of a `let` or an abstraction, which may also have a type
+/// annotation node.
+fn expression_child(node: &Node) -> Option<&Node> {
+    node.nodes().find(|child| !child.kind.is_type())
+}
+
+/// `Some(None)` if the node has no annotation, `None` if the annotation does
+/// not lower.
+fn lower_annotation(node: &Node) -> Option<Option<Type>> {
+    match node.nodes().find(|child| child.kind.is_type()) {
+        Some(annotation) => Some(Some(lower_type(annotation)?)),
+        None => Some(None),
+    }
+}
+
+#[cfg(test)]
+mod cst_tests {
+    use super::NodeKind;
+    use crate::ast::{Expression, Statement};
+    use crate::lexer::RawToken;
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    #[test]
+    fn lossless() {
+        let source = "-- id\nlet i =  λx . (x) ;\n\ni  i;  -- app\nlet = oops;\n\
+                      infixl 6 + = add; a  +b -- sum\n * c; (+ a) (a +)( + );\n\
+                      syntax [ or ]either a or b = a  b;\n\
+                      data List=Nil |Cons  h t;\n\
+                      if  c then (match xs{Nil=>a;Cons h t => b;}) else  c;";
+        let cst = Parser::new(source, "<stdin>", &mut CodeMap::new()).parse_cst();
+        assert_eq!(cst.text(), source);
+    }
+
+    #[test]
+    fn keeps_separator() {
+        let cst = Parser::new("let i = \\x . x;", "<stdin>", &mut CodeMap::new()).parse_cst();
+        let statement = cst.nodes().next().unwrap();
+        assert_eq!(statement.kind, NodeKind::Let);
+
+        let abstraction = statement.nodes().next().unwrap();
+        assert_eq!(abstraction.kind, NodeKind::Abstraction);
+        assert_eq!(abstraction.span(), 8..14);
+        assert!(abstraction.token(RawToken::Period).is_some());
+    }
+
+    #[test]
+    fn deep_and_wide_terms() {
+        let depth = 500;
+        let deep = format!(
+            "let n = \\f => \\x => {}x{};",
+            "f (".repeat(depth),
+            ")".repeat(depth)
+        );
+        let ast = Parser::new(&deep, "<stdin>", &mut CodeMap::new()).parse();
+        match &ast[..] {
+            [Statement::Let {
+                expression_span, ..
+            }] => {
+                assert_eq!(expression_span, &(8..deep.len() - 1))
+            }
+            ast => panic!("unexpected statements: {:?}", ast),
+        }
+
+        let width = 10_000;
+        let wide = format!("f{};", " x".repeat(width));
+        let ast = Parser::new(&wide, "<stdin>", &mut CodeMap::new()).parse();
+        match &ast[..] {
+            [Statement::Expression {
+                expression: Expression::Apply { lhs_span, .. },
+                ..
+            }] => assert_eq!(lhs_span, &(0..2 * width - 1)),
+            ast => panic!("unexpected statements: {:?}", ast),
+        }
+    }
+}
@@ -0,0 +1,756 @@
+//! Algebraic data types, Scott-encoded, and the `match` and `if` that take
+//! their values apart.
+//!
+//! `data List = Nil | Cons h t;` defines a function for every constructor,
+//! `Nil = \nil => \cons => nil` and
+//! `Cons = \h => \t => \nil => \cons => cons h t`: a value takes one case
+//! per constructor and passes its fields to the case of its own. The
+//! eliminator `list = \nil => \cons => \x => x nil cons` is named after the
+//! type and takes the cases first, like Haskell's `maybe`.
+//!
+//! `match xs { Nil => a; Cons h t => b }` is then `xs a (\h => \t => b)`,
+//! with the arms in the order of the constructors and `_` standing for the
+//! constructors without an arm. `if c then a else b` is `c a b`, for Church
+//! booleans and `data Bool = True | False;` alike.
+
+use std::collections::HashMap;
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Arm, Expression, Span, Statement};
+use crate::reduce;
+
+/// The arm that matches every constructor without an arm of its own.
+pub const WILDCARD: &str = "_";
+
+/// Data types declared so far.
+#[derive(Clone, Debug, Default)]
+pub struct DataTypes {
+    /// Constructors of every type in declaration order, with the number of
+    /// their fields.
+    types: HashMap<String, Vec<(String, usize)>>,
+    /// Type of every constructor.
+    constructors: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DataError {
+    /// `constructor` is declared at `span` and at `previous` in the
+    /// declaration of `name`.
+    DuplicateConstructor {
+        name: String,
+        constructor: String,
+        span: Span,
+        previous: Span,
+    },
+    /// The arm at `span` matches `constructor`, which is not declared.
+    UnknownConstructor { constructor: String, span: Span },
+    /// `constructor`, matched at `span`, is a constructor of `found`, but
+    /// the match is on `expected`, the type of its first arm.
+    WrongType {
+        constructor: String,
+        span: Span,
+        expected: String,
+        found: String,
+    },
+    /// The pattern at `span` has `found` fields, but `constructor` has
+    /// `expected`.
+    WrongArity {
+        constructor: String,
+        span: Span,
+        expected: usize,
+        found: usize,
+    },
+    /// The arm at `span` matches nothing: the arm at `previous` matches the
+    /// same constructor, or there is no `previous` and every constructor has
+    /// an arm before it.
+    Unreachable { span: Span, previous: Option<Span> },
+    /// The `match` at `span` has no arm for the `missing` patterns, like
+    /// `Cons _ _`.
+    NonExhaustive { span: Span, missing: Vec<String> },
+    /// The declaration at `span` is checked under `typing`, a type system
+    /// that cannot type its constructors without annotations.
+    Typed { span: Span, typing: String },
+}
+
+impl DataError {
+    pub fn diagnostic(&self, file_span: codemap::Span) -> Diagnostic {
+        let span = |span: &Span| file_span.subspan(span.start as u64, span.end as u64);
+        let label = |at: &Span, style, label: String| SpanLabel {
+            span: span(at),
+            style,
+            label: Some(label),
+        };
+
+        let (message, spans) = match self {
+            DataError::DuplicateConstructor {
+                name,
+                constructor,
+                span: at,
+                previous,
+            } => (
+                format!("`{}` is declared twice in `{}`", constructor, name),
+                vec![
+                    label(at, SpanStyle::Primary, "declared again here".to_owned()),
+                    label(
+                        previous,
+                        SpanStyle::Secondary,
+                        "first declared here".to_owned(),
+                    ),
+                ],
+            ),
+            DataError::UnknownConstructor {
+                constructor,
+                span: at,
+            } => (
+                format!("unknown constructor `{}`", constructor),
+                vec![label(
+                    at,
+                    SpanStyle::Primary,
+                    "help: declare it with `data`, or use `_` for any other constructor".to_owned(),
+                )],
+            ),
+            DataError::WrongType {
+                constructor,
+                span: at,
+                expected,
+                found,
+            } => (
+                format!("`{}` is not a constructor of `{}`", constructor, expected),
+                vec![label(
+                    at,
+                    SpanStyle::Primary,
+                    format!("is a constructor of `{}`", found),
+                )],
+            ),
+            DataError::WrongArity {
+                constructor,
+                span: at,
+                expected,
+                found,
+            } => (
+                format!("wrong number of fields for `{}`", constructor),
+                vec![label(
+                    at,
+                    SpanStyle::Primary,
+                    format!("has {} fields instead of {}", found, expected),
+                )],
+            ),
+            DataError::Unreachable { span: at, previous } => (
+                "unreachable arm".to_owned(),
+                match previous {
+                    Some(previous) => vec![
+                        label(at, SpanStyle::Primary, "never matches".to_owned()),
+                        label(
+                            previous,
+                            SpanStyle::Secondary,
+                            "this arm matches the same values".to_owned(),
+                        ),
+                    ],
+                    None => vec![label(
+                        at,
+                        SpanStyle::Primary,
+                        "every constructor has an arm before this one".to_owned(),
+                    )],
+                },
+            ),
+            DataError::NonExhaustive { span: at, missing } => {
+                let missing: Vec<_> = missing
+                    .iter()
+                    .map(|pattern| format!("`{}`", pattern))
+                    .collect();
+                (
+                    "non-exhaustive match".to_owned(),
+                    vec![label(
+                        at,
+                        SpanStyle::Primary,
+                        format!("{} not covered", missing.join(", ")),
+                    )],
+                )
+            }
+            DataError::Typed { span: at, typing } => (
+                "data types are only supported untyped or under `--typed=hm`".to_owned(),
+                vec![label(
+                    at,
+                    SpanStyle::Primary,
+                    format!("not supported under `--typed={}`", typing),
+                )],
+            ),
+        };
+
+        Diagnostic {
+            level: Level::Error,
+            message,
+            code: Some("E016".to_owned()),
+            spans,
+        }
+    }
+}
+
+/// The `let`s of the constructors and the eliminator of a `data` statement,
+/// in this order. Other statements define nothing.
+pub fn definitions(statement: &Statement) -> Result<Vec<Statement>, DataError> {
+    let Statement::Data {
+        name,
+        name_span,
+        constructors,
+        span,
+    } = statement
+    else {
+        return Ok(vec![]);
+    };
+
+    for (i, constructor) in constructors.iter().enumerate() {
+        if let Some(previous) = constructors[..i]
+            .iter()
+            .find(|previous| previous.name == constructor.name)
+        {
+            return Err(DataError::DuplicateConstructor {
+                name: name.clone(),
+                constructor: constructor.name.clone(),
+                span: constructor.name_span.clone(),
+                previous: previous.name_span.clone(),
+            });
+        }
+    }
+
+    let names: Vec<_> = constructors
+        .iter()
+        .map(|constructor| constructor.name.as_str())
+        .collect();
+    let let_ = |name: &str, name_span: &Span, expression, span: &Span| Statement::Let {
+        name: name.to_owned(),
+        name_span: name_span.clone(),
+        annotation: None,
+        fixity: None,
+        expression,
+        expression_span: span.clone(),
+        span: span.clone(),
+    };
+
+    let mut definitions = vec![];
+    for (i, constructor) in constructors.iter().enumerate() {
+        let at = &constructor.span;
+
+        // Fields are only documentation, so repeated names are renamed.
+        let mut fields: Vec<String> = vec![];
+        for (field, _) in &constructor.fields {
+            fields.push(match fields.contains(field) {
+                true => reduce::fresh_name(field, |name| fields.iter().any(|field| field == name)),
+                false => field.clone(),
+            });
+        }
+
+        let cases = case_names(&names, &fields);
+        let body = fields.iter().fold(var(&cases[i], at), |function, field| {
+            apply(function, var(field, at), at)
+        });
+        let term = abstractions(fields.iter().chain(&cases), body, at);
+        definitions.push(let_(&constructor.name, &constructor.name_span, term, at));
+    }
+
+    let cases = case_names(&names, &[]);
+    let taken = |name: &str| cases.iter().any(|case| case == name);
+    let value = match taken("x") {
+        true => reduce::fresh_name("x", taken),
+        false => "x".to_owned(),
+    };
+    let body = cases.iter().fold(var(&value, span), |function, case| {
+        apply(function, var(case, span), span)
+    });
+    let term = abstractions(cases.iter().chain([&value]), body, span);
+    definitions.push(let_(&lowercase(name), name_span, term, span));
+
+    Ok(definitions)
+}
+
+impl DataTypes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the constructors of a `data` statement, once its
+    /// [`definitions`] are defined. Other statements are ignored.
+    pub fn declare(&mut self, statement: &Statement) {
+        if let Statement::Data {
+            name, constructors, ..
+        } = statement
+        {
+            for constructor in constructors {
+                self.constructors
+                    .insert(constructor.name.clone(), name.clone());
+            }
+            self.types.insert(
+                name.clone(),
+                constructors
+                    .iter()
+                    .map(|constructor| (constructor.name.clone(), constructor.fields.len()))
+                    .collect(),
+            );
+        }
+    }
+
+    /// `expression` with every `if` and `match` turned into applications.
+    pub fn desugar(&self, expression: &Expression) -> Result<Expression, DataError> {
+        Ok(match expression {
+            Expression::If {
+                condition,
+                condition_span,
+                then,
+                then_span,
+                otherwise,
+                otherwise_span,
+            } => applications(
+                (self.desugar(condition)?, condition_span.clone()),
+                vec![
+                    (self.desugar(then)?, then_span.clone()),
+                    (self.desugar(otherwise)?, otherwise_span.clone()),
+                ],
+            ),
+            Expression::Match {
+                keyword_span,
+                scrutinee,
+                scrutinee_span,
+                arms,
+            } => {
+                let function = (self.desugar(scrutinee)?, scrutinee_span.clone());
+                match self.cases(keyword_span, arms)? {
+                    Some(cases) => applications(function, cases),
+                    // Only `_`, which matches anything without looking.
+                    None => self.desugar(&arms[0].body)?,
+                }
+            }
+            Expression::Var { .. } => expression.clone(),
+            Expression::Apply {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => Expression::Apply {
+                lhs: Box::new(self.desugar(lhs)?),
+                lhs_span: lhs_span.clone(),
+                rhs: Box::new(self.desugar(rhs)?),
+                rhs_span: rhs_span.clone(),
+            },
+            Expression::Dollar {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => Expression::Dollar {
+                lhs: Box::new(self.desugar(lhs)?),
+                lhs_span: lhs_span.clone(),
+                rhs: Box::new(self.desugar(rhs)?),
+                rhs_span: rhs_span.clone(),
+            },
+            Expression::Paren {
+                expression,
+                expression_span,
+            } => Expression::Paren {
+                expression: Box::new(self.desugar(expression)?),
+                expression_span: expression_span.clone(),
+            },
+            Expression::Abstraction {
+                name,
+                name_span,
+                annotation,
+                expression,
+                expression_span,
+            } => Expression::Abstraction {
+                name: name.clone(),
+                name_span: name_span.clone(),
+                annotation: annotation.clone(),
+                expression: Box::new(self.desugar(expression)?),
+                expression_span: expression_span.clone(),
+            },
+            Expression::TypeAbstraction {
+                name,
+                name_span,
+                expression,
+                expression_span,
+            } => Expression::TypeAbstraction {
+                name: name.clone(),
+                name_span: name_span.clone(),
+                expression: Box::new(self.desugar(expression)?),
+                expression_span: expression_span.clone(),
+            },
+            Expression::TypeApply {
+                expression,
+                expression_span,
+                argument,
+            } => Expression::TypeApply {
+                expression: Box::new(self.desugar(expression)?),
+                expression_span: expression_span.clone(),
+                argument: argument.clone(),
+            },
+        })
+    }
+
+    /// `statement` with its expressions desugared.
+    pub fn desugar_statement(&self, statement: &Statement) -> Result<Statement, DataError> {
+        let mut statement = statement.clone();
+        match &mut statement {
+            Statement::Expression { expression, .. }
+            | Statement::Let { expression, .. }
+            | Statement::Assert { expression, .. } => *expression = self.desugar(expression)?,
+            Statement::AssertEq { lhs, rhs, .. } => {
+                *lhs = self.desugar(lhs)?;
+                *rhs = self.desugar(rhs)?;
+            }
+            Statement::Syntax { .. } | Statement::Data { .. } => {}
+        }
+
+        Ok(statement)
+    }
+
+    /// The cases of the `match` at `keyword_span`, in the order of the
+    /// constructors of its type, or `None` if it only has a `_` arm.
+    fn cases(
+        &self,
+        keyword_span: &Span,
+        arms: &[Arm],
+    ) -> Result<Option<Vec<(Expression, Span)>>, DataError> {
+        let mut ty: Option<&String> = None;
+        let mut matched: Vec<&Arm> = vec![];
+        let mut wildcard: Option<&Arm> = None;
+        for arm in arms {
+            if let Some(wildcard) = wildcard {
+                return Err(DataError::Unreachable {
+                    span: arm.span(),
+                    previous: Some(wildcard.span()),
+                });
+            }
+
+            let pattern = arm.constructor_span.start
+                ..arm
+                    .fields
+                    .last()
+                    .map_or(arm.constructor_span.end, |(_, span)| span.end);
+            if arm.constructor == WILDCARD {
+                if !arm.fields.is_empty() {
+                    return Err(DataError::WrongArity {
+                        constructor: WILDCARD.to_owned(),
+                        span: pattern,
+                        expected: 0,
+                        found: arm.fields.len(),
+                    });
+                }
+
+                wildcard = Some(arm);
+                continue;
+            }
+
+            let found = self.constructors.get(&arm.constructor).ok_or_else(|| {
+                DataError::UnknownConstructor {
+                    constructor: arm.constructor.clone(),
+                    span: arm.constructor_span.clone(),
+                }
+            })?;
+            match ty {
+                Some(expected) if expected != found => {
+                    return Err(DataError::WrongType {
+                        constructor: arm.constructor.to_owned(),
+                        span: arm.constructor_span.clone(),
+                        expected: expected.clone(),
+                        found: found.clone(),
+                    })
+                }
+                _ => ty = Some(found),
+            }
+
+            if let Some(previous) = matched
+                .iter()
+                .find(|previous| previous.constructor == arm.constructor)
+            {
+                return Err(DataError::Unreachable {
+                    span: arm.span(),
+                    previous: Some(previous.span()),
+                });
+            }
+
+            let (_, arity) = self.types[found]
+                .iter()
+                .find(|(constructor, _)| *constructor == arm.constructor)
+                .unwrap();
+            if arm.fields.len() != *arity {
+                return Err(DataError::WrongArity {
+                    constructor: arm.constructor.to_owned(),
+                    span: pattern,
+                    expected: *arity,
+                    found: arm.fields.len(),
+                });
+            }
+
+            matched.push(arm);
+        }
+
+        let Some(ty) = ty else {
+            return Ok(None);
+        };
+
+        let mut cases = vec![];
+        let mut missing = vec![];
+        for (constructor, arity) in &self.types[ty] {
+            match matched.iter().find(|arm| &arm.constructor == constructor) {
+                Some(arm) => cases.push(self.case(arm)?),
+                None => match wildcard {
+                    Some(arm) => {
+                        cases.push((self.ignore_fields(arm, *arity)?, arm.body_span.clone()))
+                    }
+                    None => missing.push(
+                        std::iter::once(constructor.as_str())
+                            .chain(std::iter::repeat_n(WILDCARD, *arity))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                },
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(DataError::NonExhaustive {
+                span: keyword_span.clone(),
+                missing,
+            });
+        }
+
+        match wildcard {
+            Some(arm) if matched.len() == self.types[ty].len() => Err(DataError::Unreachable {
+                span: arm.span(),
+                previous: None,
+            }),
+            _ => Ok(Some(cases)),
+        }
+    }
+
+    /// `\h => \t => b` for the arm `Cons h t => b`, and its span from the
+    /// first field.
+    fn case(&self, arm: &Arm) -> Result<(Expression, Span), DataError> {
+        let mut case = self.desugar(&arm.body)?;
+        let mut span = arm.body_span.clone();
+        for (field, field_span) in arm.fields.iter().rev() {
+            case = Expression::Abstraction {
+                name: field.clone(),
+                name_span: field_span.clone(),
+                annotation: None,
+                expression: Box::new(case),
+                expression_span: span.clone(),
+            };
+            span = field_span.start..span.end;
+        }
+
+        Ok((case, span))
+    }
+
+    /// The case of the `_` arm for a constructor with `arity` fields, which
+    /// are not used.
+    fn ignore_fields(&self, arm: &Arm, arity: usize) -> Result<Expression, DataError> {
+        let case = self.desugar(&arm.body)?;
+        let name = match reduce::free_variables(&case).contains(WILDCARD) {
+            true => reduce::fresh_name(WILDCARD, |name| {
+                reduce::free_variables(&case).contains(name)
+            }),
+            false => WILDCARD.to_owned(),
+        };
+
+        Ok(abstractions(
+            std::iter::repeat_n(&name, arity),
+            case,
+            &arm.constructor_span,
+        ))
+    }
+}
+
+/// Names of the cases of the constructors `names`, like `cons` for `Cons`,
+/// that are not taken by `fields`.
+fn case_names(names: &[&str], fields: &[String]) -> Vec<String> {
+    let mut cases: Vec<String> = vec![];
+    for name in names {
+        let case = lowercase(name);
+        let taken = |name: &str| fields.iter().chain(&cases).any(|taken| taken == name);
+        cases.push(match taken(&case) {
+            true => reduce::fresh_name(&case, taken),
+            false => case,
+        });
+    }
+
+    cases
+}
+
+/// `name` with its first letter in lower case, like `list` for `List`.
+fn lowercase(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// `function` applied to the `arguments` in turn.
+fn applications(function: (Expression, Span), arguments: Vec<(Expression, Span)>) -> Expression {
+    let (mut function, mut span) = function;
+    for (argument, argument_span) in arguments {
+        let lhs_span = span.clone();
+        span = span.start..argument_span.end;
+        function = Expression::Apply {
+            lhs: Box::new(function),
+            lhs_span,
+            rhs: Box::new(argument),
+            rhs_span: argument_span,
+        };
+    }
+
+    function
+}
+
+fn var(name: &str, span: &Span) -> Expression {
+    Expression::Var {
+        name: name.to_owned(),
+        name_span: span.clone(),
+    }
+}
+
+fn apply(lhs: Expression, rhs: Expression, span: &Span) -> Expression {
+    Expression::Apply {
+        lhs: Box::new(lhs),
+        lhs_span: span.clone(),
+        rhs: Box::new(rhs),
+        rhs_span: span.clone(),
+    }
+}
+
+/// `body` under abstractions over `names`, the first one outermost.
+fn abstractions<'a>(
+    names: impl DoubleEndedIterator<Item = &'a String>,
+    body: Expression,
+    span: &Span,
+) -> Expression {
+    names
+        .rev()
+        .fold(body, |body, name| Expression::Abstraction {
+            name: name.clone(),
+            name_span: span.clone(),
+            annotation: None,
+            expression: Box::new(body),
+            expression_span: span.clone(),
+        })
+}
+
+#[cfg(test)]
+mod data_tests {
+    use super::{definitions, DataError, DataTypes};
+    use crate::ast::Statement;
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    /// Declares the data types of `source` and desugars its expression
+    /// statements, or returns the definitions of its data types.
+    fn desugar(source: &str) -> Result<Vec<String>, DataError> {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors(), "{:?}", parser.diagnostics);
+
+        let mut data = DataTypes::new();
+        let mut desugared = vec![];
+        for statement in &ast {
+            match statement {
+                Statement::Data { .. } => {
+                    for definition in definitions(statement)? {
+                        if let Statement::Let {
+                            name, expression, ..
+                        } = definition
+                        {
+                            desugared.push(format!("{} = {}", name, expression));
+                        }
+                    }
+                    data.declare(statement);
+                }
+                Statement::Expression { expression, .. } => {
+                    desugared.push(data.desugar(expression)?.to_string())
+                }
+                _ => {}
+            }
+        }
+
+        Ok(desugared)
+    }
+
+    #[test]
+    fn constructors_and_eliminators() {
+        assert_eq!(
+            desugar("data List = Nil | Cons h t; data Pair = Pair a a;").unwrap(),
+            vec![
+                "Nil = \\nil => \\cons => nil",
+                "Cons = \\h => \\t => \\nil => \\cons => cons h t",
+                "list = \\nil => \\cons => \\x => x nil cons",
+                "Pair = \\a => \\a1 => \\pair => pair a a1",
+                "pair = \\pair => \\x => x pair",
+            ]
+        );
+        assert!(matches!(
+            desugar("data Bool = True | True;"),
+            Err(DataError::DuplicateConstructor { span, previous, .. })
+                if span == (19..23) && previous == (12..16)
+        ));
+    }
+
+    #[test]
+    fn matches_and_conditionals() {
+        let lists = "data List = Nil | Cons h t;";
+        assert_eq!(
+            desugar(&format!(
+                "{} match xs {{ Cons h t => h; Nil => a }}; match xs {{ Nil => a; _ => b }};
+                 if c then (match xs {{ _ => a }}) else \\x => x;",
+                lists
+            ))
+            .unwrap()[3..],
+            [
+                "xs a (\\h => \\t => h)",
+                "xs a (\\_ => \\_ => b)",
+                "c (a) (\\x => x)",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let lists = "data List = Nil | Cons h t; data Bool = True | False;";
+        let error = |source: &str| desugar(&format!("{} {}", lists, source)).unwrap_err();
+
+        assert_eq!(
+            error("match xs { Nil => a };"),
+            DataError::NonExhaustive {
+                span: 54..59,
+                missing: vec!["Cons _ _".to_owned()]
+            }
+        );
+        assert!(matches!(
+            error("match xs { Nil => a; True => b };"),
+            DataError::WrongType { expected, found, .. } if expected == "List" && found == "Bool"
+        ));
+        assert!(matches!(
+            error("match xs { Nil => a; Cons h => b };"),
+            DataError::WrongArity {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("match xs { Nil => a; Nothing => b };"),
+            DataError::UnknownConstructor { .. }
+        ));
+        assert!(matches!(
+            error("match xs { _ => a; Nil => b };"),
+            DataError::Unreachable {
+                previous: Some(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            error("match c { True => a; False => b; _ => c };"),
+            DataError::Unreachable { previous: None, .. }
+        ));
+    }
+}
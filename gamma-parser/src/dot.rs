@@ -0,0 +1,255 @@
+//! Pictures of terms in the DOT language of Graphviz: the tree of an
+//! expression with its binders linked to the variables they bind, and the
+//! graph of the terms that an expression reduces to.
+//!
+//! `dot -Tsvg` renders them.
+
+use std::fmt::Write;
+
+use crate::ast::Expression;
+use crate::format::format_expression;
+use crate::reduce::{alpha_equivalent, reducts};
+
+/// `label` as a quoted DOT string.
+fn quote(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The tree of `expression`, with a dashed edge from every abstraction to
+/// each variable it binds. Free variables are boxes, and parentheses are
+/// left out.
+pub fn term_tree(expression: &Expression) -> String {
+    let mut tree = Tree::default();
+    tree.node(expression, &mut vec![]);
+
+    let mut dot = String::from("digraph {\n");
+    for line in tree.lines {
+        writeln!(dot, "    {};", line).unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[derive(Default)]
+struct Tree {
+    lines: Vec<String>,
+    nodes: usize,
+}
+
+impl Tree {
+    fn add(&mut self, attributes: String) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        self.lines.push(format!("n{} [{}]", id, attributes));
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        self.lines.push(format!("n{} -> n{}", from, to));
+    }
+
+    /// Adds the nodes of `expression` and returns the root. `scope` has the
+    /// abstractions around it, innermost last.
+    fn node<'e>(&mut self, expression: &'e Expression, scope: &mut Vec<(&'e str, usize)>) -> usize {
+        match expression {
+            Expression::Var { name, .. } => {
+                let binder = scope
+                    .iter()
+                    .rev()
+                    .find(|(binder, _)| binder == name)
+                    .map(|&(_, id)| id);
+                match binder {
+                    Some(binder) => {
+                        let id = self.add(format!("label={}", quote(name)));
+                        self.lines.push(format!(
+                            "n{} -> n{} [style=dashed, constraint=false]",
+                            binder, id
+                        ));
+                        id
+                    }
+                    None => self.add(format!("label={}, shape=box", quote(name))),
+                }
+            }
+            Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+                let id = self.add("label=\"@\"".to_owned());
+                let lhs = self.node(lhs, scope);
+                let rhs = self.node(rhs, scope);
+                self.edge(id, lhs);
+                self.edge(id, rhs);
+                id
+            }
+            Expression::Paren { expression, .. } => self.node(expression, scope),
+            Expression::Abstraction {
+                name,
+                annotation,
+                expression,
+                ..
+            } => {
+                let label = match annotation {
+                    Some(annotation) => format!("λ{} : {}", name, annotation),
+                    None => format!("λ{}", name),
+                };
+                let id = self.add(format!("label={}", quote(&label)));
+                scope.push((name, id));
+                let body = self.node(expression, scope);
+                scope.pop();
+                self.edge(id, body);
+                id
+            }
+            Expression::TypeAbstraction {
+                name, expression, ..
+            } => {
+                let id = self.add(format!("label={}", quote(&format!("Λ{}", name))));
+                let body = self.node(expression, scope);
+                self.edge(id, body);
+                id
+            }
+            Expression::TypeApply {
+                expression,
+                argument,
+                ..
+            } => {
+                let id = self.add(format!("label={}", quote(&format!("[{}]", argument))));
+                let function = self.node(expression, scope);
+                self.edge(id, function);
+                id
+            }
+            Expression::If { .. } | Expression::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before drawing")
+            }
+        }
+    }
+}
+
+/// The graph of the terms reachable from `expression` by beta reductions,
+/// up to alpha equivalence, with an edge for every redex. Paths that split
+/// and meet again show confluence, and cycles terms that reduce to
+/// themselves.
+///
+/// At most `limit` terms are shown: those whose reducts did not all fit are
+/// dashed. Normal forms have a double border, and the steps that normal order
+/// takes are bold.
+pub fn reduction_graph(expression: &Expression, limit: usize) -> String {
+    let mut terms = vec![expression.clone()];
+    let mut edges = vec![];
+    let mut truncated = vec![];
+    let mut normal = vec![];
+
+    let mut next = 0;
+    while next < terms.len() {
+        let reducts = reducts(&terms[next]);
+        normal.push(reducts.is_empty());
+        truncated.push(false);
+
+        for (index, reduct) in reducts.into_iter().enumerate() {
+            let to = match terms
+                .iter()
+                .position(|term| alpha_equivalent(term, &reduct))
+            {
+                Some(to) => to,
+                None if terms.len() < limit => {
+                    terms.push(reduct);
+                    terms.len() - 1
+                }
+                None => {
+                    truncated[next] = true;
+                    continue;
+                }
+            };
+            edges.push((next, to, index == 0));
+        }
+
+        next += 1;
+    }
+
+    let mut dot = String::from("digraph {\n    node [shape=box];\n");
+    for (id, term) in terms.iter().enumerate() {
+        let mut attributes = vec![format!("label={}", quote(&format_expression(term)))];
+        if normal[id] {
+            attributes.push("peripheries=2".to_owned());
+        }
+        if truncated[id] {
+            attributes.push("style=dashed".to_owned());
+        }
+        writeln!(dot, "    n{} [{}];", id, attributes.join(", ")).unwrap();
+    }
+    for (from, to, normal_order) in edges {
+        match normal_order {
+            true => writeln!(dot, "    n{} -> n{} [style=bold];", from, to),
+            false => writeln!(dot, "    n{} -> n{};", from, to),
+        }
+        .unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod dot_tests {
+    use super::{reduction_graph, term_tree};
+    use crate::ast::Expression;
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    fn expression(source: &str) -> Expression {
+        let mut codemap = CodeMap::new();
+        let expression = Parser::new(source, "<stdin>", &mut codemap)
+            .parse_single_expression()
+            .unwrap();
+        expression
+    }
+
+    #[test]
+    fn binders_point_to_their_variables() {
+        assert_eq!(
+            term_tree(&expression("\\x => \\x => (x y)")),
+            "digraph {
+    n0 [label=\"λx\"];
+    n1 [label=\"λx\"];
+    n2 [label=\"@\"];
+    n3 [label=\"x\"];
+    n1 -> n3 [style=dashed, constraint=false];
+    n4 [label=\"y\", shape=box];
+    n2 -> n3;
+    n2 -> n4;
+    n1 -> n2;
+    n0 -> n1;
+}
+"
+        );
+    }
+
+    #[test]
+    fn reductions_meet_again() {
+        assert_eq!(
+            reduction_graph(&expression("(\\x => \\y => x) a ((\\z => z) b)"), 100),
+            "digraph {
+    node [shape=box];
+    n0 [label=\"(\\\\x => \\\\y => x) a ((\\\\z => z) b)\"];
+    n1 [label=\"(\\\\y => a) ((\\\\z => z) b)\"];
+    n2 [label=\"(\\\\x => \\\\y => x) a b\"];
+    n3 [label=\"a\", peripheries=2];
+    n4 [label=\"(\\\\y => a) b\"];
+    n0 -> n1 [style=bold];
+    n0 -> n2;
+    n1 -> n3 [style=bold];
+    n1 -> n4;
+    n2 -> n4 [style=bold];
+    n4 -> n3 [style=bold];
+}
+"
+        );
+    }
+
+    #[test]
+    fn divergent_terms() {
+        let omega = reduction_graph(&expression("(\\x => x x) (\\x => x x)"), 100);
+        assert!(omega.contains("n0 -> n0 [style=bold];"));
+        assert!(!omega.contains("n1"));
+
+        let growing = reduction_graph(&expression("(\\x => x x x) (\\x => x x x)"), 3);
+        assert!(growing.contains("n2 ["));
+        assert!(growing.contains("style=dashed"));
+        assert!(!growing.contains("n3"));
+    }
+}
@@ -0,0 +1,908 @@
+use std::collections::HashMap;
+
+use crate::ast::{Arm, Associativity, Expression, Fixity, Statement, Type, AST};
+use crate::lexer::{is_operator, Token};
+use crate::reduce::free_variables;
+
+/// Options for [`format`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Column after which long applications are wrapped onto several lines.
+    pub width: usize,
+    /// Number of spaces used to indent wrapped arguments.
+    pub indent: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+/// Re-prints a parsed program with canonical spacing.
+///
+/// `source` and `comments` must come from the same [`crate::parser::Parser`]
+/// run that produced `ast`. Comments are kept in place: a comment on the same
+/// line as the end of a statement stays after it, a comment between
+/// statements stays on its own line, and a statement containing comments is
+/// printed as written. Single blank lines between statements are preserved.
+pub fn format(source: &str, ast: &AST, comments: &[Token], config: &Config) -> String {
+    let fixities = ast
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Let {
+                name,
+                fixity: Some(fixity),
+                ..
+            } => Some((name.clone(), *fixity)),
+            _ => None,
+        })
+        .collect();
+
+    let mut printer = Printer {
+        config,
+        fixities: &fixities,
+        output: String::new(),
+        column: 0,
+    };
+
+    let mut comments = comments.iter().peekable();
+    let mut previous_end = None;
+
+    for statement in ast {
+        let span = statement_span(statement);
+
+        while let Some(comment) = comments.next_if(|comment| comment.span.start < span.start) {
+            if let Some(end) = previous_end {
+                if !source[end..comment.span.start].contains('\n') {
+                    printer.trailing_comment(comment);
+                    previous_end = Some(comment.span.end);
+                    continue;
+                }
+            }
+
+            printer.separate(source, previous_end, comment.span.start);
+            printer.write(&comment.literal);
+            printer.newline();
+            previous_end = Some(comment.span.end);
+        }
+
+        printer.separate(source, previous_end, span.start);
+        if comments
+            .peek()
+            .is_some_and(|comment| comment.span.start < span.end)
+        {
+            // Reprinting would have to move the comments out of the statement.
+            printer.write_verbatim(&source[span.clone()]);
+            while comments
+                .next_if(|comment| comment.span.start < span.end)
+                .is_some()
+            {}
+        } else {
+            printer.statement(statement);
+        }
+        previous_end = Some(span.end);
+    }
+
+    for comment in comments {
+        match previous_end {
+            Some(end) if !source[end..comment.span.start].contains('\n') => {
+                printer.trailing_comment(comment);
+                previous_end = Some(comment.span.end);
+                continue;
+            }
+            _ => {}
+        }
+
+        printer.separate(source, previous_end, comment.span.start);
+        printer.write(&comment.literal);
+        printer.newline();
+        previous_end = Some(comment.span.end);
+    }
+
+    printer.finish()
+}
+
+/// Prints an expression on a single line, adding the parentheses needed to
+/// read it back as the same tree. Operations are parenthesized whenever they
+/// are operands, as the fixities of their operators are not known.
+pub fn format_expression(expression: &Expression) -> String {
+    let mut output = String::new();
+    write_flat(&mut output, expression, &HashMap::new());
+    output
+}
+
+/// Prints a type, adding the parentheses needed to read it back as the same
+/// tree.
+pub fn format_type(ty: &Type) -> String {
+    match ty {
+        Type::Var { name, .. } => name.clone(),
+        Type::Arrow { lhs, rhs, .. } => match **lhs {
+            Type::Arrow { .. } | Type::Forall { .. } => {
+                format!("({}) -> {}", format_type(lhs), format_type(rhs))
+            }
+            _ => format!("{} -> {}", format_type(lhs), format_type(rhs)),
+        },
+        Type::Forall { name, body, .. } => format!("forall {}. {}", name, format_type(body)),
+    }
+}
+
+/// `\x => ` or `\x: A => `, the part of an abstraction before its body.
+fn abstraction_head(name: &str, annotation: &Option<Type>) -> String {
+    match annotation {
+        Some(annotation) => format!("\\{}: {} => ", name, format_type(annotation)),
+        None => format!("\\{} => ", name),
+    }
+}
+
+/// `Cons h t => `, the part of an arm before its body.
+fn arm_head(arm: &Arm) -> String {
+    let mut head = arm.constructor.clone();
+    for (field, _) in &arm.fields {
+        head.push(' ');
+        head.push_str(field);
+    }
+    head + " => "
+}
+
+fn statement_span(statement: &Statement) -> crate::ast::Span {
+    match statement {
+        Statement::Expression { span, .. }
+        | Statement::Let { span, .. }
+        | Statement::Syntax { span, .. }
+        | Statement::Data { span, .. }
+        | Statement::Assert { span, .. }
+        | Statement::AssertEq { span, .. } => span.clone(),
+    }
+}
+
+type Fixities = HashMap<String, Fixity>;
+
+fn write_flat(output: &mut String, expression: &Expression, fixities: &Fixities) {
+    if let Some((operator, lhs, rhs)) = infix(expression) {
+        let parens = operand_needs_parens(lhs, operator, Associativity::Left, fixities);
+        write_operand(output, lhs, fixities, parens);
+        output.push(' ');
+        output.push_str(operator);
+        output.push(' ');
+        let parens = operand_needs_parens(rhs, operator, Associativity::Right, fixities);
+        write_operand(output, rhs, fixities, parens);
+        return;
+    }
+
+    if let Some((operator, lhs)) = left_section(expression) {
+        output.push('(');
+        let parens = operand_needs_parens(lhs, operator, Associativity::Left, fixities);
+        write_operand(output, lhs, fixities, parens);
+        output.push(' ');
+        output.push_str(operator);
+        output.push(')');
+        return;
+    }
+
+    if let Some((operator, rhs)) = right_section(expression) {
+        output.push('(');
+        output.push_str(operator);
+        output.push(' ');
+        let parens = operand_needs_parens(rhs, operator, Associativity::Right, fixities);
+        write_operand(output, rhs, fixities, parens);
+        output.push(')');
+        return;
+    }
+
+    match expression {
+        Expression::Var { name, .. } if is_operator(name) => {
+            output.push('(');
+            output.push_str(name);
+            output.push(')');
+        }
+        Expression::Var { name, .. } => output.push_str(name),
+        Expression::Apply { lhs, rhs, .. } => {
+            write_operand(output, lhs, fixities, function_needs_parens(lhs));
+            output.push(' ');
+            write_operand(output, rhs, fixities, argument_needs_parens(rhs));
+        }
+        Expression::Dollar { lhs, rhs, .. } => {
+            write_operand(output, lhs, fixities, dollar_function_needs_parens(lhs));
+            output.push_str(" $ ");
+            write_flat(output, rhs, fixities);
+        }
+        Expression::TypeApply {
+            expression,
+            argument,
+            ..
+        } => {
+            write_operand(
+                output,
+                expression,
+                fixities,
+                function_needs_parens(expression),
+            );
+            output.push_str(" [");
+            output.push_str(&format_type(argument));
+            output.push(']');
+        }
+        Expression::Paren { expression, .. } => {
+            output.push('(');
+            write_flat(output, expression, fixities);
+            output.push(')');
+        }
+        Expression::Abstraction {
+            name,
+            annotation,
+            expression,
+            ..
+        } => {
+            output.push_str(&abstraction_head(name, annotation));
+            write_flat(output, expression, fixities);
+        }
+        Expression::TypeAbstraction {
+            name, expression, ..
+        } => {
+            output.push_str("/\\");
+            output.push_str(name);
+            output.push_str(" => ");
+            write_flat(output, expression, fixities);
+        }
+        Expression::If {
+            condition,
+            then,
+            otherwise,
+            ..
+        } => {
+            output.push_str("if ");
+            write_flat(output, condition, fixities);
+            output.push_str(" then ");
+            write_flat(output, then, fixities);
+            output.push_str(" else ");
+            write_flat(output, otherwise, fixities);
+        }
+        Expression::Match {
+            scrutinee, arms, ..
+        } => {
+            output.push_str("match ");
+            write_flat(output, scrutinee, fixities);
+            output.push_str(" { ");
+            for (i, arm) in arms.iter().enumerate() {
+                if i > 0 {
+                    output.push_str("; ");
+                }
+                output.push_str(&arm_head(arm));
+                write_flat(output, &arm.body, fixities);
+            }
+            output.push_str(" }");
+        }
+    }
+}
+
+fn write_operand(
+    output: &mut String,
+    expression: &Expression,
+    fixities: &Fixities,
+    parenthesize: bool,
+) {
+    if parenthesize {
+        output.push('(');
+    }
+
+    write_flat(output, expression, fixities);
+
+    if parenthesize {
+        output.push(')');
+    }
+}
+
+/// Splits an application chain `f a b c` into `f` and `[a, b, c]`.
+fn spine(mut expression: &Expression) -> (&Expression, Vec<&Expression>) {
+    let mut arguments = vec![];
+    while let Expression::Apply { lhs, rhs, .. } = expression {
+        if infix(expression).is_some() {
+            break;
+        }
+
+        arguments.push(&**rhs);
+        expression = lhs;
+    }
+
+    arguments.reverse();
+    (expression, arguments)
+}
+
+/// Splits `f $ g $ x` into `[f, g, x]`.
+fn dollar_chain(mut expression: &Expression) -> Vec<&Expression> {
+    let mut operands = vec![];
+    while let Expression::Dollar { lhs, rhs, .. } = expression {
+        operands.push(&**lhs);
+        expression = rhs;
+    }
+
+    operands.push(expression);
+    operands
+}
+
+/// `(+, a, b)` if `expression` is the operation `a + b`.
+fn infix(expression: &Expression) -> Option<(&str, &Expression, &Expression)> {
+    match expression {
+        Expression::Apply { lhs, rhs, .. } => match left_section(lhs) {
+            Some((operator, lhs)) => Some((operator, lhs, rhs)),
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+/// `(+, a)` if `expression` is the section `(a +)`.
+fn left_section(expression: &Expression) -> Option<(&str, &Expression)> {
+    match expression {
+        Expression::Apply { lhs, rhs, .. } => match &**lhs {
+            Expression::Var { name, .. } if is_operator(name) => Some((name, rhs)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `(+, b)` if `expression` is the section `(+ b)`, that is `\x => x + b`
+/// where `x` is not free in `b`.
+fn right_section(expression: &Expression) -> Option<(&str, &Expression)> {
+    let Expression::Abstraction {
+        name,
+        annotation: None,
+        expression,
+        ..
+    } = expression
+    else {
+        return None;
+    };
+
+    match infix(expression)? {
+        (operator, Expression::Var { name: lhs, .. }, rhs)
+            if lhs == name && !free_variables(rhs).contains(name) =>
+        {
+            Some((operator, rhs))
+        }
+        _ => None,
+    }
+}
+
+/// Operator names and sections, which are printed with their parentheses.
+fn is_section(expression: &Expression) -> bool {
+    match expression {
+        Expression::Var { name, .. } => is_operator(name),
+        _ => left_section(expression).is_some() || right_section(expression).is_some(),
+    }
+}
+
+struct Printer<'a> {
+    config: &'a Config,
+    fixities: &'a Fixities,
+    output: String,
+    column: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    fn write_verbatim(&mut self, text: &str) {
+        match text.rsplit_once('\n') {
+            Some((_, last)) => {
+                self.output.push_str(text);
+                self.column = last.chars().count();
+            }
+            None => self.write(text),
+        }
+    }
+
+    fn newline(&mut self) {
+        self.output.push('\n');
+        self.column = 0;
+    }
+
+    fn finish(mut self) -> String {
+        if self.column != 0 {
+            self.newline();
+        }
+
+        self.output
+    }
+
+    /// Keeps at most one blank line between the previous item and the next.
+    fn separate(&mut self, source: &str, previous_end: Option<usize>, next_start: usize) {
+        if self.column != 0 {
+            self.newline();
+        }
+
+        if let Some(gap) = previous_end.and_then(|end| source.get(end..next_start)) {
+            if gap.trim().is_empty() && gap.matches('\n').count() > 1 {
+                self.newline();
+            }
+        }
+    }
+
+    fn trailing_comment(&mut self, comment: &Token) {
+        self.write(" ");
+        self.write(&comment.literal);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Expression { expression, .. } => {
+                self.expression(expression, 0, 1);
+            }
+            Statement::Let {
+                name,
+                annotation,
+                fixity,
+                expression,
+                ..
+            } => {
+                match fixity {
+                    Some(fixity) => {
+                        self.write(&fixity.to_string());
+                        self.write(" ");
+                    }
+                    None => self.write("let "),
+                }
+                self.write(name);
+                if let Some(annotation) = annotation {
+                    self.write(" : ");
+                    self.write(&format_type(annotation));
+                }
+                self.write(" = ");
+                self.expression(expression, 0, 1);
+            }
+            Statement::Syntax {
+                name,
+                keywords,
+                pattern,
+                template,
+                ..
+            } => {
+                self.write("syntax ");
+                if !keywords.is_empty() {
+                    let keywords: Vec<_> = keywords
+                        .iter()
+                        .map(|(keyword, _)| keyword.as_str())
+                        .collect();
+                    self.write(&format!("[{}] ", keywords.join(" ")));
+                }
+                self.write(name);
+                for (name, _) in pattern {
+                    self.write(" ");
+                    self.write(name);
+                }
+                self.write(" = ");
+                self.expression(template, 0, 1);
+            }
+            Statement::Data {
+                name, constructors, ..
+            } => {
+                self.write("data ");
+                self.write(name);
+                self.write(" = ");
+                let constructors: Vec<_> = constructors
+                    .iter()
+                    .map(|constructor| {
+                        let mut names = vec![constructor.name.as_str()];
+                        names.extend(constructor.fields.iter().map(|(field, _)| field.as_str()));
+                        names.join(" ")
+                    })
+                    .collect();
+                self.write(&constructors.join(" | "));
+            }
+            Statement::Assert { expression, .. } => {
+                self.write("assert ");
+                self.expression(expression, 0, 1);
+            }
+            Statement::AssertEq { lhs, rhs, .. } => {
+                self.write("assert_eq ");
+                let lhs = self.flat(lhs);
+                self.write(&lhs);
+                self.write(" ");
+                self.expression(rhs, 0, 1);
+            }
+        }
+
+        self.write(";");
+    }
+
+    fn flat(&self, expression: &Expression) -> String {
+        let mut output = String::new();
+        write_flat(&mut output, expression, self.fixities);
+        output
+    }
+
+    fn fits(&self, text: &str, trailing: usize) -> bool {
+        self.column + text.chars().count() + trailing <= self.config.width
+    }
+
+    /// Prints `expression`, wrapping applications that do not fit. `trailing`
+    /// is the number of characters that will follow on the same line.
+    fn expression(&mut self, expression: &Expression, indent: usize, trailing: usize) {
+        let flat = self.flat(expression);
+        if self.fits(&flat, trailing) || is_section(expression) {
+            self.write(&flat);
+            return;
+        }
+
+        if let Some((operator, lhs, rhs)) = infix(expression) {
+            let inner = indent + self.config.indent;
+            let parens = operand_needs_parens(lhs, operator, Associativity::Left, self.fixities);
+            self.operand(lhs, indent, operator.chars().count() + 1, parens);
+            self.write(" ");
+            self.write(operator);
+
+            self.newline();
+            self.write(&" ".repeat(inner));
+            let parens = operand_needs_parens(rhs, operator, Associativity::Right, self.fixities);
+            self.operand(rhs, inner, trailing, parens);
+            return;
+        }
+
+        match expression {
+            Expression::Var { name, .. } => self.write(name),
+            Expression::Paren { expression, .. } => {
+                self.write("(");
+                self.expression(expression, indent, trailing + 1);
+                self.write(")");
+            }
+            Expression::Abstraction {
+                name,
+                annotation,
+                expression,
+                ..
+            } => {
+                self.write(&abstraction_head(name, annotation));
+                self.expression(expression, indent, trailing);
+            }
+            Expression::TypeAbstraction {
+                name, expression, ..
+            } => {
+                self.write("/\\");
+                self.write(name);
+                self.write(" => ");
+                self.expression(expression, indent, trailing);
+            }
+            Expression::TypeApply { .. } => self.write(&flat),
+            Expression::Dollar { .. } => {
+                let operands = dollar_chain(expression);
+                let inner = indent + self.config.indent;
+
+                let (last, functions) = operands.split_last().unwrap();
+                for (i, function) in functions.iter().enumerate() {
+                    if i > 0 {
+                        self.newline();
+                        self.write(&" ".repeat(inner));
+                    }
+
+                    let parens = dollar_function_needs_parens(function);
+                    self.operand(function, inner, 2, parens);
+                    self.write(" $");
+                }
+
+                self.newline();
+                self.write(&" ".repeat(inner));
+                self.expression(last, inner, trailing);
+            }
+            Expression::Apply { .. } => {
+                let (function, arguments) = spine(expression);
+                let inner = indent + self.config.indent;
+
+                self.operand(function, indent, 0, function_needs_parens(function));
+                for (i, argument) in arguments.iter().enumerate() {
+                    self.newline();
+                    self.write(&" ".repeat(inner));
+
+                    let last = i + 1 == arguments.len();
+                    self.operand(
+                        argument,
+                        inner,
+                        if last { trailing } else { 0 },
+                        argument_needs_parens(argument),
+                    );
+                }
+            }
+            Expression::If {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                let inner = indent + self.config.indent;
+                self.write("if ");
+                self.expression(condition, indent, " then".len());
+                self.write(" then");
+                self.newline();
+                self.write(&" ".repeat(inner));
+                self.expression(then, inner, 0);
+
+                self.newline();
+                self.write(&" ".repeat(indent));
+                // `else if` chains stay at the same indentation.
+                if let Expression::If { .. } = **otherwise {
+                    self.write("else ");
+                    self.expression(otherwise, indent, trailing);
+                } else {
+                    self.write("else");
+                    self.newline();
+                    self.write(&" ".repeat(inner));
+                    self.expression(otherwise, inner, trailing);
+                }
+            }
+            Expression::Match {
+                scrutinee, arms, ..
+            } => {
+                let inner = indent + self.config.indent;
+                self.write("match ");
+                self.expression(scrutinee, indent, " {".len());
+                self.write(" {");
+                for arm in arms {
+                    self.newline();
+                    self.write(&" ".repeat(inner));
+                    self.write(&arm_head(arm));
+                    self.expression(&arm.body, inner, 1);
+                    self.write(";");
+                }
+
+                self.newline();
+                self.write(&" ".repeat(indent));
+                self.write("}");
+            }
+        }
+    }
+
+    fn operand(&mut self, expression: &Expression, indent: usize, trailing: usize, parens: bool) {
+        if parens {
+            self.write("(");
+            self.expression(expression, indent, trailing + 1);
+            self.write(")");
+        } else {
+            self.expression(expression, indent, trailing);
+        }
+    }
+}
+
+fn function_needs_parens(expression: &Expression) -> bool {
+    if is_section(expression) {
+        return false;
+    }
+
+    match expression {
+        // `$`, `if` and `match` extend as far right as abstractions:
+        // `(f $ x) y` is not `f $ x y`.
+        Expression::Abstraction { .. }
+        | Expression::TypeAbstraction { .. }
+        | Expression::Dollar { .. }
+        | Expression::If { .. }
+        | Expression::Match { .. } => true,
+        Expression::Apply { .. } => infix(expression).is_some(),
+        _ => false,
+    }
+}
+
+/// Whether `f` needs parentheses in `f $ x`, which binds weaker than
+/// operators.
+fn dollar_function_needs_parens(expression: &Expression) -> bool {
+    infix(expression).is_none() && function_needs_parens(expression)
+}
+
+fn argument_needs_parens(expression: &Expression) -> bool {
+    !is_section(expression)
+        && matches!(
+            expression,
+            Expression::Apply { .. }
+                | Expression::Dollar { .. }
+                | Expression::TypeApply { .. }
+                | Expression::Abstraction { .. }
+                | Expression::TypeAbstraction { .. }
+                | Expression::If { .. }
+                | Expression::Match { .. }
+        )
+}
+
+/// Whether `expression` needs parentheses as the operand of `operator` on
+/// the given side. Operations of operators without a known fixity always do.
+fn operand_needs_parens(
+    expression: &Expression,
+    operator: &str,
+    side: Associativity,
+    fixities: &Fixities,
+) -> bool {
+    if is_section(expression) {
+        return false;
+    }
+
+    if let Some((inner, ..)) = infix(expression) {
+        return match (fixities.get(operator), fixities.get(inner)) {
+            (Some(outer), Some(inner)) => {
+                inner.precedence < outer.precedence
+                    || (inner.precedence == outer.precedence
+                        && (inner.associativity != outer.associativity
+                            || inner.associativity != side))
+            }
+            _ => true,
+        };
+    }
+
+    matches!(
+        expression,
+        Expression::Abstraction { .. }
+            | Expression::TypeAbstraction { .. }
+            | Expression::Dollar { .. }
+            | Expression::If { .. }
+            | Expression::Match { .. }
+    )
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::{format, Config};
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    fn fmt(source: &str, width: usize) -> String {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors());
+
+        format(
+            source,
+            &ast,
+            &parser.comments,
+            &Config {
+                width,
+                ..Config::default()
+            },
+        )
+    }
+
+    #[test]
+    fn canonical_spacing() {
+        assert_eq!(
+            fmt("let a=  \\x => x;\nlet   b=a ( a  a);", 80),
+            "let a = \\x => x;\nlet b = a (a a);\n"
+        );
+    }
+
+    #[test]
+    fn period_becomes_arrow() {
+        assert_eq!(fmt("let i = λx . x;", 80), "let i = \\x => x;\n");
+    }
+
+    #[test]
+    fn assertions() {
+        assert_eq!(
+            fmt("assert   not false;\nassert_eq(not  true)false;", 80),
+            "assert not false;\nassert_eq (not true) false;\n"
+        );
+    }
+
+    #[test]
+    fn type_annotations() {
+        assert_eq!(
+            fmt("let k:A->(B->A)=\\x:A=>\\y :B . x;", 80),
+            "let k : A -> B -> A = \\x: A => \\y: B => x;\n"
+        );
+    }
+
+    #[test]
+    fn polymorphism() {
+        assert_eq!(
+            fmt("let id:∀a.a->a=Λa.\\x:a=>x; id[forall b.b->b] id;", 80),
+            "let id : forall a. a -> a = /\\a => \\x: a => x;\nid [forall b. b -> b] id;\n"
+        );
+    }
+
+    #[test]
+    fn comments_stay_in_place() {
+        assert_eq!(
+            fmt(
+                "-- booleans\nlet true = \\x => \\y => x; -- K\n\n\n-- between\nlet false=\\x=>\\y=>y;\n-- end\n",
+                80
+            ),
+            "-- booleans\nlet true = \\x => \\y => x; -- K\n\n-- between\nlet false = \\x => \\y => y;\n-- end\n"
+        );
+    }
+
+    #[test]
+    fn statements_with_comments_inside_are_kept_as_written() {
+        let source = "let not = \\b =>\n  -- negation\n  b  false true;\nlet  id=\\x => x; -- I\nf (a -- first\n  ) b;\n";
+        assert_eq!(
+            fmt(source, 80),
+            "let not = \\b =>\n  -- negation\n  b  false true;\nlet id = \\x => x; -- I\nf (a -- first\n  ) b;\n"
+        );
+        assert_eq!(fmt(&fmt(source, 80), 80), fmt(source, 80));
+    }
+
+    #[test]
+    fn long_applications_are_wrapped() {
+        assert_eq!(
+            fmt("let f = function argument (other argument) last;", 30),
+            "let f = function\n    argument\n    (other argument)\n    last;\n"
+        );
+    }
+
+    #[test]
+    fn dollar() {
+        assert_eq!(
+            fmt("f  a$g   $\\x=>x;\n(f $ a) b;\n(\\x => x) $ a;", 80),
+            "f a $ g $ \\x => x;\n(f $ a) b;\n(\\x => x) $ a;\n"
+        );
+        assert_eq!(
+            fmt("let f = function argument $ other argument $ last;", 30),
+            "let f = function argument $\n    other argument $\n    last;\n"
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let prelude = "infixl 6 + = add;\ninfixl 7 * = mul;\ninfixr 5 ++ = append;\n";
+        assert_eq!(
+            fmt(
+                &format!("{}a+b*c+(d+e);\na ++ (b ++ c);\n(a ++ b) ++ c;", prelude),
+                80
+            ),
+            format!(
+                "{}a + b * c + (d + e);\na ++ (b ++ c);\n(a ++ b) ++ c;\n",
+                prelude
+            )
+        );
+        assert_eq!(
+            fmt(&format!("{}f (a*b +)  (+ c) (+)  $ x;", prelude), 80),
+            format!("{}f (a * b +) (+ c) (+) $ x;\n", prelude)
+        );
+        assert_eq!(
+            fmt(
+                &format!("{}let f = argument + other argument * last;", prelude),
+                30
+            ),
+            format!(
+                "{}let f = argument +\n    other argument * last;\n",
+                prelude
+            )
+        );
+    }
+
+    #[test]
+    fn syntax_rules() {
+        assert_eq!(
+            fmt(
+                "syntax [do  otherwise]when c do a otherwise b=c a b;syntax pair a b = \\f.f a b;",
+                80
+            ),
+            "syntax [do otherwise] when c do a otherwise b = c a b;\nsyntax pair a b = \\f => f a b;\n"
+        );
+    }
+
+    #[test]
+    fn data_types() {
+        assert_eq!(
+            fmt(
+                "data List=Nil|Cons  h t;\nlet len = \\xs=>match xs{Nil=>zero;Cons h t=>succ (len t);};\nf (if c then a else b) $ if c then a else b;",
+                80
+            ),
+            "data List = Nil | Cons h t;\nlet len = \\xs => match xs { Nil => zero; Cons h t => succ (len t) };\nf (if c then a else b) $ if c then a else b;\n"
+        );
+        assert_eq!(
+            fmt(
+                "let f = \\xs => match xs { Nil => if c then a else if d then b else c; Cons h t => h };",
+                30
+            ),
+            "let f = \\xs => match xs {\n    Nil => if c then\n        a\n    else if d then b else c;\n    Cons h t => h;\n};\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let source = "let f = function argument (\\x => other x argument) last; -- c\n";
+        let once = fmt(source, 20);
+        assert_eq!(fmt(&once, 20), once);
+    }
+}
@@ -0,0 +1,602 @@
+//! Hindley–Milner type inference (algorithm W).
+//!
+//! Unannotated programs get their principal types, e.g. `\x => \y => x` has
+//! type `a -> b -> a`. Top-level `let`s are generalized, so a `let` can be
+//! used at different types in the statements after it. Identifiers in type
+//! annotations are base types, like in [`crate::stlc`], unless a `forall`
+//! binds them. Type abstractions and applications are ignored.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Expression, Span, Statement, Type};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Ty {
+    Var(usize),
+    Base(String),
+    Arrow(Box<Ty>, Box<Ty>),
+}
+
+/// A type whose variables `variables` can be instantiated differently at
+/// every use.
+#[derive(Clone, Debug, PartialEq)]
+struct Scheme {
+    variables: Vec<usize>,
+    ty: Ty,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InferError {
+    /// The expression at `span` has type `found` where `expected` is needed.
+    /// `reason` points at what requires `expected`.
+    Mismatch {
+        expected: Box<Type>,
+        found: Box<Type>,
+        span: Span,
+        reason: Option<(Span, String)>,
+    },
+    /// The expression at `span` would need the type `variable`, which occurs
+    /// in `ty`, to be equal to `ty`.
+    Infinite {
+        variable: Box<Type>,
+        ty: Box<Type>,
+        span: Span,
+    },
+    /// The variable at `span` is not bound.
+    Unbound { name: String, span: Span },
+    /// The variable at `span` refers to a `let` whose value has no type.
+    Untypeable { name: String, span: Span },
+}
+
+impl fmt::Display for InferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferError::Mismatch { .. } => write!(f, "mismatched types"),
+            InferError::Infinite { .. } => write!(f, "the term is not simply typeable"),
+            InferError::Unbound { name, .. } => write!(f, "unknown variable `{}`", name),
+            InferError::Untypeable { name, .. } => {
+                write!(f, "`{}` is not simply typeable", name)
+            }
+        }
+    }
+}
+
+impl InferError {
+    pub fn diagnostic(&self, file_span: codemap::Span) -> Diagnostic {
+        let span = |span: &Span| file_span.subspan(span.start as u64, span.end as u64);
+        let primary = |at: &Span, label: String| SpanLabel {
+            span: span(at),
+            style: SpanStyle::Primary,
+            label: Some(label),
+        };
+
+        let (code, spans) = match self {
+            InferError::Mismatch {
+                expected,
+                found,
+                span: at,
+                reason,
+            } => {
+                let mut spans = vec![primary(
+                    at,
+                    format!("expected `{}`, found `{}`", expected, found),
+                )];
+                if let Some((at, label)) = reason {
+                    spans.push(SpanLabel {
+                        span: span(at),
+                        style: SpanStyle::Secondary,
+                        label: Some(label.clone()),
+                    });
+                }
+
+                ("E005", spans)
+            }
+            InferError::Infinite {
+                variable,
+                ty,
+                span: at,
+            } => (
+                "E008",
+                vec![primary(
+                    at,
+                    format!("this would need the infinite type `{} = {}`", variable, ty),
+                )],
+            ),
+            InferError::Unbound { span: at, .. } => (
+                "E007",
+                vec![primary(
+                    at,
+                    "not defined by any `let` or parameter".to_owned(),
+                )],
+            ),
+            InferError::Untypeable { span: at, .. } => (
+                "E008",
+                vec![primary(at, "its definition has no type".to_owned())],
+            ),
+        };
+
+        Diagnostic {
+            level: Level::Error,
+            message: self.to_string(),
+            code: Some(code.to_owned()),
+            spans,
+        }
+    }
+}
+
+/// Type schemes of the top-level `let`s inferred so far. `None` marks a
+/// `let` whose value has no type.
+#[derive(Clone, Debug, Default)]
+pub struct Inference {
+    globals: HashMap<String, Option<Scheme>>,
+}
+
+impl Inference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Infers the type of a statement. The variable defined by a `let` is
+    /// generalized and declared; if the value has no type, uses of the
+    /// variable are errors.
+    pub fn check_statement(&mut self, statement: &Statement) -> Result<Type, InferError> {
+        match statement {
+            Statement::Expression { expression, .. } | Statement::Assert { expression, .. } => {
+                self.infer(expression)
+            }
+            Statement::AssertEq { lhs, rhs, .. } => {
+                self.infer(rhs)?;
+                self.infer(lhs)
+            }
+            Statement::Syntax { .. } => {
+                unreachable!("syntax rules are expanded before type checking")
+            }
+            Statement::Data { .. } => {
+                unreachable!("data types are defined by `let`s of their constructors")
+            }
+            Statement::Let {
+                name,
+                annotation,
+                expression,
+                expression_span,
+                ..
+            } => {
+                let mut w = W::new(&self.globals);
+                let result = w.infer(expression, &mut vec![]).and_then(|found| {
+                    if let Some(annotation) = annotation {
+                        let expected = w.annotation(annotation, &mut vec![]);
+                        if w.unify(&expected, &found).is_err() {
+                            return Err(w.mismatch(
+                                &expected,
+                                &found,
+                                expression_span.clone(),
+                                Some((
+                                    annotation.span(),
+                                    "expected because of this annotation".to_owned(),
+                                )),
+                            ));
+                        }
+                    }
+
+                    Ok(w.resolve(&found))
+                });
+
+                let scheme = result.as_ref().ok().map(|ty| Scheme {
+                    variables: variables(ty),
+                    ty: ty.clone(),
+                });
+                self.globals.insert(name.clone(), scheme);
+
+                result.map(|ty| Namer::default().name(&ty))
+            }
+        }
+    }
+
+    /// Infers the principal type of `expression`.
+    pub fn infer(&self, expression: &Expression) -> Result<Type, InferError> {
+        let mut w = W::new(&self.globals);
+        let ty = w.infer(expression, &mut vec![])?;
+        Ok(Namer::default().name(&w.resolve(&ty)))
+    }
+
+    /// Infers the type of a value defined outside of the source, like a `let`.
+    pub fn declare(&mut self, name: &str, expression: &Expression) {
+        let mut w = W::new(&self.globals);
+        let scheme = w.infer(expression, &mut vec![]).ok().map(|ty| {
+            let ty = w.resolve(&ty);
+            Scheme {
+                variables: variables(&ty),
+                ty,
+            }
+        });
+
+        self.globals.insert(name.to_owned(), scheme);
+    }
+
+    /// Type of the variable `name`, if it has one.
+    pub fn type_of(&self, name: &str) -> Option<Type> {
+        let scheme = self.globals.get(name)?.as_ref()?;
+        Some(Namer::default().name(&scheme.ty))
+    }
+}
+
+/// State of one run of algorithm W.
+struct W<'a> {
+    globals: &'a HashMap<String, Option<Scheme>>,
+    substitution: HashMap<usize, Ty>,
+    next: usize,
+}
+
+impl<'a> W<'a> {
+    fn new(globals: &'a HashMap<String, Option<Scheme>>) -> Self {
+        // Schemes are closed and instantiated with fresh variables, so their
+        // own variables never meet the ones of this run.
+        Self {
+            globals,
+            substitution: HashMap::new(),
+            next: 0,
+        }
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.next += 1;
+        Ty::Var(self.next - 1)
+    }
+
+    fn infer(
+        &mut self,
+        expression: &Expression,
+        locals: &mut Vec<(String, Ty)>,
+    ) -> Result<Ty, InferError> {
+        match expression {
+            Expression::Var { name, name_span } => {
+                if let Some((_, ty)) = locals.iter().rev().find(|(local, _)| local == name) {
+                    return Ok(ty.clone());
+                }
+
+                match self.globals.get(name) {
+                    Some(Some(scheme)) => Ok(self.instantiate(scheme)),
+                    Some(None) => Err(InferError::Untypeable {
+                        name: name.clone(),
+                        span: name_span.clone(),
+                    }),
+                    None => Err(InferError::Unbound {
+                        name: name.clone(),
+                        span: name_span.clone(),
+                    }),
+                }
+            }
+            // Type abstractions and applications are erased.
+            Expression::Paren { expression, .. }
+            | Expression::TypeAbstraction { expression, .. }
+            | Expression::TypeApply { expression, .. } => self.infer(expression, locals),
+            Expression::Apply {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            }
+            | Expression::Dollar {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => {
+                let function = self.infer(lhs, locals)?;
+                let argument = self.infer(rhs, locals)?;
+                let (parameter, result) = (self.fresh(), self.fresh());
+                let span = lhs_span.start..rhs_span.end;
+
+                let arrow = Ty::Arrow(Box::new(parameter.clone()), Box::new(result.clone()));
+                self.unify(&function, &arrow).map_err(|error| {
+                    self.unify_error(error, &arrow, &function, lhs_span.clone(), &span)
+                })?;
+                self.unify(&parameter, &argument).map_err(|error| {
+                    let mut error =
+                        self.unify_error(error, &parameter, &argument, rhs_span.clone(), &span);
+                    if let InferError::Mismatch {
+                        expected, reason, ..
+                    } = &mut error
+                    {
+                        *reason = Some((
+                            lhs_span.clone(),
+                            format!("this function takes `{}`", expected),
+                        ));
+                    }
+
+                    error
+                })?;
+
+                Ok(result)
+            }
+            Expression::Abstraction {
+                name,
+                annotation,
+                expression,
+                ..
+            } => {
+                let parameter = match annotation {
+                    Some(annotation) => self.annotation(annotation, &mut vec![]),
+                    None => self.fresh(),
+                };
+
+                locals.push((name.clone(), parameter.clone()));
+                let result = self.infer(expression, locals);
+                locals.pop();
+
+                Ok(Ty::Arrow(Box::new(parameter), Box::new(result?)))
+            }
+            Expression::If { .. } | Expression::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before type checking")
+            }
+        }
+    }
+
+    /// Converts an annotation. Its identifiers are base types, except for the
+    /// variables of `forall`s, which get fresh type variables.
+    fn annotation(&mut self, ty: &Type, bound: &mut Vec<(String, Ty)>) -> Ty {
+        match ty {
+            Type::Var { name, .. } => match bound.iter().rev().find(|(bound, _)| bound == name) {
+                Some((_, variable)) => variable.clone(),
+                None => Ty::Base(name.clone()),
+            },
+            Type::Arrow { lhs, rhs, .. } => Ty::Arrow(
+                Box::new(self.annotation(lhs, bound)),
+                Box::new(self.annotation(rhs, bound)),
+            ),
+            Type::Forall { name, body, .. } => {
+                let variable = self.fresh();
+                bound.push((name.clone(), variable));
+                let body = self.annotation(body, bound);
+                bound.pop();
+                body
+            }
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Ty {
+        let fresh: HashMap<usize, Ty> = scheme
+            .variables
+            .iter()
+            .map(|&variable| (variable, self.fresh()))
+            .collect();
+        replace(&scheme.ty, &fresh)
+    }
+
+    /// Applies the substitution found so far.
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(variable) => match self.substitution.get(variable) {
+                Some(ty) => self.resolve(ty),
+                None => ty.clone(),
+            },
+            Ty::Base(_) => ty.clone(),
+            Ty::Arrow(lhs, rhs) => {
+                Ty::Arrow(Box::new(self.resolve(lhs)), Box::new(self.resolve(rhs)))
+            }
+        }
+    }
+
+    /// Extends the substitution so that `a` and `b` become equal. Fails with
+    /// the variable and the type it occurs in if that needs an infinite type.
+    fn unify(&mut self, a: &Ty, b: &Ty) -> Result<(), Option<(usize, Ty)>> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Ty::Var(a), Ty::Var(b)) if a == b => Ok(()),
+            (Ty::Var(variable), ty) | (ty, Ty::Var(variable)) => {
+                if occurs(variable, &ty) {
+                    return Err(Some((variable, ty)));
+                }
+
+                self.substitution.insert(variable, ty);
+                Ok(())
+            }
+            (Ty::Base(a), Ty::Base(b)) if a == b => Ok(()),
+            (Ty::Arrow(a_lhs, a_rhs), Ty::Arrow(b_lhs, b_rhs)) => {
+                self.unify(&a_lhs, &b_lhs)?;
+                self.unify(&a_rhs, &b_rhs)
+            }
+            _ => Err(None),
+        }
+    }
+
+    fn unify_error(
+        &self,
+        error: Option<(usize, Ty)>,
+        expected: &Ty,
+        found: &Ty,
+        span: Span,
+        application: &Span,
+    ) -> InferError {
+        match error {
+            Some((variable, ty)) => {
+                let mut namer = Namer::default();
+                InferError::Infinite {
+                    variable: Box::new(namer.name(&Ty::Var(variable))),
+                    ty: Box::new(namer.name(&self.resolve(&ty))),
+                    span: application.clone(),
+                }
+            }
+            None => self.mismatch(expected, found, span, None),
+        }
+    }
+
+    fn mismatch(
+        &self,
+        expected: &Ty,
+        found: &Ty,
+        span: Span,
+        reason: Option<(Span, String)>,
+    ) -> InferError {
+        let mut namer = Namer::default();
+        InferError::Mismatch {
+            expected: Box::new(namer.name(&self.resolve(expected))),
+            found: Box::new(namer.name(&self.resolve(found))),
+            span,
+            reason,
+        }
+    }
+}
+
+fn occurs(variable: usize, ty: &Ty) -> bool {
+    match ty {
+        Ty::Var(other) => *other == variable,
+        Ty::Base(_) => false,
+        Ty::Arrow(lhs, rhs) => occurs(variable, lhs) || occurs(variable, rhs),
+    }
+}
+
+fn replace(ty: &Ty, substitution: &HashMap<usize, Ty>) -> Ty {
+    match ty {
+        Ty::Var(variable) => substitution
+            .get(variable)
+            .cloned()
+            .unwrap_or_else(|| ty.clone()),
+        Ty::Base(_) => ty.clone(),
+        Ty::Arrow(lhs, rhs) => Ty::Arrow(
+            Box::new(replace(lhs, substitution)),
+            Box::new(replace(rhs, substitution)),
+        ),
+    }
+}
+
+/// Type variables in order of appearance.
+fn variables(ty: &Ty) -> Vec<usize> {
+    fn collect(ty: &Ty, variables: &mut Vec<usize>) {
+        match ty {
+            Ty::Var(variable) => {
+                if !variables.contains(variable) {
+                    variables.push(*variable);
+                }
+            }
+            Ty::Base(_) => {}
+            Ty::Arrow(lhs, rhs) => {
+                collect(lhs, variables);
+                collect(rhs, variables);
+            }
+        }
+    }
+
+    let mut result = vec![];
+    collect(ty, &mut result);
+    result
+}
+
+/// Names type variables `a`, `b`, ... in the order they are first printed.
+#[derive(Default)]
+struct Namer {
+    names: HashMap<usize, String>,
+}
+
+impl Namer {
+    fn name(&mut self, ty: &Ty) -> Type {
+        match ty {
+            Ty::Var(variable) => {
+                let count = self.names.len();
+                let name = self.names.entry(*variable).or_insert_with(|| {
+                    let letter = ((b'a' + (count % 26) as u8) as char).to_string();
+                    match count / 26 {
+                        0 => letter,
+                        n => format!("{}{}", letter, n),
+                    }
+                });
+
+                Type::Var {
+                    name: name.clone(),
+                    span: 0..0,
+                }
+            }
+            Ty::Base(name) => Type::Var {
+                name: name.clone(),
+                span: 0..0,
+            },
+            Ty::Arrow(lhs, rhs) => Type::Arrow {
+                lhs: Box::new(self.name(lhs)),
+                rhs: Box::new(self.name(rhs)),
+                span: 0..0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod infer_tests {
+    use super::{InferError, Inference};
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    fn infer(source: &str) -> Vec<Result<String, InferError>> {
+        let ast = Parser::new(source, "<stdin>", &mut CodeMap::new()).parse();
+        let mut inference = Inference::new();
+        ast.iter()
+            .map(|statement| {
+                inference
+                    .check_statement(statement)
+                    .map(|ty| ty.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn principal_types() {
+        assert_eq!(
+            infer(
+                "let true = \\x => \\y => x;
+                 let compose = \\f => \\g => \\x => f (g x);
+                 let s = \\x => \\y => \\z => x z (y z);
+                 \\x: A => x;"
+            ),
+            vec![
+                Ok("a -> b -> a".to_owned()),
+                Ok("(a -> b) -> (c -> a) -> c -> b".to_owned()),
+                Ok("(a -> b -> c) -> (a -> b) -> a -> c".to_owned()),
+                Ok("A -> A".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn let_polymorphism() {
+        assert_eq!(
+            infer("let id = \\x => x; id id; (\\i => i i) id;")[1..],
+            [
+                Ok("a -> a".to_owned()),
+                Err(InferError::Infinite {
+                    variable: Box::new(crate::ast::Type::Var {
+                        name: "a".to_owned(),
+                        span: 0..0
+                    }),
+                    ty: Box::new(crate::ast::Type::Arrow {
+                        lhs: Box::new(crate::ast::Type::Var {
+                            name: "a".to_owned(),
+                            span: 0..0
+                        }),
+                        rhs: Box::new(crate::ast::Type::Var {
+                            name: "b".to_owned(),
+                            span: 0..0
+                        }),
+                        span: 0..0
+                    }),
+                    span: 32..35
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let results = infer(
+            "let omega = \\x => x x;
+             omega;
+             let k : A -> A = \\x => \\y => x;
+             y;",
+        );
+
+        assert!(matches!(&results[0], Err(InferError::Infinite { span, .. }) if *span == (18..21)));
+        assert!(matches!(&results[1], Err(InferError::Untypeable { .. })));
+        assert!(matches!(&results[2], Err(InferError::Mismatch { .. })));
+        assert!(matches!(&results[3], Err(InferError::Unbound { .. })));
+    }
+}
@@ -2,7 +2,9 @@ use logos::Logos;
 
 #[derive(Logos, Debug, PartialEq, Clone)]
 pub enum RawToken {
-    #[regex(r"[ \t\n\r\f]+", logos::skip)]
+    #[regex(r"[ \t\n\r\f]+")]
+    Whitespace,
+
     #[token(";")]
     Semicolon,
 
@@ -12,6 +14,12 @@ pub enum RawToken {
     #[token("=>")]
     RightArrow,
 
+    #[token("->")]
+    Arrow,
+
+    #[token(":")]
+    Colon,
+
     #[token(".")]
     Period,
 
@@ -23,18 +31,84 @@ pub enum RawToken {
     #[token("λ")]
     Lambda,
 
+    #[token(r#"/\"#)]
+    #[token("Λ")]
+    TypeLambda,
+
+    #[token("forall")]
+    #[token("∀")]
+    Forall,
+
     #[token("let")]
     Let,
 
+    #[token("assert")]
+    Assert,
+
+    #[token("assert_eq")]
+    AssertEq,
+
+    #[token("infixl")]
+    InfixLeft,
+
+    #[token("infixr")]
+    InfixRight,
+
+    #[token("infix")]
+    Infix,
+
+    #[token("syntax")]
+    Syntax,
+
+    #[token("data")]
+    Data,
+
+    #[token("if")]
+    If,
+
+    #[token("then")]
+    Then,
+
+    #[token("else")]
+    Else,
+
+    #[token("match")]
+    Match,
+
     #[token("(")]
     Lparen,
 
     #[token(")")]
     Rparen,
 
-    #[regex(r"[_0-9a-zA-Z]+")]
+    #[token("[")]
+    Lbracket,
+
+    #[token("]")]
+    Rbracket,
+
+    #[token("{")]
+    Lbrace,
+
+    #[token("}")]
+    Rbrace,
+
+    /// Letters, digits and underscores of any script (Unicode XID), in any
+    /// order. `λ` and `Λ` are reserved for abstractions. Primes and `?` may
+    /// end a name, as in `f′` or `succ?`.
+    #[regex(r"[\p{XID_Continue}--[λΛ]]+['′″‴?]*")]
     Identifier,
 
+    /// Symbols of user-defined operators, as in `a + b` or `f ∘ g`. Symbols
+    /// that already mean something, like `=`, `=>` or `$`, are not operators
+    /// on their own.
+    #[regex(r"[[+\-*/<>=!&|^%~@$\p{Sm}]--\p{XID_Continue}]+")]
+    Operator,
+
+    #[regex(r"--[^\n]*")]
+    Comment,
+
+    /// A run of characters that no token starts with.
     #[error]
     Error,
 }
@@ -46,13 +120,37 @@ pub struct Token {
     pub literal: String,
 }
 
+/// Whether `name` is made of operator symbols, like `+`, rather than of
+/// letters and digits.
+pub fn is_operator(name: &str) -> bool {
+    let mut tokens = lex(name);
+    matches!(
+        (tokens.next(), tokens.next()),
+        (
+            Some(Token {
+                raw: RawToken::Operator,
+                ..
+            }),
+            None
+        )
+    )
+}
+
 pub fn lex(src: &str) -> impl Iterator<Item = Token> + '_ {
-    RawToken::lexer(src)
-        .spanned()
-        .map(|(raw, span)| Token {
-            raw: raw,
-            span: span.clone(),
-            literal: src[span.start..span.end].to_owned(),
+    let mut tokens = RawToken::lexer(src).spanned().peekable();
+    std::iter::from_fn(move || {
+        let (raw, mut span) = tokens.next()?;
+        if raw == RawToken::Error {
+            while let Some((RawToken::Error, next)) = tokens.peek() {
+                span.end = next.end;
+                tokens.next();
+            }
+        }
+
+        Some(Token {
+            raw,
+            literal: src[span.clone()].to_owned(),
+            span,
         })
-        .peekable()
+    })
 }
@@ -1,3 +1,16 @@
 pub mod ast;
+pub mod blc;
+pub mod cst;
+pub mod data;
+pub mod dot;
+pub mod format;
+pub mod infer;
 pub mod lexer;
+pub mod macros;
 pub mod parser;
+pub mod reduce;
+pub mod rename;
+pub mod resolve;
+pub mod ski;
+pub mod stlc;
+pub mod system_f;
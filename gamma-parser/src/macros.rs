@@ -0,0 +1,1183 @@
+//! Syntax rules: notations defined in Gamma code with `syntax`, expanded
+//! after parsing.
+//!
+//! `syntax [do otherwise] when c do a otherwise b = c a b;` rewrites every
+//! application of `when` to five arguments, the second one `do` and the
+//! fourth one `otherwise`, into `c a b` with the arguments in place of `c`,
+//! `a` and `b`. Uses are ordinary applications, so nested uses need parentheses.
+//!
+//! Expansion is hygienic. Parameters that a template introduces are renamed
+//! when they would capture variables of the arguments, and variables that a
+//! template uses without binding them refer to the definitions outside of
+//! the use, even under a parameter of the same name. A template parameter
+//! named after a pattern variable, like `\x => e` in
+//! `syntax [in] with x v in e = (\x => e) v;`, is the binder that the use
+//! names instead, and captures what the use means it to.
+
+use std::collections::{HashMap, HashSet};
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Arm, Expression, Span, Statement, Type};
+use crate::reduce;
+
+/// Syntax rules defined so far, by name, in the order they are tried.
+#[derive(Clone, Debug, Default)]
+pub struct Macros {
+    rules: HashMap<String, Vec<Rule>>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    /// Names of the pattern after the name of the rule, keywords included.
+    pattern: Vec<String>,
+    keywords: Vec<String>,
+    /// Expanded template. Its free variables other than the parameters are
+    /// marked, see [`mark`].
+    template: Expression,
+    /// Span of the pattern, with the name.
+    definition: codemap::Span,
+}
+
+/// Use of a rule, recorded so that diagnostics about the expanded code can
+/// point at the rule too.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    /// Span of the use. Nodes that come from the template have this span.
+    pub site: Span,
+    /// Span of the pattern of the rule.
+    pub definition: codemap::Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroError {
+    /// The use of `name` at `span` matches none of its rules, whose patterns
+    /// are at `rules`.
+    NoMatch {
+        name: String,
+        span: Span,
+        rules: Vec<codemap::Span>,
+    },
+    /// The argument at `span` is not a name, but the rule of `name` at
+    /// `rule` uses its `parameter` as the parameter of an abstraction.
+    NotAName {
+        name: String,
+        parameter: String,
+        span: Span,
+        rule: codemap::Span,
+    },
+    /// `parameter` appears at `span` and at `previous` in the pattern of
+    /// `name`.
+    DuplicateParameter {
+        name: String,
+        parameter: String,
+        span: Span,
+        previous: Span,
+    },
+}
+
+impl MacroError {
+    /// The error (E015), followed by notes that point at the rules.
+    pub fn diagnostics(&self, file_span: codemap::Span) -> Vec<Diagnostic> {
+        let span = |span: &Span| file_span.subspan(span.start as u64, span.end as u64);
+        let label = |span, style, label: String| SpanLabel {
+            span,
+            style,
+            label: Some(label),
+        };
+        let error = |message, spans| Diagnostic {
+            level: Level::Error,
+            message,
+            code: Some("E015".to_owned()),
+            spans,
+        };
+
+        match self {
+            MacroError::NoMatch {
+                name,
+                span: at,
+                rules,
+            } => {
+                let mut diagnostics = vec![error(
+                    format!("invalid use of the syntax `{}`", name),
+                    vec![label(
+                        span(at),
+                        SpanStyle::Primary,
+                        "does not match any rule".to_owned(),
+                    )],
+                )];
+                diagnostics.extend(rules.iter().map(|&rule| {
+                    rule_note(
+                        format!("no rule of `{}` matches", name),
+                        rule,
+                        "expected this pattern",
+                    )
+                }));
+                diagnostics
+            }
+            MacroError::NotAName {
+                name,
+                parameter,
+                span: at,
+                rule,
+            } => vec![
+                error(
+                    format!("expected a name for `{}`", parameter),
+                    vec![label(
+                        span(at),
+                        SpanStyle::Primary,
+                        "help: use a variable here".to_owned(),
+                    )],
+                ),
+                rule_note(
+                    format!(
+                        "the rule of `{}` binds `{}` in its template",
+                        name, parameter
+                    ),
+                    *rule,
+                    "rule defined here",
+                ),
+            ],
+            MacroError::DuplicateParameter {
+                name,
+                parameter,
+                span: at,
+                previous,
+            } => vec![error(
+                format!("`{}` appears twice in the pattern of `{}`", parameter, name),
+                vec![
+                    label(
+                        span(at),
+                        SpanStyle::Primary,
+                        "help: rename it, or make it a keyword".to_owned(),
+                    ),
+                    label(
+                        span(previous),
+                        SpanStyle::Secondary,
+                        "first appears here".to_owned(),
+                    ),
+                ],
+            )],
+        }
+    }
+}
+
+fn rule_note(message: String, rule: codemap::Span, label: &str) -> Diagnostic {
+    Diagnostic {
+        level: Level::Note,
+        message,
+        spans: vec![SpanLabel {
+            span: rule,
+            style: SpanStyle::Primary,
+            label: Some(label.to_owned()),
+        }],
+        code: Some("N015".to_owned()),
+    }
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the rule of a `syntax` statement of the file at `file_span`. Its
+    /// template may use the rules defined before it. Other statements are
+    /// ignored.
+    pub fn define(
+        &mut self,
+        statement: &Statement,
+        file_span: codemap::Span,
+    ) -> Result<(), MacroError> {
+        let (name, name_span, keywords, pattern, template, template_span) = match statement {
+            Statement::Syntax {
+                name,
+                name_span,
+                keywords,
+                pattern,
+                template,
+                template_span,
+                ..
+            } => (name, name_span, keywords, pattern, template, template_span),
+            _ => return Ok(()),
+        };
+
+        let keywords: Vec<_> = keywords
+            .iter()
+            .map(|(keyword, _)| keyword.clone())
+            .collect();
+        let mut parameters: Vec<(&String, &Span)> = vec![];
+        for (parameter, span) in pattern {
+            if keywords.contains(parameter) {
+                continue;
+            }
+
+            if let Some((_, previous)) = parameters.iter().find(|(other, _)| *other == parameter) {
+                return Err(MacroError::DuplicateParameter {
+                    name: name.clone(),
+                    parameter: parameter.clone(),
+                    span: span.clone(),
+                    previous: (*previous).clone(),
+                });
+            }
+            parameters.push((parameter, span));
+        }
+        let parameters: Vec<_> = parameters
+            .into_iter()
+            .map(|(parameter, _)| parameter.clone())
+            .collect();
+
+        let mut expander = Expander {
+            macros: self,
+            expansions: vec![],
+        };
+        let template = expander.expand(template, template_span, &mut parameters.clone())?;
+        let template = rename_capturing(&mark(&template, &parameters), &parameters);
+
+        let end = pattern.last().map_or(name_span.end, |(_, span)| span.end);
+        self.rules.entry(name.clone()).or_default().push(Rule {
+            pattern: pattern.iter().map(|(name, _)| name.clone()).collect(),
+            keywords,
+            template,
+            definition: file_span.subspan(name_span.start as u64, end as u64),
+        });
+
+        Ok(())
+    }
+
+    /// `expression`, found at `span`, with every use of a rule expanded, and
+    /// the uses.
+    pub fn expand(
+        &self,
+        expression: &Expression,
+        span: &Span,
+    ) -> Result<(Expression, Vec<Expansion>), MacroError> {
+        if self.rules.is_empty() {
+            return Ok((expression.clone(), vec![]));
+        }
+
+        let mut expander = Expander {
+            macros: self,
+            expansions: vec![],
+        };
+        let expanded = expander.expand(expression, span, &mut vec![])?;
+        let expanded = unmark(&rename_capturing(&expanded, &[]));
+
+        Ok((expanded, expander.expansions))
+    }
+
+    /// `statement` with its expressions expanded, and the uses of rules.
+    pub fn expand_statement(
+        &self,
+        statement: &Statement,
+    ) -> Result<(Statement, Vec<Expansion>), MacroError> {
+        let mut statement = statement.clone();
+        let mut expansions = vec![];
+        let mut expand = |expression: &mut Expression, span: &Span| {
+            let (expanded, more) = self.expand(expression, span)?;
+            *expression = expanded;
+            expansions.extend(more);
+            Ok(())
+        };
+
+        match &mut statement {
+            Statement::Expression {
+                expression,
+                expression_span,
+                ..
+            }
+            | Statement::Let {
+                expression,
+                expression_span,
+                ..
+            }
+            | Statement::Assert {
+                expression,
+                expression_span,
+                ..
+            } => expand(expression, expression_span)?,
+            Statement::AssertEq {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+                ..
+            } => {
+                expand(lhs, lhs_span)?;
+                expand(rhs, rhs_span)?;
+            }
+            Statement::Syntax { .. } | Statement::Data { .. } => {}
+        }
+
+        Ok((statement, expansions))
+    }
+}
+
+/// `diagnostics` with a note after each diagnostic about code that comes
+/// from one of the `expansions`, pointing at its rule.
+pub fn annotate(
+    diagnostics: Vec<Diagnostic>,
+    expansions: &[Expansion],
+    file_span: codemap::Span,
+) -> Vec<Diagnostic> {
+    let mut annotated = vec![];
+    for diagnostic in diagnostics {
+        let mut notes: Vec<Diagnostic> = vec![];
+        for expansion in expansions {
+            let site = file_span.subspan(expansion.site.start as u64, expansion.site.end as u64);
+            if diagnostic.spans.iter().any(|label| label.span == site)
+                && !notes
+                    .iter()
+                    .any(|note| note.spans[0].span == expansion.definition)
+            {
+                notes.push(rule_note(
+                    format!("this code comes from a rule of `{}`", expansion.name),
+                    expansion.definition,
+                    "rule defined here",
+                ));
+            }
+        }
+
+        annotated.push(diagnostic);
+        annotated.extend(notes);
+    }
+
+    annotated
+}
+
+impl Rule {
+    fn matches(&self, arguments: &[(&Expression, Span, Span)]) -> bool {
+        arguments.len() >= self.pattern.len()
+            && self
+                .pattern
+                .iter()
+                .zip(arguments)
+                .all(|(name, (argument, _, _))| {
+                    !self.keywords.contains(name)
+                        || matches!(argument, Expression::Var { name: word, .. } if word == name)
+                })
+    }
+}
+
+struct Expander<'a> {
+    macros: &'a Macros,
+    expansions: Vec<Expansion>,
+}
+
+impl Expander<'_> {
+    /// Expands `expression`, found at `span`, under the parameters `bound`.
+    /// A parameter hides the rules of the same name.
+    fn expand(
+        &mut self,
+        expression: &Expression,
+        span: &Span,
+        bound: &mut Vec<String>,
+    ) -> Result<Expression, MacroError> {
+        let (head, arguments) = spine(expression);
+        if let Expression::Var { name, .. } = head {
+            if let Some(rules) = self
+                .macros
+                .rules
+                .get(name)
+                .filter(|_| !bound.contains(name))
+            {
+                return self.expand_use(name, rules, &arguments, span, bound);
+            }
+        }
+
+        Ok(match expression {
+            Expression::Var { .. } => expression.clone(),
+            Expression::Apply {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => Expression::Apply {
+                lhs: Box::new(self.expand(lhs, lhs_span, bound)?),
+                lhs_span: lhs_span.clone(),
+                rhs: Box::new(self.expand(rhs, rhs_span, bound)?),
+                rhs_span: rhs_span.clone(),
+            },
+            Expression::Dollar {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => Expression::Dollar {
+                lhs: Box::new(self.expand(lhs, lhs_span, bound)?),
+                lhs_span: lhs_span.clone(),
+                rhs: Box::new(self.expand(rhs, rhs_span, bound)?),
+                rhs_span: rhs_span.clone(),
+            },
+            Expression::Paren {
+                expression,
+                expression_span,
+            } => Expression::Paren {
+                expression: Box::new(self.expand(expression, expression_span, bound)?),
+                expression_span: expression_span.clone(),
+            },
+            Expression::Abstraction {
+                name,
+                name_span,
+                annotation,
+                expression,
+                expression_span,
+            } => {
+                bound.push(name.clone());
+                let expression = self.expand(expression, expression_span, bound);
+                bound.pop();
+
+                Expression::Abstraction {
+                    name: name.clone(),
+                    name_span: name_span.clone(),
+                    annotation: annotation.clone(),
+                    expression: Box::new(expression?),
+                    expression_span: expression_span.clone(),
+                }
+            }
+            Expression::TypeAbstraction {
+                name,
+                name_span,
+                expression,
+                expression_span,
+            } => Expression::TypeAbstraction {
+                name: name.clone(),
+                name_span: name_span.clone(),
+                expression: Box::new(self.expand(expression, expression_span, bound)?),
+                expression_span: expression_span.clone(),
+            },
+            Expression::TypeApply {
+                expression,
+                expression_span,
+                argument,
+            } => Expression::TypeApply {
+                expression: Box::new(self.expand(expression, expression_span, bound)?),
+                expression_span: expression_span.clone(),
+                argument: argument.clone(),
+            },
+            Expression::If {
+                condition,
+                condition_span,
+                then,
+                then_span,
+                otherwise,
+                otherwise_span,
+            } => Expression::If {
+                condition: Box::new(self.expand(condition, condition_span, bound)?),
+                condition_span: condition_span.clone(),
+                then: Box::new(self.expand(then, then_span, bound)?),
+                then_span: then_span.clone(),
+                otherwise: Box::new(self.expand(otherwise, otherwise_span, bound)?),
+                otherwise_span: otherwise_span.clone(),
+            },
+            Expression::Match {
+                keyword_span,
+                scrutinee,
+                scrutinee_span,
+                arms,
+            } => {
+                let scrutinee = self.expand(scrutinee, scrutinee_span, bound)?;
+                let mut expanded = vec![];
+                for arm in arms {
+                    let depth = bound.len();
+                    bound.extend(arm.fields.iter().map(|(field, _)| field.clone()));
+                    let body = self.expand(&arm.body, &arm.body_span, bound);
+                    bound.truncate(depth);
+
+                    expanded.push(Arm {
+                        constructor: arm.constructor.clone(),
+                        constructor_span: arm.constructor_span.clone(),
+                        fields: arm.fields.clone(),
+                        body: body?,
+                        body_span: arm.body_span.clone(),
+                    });
+                }
+
+                Expression::Match {
+                    keyword_span: keyword_span.clone(),
+                    scrutinee: Box::new(scrutinee),
+                    scrutinee_span: scrutinee_span.clone(),
+                    arms: expanded,
+                }
+            }
+        })
+    }
+
+    /// Expands the use at `span` of the rules of `name`, applied to
+    /// `arguments`. Arguments after those of the pattern are applied to the
+    /// expansion.
+    fn expand_use(
+        &mut self,
+        name: &str,
+        rules: &[Rule],
+        arguments: &[(&Expression, Span, Span)],
+        span: &Span,
+        bound: &mut Vec<String>,
+    ) -> Result<Expression, MacroError> {
+        let rule = rules
+            .iter()
+            .find(|rule| rule.matches(arguments))
+            .ok_or_else(|| MacroError::NoMatch {
+                name: name.to_owned(),
+                span: span.clone(),
+                rules: rules.iter().map(|rule| rule.definition).collect(),
+            })?;
+
+        let count = rule.pattern.len();
+        let site = match arguments.get(count) {
+            Some((_, _, prefix_span)) => prefix_span.clone(),
+            None => span.clone(),
+        };
+
+        let mut values = HashMap::new();
+        for (parameter, (argument, argument_span, _)) in rule.pattern.iter().zip(arguments) {
+            if !rule.keywords.contains(parameter) {
+                let value = self.expand(argument, argument_span, bound)?;
+                values.insert(parameter.as_str(), (value, argument_span.clone()));
+            }
+        }
+
+        let mut instance = Instance {
+            name,
+            rule,
+            values: &values,
+            taken: HashSet::new(),
+            renamed: vec![],
+            site: &site,
+        };
+        for (value, _) in values.values() {
+            instance.taken.extend(reduce::free_variables(value));
+        }
+        let arguments_variables = instance.taken.clone();
+        collect_names(&rule.template, &mut instance.taken);
+
+        let mut expansion = instance.template(&rule.template, &arguments_variables)?.0;
+        self.expansions.push(Expansion {
+            name: name.to_owned(),
+            site,
+            definition: rule.definition,
+        });
+
+        for (argument, argument_span, prefix_span) in &arguments[count..] {
+            expansion = Expression::Apply {
+                lhs: Box::new(expansion),
+                lhs_span: prefix_span.clone(),
+                rhs: Box::new(self.expand(argument, argument_span, bound)?),
+                rhs_span: argument_span.clone(),
+            };
+        }
+
+        Ok(expansion)
+    }
+}
+
+/// A template being filled in with the arguments of a use.
+struct Instance<'a> {
+    name: &'a str,
+    rule: &'a Rule,
+    /// Expanded arguments for the parameters of the pattern, with their spans.
+    values: &'a HashMap<&'a str, (Expression, Span)>,
+    /// Names that renamed parameters of the template must avoid.
+    taken: HashSet<String>,
+    /// Parameters of the template in scope and their new names.
+    renamed: Vec<(String, String)>,
+    site: &'a Span,
+}
+
+impl Instance<'_> {
+    /// Part `template` of the template, filled in, and its span: the span of
+    /// the argument for a parameter of the pattern and the site otherwise.
+    /// Parameters of the template that are free in `arguments_variables` are
+    /// renamed.
+    fn template(
+        &mut self,
+        template: &Expression,
+        arguments_variables: &HashSet<String>,
+    ) -> Result<(Expression, Span), MacroError> {
+        let site = self.site.clone();
+        let expression = match template {
+            Expression::Var { name, .. } => {
+                if let Some((_, renamed)) = self.renamed.iter().rev().find(|(from, _)| from == name)
+                {
+                    Expression::Var {
+                        name: renamed.clone(),
+                        name_span: site.clone(),
+                    }
+                } else if let Some((value, span)) = self.values.get(name.as_str()) {
+                    return Ok((value.clone(), span.clone()));
+                } else {
+                    Expression::Var {
+                        name: name.clone(),
+                        name_span: site.clone(),
+                    }
+                }
+            }
+            Expression::Apply { lhs, rhs, .. } => {
+                let (lhs, lhs_span) = self.template(lhs, arguments_variables)?;
+                let (rhs, rhs_span) = self.template(rhs, arguments_variables)?;
+                Expression::Apply {
+                    lhs: Box::new(lhs),
+                    lhs_span,
+                    rhs: Box::new(rhs),
+                    rhs_span,
+                }
+            }
+            Expression::Dollar { lhs, rhs, .. } => {
+                let (lhs, lhs_span) = self.template(lhs, arguments_variables)?;
+                let (rhs, rhs_span) = self.template(rhs, arguments_variables)?;
+                Expression::Dollar {
+                    lhs: Box::new(lhs),
+                    lhs_span,
+                    rhs: Box::new(rhs),
+                    rhs_span,
+                }
+            }
+            Expression::Paren { expression, .. } => {
+                let (expression, expression_span) =
+                    self.template(expression, arguments_variables)?;
+                Expression::Paren {
+                    expression: Box::new(expression),
+                    expression_span,
+                }
+            }
+            Expression::Abstraction {
+                name,
+                annotation,
+                expression,
+                ..
+            } => {
+                let (binder, name_span) = self.binder(name, arguments_variables)?;
+                self.renamed.push((name.clone(), binder.clone()));
+                let body = self.template(expression, arguments_variables);
+                self.renamed.pop();
+                let (body, expression_span) = body?;
+
+                Expression::Abstraction {
+                    name: binder,
+                    name_span,
+                    annotation: annotation
+                        .as_ref()
+                        .map(|annotation| relocate(annotation, &site)),
+                    expression: Box::new(body),
+                    expression_span,
+                }
+            }
+            Expression::TypeAbstraction {
+                name, expression, ..
+            } => {
+                let (expression, expression_span) =
+                    self.template(expression, arguments_variables)?;
+                Expression::TypeAbstraction {
+                    name: name.clone(),
+                    name_span: site.clone(),
+                    expression: Box::new(expression),
+                    expression_span,
+                }
+            }
+            Expression::TypeApply {
+                expression,
+                argument,
+                ..
+            } => {
+                let (expression, expression_span) =
+                    self.template(expression, arguments_variables)?;
+                Expression::TypeApply {
+                    expression: Box::new(expression),
+                    expression_span,
+                    argument: relocate(argument, &site),
+                }
+            }
+            Expression::If {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                let (condition, condition_span) = self.template(condition, arguments_variables)?;
+                let (then, then_span) = self.template(then, arguments_variables)?;
+                let (otherwise, otherwise_span) = self.template(otherwise, arguments_variables)?;
+                Expression::If {
+                    condition: Box::new(condition),
+                    condition_span,
+                    then: Box::new(then),
+                    then_span,
+                    otherwise: Box::new(otherwise),
+                    otherwise_span,
+                }
+            }
+            Expression::Match {
+                scrutinee, arms, ..
+            } => {
+                let (scrutinee, scrutinee_span) = self.template(scrutinee, arguments_variables)?;
+                let mut filled = vec![];
+                for arm in arms {
+                    let depth = self.renamed.len();
+                    let fields = arm
+                        .fields
+                        .iter()
+                        .map(|(field, _)| {
+                            let binder = self.binder(field, arguments_variables)?;
+                            self.renamed.push((field.clone(), binder.0.clone()));
+                            Ok(binder)
+                        })
+                        .collect::<Result<_, _>>();
+                    let body = fields.and_then(|fields| {
+                        Ok((fields, self.template(&arm.body, arguments_variables)?))
+                    });
+                    self.renamed.truncate(depth);
+                    let (fields, (body, body_span)) = body?;
+
+                    filled.push(Arm {
+                        constructor: arm.constructor.clone(),
+                        constructor_span: site.clone(),
+                        fields,
+                        body,
+                        body_span,
+                    });
+                }
+
+                Expression::Match {
+                    keyword_span: site.clone(),
+                    scrutinee: Box::new(scrutinee),
+                    scrutinee_span,
+                    arms: filled,
+                }
+            }
+        };
+
+        Ok((expression, site))
+    }
+
+    /// New name and span of `name`, a parameter of an abstraction or a field
+    /// of an arm in the template: the argument if it is a parameter of the
+    /// pattern, and a fresh name if it would capture a variable of the
+    /// arguments.
+    fn binder(
+        &mut self,
+        name: &str,
+        arguments_variables: &HashSet<String>,
+    ) -> Result<(String, Span), MacroError> {
+        Ok(match self.values.get(name) {
+            Some((Expression::Var { name, .. }, span)) => (name.clone(), span.clone()),
+            Some((_, span)) => {
+                return Err(MacroError::NotAName {
+                    name: self.name.to_owned(),
+                    parameter: name.to_owned(),
+                    span: span.clone(),
+                    rule: self.rule.definition,
+                })
+            }
+            None if arguments_variables.contains(name) => {
+                let fresh = reduce::fresh_name(name, |name| self.taken.contains(name));
+                self.taken.insert(fresh.clone());
+                (fresh, self.site.clone())
+            }
+            None => (name.to_owned(), self.site.clone()),
+        })
+    }
+}
+
+/// The head of the applications of `expression` and its arguments in order, each with its span and the span of the application
+/// that it is the argument of. Arguments after `$` count as well.
+fn spine(expression: &Expression) -> (&Expression, Vec<(&Expression, Span, Span)>) {
+    let mut arguments = vec![];
+    let mut head = expression;
+    while let Expression::Apply {
+        lhs,
+        lhs_span,
+        rhs,
+        rhs_span,
+    }
+    | Expression::Dollar {
+        lhs,
+        lhs_span,
+        rhs,
+        rhs_span,
+    } = head
+    {
+        arguments.push((rhs.as_ref(), rhs_span.clone(), lhs_span.clone()));
+        head = lhs;
+    }
+
+    arguments.reverse();
+    (head, arguments)
+}
+
+/// Names of all variables and parameters of `expression`.
+fn collect_names(expression: &Expression, names: &mut HashSet<String>) {
+    match expression {
+        Expression::Var { name, .. } => {
+            names.insert(name.clone());
+        }
+        Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+            collect_names(lhs, names);
+            collect_names(rhs, names);
+        }
+        Expression::Abstraction {
+            name, expression, ..
+        } => {
+            names.insert(name.clone());
+            collect_names(expression, names);
+        }
+        Expression::Paren { expression, .. }
+        | Expression::TypeAbstraction { expression, .. }
+        | Expression::TypeApply { expression, .. } => collect_names(expression, names),
+        Expression::If {
+            condition,
+            then,
+            otherwise,
+            ..
+        } => {
+            collect_names(condition, names);
+            collect_names(then, names);
+            collect_names(otherwise, names);
+        }
+        Expression::Match {
+            scrutinee, arms, ..
+        } => {
+            collect_names(scrutinee, names);
+            for arm in arms {
+                names.extend(arm.fields.iter().map(|(field, _)| field.clone()));
+                collect_names(&arm.body, names);
+            }
+        }
+    }
+}
+
+/// Marked variables refer to the definitions outside of any use, and stay
+/// marked until the whole expression is expanded. `#` is never part of a
+/// name in the source.
+const MARK: char = '#';
+
+/// `template` with its free variables other than `parameters` marked.
+fn mark(template: &Expression, parameters: &[String]) -> Expression {
+    let mut free: Vec<_> = reduce::free_variables(template)
+        .into_iter()
+        .filter(|name| !name.ends_with(MARK) && !parameters.contains(name))
+        .collect();
+    free.sort();
+
+    free.into_iter().fold(template.clone(), |template, name| {
+        let marked = Expression::Var {
+            name: format!("{}{}", name, MARK),
+            name_span: 0..0,
+        };
+        reduce::substitute(&template, &name, &marked)
+    })
+}
+
+/// Renames the parameters of `expression` that have the name of a marked
+/// variable of their body, except `keep`.
+fn rename_capturing(expression: &Expression, keep: &[String]) -> Expression {
+    let recurse = |expression: &Expression| Box::new(rename_capturing(expression, keep));
+    match expression {
+        Expression::Var { .. } => expression.clone(),
+        Expression::Apply {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => Expression::Apply {
+            lhs: recurse(lhs),
+            lhs_span: lhs_span.clone(),
+            rhs: recurse(rhs),
+            rhs_span: rhs_span.clone(),
+        },
+        Expression::Dollar {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => Expression::Dollar {
+            lhs: recurse(lhs),
+            lhs_span: lhs_span.clone(),
+            rhs: recurse(rhs),
+            rhs_span: rhs_span.clone(),
+        },
+        Expression::Paren {
+            expression,
+            expression_span,
+        } => Expression::Paren {
+            expression: recurse(expression),
+            expression_span: expression_span.clone(),
+        },
+        Expression::Abstraction {
+            name,
+            name_span,
+            annotation,
+            expression,
+            expression_span,
+        } => {
+            let mut body = rename_capturing(expression, keep);
+            let mut name = name.clone();
+            let free = reduce::free_variables(&body);
+            if !keep.contains(&name) && free.contains(&format!("{}{}", name, MARK)) {
+                let fresh = reduce::fresh_name(&name, |candidate| free.contains(candidate));
+                let parameter = Expression::Var {
+                    name: fresh.clone(),
+                    name_span: name_span.clone(),
+                };
+                body = reduce::substitute(&body, &name, &parameter);
+                name = fresh;
+            }
+
+            Expression::Abstraction {
+                name,
+                name_span: name_span.clone(),
+                annotation: annotation.clone(),
+                expression: Box::new(body),
+                expression_span: expression_span.clone(),
+            }
+        }
+        Expression::TypeAbstraction {
+            name,
+            name_span,
+            expression,
+            expression_span,
+        } => Expression::TypeAbstraction {
+            name: name.clone(),
+            name_span: name_span.clone(),
+            expression: recurse(expression),
+            expression_span: expression_span.clone(),
+        },
+        Expression::TypeApply {
+            expression,
+            expression_span,
+            argument,
+        } => Expression::TypeApply {
+            expression: recurse(expression),
+            expression_span: expression_span.clone(),
+            argument: argument.clone(),
+        },
+        Expression::If {
+            condition,
+            condition_span,
+            then,
+            then_span,
+            otherwise,
+            otherwise_span,
+        } => Expression::If {
+            condition: recurse(condition),
+            condition_span: condition_span.clone(),
+            then: recurse(then),
+            then_span: then_span.clone(),
+            otherwise: recurse(otherwise),
+            otherwise_span: otherwise_span.clone(),
+        },
+        Expression::Match {
+            keyword_span,
+            scrutinee,
+            scrutinee_span,
+            arms,
+        } => Expression::Match {
+            keyword_span: keyword_span.clone(),
+            scrutinee: recurse(scrutinee),
+            scrutinee_span: scrutinee_span.clone(),
+            arms: arms
+                .iter()
+                .map(|arm| {
+                    let mut body = rename_capturing(&arm.body, keep);
+                    let mut fields = vec![];
+                    for (field, field_span) in &arm.fields {
+                        let free = reduce::free_variables(&body);
+                        if keep.contains(field) || !free.contains(&format!("{}{}", field, MARK)) {
+                            fields.push((field.clone(), field_span.clone()));
+                            continue;
+                        }
+
+                        let fresh = reduce::fresh_name(field, |candidate| {
+                            free.contains(candidate)
+                                || arm.fields.iter().any(|(field, _)| field == candidate)
+                        });
+                        let parameter = Expression::Var {
+                            name: fresh.clone(),
+                            name_span: field_span.clone(),
+                        };
+                        body = reduce::substitute(&body, field, &parameter);
+                        fields.push((fresh, field_span.clone()));
+                    }
+
+                    Arm {
+                        constructor: arm.constructor.clone(),
+                        constructor_span: arm.constructor_span.clone(),
+                        fields,
+                        body,
+                        body_span: arm.body_span.clone(),
+                    }
+                })
+                .collect(),
+        },
+    }
+}
+
+/// `expression` with the marks removed. Marked variables are never bound,
+/// so they are simply renamed.
+fn unmark(expression: &Expression) -> Expression {
+    let mut expression = expression.clone();
+    unmark_in_place(&mut expression);
+    expression
+}
+
+fn unmark_in_place(expression: &mut Expression) {
+    match expression {
+        Expression::Var { name, .. } => {
+            if name.ends_with(MARK) {
+                name.pop();
+            }
+        }
+        Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+            unmark_in_place(lhs);
+            unmark_in_place(rhs);
+        }
+        Expression::Paren { expression, .. }
+        | Expression::Abstraction { expression, .. }
+        | Expression::TypeAbstraction { expression, .. }
+        | Expression::TypeApply { expression, .. } => unmark_in_place(expression),
+        Expression::If {
+            condition,
+            then,
+            otherwise,
+            ..
+        } => {
+            unmark_in_place(condition);
+            unmark_in_place(then);
+            unmark_in_place(otherwise);
+        }
+        Expression::Match {
+            scrutinee, arms, ..
+        } => {
+            unmark_in_place(scrutinee);
+            for arm in arms {
+                unmark_in_place(&mut arm.body);
+            }
+        }
+    }
+}
+
+/// `ty` with all its spans at `span`, for annotations that come from a
+/// template.
+fn relocate(ty: &Type, span: &Span) -> Type {
+    match ty {
+        Type::Var { name, .. } => Type::Var {
+            name: name.clone(),
+            span: span.clone(),
+        },
+        Type::Arrow { lhs, rhs, .. } => Type::Arrow {
+            lhs: Box::new(relocate(lhs, span)),
+            rhs: Box::new(relocate(rhs, span)),
+            span: span.clone(),
+        },
+        Type::Forall { name, body, .. } => Type::Forall {
+            name: name.clone(),
+            name_span: span.clone(),
+            body: Box::new(relocate(body, span)),
+            span: span.clone(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod macros_tests {
+    use super::{MacroError, Macros};
+    use crate::ast::Statement;
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    /// Defines the rules of `source` and expands its expression statements.
+    fn expand(source: &str) -> Result<Vec<String>, MacroError> {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors(), "{:?}", parser.diagnostics);
+
+        let mut macros = Macros::new();
+        let mut expanded = vec![];
+        for statement in &ast {
+            match statement {
+                Statement::Syntax { .. } => macros.define(statement, parser.file_span)?,
+                Statement::Expression {
+                    expression,
+                    expression_span,
+                    ..
+                } => expanded.push(macros.expand(expression, expression_span)?.0.to_string()),
+                _ => {}
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    #[test]
+    fn keywords_and_parameters() {
+        assert_eq!(
+            expand(
+                "syntax [do otherwise] when c do a otherwise b = c a b;
+                 when (not x) do y otherwise (when z do x otherwise y);
+                 when x do y otherwise z w;"
+            )
+            .unwrap(),
+            vec!["(not x) y (z x y)", "x y z w"]
+        );
+    }
+
+    #[test]
+    fn parameters_of_templates_are_renamed() {
+        assert_eq!(
+            expand("syntax pair a b = \\f => f a b; \\f => pair f x; pair y x;").unwrap(),
+            vec!["\\f => \\f1 => f1 f x", "\\f => f y x"]
+        );
+    }
+
+    #[test]
+    fn free_variables_of_templates_are_not_captured() {
+        assert_eq!(
+            expand(
+                "syntax not' b = b false true;
+                 syntax nand a b = not' (a b false);
+                 \\false => not' false;
+                 \\true => nand true x;"
+            )
+            .unwrap(),
+            vec![
+                "\\false1 => false1 false true",
+                "\\true1 => (true1 x false) false true"
+            ]
+        );
+    }
+
+    #[test]
+    fn pattern_variables_name_binders() {
+        assert_eq!(
+            expand(
+                "syntax [with in] let2 p with a b in e = p (\\a => \\b => e);
+                 let2 q with x y in (f y x);"
+            )
+            .unwrap(),
+            vec!["q (\\x => \\y => (f y x))"]
+        );
+
+        let error = expand("syntax [in] with x v in e = (\\x => e) v; with (f x) v in v;");
+        assert!(matches!(error, Err(MacroError::NotAName { span, .. }) if span == (46..51)));
+    }
+
+    #[test]
+    fn parameters_hide_rules() {
+        assert_eq!(
+            expand("syntax id x = x; \\id => id y; id y;").unwrap(),
+            vec!["\\id => id y", "y"]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            expand("syntax [do otherwise] when c do a otherwise b = c a b; when x y z;"),
+            Err(MacroError::NoMatch { span, rules, .. }) if span == (55..65) && rules.len() == 1
+        ));
+        assert!(matches!(
+            expand("syntax pair a a = a;"),
+            Err(MacroError::DuplicateParameter { span, previous, .. })
+                if span == (14..15) && previous == (12..13)
+        ));
+    }
+}
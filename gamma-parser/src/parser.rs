@@ -1,9 +1,9 @@
-use std::borrow::Borrow;
-
 use crate::ast::{self, *};
+use crate::cst::{self, Element, NodeKind};
 use crate::lexer::{RawToken, Token};
 use codemap::CodeMap;
-use codemap_diagnostic::{ColorConfig, Diagnostic, Emitter, Level, SpanLabel, SpanStyle};
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+use std::collections::HashMap;
 
 macro_rules! check_token {
     ($self: expr, $rawtoken: expr, $msg: expr) => {
@@ -25,25 +25,59 @@ macro_rules! check_eof {
 /// Grammar for Gamma:
 ///
 /// Program   ::= Statement* EOF
-/// Statement ::= (Let | Expression) ";"
-/// Let       ::= "let" Identifier "=" Expression
+/// Statement ::= (Let | Infix | Syntax | Data | Assert | AssertEq | Expression) ";"
+/// Let       ::= "let" Identifier (":" Type)? "=" Expression
+/// Infix     ::= ("infixl" | "infixr" | "infix") Digit Operator "=" Expression
+/// Syntax    ::= "syntax" ("[" Identifier* "]")? Identifier+ "=" Expression
+/// Data      ::= "data" Identifier "=" Constructor ("|" Constructor)*
+/// Constructor ::= Identifier Identifier*
+/// Assert    ::= "assert" Expression
+/// AssertEq  ::= "assert_eq" Argument Argument
+/// Abstraction ::= Lambda Identifier (":" Type)? ("=>" | ".") Expression
+/// TypeAbstraction ::= TypeLambda Identifier ("=>" | ".") Expression
+/// Type      ::= Identifier ("->" Type)?
+///             | "(" Type ")" ("->" Type)?
+///             | Forall Identifier "." Type
+/// If        ::= "if" Expression "then" Expression "else" Expression
+/// Match     ::= "match" Expression "{" Arm (";" Arm)* ";"? "}"
+/// Arm       ::= Identifier Identifier* "=>" Expression
 /// Expression ::= Abstraction
-///                | Application
-///                | Identifier
-///                | "(" Expression ")"
+///                | TypeAbstraction
+///                | If
+///                | Match
+///                | Operation ("$" Expression)?
+/// Operation ::= Application (Operator Application)* (Operator (Abstraction | If | Match))?
 /// Application ::= Function Argument
+///               | Function "[" Type "]"
 /// Function ::= Identifier
 ///            | Application
 ///            | "(" Expression ")"
 /// Argument ::= Identifier
 ///            | "(" Expression ")"
+///            | "(" Operator ")"
+///            | "(" Operation Operator ")"
+///            | "(" Operator Operation ")"
+///
+/// Operations are nested by the fixity of their operators after they are
+/// parsed, see [`nest`].
 pub struct Parser<'a> {
     pub codemap: &'a CodeMap,
     pub file_span: codemap::Span,
-    pub emitter: Emitter<'a>,
+    /// Diagnostics reported while parsing, in source order.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Line comments skipped by the parser, kept for tools like the formatter.
+    pub comments: Vec<Token>,
     previous_token_span: Option<ast::Span>,
     token: Option<Token>,
     tokens_iterator: Box<dyn Iterator<Item = Token> + 'a>,
+    /// Nodes of the concrete syntax tree that are being built, the program
+    /// node at the bottom.
+    nodes: Vec<cst::Node>,
+    /// Trivia read before `token`, added to the tree when `token` is consumed.
+    trivia: Vec<Token>,
+    /// Fixities of the operators declared so far. Hosts that parse several
+    /// sources in turn, like the REPL, can carry them over.
+    pub fixities: HashMap<String, Fixity>,
 }
 
 impl<'a> Parser<'a> {
@@ -58,9 +92,13 @@ impl<'a> Parser<'a> {
             codemap: codemap_imut,
             previous_token_span: None,
             token: None,
-            file_span: file_span,
-            emitter: Emitter::stderr(ColorConfig::Always, Some(codemap_imut)),
+            file_span,
+            diagnostics: vec![],
+            comments: vec![],
             tokens_iterator: Box::new(crate::lexer::lex(source)),
+            nodes: vec![cst::Node::new(NodeKind::Program)],
+            trivia: vec![],
+            fixities: HashMap::new(),
         };
 
         parser.consume_token();
@@ -68,30 +106,68 @@ impl<'a> Parser<'a> {
     }
 
     pub fn parse(&mut self) -> AST {
-        let mut ast = vec![];
+        cst::lower(&self.parse_cst())
+    }
 
+    /// Parses the whole input into a lossless concrete syntax tree.
+    pub fn parse_cst(&mut self) -> cst::Node {
         while self.token.is_some() {
-            let statement = self.parse_statement();
-            if statement.is_some() {
-                ast.push(statement.unwrap());
-            }
+            self.parse_statement_node();
         }
 
-        ast
+        let trivia = std::mem::take(&mut self.trivia);
+        let program = &mut self.nodes[0];
+        program
+            .children
+            .extend(trivia.into_iter().map(Element::Token));
+
+        std::mem::replace(program, cst::Node::new(NodeKind::Program))
     }
 
     pub fn parse_statement(&mut self) -> Option<Statement> {
+        self.parse_statement_node()?;
+        cst::lower_statement(self.nodes[0].nodes().last()?)
+    }
+
+    /// Adds the node of the next statement to the tree.
+    fn parse_statement_node(&mut self) -> Option<()> {
         check_eof!(self);
 
         match self.token.as_ref().unwrap().raw {
-            RawToken::Let => self.parse_let_statement(),
-            _ => self.parse_expression_statement(),
+            RawToken::Let => self.node(NodeKind::Let, Self::parse_let_statement),
+            RawToken::InfixLeft | RawToken::InfixRight | RawToken::Infix => {
+                self.node(NodeKind::Infix, Self::parse_infix_statement)
+            }
+            RawToken::Syntax => self.node(NodeKind::Syntax, Self::parse_syntax_statement),
+            RawToken::Data => self.node(NodeKind::Data, Self::parse_data_statement),
+            RawToken::Assert => self.node(NodeKind::Assert, Self::parse_assert_statement),
+            RawToken::AssertEq => self.node(NodeKind::AssertEq, Self::parse_assert_eq_statement),
+            _ => self.node(
+                NodeKind::ExpressionStatement,
+                Self::parse_expression_statement,
+            ),
         }
     }
 
-    fn parse_let_statement(&mut self) -> Option<Statement> {
-        let start = self.token.as_ref().unwrap().span.start;
+    /// Parses input that consists of a single expression, optionally followed
+    /// by `;`.
+    pub fn parse_single_expression(&mut self) -> Option<Expression> {
+        self.parse_expression()?;
+        let expression = cst::lower_expression(self.nodes[0].nodes().last()?)?;
+
+        if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Semicolon) {
+            self.consume_token();
+        }
+
+        if self.token.is_some() {
+            self.unexpected_token("expected end of input after the expression".to_owned());
+            return None;
+        }
+
+        Some(expression)
+    }
 
+    fn parse_let_statement(&mut self) -> Option<()> {
         self.consume_token();
 
         check_token!(
@@ -100,11 +176,10 @@ impl<'a> Parser<'a> {
             "expected name of variable in the let statement".to_owned()
         );
 
-        let name = self.token.as_ref().unwrap().clone().literal;
-        let name_span = self.token.as_ref().unwrap().clone().span;
-
         self.consume_token();
 
+        self.parse_annotation()?;
+
         check_token!(
             self,
             RawToken::Assign,
@@ -113,7 +188,7 @@ impl<'a> Parser<'a> {
 
         self.consume_token();
 
-        let (expression, expression_span) = self.parse_expression()?;
+        self.parse_expression()?;
 
         check_token!(
             self,
@@ -121,84 +196,300 @@ impl<'a> Parser<'a> {
             "help: consider adding ';' at the end of the let statement".to_owned()
         );
 
-        let end = self.token.as_ref().unwrap().span.end;
+        self.consume_token();
+
+        Some(())
+    }
+
+    fn parse_infix_statement(&mut self) -> Option<()> {
+        let associativity = match self.token.as_ref().unwrap().raw {
+            RawToken::InfixLeft => Associativity::Left,
+            RawToken::InfixRight => Associativity::Right,
+            _ => Associativity::None,
+        };
+
+        self.consume_token();
+
+        check_eof!(self);
+
+        let precedence = self
+            .token
+            .as_ref()
+            .filter(|token| token.raw == RawToken::Identifier)
+            .and_then(|token| token.literal.parse().ok())
+            .filter(|&precedence| precedence <= 9);
+        if precedence.is_none() {
+            self.unexpected_token("expected precedence, a number from 0 to 9".to_owned());
+        }
+
+        // The rest of the declaration is still parsed, with the precedence of
+        // undeclared operators, to avoid more errors about its uses.
+        if self.token.as_ref().unwrap().raw == RawToken::Identifier {
+            self.consume_token();
+        }
+
+        check_token!(
+            self,
+            RawToken::Operator,
+            "expected operator, like `+` or `<>`".to_owned()
+        );
+
+        let operator = self.token.as_ref().unwrap().literal.clone();
+        self.fixities.insert(
+            operator,
+            Fixity {
+                associativity,
+                precedence: precedence.unwrap_or(Fixity::DEFAULT.precedence),
+            },
+        );
+
+        self.consume_token();
+
+        check_token!(
+            self,
+            RawToken::Assign,
+            "help: consider adding '=' in the operator declaration".to_owned()
+        );
+
+        self.consume_token();
+
+        self.parse_expression()?;
+
+        check_token!(
+            self,
+            RawToken::Semicolon,
+            "help: consider adding ';' at the end of the operator declaration".to_owned()
+        );
 
         self.consume_token();
 
-        Some(Statement::Let {
-            name: name,
-            name_span: name_span,
-            expression: expression,
-            expression_span: expression_span,
-            span: start..end,
-        })
+        precedence.map(|_| ())
     }
 
-    fn parse_expression_statement(&mut self) -> Option<Statement> {
-        let (expression, expression_span) = self.parse_expression()?;
+    fn parse_syntax_statement(&mut self) -> Option<()> {
+        self.consume_token();
+
+        if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Lbracket) {
+            self.consume_token();
+
+            while self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Identifier) {
+                self.consume_token();
+            }
+
+            check_token!(
+                self,
+                RawToken::Rbracket,
+                "help: consider adding ']' after the keywords".to_owned()
+            );
+
+            self.consume_token();
+        }
+
+        check_token!(
+            self,
+            RawToken::Identifier,
+            "expected name of the syntax rule".to_owned()
+        );
+
+        while self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Identifier) {
+            self.consume_token();
+        }
+
+        check_token!(
+            self,
+            RawToken::Assign,
+            "help: consider adding '=' after the pattern of the syntax rule".to_owned()
+        );
+
+        self.consume_token();
 
-        let start = expression_span.start;
+        self.parse_expression()?;
 
         check_token!(
             self,
             RawToken::Semicolon,
-            "help: consider adding ';' at the end of expression statement".to_owned()
+            "help: consider adding ';' at the end of the syntax rule".to_owned()
+        );
+
+        self.consume_token();
+
+        Some(())
+    }
+
+    fn parse_data_statement(&mut self) -> Option<()> {
+        self.consume_token();
+
+        check_token!(
+            self,
+            RawToken::Identifier,
+            "expected name of the data type".to_owned()
+        );
+
+        self.consume_token();
+
+        check_token!(
+            self,
+            RawToken::Assign,
+            "help: consider adding '=' after the name of the data type".to_owned()
         );
 
-        let end = self.token.as_ref().unwrap().span.end;
+        self.consume_token();
+
+        loop {
+            self.node(NodeKind::Constructor, Self::parse_constructor)?;
+
+            match &self.token {
+                Some(token) if token.raw == RawToken::Operator && token.literal == "|" => {
+                    self.consume_token()
+                }
+                _ => break,
+            }
+        }
+
+        check_token!(
+            self,
+            RawToken::Semicolon,
+            "help: consider adding ';' at the end of the data declaration, or '|' between constructors".to_owned()
+        );
 
         self.consume_token();
 
-        Some(Statement::Expression {
-            expression: expression,
-            expression_span: expression_span,
-            span: start..end,
-        })
+        Some(())
     }
 
-    fn parse_name_expression(&mut self) -> Option<(Expression, ast::Span)> {
-        let name = self.token.as_ref().unwrap().clone().literal;
-        let name_span = self.token.as_ref().unwrap().clone().span;
+    fn parse_constructor(&mut self) -> Option<()> {
+        check_token!(
+            self,
+            RawToken::Identifier,
+            "expected name of the constructor".to_owned()
+        );
+
+        while self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Identifier) {
+            self.consume_token();
+        }
+
+        Some(())
+    }
 
+    fn parse_assert_statement(&mut self) -> Option<()> {
         self.consume_token();
 
-        Some((
-            ast::Expression::Var {
-                name: name,
-                name_span: name_span.clone(),
-            },
-            name_span,
-        ))
+        self.parse_expression()?;
+
+        check_token!(
+            self,
+            RawToken::Semicolon,
+            "help: consider adding ';' at the end of the assertion".to_owned()
+        );
+
+        self.consume_token();
+
+        Some(())
     }
 
-    fn parse_paren_expression(&mut self) -> Option<(Expression, ast::Span)> {
-        let start = self.token.as_ref().unwrap().clone().span.start;
+    fn parse_assert_eq_statement(&mut self) -> Option<()> {
         self.consume_token();
 
-        let (expression, expression_span) = self.parse_expression()?;
+        self.parse_argument()?;
+        self.parse_argument()?;
 
         check_token!(
             self,
-            RawToken::Rparen,
-            "help: consider adding ')' at the end of parenthesised expression".to_owned()
+            RawToken::Semicolon,
+            "help: consider adding ';' at the end of the assertion, and parentheses around applications".to_owned()
         );
 
         self.consume_token();
 
+        Some(())
+    }
+
+    /// Parses an identifier or a parenthesised expression.
+    fn parse_argument(&mut self) -> Option<()> {
         check_eof!(self);
 
-        let end = self.token.as_ref()?.clone().span.start;
-        Some((
-            Expression::Paren {
-                expression: Box::new(expression),
-                expression_span: expression_span,
-            },
-            start..end,
-        ))
+        match self.token.as_ref().unwrap().raw {
+            RawToken::Identifier => self.node(NodeKind::Var, Self::parse_name_expression),
+            RawToken::Lparen => self.node(NodeKind::Paren, Self::parse_paren_expression),
+            _ => {
+                self.unexpected_token(
+                    "expected identifier or '(', put parentheses around other expressions"
+                        .to_owned(),
+                );
+                self.consume_token();
+                None
+            }
+        }
+    }
+
+    fn parse_expression_statement(&mut self) -> Option<()> {
+        self.parse_expression()?;
+
+        check_token!(
+            self,
+            RawToken::Semicolon,
+            "help: consider adding ';' at the end of expression statement".to_owned()
+        );
+
+        self.consume_token();
+
+        Some(())
+    }
+
+    fn parse_name_expression(&mut self) -> Option<()> {
+        self.consume_token();
+
+        Some(())
     }
 
-    fn parse_abstraction_expression(&mut self) -> Option<(Expression, ast::Span)> {
-        let start = self.token.as_ref().unwrap().clone().span.start;
+    /// Parses `(e)`, or one of `(+)`, `(a +)` and `(+ b)`, in which case the
+    /// node is given the kind of the section.
+    fn parse_paren_expression(&mut self) -> Option<()> {
+        self.consume_token();
+
+        check_eof!(self);
+
+        match self.token.as_ref().unwrap().raw {
+            RawToken::Operator => {
+                let operator = self.token.clone().unwrap();
+                self.consume_token();
+
+                if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Rparen) {
+                    self.current_node().kind = NodeKind::OperatorName;
+                } else {
+                    self.current_node().kind = NodeKind::RightSection;
+
+                    let checkpoint = self.checkpoint();
+                    self.parse_operands(false)?;
+                    self.nest_operators(checkpoint, Some(&operator));
+                }
+            }
+            RawToken::Lparen | RawToken::Identifier => {
+                let checkpoint = self.checkpoint();
+                let left_section = self.parse_operands(true)?;
+                self.nest_operators(checkpoint, None);
+
+                if left_section {
+                    self.current_node().kind = NodeKind::LeftSection;
+                } else {
+                    self.parse_dollar(checkpoint)?;
+                }
+            }
+            _ => self.parse_expression()?,
+        }
+
+        check_token!(
+            self,
+            RawToken::Rparen,
+            "help: consider adding ')' at the end of parenthesised expression".to_owned()
+        );
+
+        self.consume_token();
+
+        Some(())
+    }
+
+    fn parse_abstraction_expression(&mut self) -> Option<()> {
         self.consume_token();
 
         check_token!(
@@ -207,13 +498,12 @@ impl<'a> Parser<'a> {
             "expected argument name".to_owned()
         );
 
-        let name = self.token.as_ref().unwrap().clone().literal;
-        let name_span = self.token.as_ref().unwrap().clone().span;
-
         self.consume_token();
 
+        self.parse_annotation()?;
+
         if self.token.is_some() && self.token.as_ref().unwrap().raw == RawToken::Period {
-            self.emitter.emit(&[Diagnostic {
+            self.diagnostics.push(Diagnostic {
                         level: Level::Warning,
                         message: "use '=>' in abstractions".to_owned(),
                         spans: vec![SpanLabel {
@@ -222,7 +512,7 @@ impl<'a> Parser<'a> {
                             label: Some("help: use '=>' instead of '.' because Gamma uses different syntax rather than usual one in Lambda calculus.".to_owned()),
                         }],
                         code: Some("W002".to_owned()),
-                    }]);
+                    });
         } else {
             check_token!(
                 self,
@@ -233,131 +523,608 @@ impl<'a> Parser<'a> {
 
         self.consume_token();
 
-        let (expression, expression_span) = self.parse_expression()?;
+        self.parse_expression()
+    }
 
-        check_eof!(self);
+    fn parse_if_expression(&mut self) -> Option<()> {
+        self.consume_token();
 
-        let end = self.token.as_ref()?.clone().span.start;
+        self.parse_expression()?;
 
-        Some((
-            Expression::Abstraction {
-                name: name,
-                name_span: name_span,
-                expression: Box::new(expression),
-                expression_span: expression_span,
-            },
-            start..end,
-        ))
+        check_token!(
+            self,
+            RawToken::Then,
+            "help: consider adding 'then' after the condition".to_owned()
+        );
+
+        self.consume_token();
+
+        self.parse_expression()?;
+
+        check_token!(
+            self,
+            RawToken::Else,
+            "help: consider adding 'else', an 'if' needs both branches".to_owned()
+        );
+
+        self.consume_token();
+
+        self.parse_expression()
     }
 
-    fn parse_application(&mut self) -> Option<(Expression, ast::Span)> {
-        let mut expressions = vec![];
+    fn parse_match_expression(&mut self) -> Option<()> {
+        self.consume_token();
+
+        self.parse_expression()?;
+
+        check_token!(
+            self,
+            RawToken::Lbrace,
+            "help: consider adding '{' before the arms of the match".to_owned()
+        );
+
+        self.consume_token();
+
         loop {
-            check_eof!(self);
+            self.node(NodeKind::Arm, Self::parse_arm)?;
 
-            match self.token.as_ref().unwrap().raw {
-                RawToken::Identifier => {
-                    expressions.push(self.parse_name_expression()?);
-                }
-                RawToken::Lparen => {
-                    expressions.push(self.parse_paren_expression()?);
-                }
-                _ => {
-                    return match expressions.len() {
-                        0 => {
-                            self.unexpected_token("do not write empty expressions".to_owned());
-                            None
-                        }
-                        1 => Some(expressions[0].to_owned()),
-                        _ => {
-                            let mut accumulator = expressions.pop()?;
-                            let start_defined = false;
-                            let mut start = 0;
-                            while !expressions.is_empty() {
-                                let (rhs, rhs_span) = accumulator;
-                                let (lhs, lhs_span) = expressions.pop().unwrap();
-
-                                if !start_defined {
-                                    start = lhs_span.start;
-                                }
-
-                                let end = rhs_span.end;
-                                accumulator = (
-                                    Expression::Apply {
-                                        lhs: Box::new(lhs),
-                                        lhs_span,
-                                        rhs: Box::new(rhs),
-                                        rhs_span,
-                                    },
-                                    start..end,
-                                )
-                            }
-
-                            Some(accumulator)
-                        }
-                    };
-                }
+            if self.token.as_ref().map(|token| &token.raw) != Some(&RawToken::Semicolon) {
+                break;
             }
-        }
-    }
 
-    fn parse_expression(&mut self) -> Option<(Expression, ast::Span)> {
-        check_eof!(self);
+            self.consume_token();
 
-        match self.token.as_ref().unwrap().raw {
-            RawToken::Lambda => self.parse_abstraction_expression(),
-            RawToken::Lparen => self.parse_application(),
-            RawToken::Identifier => self.parse_application(),
-            _ => {
-                self.unexpected_token(
-                    "expression must start with identifier, 'lambda', '\\' or '('".to_owned(),
-                );
-                self.consume_token();
-                None
+            if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Rbrace) {
+                break;
             }
         }
-    }
 
-    fn consume_token(&mut self) {
-        let previous_token = self.token.as_ref();
-        if previous_token.is_some() {
-            self.previous_token_span = Some(previous_token.unwrap().span.clone());
-        } else {
-            self.previous_token_span = None;
-        }
+        check_token!(
+            self,
+            RawToken::Rbrace,
+            "help: consider adding '}' after the arms, or ';' between them".to_owned()
+        );
 
-        self.token = self.tokens_iterator.next();
+        self.consume_token();
+
+        Some(())
     }
 
-    fn token_span(&self, token: &Option<Token>) -> codemap::Span {
-        return self.file_span.subspan(
-            token.as_ref().unwrap().span.start as u64,
-            token.as_ref().unwrap().span.end as u64,
+    /// Parses `Cons h t => e`.
+    fn parse_arm(&mut self) -> Option<()> {
+        check_token!(
+            self,
+            RawToken::Identifier,
+            "expected constructor, or '_' for any other one".to_owned()
         );
-    }
 
-    fn span(&self, span: &ast::Span) -> codemap::Span {
-        return self.file_span.subspan(span.start as u64, span.end as u64);
-    }
+        while self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Identifier) {
+            self.consume_token();
+        }
 
-    pub fn unexpected_eof(&mut self) {
-        self.emitter.emit(&[Diagnostic {
-            level: Level::Error,
-            spans: vec![SpanLabel {
+        check_token!(
+            self,
+            RawToken::RightArrow,
+            "help: consider adding '=>' after the pattern".to_owned()
+        );
+
+        self.consume_token();
+
+        self.parse_expression()
+    }
+
+    /// Parses `: Type` if the current token is a colon.
+    fn parse_annotation(&mut self) -> Option<()> {
+        if self.token.as_ref().map(|token| &token.raw) != Some(&RawToken::Colon) {
+            return Some(());
+        }
+
+        self.consume_token();
+
+        self.parse_type()
+    }
+
+    fn parse_type(&mut self) -> Option<()> {
+        let checkpoint = self.checkpoint();
+
+        check_eof!(self);
+
+        match self.token.as_ref().unwrap().raw {
+            RawToken::Identifier => self.node(NodeKind::TypeVar, Self::parse_name_expression)?,
+            RawToken::Lparen => self.node(NodeKind::TypeParen, Self::parse_paren_type)?,
+            RawToken::Forall => return self.node(NodeKind::TypeForall, Self::parse_forall_type),
+            _ => {
+                self.unexpected_token("expected type".to_owned());
+                return None;
+            }
+        }
+
+        // Arrows are right associative: `A -> B -> C` is `A -> (B -> C)`.
+        if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Arrow) {
+            self.consume_token();
+            self.parse_type()?;
+            self.wrap(checkpoint, NodeKind::TypeArrow);
+        }
+
+        Some(())
+    }
+
+    fn parse_forall_type(&mut self) -> Option<()> {
+        self.consume_token();
+
+        check_token!(
+            self,
+            RawToken::Identifier,
+            "expected type variable name".to_owned()
+        );
+
+        self.consume_token();
+
+        check_token!(
+            self,
+            RawToken::Period,
+            "help: consider adding '.' after the type variable".to_owned()
+        );
+
+        self.consume_token();
+
+        self.parse_type()
+    }
+
+    fn parse_type_abstraction_expression(&mut self) -> Option<()> {
+        self.consume_token();
+
+        check_token!(
+            self,
+            RawToken::Identifier,
+            "expected type variable name".to_owned()
+        );
+
+        self.consume_token();
+
+        if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Period) {
+            self.consume_token();
+        } else {
+            check_token!(
+                self,
+                RawToken::RightArrow,
+                "help: consider adding '=>'".to_owned()
+            );
+
+            self.consume_token();
+        }
+
+        self.parse_expression()
+    }
+
+    /// Parses `[Type]` after the expression that starts at `checkpoint`.
+    fn parse_type_argument(&mut self, checkpoint: usize) -> Option<()> {
+        self.consume_token();
+
+        self.parse_type()?;
+
+        check_token!(
+            self,
+            RawToken::Rbracket,
+            "help: consider adding ']' at the end of the type argument".to_owned()
+        );
+
+        self.consume_token();
+
+        self.wrap(checkpoint, NodeKind::TypeApply);
+
+        Some(())
+    }
+
+    fn parse_paren_type(&mut self) -> Option<()> {
+        self.consume_token();
+
+        self.parse_type()?;
+
+        check_token!(
+            self,
+            RawToken::Rparen,
+            "help: consider adding ')' at the end of parenthesised type".to_owned()
+        );
+
+        self.consume_token();
+
+        Some(())
+    }
+
+    fn parse_application(&mut self) -> Option<()> {
+        let checkpoint = self.checkpoint();
+        let mut operands = 0;
+        loop {
+            if self.token.is_none() && operands > 0 {
+                return Some(());
+            }
+
+            check_eof!(self);
+
+            match self.token.as_ref().unwrap().raw {
+                RawToken::Identifier => {
+                    self.node(NodeKind::Var, Self::parse_name_expression)?;
+                }
+                RawToken::Lparen => {
+                    self.node(NodeKind::Paren, Self::parse_paren_expression)?;
+                }
+                RawToken::Lbracket if operands > 0 => {
+                    self.parse_type_argument(checkpoint)?;
+                    continue;
+                }
+                _ => {
+                    if operands == 0 {
+                        self.unexpected_token("do not write empty expressions".to_owned());
+                        return None;
+                    }
+
+                    return Some(());
+                }
+            }
+
+            // Application is left associative: `f a b` is `(f a) b`.
+            if operands > 0 {
+                self.wrap(checkpoint, NodeKind::Apply);
+            }
+
+            operands += 1;
+        }
+    }
+
+    /// Parses operands separated by operators, as in `f a + b * c`, without
+    /// nesting them. If `section` is set, the operands may be followed by an
+    /// operator and `)`, as in `(a +)`, and `true` is returned.
+    fn parse_operands(&mut self, section: bool) -> Option<bool> {
+        self.parse_application()?;
+
+        while self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Operator) {
+            self.consume_token();
+
+            check_eof!(self);
+
+            match self.token.as_ref().unwrap().raw {
+                RawToken::Rparen if section => return Some(true),
+                RawToken::Identifier | RawToken::Lparen => self.parse_application()?,
+                // Abstractions extend as far right as possible, so only the
+                // last operand can be one, like `if` and `match`.
+                RawToken::Lambda => {
+                    return self
+                        .node(NodeKind::Abstraction, Self::parse_abstraction_expression)
+                        .map(|_| false)
+                }
+                RawToken::If => {
+                    return self
+                        .node(NodeKind::If, Self::parse_if_expression)
+                        .map(|_| false)
+                }
+                RawToken::Match => {
+                    return self
+                        .node(NodeKind::Match, Self::parse_match_expression)
+                        .map(|_| false)
+                }
+                RawToken::TypeLambda => {
+                    return self
+                        .node(
+                            NodeKind::TypeAbstraction,
+                            Self::parse_type_abstraction_expression,
+                        )
+                        .map(|_| false)
+                }
+                _ => {
+                    self.unexpected_token("expected operand after the operator".to_owned());
+                    return None;
+                }
+            }
+        }
+
+        Some(false)
+    }
+
+    /// Nests the operands and operators added since `checkpoint` by the
+    /// fixity of the operators. In the right section `(+ b)`, `operator` is
+    /// the `+` before the operands. In the left section `(a +)`, the last
+    /// operator is left after the nested operands.
+    ///
+    /// Operators that cannot be nested are reported, and nested as if they
+    /// all had the same precedence so that parsing goes on.
+    fn nest_operators(&mut self, checkpoint: usize, operator: Option<&Token>) {
+        let elements = self.current_node().children.split_off(checkpoint);
+        let is_operator = |element: &Element| {
+            matches!(
+                element,
+                Element::Token(Token {
+                    raw: RawToken::Operator,
+                    ..
+                })
+            )
+        };
+        if operator.is_none() && !elements.iter().any(is_operator) {
+            self.current_node().children.extend(elements);
+            return;
+        }
+
+        // Operands, and the tokens after each of them: trivia, then the
+        // operator that follows, if any.
+        let mut operands = vec![];
+        let mut operators: Vec<Vec<Element>> = vec![];
+        for element in elements {
+            match element {
+                Element::Node(_) => {
+                    operands.push(Some(element));
+                    operators.push(vec![]);
+                }
+                Element::Token(_) => operators.last_mut().unwrap().push(element),
+            }
+        }
+
+        // The operand that a section lacks.
+        let left_section = operators.last().is_some_and(|last| !last.is_empty());
+        if left_section {
+            operands.push(None);
+        } else {
+            operators.pop();
+        }
+
+        let mut tokens: Vec<_> = operators
+            .iter()
+            .flatten()
+            .filter_map(|element| match element {
+                Element::Token(token) if token.raw == RawToken::Operator => Some(token.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut operators: Vec<_> = operators.into_iter().map(Some).collect();
+        if let Some(operator) = operator {
+            tokens.insert(0, operator.clone());
+            operators.insert(0, None);
+            operands.insert(0, None);
+        }
+
+        let mut fixities: Vec<_> = tokens
+            .iter()
+            .map(|token| {
+                self.fixities
+                    .get(&token.literal)
+                    .copied()
+                    .unwrap_or(Fixity::DEFAULT)
+            })
+            .collect();
+
+        // A section is the operation with the missing operand, with that
+        // operand taken out: `(a * b +)` is `a * b + x` without `+ x`.
+        let last = tokens.len() - 1;
+        let section = |nesting| match (nesting, left_section) {
+            (Nesting::Operation(lhs, root, _), true) if root == last => Ok(*lhs),
+            (Nesting::Operation(_, 0, rhs), false) if operator.is_some() => Ok(*rhs),
+            (nesting, _) if !left_section && operator.is_none() => Ok(nesting),
+            (Nesting::Operation(_, root, _), true) => Err((root, last)),
+            (Nesting::Operation(_, root, _), false) => Err((0, root)),
+            (Nesting::Operand(_), _) => unreachable!("operations have operators"),
+        };
+
+        let nesting = match nest(&fixities).and_then(section) {
+            Ok(nesting) => nesting,
+            Err((first, second)) => {
+                self.ambiguous_operators(
+                    (&tokens[first], fixities[first]),
+                    (&tokens[second], fixities[second]),
+                );
+
+                let associativity = match operator {
+                    Some(_) => Associativity::Right,
+                    None => Associativity::Left,
+                };
+                fixities.fill(Fixity {
+                    associativity,
+                    ..Fixity::DEFAULT
+                });
+                nest(&fixities).and_then(section).unwrap()
+            }
+        };
+
+        let nested = build(nesting, &mut operands, &mut operators);
+        let node = self.current_node();
+        node.children.push(nested);
+        if left_section {
+            node.children.extend(operators.pop().unwrap().unwrap());
+        }
+    }
+
+    /// Parses `$ e` after the expression that starts at `checkpoint`, if the
+    /// current token is `$`.
+    fn parse_dollar(&mut self, checkpoint: usize) -> Option<()> {
+        // `$` binds weaker than any operator and associates to the right:
+        // `f a $ g $ x` is `f a (g x)`.
+        if self.token.as_ref().map(|token| &token.raw) == Some(&RawToken::Dollar) {
+            self.node(NodeKind::Dollar, Self::parse_dollar_argument)?;
+            self.wrap(checkpoint, NodeKind::Apply);
+        }
+
+        Some(())
+    }
+
+    fn parse_dollar_argument(&mut self) -> Option<()> {
+        self.consume_token();
+
+        self.parse_expression()
+    }
+
+    fn parse_expression(&mut self) -> Option<()> {
+        check_eof!(self);
+
+        match self.token.as_ref().unwrap().raw {
+            RawToken::Lambda => {
+                self.node(NodeKind::Abstraction, Self::parse_abstraction_expression)
+            }
+            RawToken::TypeLambda => self.node(
+                NodeKind::TypeAbstraction,
+                Self::parse_type_abstraction_expression,
+            ),
+            RawToken::If => self.node(NodeKind::If, Self::parse_if_expression),
+            RawToken::Match => self.node(NodeKind::Match, Self::parse_match_expression),
+            RawToken::Lparen | RawToken::Identifier => {
+                let checkpoint = self.checkpoint();
+                self.parse_operands(false)?;
+                self.nest_operators(checkpoint, None);
+                self.parse_dollar(checkpoint)
+            }
+            _ => {
+                self.unexpected_token(
+                    "expression must start with identifier, 'lambda', '\\', '/\\', 'if', 'match' or '('"
+                        .to_owned(),
+                );
+                self.consume_token();
+                None
+            }
+        }
+    }
+
+    /// Runs `parse` inside a new node of the given kind. The node is turned
+    /// into an error node if `parse` fails.
+    fn node(&mut self, kind: NodeKind, parse: fn(&mut Self) -> Option<()>) -> Option<()> {
+        self.nodes.push(cst::Node::new(kind));
+
+        let result = parse(self);
+
+        let mut node = self.nodes.pop().unwrap();
+        if result.is_none() {
+            node.kind = NodeKind::Error;
+        }
+
+        self.current_node().children.push(Element::Node(node));
+        result
+    }
+
+    fn current_node(&mut self) -> &mut cst::Node {
+        self.nodes.last_mut().unwrap()
+    }
+
+    fn checkpoint(&mut self) -> usize {
+        self.current_node().children.len()
+    }
+
+    /// Moves the children added to the current node since `checkpoint` into
+    /// a new node of the given kind.
+    fn wrap(&mut self, checkpoint: usize, kind: NodeKind) {
+        let children = self.current_node().children.split_off(checkpoint);
+        self.current_node()
+            .children
+            .push(Element::Node(cst::Node { kind, children }));
+    }
+
+    fn consume_token(&mut self) {
+        self.previous_token_span = self.token.as_ref().map(|token| token.span.clone());
+
+        let trivia = std::mem::take(&mut self.trivia);
+        let token = self.token.take();
+        let node = self.current_node();
+        node.children.extend(trivia.into_iter().map(Element::Token));
+        node.children.extend(token.map(Element::Token));
+
+        self.token = self.tokens_iterator.next();
+        while let Some(token) = self.token.take() {
+            if !token.raw.is_trivia() {
+                self.token = Some(token);
+                break;
+            }
+
+            if token.raw == RawToken::Comment {
+                self.comments.push(token.clone());
+            }
+
+            if token.raw == RawToken::Error {
+                self.unknown_characters(&token);
+            }
+
+            self.trivia.push(token);
+            self.token = self.tokens_iterator.next();
+        }
+    }
+
+    fn token_span(&self, token: &Option<Token>) -> codemap::Span {
+        self.file_span.subspan(
+            token.as_ref().unwrap().span.start as u64,
+            token.as_ref().unwrap().span.end as u64,
+        )
+    }
+
+    fn span(&self, span: &ast::Span) -> codemap::Span {
+        self.file_span.subspan(span.start as u64, span.end as u64)
+    }
+
+    /// Span of the first byte, or the empty span of an empty file.
+    fn start_span(&self) -> codemap::Span {
+        self.file_span.subspan(0, self.file_span.len().min(1))
+    }
+
+    /// Whether any error (not just a warning) was reported while parsing.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.level == Level::Error)
+    }
+
+    pub fn unexpected_eof(&mut self) {
+        self.diagnostics.push(Diagnostic {
+            level: Level::Error,
+            spans: vec![SpanLabel {
                 span: match self.previous_token_span.as_ref() {
                     Some(_) => self.span(self.previous_token_span.as_ref().unwrap()),
-                    None => self.file_span.subspan(0, 1),
+                    None => self.start_span(),
                 },
                 style: SpanStyle::Primary,
                 label: Some("unexpected end of file/input".to_owned()),
             }],
             message: "parsing error found".to_owned(),
             code: Some("E001".to_owned()),
-        }]);
+        });
+    }
+
+    /// Reports characters that no token starts with. The parser skips them
+    /// like whitespace.
+    fn unknown_characters(&mut self, token: &Token) {
+        let (message, label) = match token.literal.chars().count() {
+            1 => ("unknown character", "is not part of the Gamma syntax"),
+            _ => ("unknown characters", "are not part of the Gamma syntax"),
+        };
+        self.diagnostics.push(Diagnostic {
+            level: Level::Error,
+            spans: vec![SpanLabel {
+                span: self.span(&token.span),
+                style: SpanStyle::Primary,
+                label: Some(format!("`{}` {}", token.literal, label)),
+            }],
+            message: message.to_owned(),
+            code: Some("E013".to_owned()),
+        });
+    }
+
+    fn ambiguous_operators(&mut self, first: (&Token, Fixity), second: (&Token, Fixity)) {
+        let label = |(token, fixity): (&Token, Fixity)| {
+            Some(format!("`{}` is `{}`", token.literal, fixity))
+        };
+        self.diagnostics.push(Diagnostic {
+            level: Level::Error,
+            message: "ambiguous operator precedence, consider adding parentheses".to_owned(),
+            spans: vec![
+                SpanLabel {
+                    span: self.span(&second.0.span),
+                    style: SpanStyle::Primary,
+                    label: label(second),
+                },
+                SpanLabel {
+                    span: self.span(&first.0.span),
+                    style: SpanStyle::Secondary,
+                    label: label(first),
+                },
+            ],
+            code: Some("E014".to_owned()),
+        });
     }
 
     pub fn unexpected_token(&mut self, message: String) {
-        self.emitter.emit(&[Diagnostic {
+        self.diagnostics.push(Diagnostic {
             level: Level::Error,
             spans: vec![SpanLabel {
                 span: self.token_span(&self.token),
@@ -366,19 +1133,19 @@ impl<'a> Parser<'a> {
             }],
             message: "parsing error found".to_owned(),
             code: Some("E001".to_owned()),
-        }]);
+        });
     }
 
     pub fn check_token(&mut self, expected: RawToken, message: String) -> bool {
         if self.token.is_none() || self.token.as_ref().unwrap().raw != expected {
-            self.emitter.emit(&[Diagnostic {
+            self.diagnostics.push(Diagnostic {
                 level: Level::Error,
                 message: "parsing error found".to_owned(),
                 spans: vec![SpanLabel {
                     span: if self.token.is_none() {
                         match self.previous_token_span.as_ref() {
                             Some(_) => self.span(self.previous_token_span.as_ref().unwrap()),
-                            None => self.file_span.subspan(0, 1),
+                            None => self.start_span(),
                         }
                     } else {
                         self.token_span(&self.token)
@@ -387,7 +1154,7 @@ impl<'a> Parser<'a> {
                     label: Some(message),
                 }],
                 code: Some("E001".to_owned()),
-            }]);
+            });
 
             self.consume_token();
 
@@ -398,9 +1165,86 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Operation whose operands and operators are numbered from the left.
+#[derive(Debug, PartialEq)]
+pub enum Nesting {
+    Operand(usize),
+    Operation(Box<Nesting>, usize, Box<Nesting>),
+}
+
+/// Nests the operation `x0 op0 x1 op1 x2 ...` by the fixities of its
+/// operators, following section 10.6 of the Haskell 2010 report. Fails with
+/// two operators that cannot be nested without parentheses: operators of
+/// the same precedence that are not both left or both right associative.
+pub fn nest(fixities: &[Fixity]) -> Result<Nesting, (usize, usize)> {
+    nest_after(fixities, &mut 0, None, Nesting::Operand(0))
+}
+
+/// Extends `lhs`, the operand after the operator `previous`, with the
+/// operators from `next` on that bind tighter than `previous`.
+fn nest_after(
+    fixities: &[Fixity],
+    next: &mut usize,
+    previous: Option<usize>,
+    mut lhs: Nesting,
+) -> Result<Nesting, (usize, usize)> {
+    while let Some(&fixity) = fixities.get(*next) {
+        let operator = *next;
+        if let Some(previous) = previous {
+            let previous_fixity = fixities[previous];
+            if previous_fixity.precedence == fixity.precedence
+                && (previous_fixity.associativity != fixity.associativity
+                    || fixity.associativity == Associativity::None)
+            {
+                return Err((previous, operator));
+            }
+
+            if previous_fixity.precedence > fixity.precedence
+                || (previous_fixity.precedence == fixity.precedence
+                    && fixity.associativity == Associativity::Left)
+            {
+                return Ok(lhs);
+            }
+        }
+
+        *next += 1;
+        let rhs = nest_after(
+            fixities,
+            next,
+            Some(operator),
+            Nesting::Operand(operator + 1),
+        )?;
+        lhs = Nesting::Operation(Box::new(lhs), operator, Box::new(rhs));
+    }
+
+    Ok(lhs)
+}
+
+/// Builds the nodes of `nesting`, taking its operands and the tokens of its
+/// operators out of `operands` and `operators`.
+fn build(
+    nesting: Nesting,
+    operands: &mut [Option<Element>],
+    operators: &mut [Option<Vec<Element>>],
+) -> Element {
+    match nesting {
+        Nesting::Operand(operand) => operands[operand].take().unwrap(),
+        Nesting::Operation(lhs, operator, rhs) => {
+            let mut children = vec![build(*lhs, operands, operators)];
+            children.extend(operators[operator].take().unwrap());
+            children.push(build(*rhs, operands, operators));
+            Element::Node(cst::Node {
+                kind: NodeKind::Operation,
+                children,
+            })
+        }
+    }
+}
+
 #[cfg(test)]
 mod parser_tests {
     use super::Parser;
+    use crate::format::format_expression;
     use crate::parser::CodeMap;
     use crate::parser::Expression::*;
     use crate::parser::Statement::*;
@@ -412,6 +1256,8 @@ mod parser_tests {
             Let {
                 name: "a".to_string(),
                 name_span: 4..5,
+                annotation: None,
+                fixity: None,
                 expression: Var {
                     name: "x".to_string(),
                     name_span: 8..9
@@ -429,9 +1275,12 @@ mod parser_tests {
             Let {
                 name: "a".to_string(),
                 name_span: 4..5,
+                annotation: None,
+                fixity: None,
                 expression: Abstraction {
                     name: "x".to_string(),
                     name_span: 9..10,
+                    annotation: None,
                     expression: Box::new(Var {
                         name: "x".to_string(),
                         name_span: 14..15
@@ -443,4 +1292,395 @@ mod parser_tests {
             }
         );
     }
+
+    #[test]
+    fn type_annotations() {
+        let ast = Parser::new(
+            "let f : (A -> B) -> A -> B = \\g: A -> B => g;",
+            "<stdin>",
+            &mut CodeMap::new(),
+        )
+        .parse();
+
+        match &ast[0] {
+            Let {
+                annotation: Some(annotation),
+                expression:
+                    Abstraction {
+                        annotation: Some(parameter),
+                        ..
+                    },
+                ..
+            } => {
+                assert_eq!(annotation.to_string(), "(A -> B) -> A -> B");
+                assert_eq!(annotation.span(), 8..26);
+                assert_eq!(parameter.to_string(), "A -> B");
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn application_is_left_associative() {
+        assert_eq!(
+            Parser::new("f a b;", "<stdin>", &mut CodeMap::new()).parse()[0],
+            Expression {
+                expression: Apply {
+                    lhs: Box::new(Apply {
+                        lhs: Box::new(Var {
+                            name: "f".to_string(),
+                            name_span: 0..1
+                        }),
+                        lhs_span: 0..1,
+                        rhs: Box::new(Var {
+                            name: "a".to_string(),
+                            name_span: 2..3
+                        }),
+                        rhs_span: 2..3
+                    }),
+                    lhs_span: 0..3,
+                    rhs: Box::new(Var {
+                        name: "b".to_string(),
+                        name_span: 4..5
+                    }),
+                    rhs_span: 4..5
+                },
+                expression_span: 0..5,
+                span: 0..6
+            }
+        );
+    }
+
+    #[test]
+    fn long_applications_nest_to_the_left() {
+        let ast = Parser::new("f a (b c) d;", "<stdin>", &mut CodeMap::new()).parse();
+        let Expression { expression, .. } = &ast[0] else {
+            panic!("unexpected statement: {:?}", ast[0]);
+        };
+
+        // Each application spans from `f` to the end of its argument.
+        let mut spans = vec![];
+        let mut function = expression;
+        while let Apply {
+            lhs,
+            lhs_span,
+            rhs_span,
+            ..
+        } = function
+        {
+            spans.push((lhs_span.clone(), rhs_span.clone()));
+            function = lhs;
+        }
+        assert_eq!(spans, vec![(0..9, 10..11), (0..3, 4..9), (0..1, 2..3)]);
+        assert_eq!(format_expression(expression), "f a (b c) d");
+    }
+
+    #[test]
+    fn dollar_is_right_associative() {
+        let ast = Parser::new("f a $ g $ x;", "<stdin>", &mut CodeMap::new()).parse();
+        let Expression {
+            expression:
+                Dollar {
+                    lhs,
+                    lhs_span,
+                    rhs,
+                    rhs_span,
+                },
+            expression_span,
+            ..
+        } = &ast[0]
+        else {
+            panic!("unexpected statement: {:?}", ast[0]);
+        };
+
+        assert_eq!(
+            (lhs_span, rhs_span, expression_span),
+            (&(0..3), &(6..11), &(0..11))
+        );
+        assert!(matches!(**lhs, Apply { .. }));
+        match &**rhs {
+            Dollar {
+                lhs_span, rhs_span, ..
+            } => {
+                assert_eq!((lhs_span, rhs_span), (&(6..7), &(10..11)));
+                assert_eq!(rhs.to_string(), "g $ x");
+            }
+            rhs => panic!("unexpected argument: {:?}", rhs),
+        }
+    }
+
+    #[test]
+    fn operators_nest_by_fixity() {
+        let parse = |source: &str| {
+            let mut codemap = CodeMap::new();
+            let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+            let ast = parser.parse();
+            assert!(!parser.has_errors(), "{}", source);
+            match ast.last() {
+                Some(Expression { expression, .. }) => format_expression(expression),
+                statement => panic!("unexpected statement: {:?}", statement),
+            }
+        };
+        let prelude = "infixl 6 + = add; infixl 7 * = mul; infixr 5 ++ = append;";
+
+        assert_eq!(
+            parse(&format!("{} a + b * c + d;", prelude)),
+            "(a + (b * c)) + d"
+        );
+        assert_eq!(
+            parse(&format!("{} a ++ b ++ f c;", prelude)),
+            "a ++ (b ++ f c)"
+        );
+        assert_eq!(
+            parse(&format!("{} f (a * b +) (+ c);", prelude)),
+            "f ((a * b) +) (+ c)"
+        );
+        assert_eq!(
+            parse(&format!("{} a + \\x => b + x;", prelude)),
+            "a + (\\x => b + x)"
+        );
+        // Undeclared operators are `infixl 9`.
+        assert_eq!(
+            parse(&format!("{} a + b <> c <> d;", prelude)),
+            "a + ((b <> c) <> d)"
+        );
+    }
+
+    #[test]
+    fn operations_are_applications() {
+        let ast = Parser::new("infixl 6 + = add; a + b;", "<stdin>", &mut CodeMap::new()).parse();
+        match &ast[..] {
+            [Let {
+                name,
+                name_span,
+                fixity: Some(fixity),
+                ..
+            }, Expression {
+                expression: Apply {
+                    lhs, lhs_span, rhs, ..
+                },
+                ..
+            }] => {
+                assert_eq!((name.as_str(), name_span), ("+", &(9..10)));
+                assert_eq!(fixity.to_string(), "infixl 6");
+                assert_eq!(lhs_span, &(18..21));
+                assert!(matches!(&**rhs, Var { name, .. } if name == "b"));
+                match &**lhs {
+                    Apply { lhs, rhs, .. } => {
+                        assert!(matches!(&**lhs, Var { name_span, .. } if name_span == &(20..21)));
+                        assert!(matches!(&**rhs, Var { name, .. } if name == "a"));
+                    }
+                    lhs => panic!("unexpected operation: {:?}", lhs),
+                }
+            }
+            ast => panic!("unexpected statements: {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn right_sections_avoid_capture() {
+        let ast = Parser::new("(+ x) y;", "<stdin>", &mut CodeMap::new()).parse();
+        match &ast[0] {
+            Expression {
+                expression: Apply { lhs, .. },
+                ..
+            } => match &**lhs {
+                Abstraction { name, .. } => assert_eq!(name, "x1"),
+                lhs => panic!("unexpected section: {:?}", lhs),
+            },
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn data_declarations() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("data List = Nil | Cons h t;", "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors());
+
+        match &ast[0] {
+            Data {
+                name,
+                constructors,
+                span,
+                ..
+            } => {
+                assert_eq!((name.as_str(), span), ("List", &(0..27)));
+                let fields: Vec<_> = constructors
+                    .iter()
+                    .map(|constructor| (constructor.name.as_str(), constructor.fields.len()))
+                    .collect();
+                assert_eq!(fields, vec![("Nil", 0), ("Cons", 2)]);
+                assert_eq!(constructors[1].span, 18..26);
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("data List = Nil | ;", "<stdin>", &mut codemap);
+        parser.parse();
+        assert!(parser.has_errors());
+    }
+
+    #[test]
+    fn if_and_match_expressions() {
+        let source = "if c then match xs { Nil => a; Cons h t => h; } else \\x => x;";
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors());
+
+        let Expression { expression, .. } = &ast[0] else {
+            panic!("unexpected statement: {:?}", ast[0]);
+        };
+        let If {
+            then, otherwise, ..
+        } = expression
+        else {
+            panic!("unexpected expression: {:?}", expression);
+        };
+        assert!(matches!(**otherwise, Abstraction { .. }));
+
+        let Match { arms, .. } = &**then else {
+            panic!("unexpected branch: {:?}", then);
+        };
+        let patterns: Vec<_> = arms
+            .iter()
+            .map(|arm| (arm.constructor.as_str(), arm.fields.len(), arm.span()))
+            .collect();
+        assert_eq!(patterns, vec![("Nil", 0, 21..29), ("Cons", 2, 31..44)]);
+        assert!(matches!(&arms[1].body, Var { name, .. } if name == "h"));
+
+        for source in ["if c then a;", "match xs { Nil => a", "match xs { }"] {
+            let mut codemap = CodeMap::new();
+            let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+            parser.parse();
+            assert!(parser.has_errors(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn ambiguous_operators() {
+        for source in [
+            "infix 4 == = eq; a == b == c;",
+            "infixl 6 + = add; infixr 6 ++ = append; a + b ++ c;",
+            "infix 4 == = eq; infixl 6 + = add; (a == b +);",
+            "infix 4 == = eq; infixl 6 + = add; (+ a == b);",
+        ] {
+            let mut codemap = CodeMap::new();
+            let mut parser = Parser::new(source, "<stdin>", &mut codemap);
+            let ast = parser.parse();
+            let codes: Vec<_> = parser
+                .diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.code.as_deref())
+                .collect();
+            assert_eq!(codes, vec![Some("E014")], "{}", source);
+            // Parsing goes on after the error.
+            assert!(matches!(ast.last(), Some(Expression { .. })), "{}", source);
+        }
+    }
+
+    #[test]
+    fn assertions() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("assert p q; assert_eq (f a) b;", "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors());
+
+        match &ast[..] {
+            [Assert {
+                expression_span: first,
+                ..
+            }, AssertEq {
+                lhs: Paren { .. },
+                lhs_span,
+                rhs: Var { .. },
+                rhs_span,
+                span,
+            }] => {
+                assert_eq!(first, &(7..10));
+                assert_eq!(
+                    (lhs_span, rhs_span, span),
+                    (&(22..27), &(28..29), &(12..30))
+                );
+            }
+            ast => panic!("unexpected statements: {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn assert_eq_takes_two_arguments() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("assert_eq f a b;", "<stdin>", &mut codemap);
+        parser.parse();
+        assert!(parser.has_errors());
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("let f′ = λα => α' succ?;", "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(!parser.has_errors());
+
+        match &ast[0] {
+            Let {
+                name,
+                name_span,
+                expression: Abstraction {
+                    name: parameter, ..
+                },
+                ..
+            } => {
+                assert_eq!((name.as_str(), name_span), ("f′", &(4..8)));
+                assert_eq!(parameter, "α");
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn unknown_characters() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("let é = x §§ y;", "<stdin>", &mut codemap);
+        let ast = parser.parse();
+
+        assert_eq!(parser.diagnostics.len(), 1);
+        assert_eq!(parser.diagnostics[0].code.as_deref(), Some("E013"));
+        let span = parser.diagnostics[0].spans[0].span;
+        // Columns count characters from 0, not bytes.
+        assert_eq!(parser.codemap.look_up_span(span).begin.column, 10);
+        assert_eq!(
+            ast[0],
+            Parser::new("let é = x      y;", "<stdin>", &mut CodeMap::new()).parse()[0]
+        );
+    }
+
+    #[test]
+    fn diagnostics_are_collected_in_source_order() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("let a = \\x. x;", "<stdin>", &mut codemap);
+        parser.parse();
+        // Warnings alone are not errors.
+        assert!(!parser.has_errors());
+
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new("let a = \\x. x; let = b; c;", "<stdin>", &mut codemap);
+        let ast = parser.parse();
+        assert!(parser.has_errors());
+        let diagnostics: Vec<_> = parser
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code.as_deref(), diagnostic.spans[0].span))
+            .collect();
+        let span = |start: u64, end: u64| parser.file_span.subspan(start, end);
+        assert_eq!(
+            diagnostics,
+            vec![(Some("W002"), span(10, 11)), (Some("E001"), span(19, 20))]
+        );
+        // Parsing goes on after the error.
+        assert!(matches!(ast.last(), Some(Expression { .. })));
+    }
 }
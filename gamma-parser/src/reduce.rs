@@ -0,0 +1,1021 @@
+//! Normal-order reduction of expressions by capture-avoiding substitution.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Arm, Expression, Span, Type};
+
+/// Number of variables, abstractions and applications that a normal form may
+/// have. Larger ones are given up on, since many functions that work on terms
+/// recurse into them and would overflow the stack.
+pub const SIZE_LIMIT: usize = 10_000;
+
+/// Number of nodes that a term may grow to on the way to its normal form.
+/// It can be larger than the normal form, but a term that keeps growing
+/// would run out of memory.
+pub const GROWTH_LIMIT: usize = 10 * SIZE_LIMIT;
+
+/// Values of the variables that are defined outside of the reduced
+/// expression, e.g. by `let` statements.
+pub trait Bindings {
+    fn binding(&self, name: &str) -> Option<&Expression>;
+}
+
+impl Bindings for HashMap<String, Expression> {
+    fn binding(&self, name: &str) -> Option<&Expression> {
+        self.get(name)
+    }
+}
+
+/// When arguments are reduced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Arguments are substituted as they are, so those that are not used
+    /// are never reduced. Finds the normal form whenever there is one.
+    NormalOrder,
+    /// Arguments are reduced to weak head normal form before they are
+    /// substituted, after the function.
+    CallByValue,
+}
+
+/// Why a [`Reducer`] did not find a normal form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Reduction took more than `limit` steps.
+    Limit,
+    /// The normal form has more than [`SIZE_LIMIT`] nodes, or a term on
+    /// the way to it more than [`GROWTH_LIMIT`].
+    TooLarge,
+}
+
+/// Reduces expressions, in normal order unless another strategy is chosen.
+/// Free variables that name a binding are replaced with the bound
+/// expression when reduction needs them.
+pub struct Reducer<'a> {
+    bindings: &'a dyn Bindings,
+    pub strategy: Strategy,
+    /// Number of beta reductions and unfoldings of bindings performed so far.
+    pub steps: usize,
+    /// Number of beta reductions performed so far.
+    pub beta_steps: usize,
+    /// Number of steps after which reduction gives up.
+    pub limit: usize,
+}
+
+impl<'a> Reducer<'a> {
+    pub fn new(bindings: &'a dyn Bindings, limit: usize) -> Self {
+        Self {
+            bindings,
+            strategy: Strategy::NormalOrder,
+            steps: 0,
+            beta_steps: 0,
+            limit,
+        }
+    }
+
+    /// Reduces `expression` to its normal form. Fails if that takes more
+    /// than `limit` steps, e.g. because there is no normal form, or if the
+    /// normal form is larger than [`SIZE_LIMIT`] or a term on the way to it
+    /// larger than [`GROWTH_LIMIT`].
+    ///
+    /// Like in [`Reducer::whnf`], the parts of the normal form that are
+    /// being built wait on a stack of frames rather than on the call stack.
+    pub fn normalize(&mut self, expression: &Expression) -> Result<Expression, Error> {
+        let mut frames = vec![];
+        let mut size = 0;
+        let mut expression = expression.clone();
+        loop {
+            size += 1;
+            if size > SIZE_LIMIT {
+                return Err(Error::TooLarge);
+            }
+
+            let mut normal = match self.head_normal_form(expression)? {
+                Expression::Abstraction {
+                    name,
+                    name_span,
+                    annotation,
+                    expression: body,
+                    expression_span,
+                } => {
+                    // Binders that shadow a binding are renamed, so that inside
+                    // the body every variable named like a binding refers to it.
+                    let (name, body) = if self.bindings.binding(&name).is_some() {
+                        let avoid = free_variables(&body);
+                        let fresh = fresh_name(&name, |candidate| {
+                            avoid.contains(candidate) || self.bindings.binding(candidate).is_some()
+                        });
+                        let renamed = Expression::Var {
+                            name: fresh.clone(),
+                            name_span: name_span.clone(),
+                        };
+                        (fresh, substitute(&body, &name, &renamed))
+                    } else {
+                        (name, *body)
+                    };
+
+                    frames.push(Build::Body {
+                        name,
+                        name_span,
+                        annotation,
+                        expression_span,
+                    });
+                    expression = body;
+                    continue;
+                }
+                Expression::Apply {
+                    lhs,
+                    lhs_span,
+                    rhs,
+                    rhs_span,
+                } => {
+                    frames.push(Build::Function {
+                        lhs_span,
+                        rhs,
+                        rhs_span,
+                    });
+                    expression = *lhs;
+                    continue;
+                }
+                expression => expression,
+            };
+
+            // `normal` is in normal form: the innermost frame takes it, until
+            // one of them has an argument left to normalize.
+            expression = loop {
+                match frames.pop() {
+                    None => return Ok(normal),
+                    Some(Build::Body {
+                        name,
+                        name_span,
+                        annotation,
+                        expression_span,
+                    }) => {
+                        normal = Expression::Abstraction {
+                            name,
+                            name_span,
+                            annotation,
+                            expression: Box::new(normal),
+                            expression_span,
+                        }
+                    }
+                    Some(Build::Function {
+                        lhs_span,
+                        rhs,
+                        rhs_span,
+                    }) => {
+                        frames.push(Build::Argument {
+                            lhs: Box::new(normal),
+                            lhs_span,
+                            rhs_span,
+                        });
+                        break *rhs;
+                    }
+                    Some(Build::Argument {
+                        lhs,
+                        lhs_span,
+                        rhs_span,
+                    }) => {
+                        normal = Expression::Apply {
+                            lhs,
+                            lhs_span,
+                            rhs: Box::new(normal),
+                            rhs_span,
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    /// Reduces `expression` until it is an abstraction or an application whose
+    /// head is a free variable.
+    ///
+    /// The functions and arguments being reduced wait on a stack of frames
+    /// rather than on the call stack, which terms that diverge in
+    /// call-by-value would overflow before reaching the limit.
+    pub fn whnf(&mut self, expression: &Expression) -> Result<Expression, Error> {
+        self.head_normal_form(expression.clone())
+    }
+
+    fn head_normal_form(&mut self, mut expression: Expression) -> Result<Expression, Error> {
+        let mut frames = vec![];
+        loop {
+            let mut value = match expression {
+                // Types do not matter for reduction: type abstractions and
+                // applications are erased as they are reached.
+                Expression::Paren {
+                    expression: inner, ..
+                }
+                | Expression::TypeAbstraction {
+                    expression: inner, ..
+                }
+                | Expression::TypeApply {
+                    expression: inner, ..
+                } => {
+                    expression = *inner;
+                    continue;
+                }
+                Expression::Var { ref name, .. } => match self.bindings.binding(name) {
+                    Some(value) => {
+                        self.step()?;
+                        expression = value.clone();
+                        continue;
+                    }
+                    None => expression,
+                },
+                Expression::Apply {
+                    lhs,
+                    lhs_span,
+                    rhs,
+                    rhs_span,
+                }
+                | Expression::Dollar {
+                    lhs,
+                    lhs_span,
+                    rhs,
+                    rhs_span,
+                } => {
+                    frames.push(Frame::Function {
+                        lhs_span,
+                        rhs,
+                        rhs_span,
+                    });
+                    expression = *lhs;
+                    continue;
+                }
+                expression => expression,
+            };
+
+            // `value` is in weak head normal form: the innermost frame goes on
+            // with it, until one of them has a redex to reduce.
+            expression = loop {
+                match frames.pop() {
+                    None => return Ok(value),
+                    Some(Frame::Function {
+                        lhs_span,
+                        rhs,
+                        rhs_span,
+                    }) => match value {
+                        Expression::Abstraction {
+                            name, expression, ..
+                        } => match self.strategy {
+                            Strategy::NormalOrder => {
+                                break self.beta(&expression, &name, &rhs)?;
+                            }
+                            Strategy::CallByValue => {
+                                frames.push(Frame::Argument {
+                                    name,
+                                    body: expression,
+                                });
+                                break *rhs;
+                            }
+                        },
+                        function => {
+                            value = Expression::Apply {
+                                lhs: Box::new(function),
+                                lhs_span,
+                                rhs,
+                                rhs_span,
+                            }
+                        }
+                    },
+                    Some(Frame::Argument { name, body }) => {
+                        break self.beta(&body, &name, &value)?;
+                    }
+                }
+            };
+        }
+    }
+
+    /// Substitutes `argument` for `name` in `body`, unless the result would
+    /// be larger than [`GROWTH_LIMIT`].
+    fn beta(
+        &mut self,
+        body: &Expression,
+        name: &str,
+        argument: &Expression,
+    ) -> Result<Expression, Error> {
+        self.step()?;
+        self.beta_steps += 1;
+
+        let argument_size = size(argument);
+        let mut pending = vec![body];
+        let mut result_size = 0;
+        while let Some(expression) = pending.pop() {
+            match expression {
+                Expression::Var { name: var, .. } if var == name => result_size += argument_size,
+                // The parameter is shadowed, so the abstraction stays as it is.
+                Expression::Abstraction { name: binder, .. } if binder == name => {
+                    result_size += size(expression)
+                }
+                Expression::Var { .. } => result_size += 1,
+                Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+                    result_size += 1;
+                    pending.extend([&**lhs, &**rhs]);
+                }
+                Expression::Abstraction { expression, .. }
+                | Expression::Paren { expression, .. }
+                | Expression::TypeAbstraction { expression, .. }
+                | Expression::TypeApply { expression, .. } => {
+                    result_size += 1;
+                    pending.push(expression);
+                }
+                Expression::If { .. } | Expression::Match { .. } => {
+                    unreachable!("`if` and `match` are desugared before reducing")
+                }
+            }
+            if result_size > GROWTH_LIMIT {
+                return Err(Error::TooLarge);
+            }
+        }
+
+        Ok(substitute(body, name, argument))
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        if self.steps >= self.limit {
+            return Err(Error::Limit);
+        }
+
+        self.steps += 1;
+        Ok(())
+    }
+}
+
+/// The expressions that `expression` reduces to by one beta reduction, one
+/// for each redex, from the leftmost outermost one: the first is the step
+/// that normal order takes. The parentheses and types around a reduced part
+/// are dropped, and so are those around a substituted argument that becomes
+/// the whole part.
+pub fn reducts(expression: &Expression) -> Vec<Expression> {
+    match strip(expression) {
+        Expression::Abstraction {
+            name,
+            name_span,
+            annotation,
+            expression,
+            expression_span,
+        } => reducts(expression)
+            .into_iter()
+            .map(|expression| Expression::Abstraction {
+                name: name.clone(),
+                name_span: name_span.clone(),
+                annotation: annotation.clone(),
+                expression: Box::new(expression),
+                expression_span: expression_span.clone(),
+            })
+            .collect(),
+        Expression::Apply {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        }
+        | Expression::Dollar {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => {
+            let apply = |lhs: Expression, rhs: Expression| Expression::Apply {
+                lhs: Box::new(lhs),
+                lhs_span: lhs_span.clone(),
+                rhs: Box::new(rhs),
+                rhs_span: rhs_span.clone(),
+            };
+
+            let mut contracted = vec![];
+            if let Expression::Abstraction {
+                name, expression, ..
+            } = strip(lhs)
+            {
+                let mut contractum = substitute(expression, name, rhs);
+                while let Expression::Paren { expression, .. } = contractum {
+                    contractum = *expression;
+                }
+                contracted.push(contractum);
+            }
+
+            contracted.extend(reducts(lhs).into_iter().map(|lhs| apply(lhs, *rhs.clone())));
+            contracted.extend(reducts(rhs).into_iter().map(|rhs| apply(*lhs.clone(), rhs)));
+            contracted
+        }
+        _ => vec![],
+    }
+}
+
+/// What [`Reducer::normalize`] does with the next normal form.
+enum Build {
+    /// It is the body of an abstraction with this parameter.
+    Body {
+        name: String,
+        name_span: Span,
+        annotation: Option<Type>,
+        expression_span: Span,
+    },
+    /// It is the function of an application with this argument, which is
+    /// normalized next.
+    Function {
+        lhs_span: Span,
+        rhs: Box<Expression>,
+        rhs_span: Span,
+    },
+    /// It is the argument of an application with this function.
+    Argument {
+        lhs: Box<Expression>,
+        lhs_span: Span,
+        rhs_span: Span,
+    },
+}
+
+/// What [`Reducer::whnf`] does with the next weak head normal form.
+enum Frame {
+    /// It is the function of an application with this argument.
+    Function {
+        lhs_span: Span,
+        rhs: Box<Expression>,
+        rhs_span: Span,
+    },
+    /// It is the argument of the abstraction with this parameter and body,
+    /// in call-by-value.
+    Argument { name: String, body: Box<Expression> },
+}
+
+/// Number of variables, abstractions and applications in `expression`,
+/// counting parentheses and types as well.
+pub fn size(expression: &Expression) -> usize {
+    let mut pending = vec![expression];
+    let mut size = 0;
+    while let Some(expression) = pending.pop() {
+        size += 1;
+        match expression {
+            Expression::Var { .. } => {}
+            Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+                pending.extend([&**lhs, &**rhs])
+            }
+            Expression::Abstraction { expression, .. }
+            | Expression::Paren { expression, .. }
+            | Expression::TypeAbstraction { expression, .. }
+            | Expression::TypeApply { expression, .. } => pending.push(expression),
+            Expression::If {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => pending.extend([&**condition, &**then, &**otherwise]),
+            Expression::Match {
+                scrutinee, arms, ..
+            } => {
+                pending.push(scrutinee);
+                pending.extend(arms.iter().map(|arm| &arm.body));
+            }
+        }
+    }
+
+    size
+}
+
+pub fn free_variables(expression: &Expression) -> HashSet<String> {
+    let mut variables = HashSet::new();
+    collect_free_variables(expression, &mut vec![], &mut variables);
+    variables
+}
+
+fn collect_free_variables<'e>(
+    expression: &'e Expression,
+    bound: &mut Vec<&'e str>,
+    variables: &mut HashSet<String>,
+) {
+    match expression {
+        Expression::Var { name, .. } => {
+            if !bound.contains(&name.as_str()) {
+                variables.insert(name.clone());
+            }
+        }
+        Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+            collect_free_variables(lhs, bound, variables);
+            collect_free_variables(rhs, bound, variables);
+        }
+        Expression::Paren { expression, .. }
+        | Expression::TypeAbstraction { expression, .. }
+        | Expression::TypeApply { expression, .. } => {
+            collect_free_variables(expression, bound, variables)
+        }
+        Expression::Abstraction {
+            name, expression, ..
+        } => {
+            bound.push(name);
+            collect_free_variables(expression, bound, variables);
+            bound.pop();
+        }
+        Expression::If {
+            condition,
+            then,
+            otherwise,
+            ..
+        } => {
+            collect_free_variables(condition, bound, variables);
+            collect_free_variables(then, bound, variables);
+            collect_free_variables(otherwise, bound, variables);
+        }
+        Expression::Match {
+            scrutinee, arms, ..
+        } => {
+            collect_free_variables(scrutinee, bound, variables);
+            for arm in arms {
+                let depth = bound.len();
+                bound.extend(arm.fields.iter().map(|(field, _)| field.as_str()));
+                collect_free_variables(&arm.body, bound, variables);
+                bound.truncate(depth);
+            }
+        }
+    }
+}
+
+/// Replaces free occurrences of `name` in `expression` with `value`, renaming
+/// binders that would capture free variables of `value`.
+pub fn substitute(expression: &Expression, name: &str, value: &Expression) -> Expression {
+    substitute_free(expression, name, value, &free_variables(value))
+}
+
+fn substitute_free(
+    expression: &Expression,
+    name: &str,
+    value: &Expression,
+    value_variables: &HashSet<String>,
+) -> Expression {
+    match expression {
+        Expression::Var { name: var, .. } if var == name => value.clone(),
+        Expression::Var { .. } => expression.clone(),
+        Expression::Apply {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => Expression::Apply {
+            lhs: Box::new(substitute_free(lhs, name, value, value_variables)),
+            lhs_span: lhs_span.clone(),
+            rhs: Box::new(substitute_free(rhs, name, value, value_variables)),
+            rhs_span: rhs_span.clone(),
+        },
+        Expression::Dollar {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => Expression::Dollar {
+            lhs: Box::new(substitute_free(lhs, name, value, value_variables)),
+            lhs_span: lhs_span.clone(),
+            rhs: Box::new(substitute_free(rhs, name, value, value_variables)),
+            rhs_span: rhs_span.clone(),
+        },
+        Expression::Paren {
+            expression,
+            expression_span,
+        } => Expression::Paren {
+            expression: Box::new(substitute_free(expression, name, value, value_variables)),
+            expression_span: expression_span.clone(),
+        },
+        Expression::TypeAbstraction {
+            name: binder,
+            name_span,
+            expression,
+            expression_span,
+        } => Expression::TypeAbstraction {
+            name: binder.clone(),
+            name_span: name_span.clone(),
+            expression: Box::new(substitute_free(expression, name, value, value_variables)),
+            expression_span: expression_span.clone(),
+        },
+        Expression::TypeApply {
+            expression,
+            expression_span,
+            argument,
+        } => Expression::TypeApply {
+            expression: Box::new(substitute_free(expression, name, value, value_variables)),
+            expression_span: expression_span.clone(),
+            argument: argument.clone(),
+        },
+        Expression::Abstraction {
+            name: binder,
+            name_span,
+            annotation,
+            expression: body,
+            expression_span,
+        } => {
+            if binder == name {
+                return expression.clone();
+            }
+
+            let body_variables = free_variables(body);
+            if !body_variables.contains(name) {
+                return expression.clone();
+            }
+
+            if !value_variables.contains(binder) {
+                return Expression::Abstraction {
+                    name: binder.clone(),
+                    name_span: name_span.clone(),
+                    annotation: annotation.clone(),
+                    expression: Box::new(substitute_free(body, name, value, value_variables)),
+                    expression_span: expression_span.clone(),
+                };
+            }
+
+            let fresh = fresh_name(binder, |candidate| {
+                candidate == name
+                    || value_variables.contains(candidate)
+                    || body_variables.contains(candidate)
+            });
+            let renamed = substitute(
+                body,
+                binder,
+                &Expression::Var {
+                    name: fresh.clone(),
+                    name_span: name_span.clone(),
+                },
+            );
+
+            Expression::Abstraction {
+                name: fresh,
+                name_span: name_span.clone(),
+                annotation: annotation.clone(),
+                expression: Box::new(substitute_free(&renamed, name, value, value_variables)),
+                expression_span: expression_span.clone(),
+            }
+        }
+        Expression::If {
+            condition,
+            condition_span,
+            then,
+            then_span,
+            otherwise,
+            otherwise_span,
+        } => Expression::If {
+            condition: Box::new(substitute_free(condition, name, value, value_variables)),
+            condition_span: condition_span.clone(),
+            then: Box::new(substitute_free(then, name, value, value_variables)),
+            then_span: then_span.clone(),
+            otherwise: Box::new(substitute_free(otherwise, name, value, value_variables)),
+            otherwise_span: otherwise_span.clone(),
+        },
+        Expression::Match {
+            keyword_span,
+            scrutinee,
+            scrutinee_span,
+            arms,
+        } => Expression::Match {
+            keyword_span: keyword_span.clone(),
+            scrutinee: Box::new(substitute_free(scrutinee, name, value, value_variables)),
+            scrutinee_span: scrutinee_span.clone(),
+            arms: arms
+                .iter()
+                .map(|arm| substitute_arm(arm, name, value, value_variables))
+                .collect(),
+        },
+    }
+}
+
+/// [`substitute_free`] in the body of `arm`, whose fields are binders like
+/// the parameter of an abstraction.
+fn substitute_arm(
+    arm: &Arm,
+    name: &str,
+    value: &Expression,
+    value_variables: &HashSet<String>,
+) -> Arm {
+    let body_variables = free_variables(&arm.body);
+    if arm.fields.iter().any(|(field, _)| field == name) || !body_variables.contains(name) {
+        return arm.clone();
+    }
+
+    let mut fields = vec![];
+    let mut body = arm.body.clone();
+    for (field, field_span) in &arm.fields {
+        if !value_variables.contains(field) {
+            fields.push((field.clone(), field_span.clone()));
+            continue;
+        }
+
+        let fresh = fresh_name(field, |candidate| {
+            candidate == name
+                || value_variables.contains(candidate)
+                || body_variables.contains(candidate)
+                || arm.fields.iter().any(|(field, _)| field == candidate)
+                || fields.iter().any(|(field, _)| field == candidate)
+        });
+        body = substitute(
+            &body,
+            field,
+            &Expression::Var {
+                name: fresh.clone(),
+                name_span: field_span.clone(),
+            },
+        );
+        fields.push((fresh, field_span.clone()));
+    }
+
+    Arm {
+        constructor: arm.constructor.clone(),
+        constructor_span: arm.constructor_span.clone(),
+        fields,
+        body: substitute_free(&body, name, value, value_variables),
+        body_span: arm.body_span.clone(),
+    }
+}
+
+/// Whether two expressions are equal up to the names of bound variables.
+/// Parentheses and types are ignored.
+pub fn alpha_equivalent(a: &Expression, b: &Expression) -> bool {
+    alpha_equivalent_in(a, b, &mut vec![])
+}
+
+fn alpha_equivalent_in<'e>(
+    a: &'e Expression,
+    b: &'e Expression,
+    binders: &mut Vec<(&'e str, &'e str)>,
+) -> bool {
+    match (strip(a), strip(b)) {
+        (Expression::Var { name: a, .. }, Expression::Var { name: b, .. }) => {
+            let a_binder = binders.iter().rposition(|(binder, _)| binder == a);
+            let b_binder = binders.iter().rposition(|(_, binder)| binder == b);
+            match (a_binder, b_binder) {
+                (None, None) => a == b,
+                (a_binder, b_binder) => a_binder == b_binder,
+            }
+        }
+        (
+            Expression::Apply {
+                lhs: a_lhs,
+                rhs: a_rhs,
+                ..
+            }
+            | Expression::Dollar {
+                lhs: a_lhs,
+                rhs: a_rhs,
+                ..
+            },
+            Expression::Apply {
+                lhs: b_lhs,
+                rhs: b_rhs,
+                ..
+            }
+            | Expression::Dollar {
+                lhs: b_lhs,
+                rhs: b_rhs,
+                ..
+            },
+        ) => {
+            alpha_equivalent_in(a_lhs, b_lhs, binders) && alpha_equivalent_in(a_rhs, b_rhs, binders)
+        }
+        (
+            Expression::Abstraction {
+                name: a_name,
+                expression: a_body,
+                ..
+            },
+            Expression::Abstraction {
+                name: b_name,
+                expression: b_body,
+                ..
+            },
+        ) => {
+            binders.push((a_name, b_name));
+            let equivalent = alpha_equivalent_in(a_body, b_body, binders);
+            binders.pop();
+            equivalent
+        }
+        _ => false,
+    }
+}
+
+fn strip(mut expression: &Expression) -> &Expression {
+    while let Expression::Paren {
+        expression: inner, ..
+    }
+    | Expression::TypeAbstraction {
+        expression: inner, ..
+    }
+    | Expression::TypeApply {
+        expression: inner, ..
+    } = expression
+    {
+        expression = inner;
+    }
+
+    expression
+}
+
+/// Makes a variant of `name` by replacing its numeric suffix, e.g. `x1`, `x2`.
+pub fn fresh_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    // Primes end a name, so the number goes before them: `f′` becomes `f1′`.
+    let stem = name.trim_end_matches(['\'', '′', '″', '‴', '?']);
+    let suffix = &name[stem.len()..];
+    let base = match stem.trim_end_matches(|c: char| c.is_ascii_digit()) {
+        "" => stem,
+        base => base,
+    };
+
+    (1..)
+        .map(|n| format!("{}{}{}", base, n, suffix))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod reduce_tests {
+    use std::collections::HashMap;
+
+    use super::{alpha_equivalent, reducts, Error, Reducer, Strategy, SIZE_LIMIT};
+    use crate::ast::Statement;
+    use crate::format::format_expression;
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    /// Normalizes the last statement of `source` with the lets before it.
+    fn normalize(source: &str) -> Option<String> {
+        let mut bindings = HashMap::new();
+        let mut result = None;
+        for statement in Parser::new(source, "<stdin>", &mut CodeMap::new()).parse() {
+            match statement {
+                Statement::Let {
+                    name, expression, ..
+                } => {
+                    bindings.insert(name, expression);
+                }
+                Statement::Expression { expression, .. } => {
+                    result = Reducer::new(&bindings, 1000)
+                        .normalize(&expression)
+                        .ok()
+                        .map(|expression| format_expression(&expression));
+                }
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn fresh_names_keep_primes() {
+        let taken = |name: &str| ["x1", "f1′"].contains(&name);
+        assert_eq!(super::fresh_name("x", taken), "x2");
+        assert_eq!(super::fresh_name("f′", taken), "f2′");
+        assert_eq!(super::fresh_name("p3?", taken), "p1?");
+    }
+
+    #[test]
+    fn booleans() {
+        let prelude = "let true = \\x => \\y => x; let false = \\x => \\y => y;
+                       let not = \\b => b false true; let and = \\a => \\b => a b a;";
+        assert_eq!(
+            normalize(&format!("{} not true;", prelude)).unwrap(),
+            "\\x => \\y => y"
+        );
+        assert_eq!(
+            normalize(&format!("{} and true true;", prelude)).unwrap(),
+            "\\x => \\y => x"
+        );
+    }
+
+    #[test]
+    fn avoids_capture() {
+        assert_eq!(normalize("(\\x => \\y => x) y;").unwrap(), "\\y1 => y");
+    }
+
+    #[test]
+    fn binders_shadow_bindings() {
+        assert_eq!(
+            normalize("let t = \\x => \\y => x; let k = \\a => t; \\t => k t;").unwrap(),
+            "\\t1 => \\x => \\y => x"
+        );
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        let expression = |source: &str| {
+            let mut codemap = CodeMap::new();
+            let expression = Parser::new(source, "<stdin>", &mut codemap)
+                .parse_single_expression()
+                .unwrap();
+            expression
+        };
+        let equivalent = |a: &str, b: &str| alpha_equivalent(&expression(a), &expression(b));
+
+        assert!(equivalent("\\x => \\y => x", "\\a => (\\b => a)"));
+        assert!(equivalent("\\x => f x", "\\y => f y"));
+        assert!(!equivalent("\\x => \\y => x", "\\x => \\y => y"));
+        assert!(!equivalent("\\x => f x", "\\f => f f"));
+        assert!(!equivalent("\\x => x", "x"));
+    }
+
+    #[test]
+    fn gives_up_without_normal_form() {
+        assert_eq!(normalize("(\\x => x x) (\\x => x x);"), None);
+    }
+
+    #[test]
+    fn one_step_reducts() {
+        let mut codemap = CodeMap::new();
+        let expression = Parser::new(
+            "\\z => (\\x => x x) ((\\y => y) z)",
+            "<stdin>",
+            &mut codemap,
+        )
+        .parse_single_expression()
+        .unwrap();
+
+        let reducts: Vec<_> = reducts(&expression).iter().map(format_expression).collect();
+        assert_eq!(
+            reducts,
+            [
+                "\\z => ((\\y => y) z) ((\\y => y) z)",
+                "\\z => (\\x => x x) z"
+            ]
+        );
+    }
+
+    #[test]
+    fn call_by_value() {
+        let mut codemap = CodeMap::new();
+        let expression = Parser::new(
+            "(\\x => \\y => y) ((\\x => x x) (\\x => x x))",
+            "<stdin>",
+            &mut codemap,
+        )
+        .parse_single_expression()
+        .unwrap();
+
+        let bindings = HashMap::new();
+        assert!(Reducer::new(&bindings, 1000).normalize(&expression).is_ok());
+        let mut reducer = Reducer::new(&bindings, 1000);
+        reducer.strategy = Strategy::CallByValue;
+        assert_eq!(reducer.normalize(&expression), Err(Error::Limit));
+    }
+
+    #[test]
+    fn call_by_value_gives_up_without_deep_recursion() {
+        let mut codemap = CodeMap::new();
+        let expression = Parser::new("(\\x => f (x x)) (\\x => f (x x))", "<stdin>", &mut codemap)
+            .parse_single_expression()
+            .unwrap();
+
+        let bindings = HashMap::from([(
+            "f".to_owned(),
+            Parser::new("\\r => \\n => n", "<stdin>", &mut codemap)
+                .parse_single_expression()
+                .unwrap(),
+        )]);
+        let mut reducer = Reducer::new(&bindings, 100_000);
+        reducer.strategy = Strategy::CallByValue;
+        assert_eq!(reducer.whnf(&expression), Err(Error::Limit));
+        assert_eq!(reducer.steps, 100_000);
+    }
+
+    #[test]
+    fn gives_up_on_large_terms() {
+        let mult = "let mult = \\m => \\n => \\f => m (n f);
+                    let ten = \\f => \\x => f (f (f (f (f (f (f (f (f (f x)))))))));
+                    let hundred = mult ten ten;";
+        let mut codemap = CodeMap::new();
+        let mut bindings = HashMap::new();
+        let mut parser = Parser::new(mult, "<stdin>", &mut codemap);
+        for statement in parser.parse() {
+            if let Statement::Let {
+                name, expression, ..
+            } = statement
+            {
+                bindings.insert(name, expression);
+            }
+        }
+        drop(parser);
+        let expression = |source: &str| {
+            Parser::new(source, "<stdin>", &mut CodeMap::new())
+                .parse_single_expression()
+                .unwrap()
+        };
+
+        // The normal form, `\f => \x => f (f (... x))`, is deeper than
+        // the stack allows to recurse into.
+        let mut reducer = Reducer::new(&bindings, 100_000);
+        assert_eq!(
+            reducer.normalize(&expression("mult hundred hundred")),
+            Err(Error::TooLarge)
+        );
+        assert!(reducer.steps < 100_000);
+        let normal = Reducer::new(&bindings, 100_000)
+            .normalize(&expression("mult ten hundred"))
+            .unwrap();
+        assert!(super::size(&normal) < SIZE_LIMIT);
+
+        // Every step copies a bigger argument.
+        let mut reducer = Reducer::new(&bindings, 100_000);
+        assert_eq!(
+            reducer.normalize(&expression(
+                "(\\w => w ((\\z => w) w)) (\\w => w ((\\z => w) w))"
+            )),
+            Err(Error::TooLarge)
+        );
+        assert!(reducer.steps < 100_000);
+    }
+}
@@ -0,0 +1,354 @@
+//! Renaming of `let` names and lambda parameters.
+
+use std::fmt;
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Expression, Span, Statement, AST};
+use crate::lexer::{is_operator, lex, RawToken};
+use crate::resolve::{resolve, Resolution, SymbolKind};
+
+/// Replacement of the text at `span`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenameError {
+    /// There is no variable or binder at the position.
+    NoSymbol,
+    /// The new name is not an identifier, or not an operator for operators.
+    InvalidName(String),
+    /// After the rename the variable at `span` would refer to another binder.
+    Capture { name: String, span: Span },
+    /// A top-level `let` named like the new name exists already.
+    Redefinition { name: String, span: Span },
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::NoSymbol => write!(f, "no variable to rename here"),
+            RenameError::InvalidName(name) => write!(f, "`{}` is not a valid name", name),
+            RenameError::Capture { name, .. } => {
+                write!(f, "renaming would capture the variable `{}`", name)
+            }
+            RenameError::Redefinition { name, .. } => {
+                write!(f, "variable `{}` is already defined", name)
+            }
+        }
+    }
+}
+
+impl RenameError {
+    pub fn diagnostic(&self, file_span: codemap::Span) -> Diagnostic {
+        let spans = match self {
+            RenameError::Capture { span, .. } => vec![SpanLabel {
+                span: file_span.subspan(span.start as u64, span.end as u64),
+                style: SpanStyle::Primary,
+                label: Some("would refer to another binder after renaming".to_owned()),
+            }],
+            RenameError::Redefinition { span, .. } => vec![SpanLabel {
+                span: file_span.subspan(span.start as u64, span.end as u64),
+                style: SpanStyle::Primary,
+                label: Some("defined here".to_owned()),
+            }],
+            _ => vec![],
+        };
+
+        Diagnostic {
+            level: Level::Error,
+            message: self.to_string(),
+            code: None,
+            spans,
+        }
+    }
+}
+
+/// Computes the edits that rename the binder defined or referenced at the byte
+/// `offset` to `new_name`, and every variable that refers to it.
+///
+/// The rename is rejected if any variable of the program would then refer to
+/// a different binder than before.
+pub fn rename(ast: &AST, offset: usize, new_name: &str) -> Result<Vec<Edit>, RenameError> {
+    let before = resolve(ast);
+    let symbol = before
+        .symbol_at(offset)
+        // The parameter of a section like `(+ 1)` is not in the source.
+        .filter(|symbol| !symbol.definition.is_empty())
+        .ok_or(RenameError::NoSymbol)?;
+
+    // Operators are renamed to operators, other names to identifiers.
+    let raw = match is_operator(&symbol.name) {
+        true => RawToken::Operator,
+        false => RawToken::Identifier,
+    };
+    let mut tokens = lex(new_name);
+    if !matches!(
+        (tokens.next(), tokens.next()),
+        (Some(token), None) if token.raw == raw
+    ) {
+        return Err(RenameError::InvalidName(new_name.to_owned()));
+    }
+
+    if symbol.kind == SymbolKind::Let {
+        if let Some(other) = before.symbols.iter().find(|other| {
+            other.kind == SymbolKind::Let
+                && other.name == new_name
+                && other.definition != symbol.definition
+        }) {
+            return Err(RenameError::Redefinition {
+                name: new_name.to_owned(),
+                span: other.definition.clone(),
+            });
+        }
+    }
+
+    let mut spans = vec![symbol.definition.clone()];
+    spans.extend(symbol.references.iter().cloned());
+
+    let mut renamed = ast.clone();
+    for statement in &mut renamed {
+        rename_statement(statement, &spans, new_name);
+    }
+
+    check_bindings(&before, &resolve(&renamed))?;
+
+    Ok(spans
+        .into_iter()
+        .map(|span| Edit {
+            span,
+            text: new_name.to_owned(),
+        })
+        .collect())
+}
+
+/// Applies non-overlapping edits to `source`.
+pub fn apply(source: &str, edits: &[Edit]) -> String {
+    let mut edits: Vec<_> = edits.iter().collect();
+    edits.sort_by_key(|edit| edit.span.start);
+
+    let mut result = String::new();
+    let mut end = 0;
+    for edit in edits {
+        result.push_str(&source[end..edit.span.start]);
+        result.push_str(&edit.text);
+        end = edit.span.end;
+    }
+
+    result.push_str(&source[end..]);
+    result
+}
+
+/// Reports the first variable that is bound differently in `after`. Spans are
+/// the same in both resolutions because renaming the AST does not move them.
+fn check_bindings(before: &Resolution, after: &Resolution) -> Result<(), RenameError> {
+    for (old, new) in before.symbols.iter().zip(&after.symbols) {
+        if let Some(span) = old
+            .references
+            .iter()
+            .find(|span| !new.references.contains(span))
+            .or_else(|| {
+                new.references
+                    .iter()
+                    .find(|span| !old.references.contains(span))
+            })
+        {
+            return Err(RenameError::Capture {
+                name: new.name.clone(),
+                span: span.clone(),
+            });
+        }
+    }
+
+    if let Some((name, span)) = after
+        .unresolved
+        .iter()
+        .find(|variable| !before.unresolved.contains(variable))
+    {
+        return Err(RenameError::Capture {
+            name: name.clone(),
+            span: span.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+fn rename_statement(statement: &mut Statement, spans: &[Span], new_name: &str) {
+    match statement {
+        Statement::Expression { expression, .. } | Statement::Assert { expression, .. } => {
+            rename_expression(expression, spans, new_name)
+        }
+        Statement::AssertEq { lhs, rhs, .. } => {
+            rename_expression(lhs, spans, new_name);
+            rename_expression(rhs, spans, new_name);
+        }
+        Statement::Let {
+            name,
+            name_span,
+            expression,
+            ..
+        } => {
+            if spans.contains(name_span) {
+                *name = new_name.to_owned();
+            }
+
+            rename_expression(expression, spans, new_name);
+        }
+        Statement::Syntax {
+            pattern, template, ..
+        } => {
+            for (name, span) in pattern {
+                if spans.contains(span) {
+                    *name = new_name.to_owned();
+                }
+            }
+
+            rename_expression(template, spans, new_name);
+        }
+        Statement::Data { constructors, .. } => {
+            for constructor in constructors {
+                if spans.contains(&constructor.name_span) {
+                    constructor.name = new_name.to_owned();
+                }
+            }
+        }
+    }
+}
+
+fn rename_expression(expression: &mut Expression, spans: &[Span], new_name: &str) {
+    match expression {
+        Expression::Var { name, name_span } => {
+            if spans.contains(name_span) {
+                *name = new_name.to_owned();
+            }
+        }
+        Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+            rename_expression(lhs, spans, new_name);
+            rename_expression(rhs, spans, new_name);
+        }
+        Expression::Paren { expression, .. }
+        | Expression::TypeAbstraction { expression, .. }
+        | Expression::TypeApply { expression, .. } => {
+            rename_expression(expression, spans, new_name)
+        }
+        Expression::Abstraction {
+            name,
+            name_span,
+            expression,
+            ..
+        } => {
+            if spans.contains(name_span) {
+                *name = new_name.to_owned();
+            }
+
+            rename_expression(expression, spans, new_name);
+        }
+        Expression::If {
+            condition,
+            then,
+            otherwise,
+            ..
+        } => {
+            rename_expression(condition, spans, new_name);
+            rename_expression(then, spans, new_name);
+            rename_expression(otherwise, spans, new_name);
+        }
+        Expression::Match {
+            scrutinee, arms, ..
+        } => {
+            rename_expression(scrutinee, spans, new_name);
+            for arm in arms {
+                if spans.contains(&arm.constructor_span) {
+                    arm.constructor = new_name.to_owned();
+                }
+                for (field, field_span) in &mut arm.fields {
+                    if spans.contains(field_span) {
+                        *field = new_name.to_owned();
+                    }
+                }
+
+                rename_expression(&mut arm.body, spans, new_name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rename_tests {
+    use super::{apply, rename, RenameError};
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    fn rename_at(source: &str, offset: usize, new_name: &str) -> Result<String, RenameError> {
+        let ast = Parser::new(source, "<stdin>", &mut CodeMap::new()).parse();
+        rename(&ast, offset, new_name).map(|edits| apply(source, &edits))
+    }
+
+    #[test]
+    fn renames_only_bound_occurrences() {
+        assert_eq!(
+            rename_at("let f = \\x => x (\\x => x); f x;", 9, "y").unwrap(),
+            "let f = \\y => y (\\x => x); f x;"
+        );
+        assert_eq!(
+            rename_at("let f = \\x => x; f f;", 18, "id").unwrap(),
+            "let id = \\x => x; id id;"
+        );
+    }
+
+    #[test]
+    fn rejects_capture() {
+        assert_eq!(
+            rename_at("\\x => \\y => x;", 1, "y"),
+            Err(RenameError::Capture {
+                name: "y".to_owned(),
+                span: 12..13
+            })
+        );
+        assert_eq!(
+            rename_at("let f = \\a => a; \\g => f g;", 4, "g"),
+            Err(RenameError::Capture {
+                name: "g".to_owned(),
+                span: 23..24
+            })
+        );
+        assert!(matches!(
+            rename_at("\\x => y x;", 1, "y"),
+            Err(RenameError::Capture { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_redefinition_and_bad_names() {
+        assert!(matches!(
+            rename_at("let a = \\x => x; let b = a;", 4, "b"),
+            Err(RenameError::Redefinition { .. })
+        ));
+        assert_eq!(
+            rename_at("let a = \\x => x;", 4, "let"),
+            Err(RenameError::InvalidName("let".to_owned()))
+        );
+        assert_eq!(
+            rename_at("let a = \\x => x;", 7, "b"),
+            Err(RenameError::NoSymbol)
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let source = "infixl 6 + = add; a + (+ b);";
+        assert_eq!(
+            rename_at(source, 20, "<+>").unwrap(),
+            "infixl 6 <+> = add; a <+> (<+> b);"
+        );
+        assert_eq!(
+            rename_at(source, 9, "plus"),
+            Err(RenameError::InvalidName("plus".to_owned()))
+        );
+        assert_eq!(rename_at(source, 22, "c"), Err(RenameError::NoSymbol));
+    }
+}
@@ -0,0 +1,346 @@
+//! Name resolution: links every variable to the `let` or lambda parameter
+//! that binds it.
+
+use std::collections::HashMap;
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Arm, Expression, Span, Statement, AST};
+use crate::data::WILDCARD;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Let,
+    Parameter,
+}
+
+/// A binder and the variables that refer to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Span of the name in the binder.
+    pub definition: Span,
+    /// Span of the whole binding: the `let` statement or the abstraction.
+    pub scope: Span,
+    pub references: Vec<Span>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Resolution {
+    pub symbols: Vec<Symbol>,
+    /// Variables that are not bound by any `let` or parameter.
+    pub unresolved: Vec<(String, Span)>,
+}
+
+impl Resolution {
+    /// Symbol defined or referenced at the byte `offset`.
+    pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+        self.symbols
+            .iter()
+            .find(|symbol| contains(&symbol.definition) || symbol.references.iter().any(contains))
+    }
+
+    /// Symbol whose binder name is at `definition`.
+    pub fn symbol(&self, definition: &Span) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.definition == *definition)
+    }
+}
+
+/// Resolves every variable of the program. A `let` is visible in the
+/// statements after it, a parameter in the body of its abstraction.
+pub fn resolve(ast: &AST) -> Resolution {
+    let mut resolver = Resolver {
+        resolution: Resolution::default(),
+        globals: HashMap::new(),
+        scope: vec![],
+    };
+
+    for statement in ast {
+        match statement {
+            Statement::Expression { expression, .. } | Statement::Assert { expression, .. } => {
+                resolver.expression(expression)
+            }
+            Statement::AssertEq { lhs, rhs, .. } => {
+                resolver.expression(lhs);
+                resolver.expression(rhs);
+            }
+            Statement::Syntax {
+                keywords,
+                pattern,
+                template,
+                span,
+                ..
+            } => {
+                for (parameter, definition) in pattern {
+                    if keywords.iter().all(|(keyword, _)| keyword != parameter) {
+                        resolver.scope.push(resolver.resolution.symbols.len());
+                        resolver.resolution.symbols.push(Symbol {
+                            name: parameter.clone(),
+                            kind: SymbolKind::Parameter,
+                            definition: definition.clone(),
+                            scope: span.clone(),
+                            references: vec![],
+                        });
+                    }
+                }
+
+                resolver.expression(template);
+                resolver.scope.clear();
+            }
+            // Constructors are defined like `let`s.
+            Statement::Data { constructors, .. } => {
+                for constructor in constructors {
+                    resolver.define(&constructor.name, &constructor.name_span, &constructor.span);
+                }
+            }
+            Statement::Let {
+                name,
+                name_span,
+                expression,
+                span,
+                ..
+            } => {
+                resolver.expression(expression);
+                resolver.define(name, name_span, span);
+            }
+        }
+    }
+
+    resolver.resolution
+}
+
+struct Resolver {
+    resolution: Resolution,
+    /// Index of the latest symbol defined by a `let` with the given name.
+    globals: HashMap<String, usize>,
+    /// Indices of the parameters in scope, innermost last.
+    scope: Vec<usize>,
+}
+
+impl Resolver {
+    /// Adds a `let` symbol, visible in the statements after `scope`.
+    fn define(&mut self, name: &str, definition: &Span, scope: &Span) {
+        self.globals
+            .insert(name.to_owned(), self.resolution.symbols.len());
+        self.resolution.symbols.push(Symbol {
+            name: name.to_owned(),
+            kind: SymbolKind::Let,
+            definition: definition.clone(),
+            scope: scope.clone(),
+            references: vec![],
+        });
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Var { name, name_span } => {
+                let symbols = &self.resolution.symbols;
+                let symbol = self
+                    .scope
+                    .iter()
+                    .rev()
+                    .find(|&&index| symbols[index].name == *name)
+                    .or_else(|| self.globals.get(name));
+
+                match symbol {
+                    Some(&index) => self.resolution.symbols[index]
+                        .references
+                        .push(name_span.clone()),
+                    None => self
+                        .resolution
+                        .unresolved
+                        .push((name.clone(), name_span.clone())),
+                }
+            }
+            Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expression::Paren { expression, .. }
+            | Expression::TypeAbstraction { expression, .. }
+            | Expression::TypeApply { expression, .. } => self.expression(expression),
+            Expression::Abstraction {
+                name,
+                name_span,
+                expression,
+                expression_span,
+                ..
+            } => {
+                self.scope.push(self.resolution.symbols.len());
+                self.resolution.symbols.push(Symbol {
+                    name: name.clone(),
+                    kind: SymbolKind::Parameter,
+                    definition: name_span.clone(),
+                    scope: name_span.start..expression_span.end,
+                    references: vec![],
+                });
+
+                self.expression(expression);
+                self.scope.pop();
+            }
+            Expression::If {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            Expression::Match {
+                scrutinee, arms, ..
+            } => {
+                self.expression(scrutinee);
+                for arm in arms {
+                    self.arm(arm);
+                }
+            }
+        }
+    }
+
+    /// Resolves the constructor of `arm` among the `let`s and binds its
+    /// fields in the body.
+    fn arm(&mut self, arm: &Arm) {
+        if arm.constructor != WILDCARD {
+            match self.globals.get(&arm.constructor) {
+                Some(&index) => self.resolution.symbols[index]
+                    .references
+                    .push(arm.constructor_span.clone()),
+                None => self
+                    .resolution
+                    .unresolved
+                    .push((arm.constructor.clone(), arm.constructor_span.clone())),
+            }
+        }
+
+        let depth = self.scope.len();
+        for (field, field_span) in &arm.fields {
+            self.scope.push(self.resolution.symbols.len());
+            self.resolution.symbols.push(Symbol {
+                name: field.clone(),
+                kind: SymbolKind::Parameter,
+                definition: field_span.clone(),
+                scope: field_span.start..arm.body_span.end,
+                references: vec![],
+            });
+        }
+
+        self.expression(&arm.body);
+        self.scope.truncate(depth);
+    }
+}
+
+/// Diagnostics for a `let` that redefines an existing variable (E003).
+/// `previous_expression_span` is the value of the first definition, or `None`
+/// if the variable was defined by the host program rather than in source.
+pub fn redefinition(
+    name: &str,
+    name_span: codemap::Span,
+    expression_span: codemap::Span,
+    previous_expression_span: Option<codemap::Span>,
+) -> Vec<Diagnostic> {
+    let previous = match previous_expression_span {
+        Some(span) => Diagnostic {
+            level: Level::Note,
+            message: format!("variable `{}` was firstly defined here", name),
+            spans: vec![SpanLabel {
+                span,
+                style: SpanStyle::Primary,
+                label: Some("previous value".to_owned()),
+            }],
+            code: Some("N003".to_owned()),
+        },
+        None => Diagnostic {
+            level: Level::Note,
+            message: format!("variable `{}` is defined by the host", name),
+            spans: vec![],
+            code: Some("N003".to_owned()),
+        },
+    };
+
+    vec![
+        Diagnostic {
+            level: Level::Error,
+            message: "trying to redefine existing variable".to_owned(),
+            spans: vec![
+                SpanLabel {
+                    span: name_span,
+                    style: SpanStyle::Primary,
+                    label: Some(format!("trying to overwrite `{}`", name)),
+                },
+                SpanLabel {
+                    span: expression_span,
+                    style: SpanStyle::Secondary,
+                    label: Some("new value".to_owned()),
+                },
+            ],
+
+            code: Some("E003".to_owned()),
+        },
+        previous,
+        Diagnostic {
+            level: Level::Note,
+            message: "consider renaming the variable".to_owned(),
+            spans: vec![SpanLabel {
+                span: name_span,
+                style: SpanStyle::Primary,
+                label: Some(format!("rename `{}` here", name)),
+            }],
+            code: Some("N003".to_owned()),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod resolve_tests {
+    use super::{resolve, SymbolKind};
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    #[test]
+    fn lets_and_parameters() {
+        let source = "let id = \\x => x; id (\\id => id) y;";
+        let resolution = resolve(&Parser::new(source, "<stdin>", &mut CodeMap::new()).parse());
+
+        let id = resolution.symbol_at(4).unwrap();
+        assert_eq!(id.kind, SymbolKind::Let);
+        assert_eq!(id.references, vec![18..20]);
+
+        let x = resolution.symbol_at(15).unwrap();
+        assert_eq!(x.kind, SymbolKind::Parameter);
+        assert_eq!(x.definition, 10..11);
+
+        let shadowing = resolution.symbol_at(29).unwrap();
+        assert_eq!(shadowing.definition, 23..25);
+
+        assert_eq!(resolution.unresolved, vec![("y".to_owned(), 33..34)]);
+    }
+
+    #[test]
+    fn match_arms() {
+        let source = "data List = Nil | Cons h t; match xs { Nil => a; Cons h t => h; _ => t };";
+        let resolution = resolve(&Parser::new(source, "<stdin>", &mut CodeMap::new()).parse());
+
+        let cons = resolution.symbol_at(18).unwrap();
+        assert_eq!(cons.kind, SymbolKind::Let);
+        assert_eq!(cons.references, vec![49..53]);
+
+        let h = resolution.symbol_at(61).unwrap();
+        assert_eq!(h.kind, SymbolKind::Parameter);
+        assert_eq!(h.definition, 54..55);
+
+        assert_eq!(
+            resolution.unresolved,
+            vec![
+                ("xs".to_owned(), 34..36),
+                ("a".to_owned(), 46..47),
+                ("t".to_owned(), 69..70),
+            ]
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Compilation of lambda terms to combinators by bracket abstraction.
+//!
+//! Every abstraction `\x => e` is replaced with a combinator term that
+//! behaves the same when applied, so the output has no bound variables. The
+//! `ski` target only uses `S`, `K` and `I`; the `bckw` target uses `B`, `C`,
+//! `K` and `W`, which keep terms smaller because an argument only goes to the
+//! side of an application that uses it.
+//!
+//! Both targets eta-reduce, so the output is extensionally but not always
+//! beta-equal to the input: `\x => f x` and `f` compile to the same term.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ast::Expression;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Combinator {
+    /// `S x y z = x z (y z)`
+    S,
+    /// `K x y = x`
+    K,
+    /// `I x = x`
+    I,
+    /// `B x y z = x (y z)`
+    B,
+    /// `C x y z = x z y`
+    C,
+    /// `W x y = x y y`
+    W,
+}
+
+impl Combinator {
+    /// Number of arguments the combinator needs to reduce.
+    pub fn arity(self) -> usize {
+        match self {
+            Combinator::I => 1,
+            Combinator::K | Combinator::W => 2,
+            Combinator::S | Combinator::B | Combinator::C => 3,
+        }
+    }
+}
+
+/// A term built from combinators, free variables and applications.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Term {
+    Combinator(Combinator),
+    Var(String),
+    Apply(Box<Term>, Box<Term>),
+}
+
+impl Term {
+    fn apply(self, argument: Term) -> Term {
+        Term::Apply(Box::new(self), Box::new(argument))
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        match self {
+            Term::Combinator(_) => false,
+            Term::Var(var) => var == name,
+            Term::Apply(lhs, rhs) => lhs.contains(name) || rhs.contains(name),
+        }
+    }
+
+    /// Whether the term only uses combinators that `target` may output.
+    pub fn fits(&self, target: Target) -> bool {
+        match self {
+            Term::Combinator(combinator) => target.combinators().contains(combinator),
+            Term::Var(_) => true,
+            Term::Apply(lhs, rhs) => lhs.fits(target) && rhs.fits(target),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Combinator(combinator) => write!(f, "{:?}", combinator),
+            Term::Var(name) => write!(f, "{}", name),
+            Term::Apply(lhs, rhs) => match **rhs {
+                Term::Apply(..) => write!(f, "{} ({})", lhs, rhs),
+                _ => write!(f, "{} {}", lhs, rhs),
+            },
+        }
+    }
+}
+
+/// Combinator basis to compile to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Ski,
+    Bckw,
+}
+
+impl Target {
+    pub fn combinators(self) -> &'static [Combinator] {
+        match self {
+            Target::Ski => &[Combinator::S, Combinator::K, Combinator::I],
+            Target::Bckw => &[Combinator::B, Combinator::C, Combinator::K, Combinator::W],
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ski" => Ok(Target::Ski),
+            "bckw" => Ok(Target::Bckw),
+            _ => Err(format!("unknown target `{}`", s)),
+        }
+    }
+}
+
+/// Translates `expression` into combinators of `target`. Free variables are
+/// kept, so bindings should be expanded first. Types are erased.
+pub fn compile(expression: &Expression, target: Target) -> Term {
+    match expression {
+        Expression::Var { name, .. } => Term::Var(name.clone()),
+        Expression::Apply { lhs, rhs, .. } | Expression::Dollar { lhs, rhs, .. } => {
+            compile(lhs, target).apply(compile(rhs, target))
+        }
+        Expression::Paren { expression, .. }
+        | Expression::TypeAbstraction { expression, .. }
+        | Expression::TypeApply { expression, .. } => compile(expression, target),
+        Expression::Abstraction {
+            name, expression, ..
+        } => abstract_variable(name, compile(expression, target), target),
+        Expression::If { .. } | Expression::Match { .. } => {
+            unreachable!("`if` and `match` are desugared before compiling")
+        }
+    }
+}
+
+/// Bracket abstraction: a term without `name` that, applied to a value,
+/// behaves like `body` with the value in place of `name`.
+fn abstract_variable(name: &str, body: Term, target: Target) -> Term {
+    use Combinator::*;
+
+    let combinator = Term::Combinator;
+    if !body.contains(name) {
+        return combinator(K).apply(body);
+    }
+
+    match body {
+        Term::Var(_) => match target {
+            Target::Ski => combinator(I),
+            Target::Bckw => combinator(W).apply(combinator(K)),
+        },
+        Term::Apply(lhs, rhs) => {
+            let variable = *rhs == Term::Var(name.to_owned());
+            if variable && !lhs.contains(name) {
+                return *lhs;
+            }
+
+            match (target, lhs.contains(name), rhs.contains(name)) {
+                (Target::Bckw, true, true) if variable => {
+                    combinator(W).apply(abstract_variable(name, *lhs, target))
+                }
+                (Target::Bckw, true, false) => combinator(C)
+                    .apply(abstract_variable(name, *lhs, target))
+                    .apply(*rhs),
+                (Target::Bckw, false, true) => combinator(B)
+                    .apply(*lhs)
+                    .apply(abstract_variable(name, *rhs, target)),
+                (Target::Ski, ..) => combinator(S)
+                    .apply(abstract_variable(name, *lhs, target))
+                    .apply(abstract_variable(name, *rhs, target)),
+                // S = B (B W) (B B C)
+                (Target::Bckw, ..) => combinator(B)
+                    .apply(combinator(B).apply(combinator(W)))
+                    .apply(combinator(B).apply(combinator(B)).apply(combinator(C)))
+                    .apply(abstract_variable(name, *lhs, target))
+                    .apply(abstract_variable(name, *rhs, target)),
+            }
+        }
+        Term::Combinator(_) => unreachable!("combinators contain no variables"),
+    }
+}
+
+/// Reduces combinator terms in normal order, leftmost redex first.
+pub struct Reducer {
+    /// Number of combinators contracted so far.
+    pub steps: usize,
+    /// Number of steps after which reduction gives up.
+    pub limit: usize,
+}
+
+impl Reducer {
+    pub fn new(limit: usize) -> Self {
+        Self { steps: 0, limit }
+    }
+
+    /// Reduces `term` to its normal form. Returns `None` if that takes more
+    /// than `limit` steps.
+    pub fn normalize(&mut self, term: &Term) -> Option<Term> {
+        let (head, arguments) = self.head_normal_form(term)?;
+        arguments.iter().try_fold(head, |term, argument| {
+            Some(term.apply(self.normalize(argument)?))
+        })
+    }
+
+    /// Contracts redexes at the head of `term` until its head is a free
+    /// variable or a combinator without enough arguments. Returns the head
+    /// and its arguments.
+    fn head_normal_form(&mut self, term: &Term) -> Option<(Term, Vec<Term>)> {
+        use Combinator::*;
+
+        // Arguments of the head, the first one last.
+        let mut arguments = vec![];
+        let mut head = term.clone();
+        loop {
+            head = match head {
+                Term::Apply(lhs, rhs) => {
+                    arguments.push(*rhs);
+                    *lhs
+                }
+                Term::Combinator(combinator) if arguments.len() >= combinator.arity() => {
+                    if self.steps >= self.limit {
+                        return None;
+                    }
+                    self.steps += 1;
+
+                    let x = arguments.pop().unwrap();
+                    let y = (combinator.arity() > 1).then(|| arguments.pop().unwrap());
+                    let z = (combinator.arity() > 2).then(|| arguments.pop().unwrap());
+                    match (combinator, y, z) {
+                        (I, ..) => x,
+                        (K, ..) => x,
+                        (W, Some(y), _) => x.apply(y.clone()).apply(y),
+                        (S, Some(y), Some(z)) => x.apply(z.clone()).apply(y.apply(z)),
+                        (B, Some(y), Some(z)) => x.apply(y.apply(z)),
+                        (C, Some(y), Some(z)) => x.apply(z).apply(y),
+                        _ => unreachable!("arguments are taken by arity"),
+                    }
+                }
+                head => {
+                    arguments.reverse();
+                    return Some((head, arguments));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod ski_tests {
+    use std::collections::HashMap;
+
+    use super::{compile, Reducer, Target, Term};
+    use crate::ast::{Expression, Statement};
+    use crate::parser::Parser;
+    use crate::reduce::{self, substitute};
+    use codemap::CodeMap;
+
+    /// Parses `source` and returns its expression statements with the lets
+    /// before them expanded.
+    fn expressions(source: &str) -> Vec<Expression> {
+        let mut bindings: HashMap<String, Expression> = HashMap::new();
+        let mut expressions = vec![];
+        for statement in Parser::new(source, "<stdin>", &mut CodeMap::new()).parse() {
+            let (name, expression) = match statement {
+                Statement::Let {
+                    name, expression, ..
+                } => (Some(name), expression),
+                Statement::Expression { expression, .. } => (None, expression),
+                _ => continue,
+            };
+            let expanded = bindings
+                .iter()
+                .fold(expression, |expression, (name, value)| {
+                    substitute(&expression, name, value)
+                });
+
+            match name {
+                Some(name) => {
+                    bindings.insert(name, expanded);
+                }
+                None => expressions.push(expanded),
+            }
+        }
+
+        expressions
+    }
+
+    fn compiled(source: &str, target: Target) -> String {
+        compile(&expressions(source)[0], target).to_string()
+    }
+
+    #[test]
+    fn bracket_abstraction() {
+        assert_eq!(compiled("\\x => x;", Target::Ski), "I");
+        assert_eq!(compiled("\\x => \\y => x;", Target::Ski), "K");
+        assert_eq!(compiled("\\x => \\y => y;", Target::Ski), "K I");
+        assert_eq!(
+            compiled("\\f => \\x => f (f x);", Target::Ski),
+            "S (S (K S) K) I"
+        );
+        assert_eq!(compiled("\\x => f x;", Target::Ski), "f");
+        assert_eq!(compiled("\\x => x x;", Target::Bckw), "W (W K)");
+        assert_eq!(compiled("\\f => \\x => f (f x);", Target::Bckw), "W B");
+        assert_eq!(compiled("\\x => \\y => y x;", Target::Bckw), "C (W K)");
+    }
+
+    #[test]
+    fn reduction() {
+        let term = compile(&expressions("(\\x => \\y => y x) a b;")[0], Target::Ski);
+        let mut reducer = Reducer::new(100);
+        assert_eq!(reducer.normalize(&term).unwrap().to_string(), "b a");
+
+        let omega = compile(&expressions("(\\x => x x) (\\x => x x);")[0], Target::Ski);
+        assert_eq!(Reducer::new(100).normalize(&omega), None);
+    }
+
+    /// Compiles every expression of the prelude and checks that the
+    /// combinator term, applied to as many free variables as the lambda
+    /// normal form has parameters, reduces to the body of the normal form.
+    #[test]
+    fn prelude_is_extensionally_equal() {
+        let prelude = "
+            let true = \\x => \\y => x; let false = \\x => \\y => y;
+            let not = \\b => b false true; let and = \\a => \\b => a b a;
+            let or = \\a => \\b => a a b;
+            let zero = \\f => \\x => x; let succ = \\n => \\f => \\x => f (n f x);
+            let two = succ (succ zero); let three = succ two;
+            let plus = \\m => \\n => \\f => \\x => m f (n f x);
+            let mult = \\m => \\n => \\f => m (n f);
+            let pow = \\b => \\e => e b;
+            let pred = \\n => \\f => \\x => n (\\g => \\h => h (g f)) (\\u => x) (\\u => u);
+            let pair = \\a => \\b => \\s => s a b; let first = \\p => p true;
+            let is_zero = \\n => n (\\x => false) true;
+            not true; and true false; or false true; succ two; plus two three;
+            mult two three; pow two three; pred three; first (pair zero two);
+            is_zero zero; is_zero three;";
+
+        let empty = HashMap::new();
+        for expression in expressions(prelude) {
+            let mut normal_form = reduce::Reducer::new(&empty, 10_000)
+                .normalize(&expression)
+                .unwrap();
+            let mut parameters = vec![];
+            while let Expression::Abstraction {
+                name, expression, ..
+            } = normal_form
+            {
+                parameters.push(name);
+                normal_form = *expression;
+            }
+
+            for target in [Target::Ski, Target::Bckw] {
+                let term = compile(&expression, target);
+                assert!(term.fits(target));
+
+                let applied = parameters.iter().fold(term, |term, parameter| {
+                    term.apply(Term::Var(parameter.clone()))
+                });
+                assert_eq!(
+                    Reducer::new(10_000).normalize(&applied).unwrap(),
+                    compile(&normal_form, target),
+                    "{} with {:?}",
+                    expression,
+                    target
+                );
+            }
+        }
+    }
+}
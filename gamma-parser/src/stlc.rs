@@ -0,0 +1,384 @@
+//! Type checking for the simply typed lambda calculus.
+//!
+//! Types are built from base types, which are any identifiers such as `A` or
+//! `Nat`, and arrows. Every lambda parameter must be annotated. The type of a
+//! `let` is computed from its value and, if the `let` is annotated, checked
+//! against the annotation.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+
+use crate::ast::{Expression, Span, Statement, Type};
+
+/// Errors of the checkers for explicitly typed terms, this one and
+/// [`crate::system_f`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeError {
+    /// The expression at `span` has type `found` where `expected` is needed.
+    /// `reason` points at what requires `expected`, like an annotation.
+    Mismatch {
+        expected: Box<Type>,
+        found: Box<Type>,
+        span: Span,
+        reason: Option<(Span, String)>,
+    },
+    /// The expression at `span` is applied to an argument but has the
+    /// non-function type `found`.
+    NotAFunction { found: Box<Type>, span: Span },
+    /// The parameter at `span` has no annotation.
+    MissingAnnotation { name: String, span: Span },
+    /// The variable at `span` is not bound.
+    Unbound { name: String, span: Span },
+    /// The expression at `span` is applied to a type but has the
+    /// non-polymorphic type `found`.
+    NotPolymorphic { found: Box<Type>, span: Span },
+    /// The type abstraction or application at `span` is not part of the
+    /// simply typed lambda calculus.
+    Unsupported { span: Span },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::Mismatch { .. }
+            | TypeError::NotAFunction { .. }
+            | TypeError::NotPolymorphic { .. } => write!(f, "mismatched types"),
+            TypeError::Unsupported { .. } => {
+                write!(f, "type abstraction and application need System F")
+            }
+            TypeError::MissingAnnotation { name, .. } => {
+                write!(f, "missing type annotation for parameter `{}`", name)
+            }
+            TypeError::Unbound { name, .. } => write!(f, "unknown variable `{}`", name),
+        }
+    }
+}
+
+impl TypeError {
+    pub fn diagnostic(&self, file_span: codemap::Span) -> Diagnostic {
+        let span = |span: &Span| file_span.subspan(span.start as u64, span.end as u64);
+        let primary = |at: &Span, label: String| SpanLabel {
+            span: span(at),
+            style: SpanStyle::Primary,
+            label: Some(label),
+        };
+
+        let (code, spans) = match self {
+            TypeError::Mismatch {
+                expected,
+                found,
+                span: at,
+                reason,
+            } => {
+                let mut spans = vec![primary(
+                    at,
+                    format!("expected `{}`, found `{}`", expected, found),
+                )];
+                if let Some((at, label)) = reason {
+                    spans.push(SpanLabel {
+                        span: span(at),
+                        style: SpanStyle::Secondary,
+                        label: Some(label.clone()),
+                    });
+                }
+
+                ("E005", spans)
+            }
+            TypeError::NotAFunction { found, span: at } => (
+                "E005",
+                vec![primary(
+                    at,
+                    format!("expected a function, found `{}`", found),
+                )],
+            ),
+            TypeError::NotPolymorphic { found, span: at } => (
+                "E005",
+                vec![primary(
+                    at,
+                    format!("expected a polymorphic type, found `{}`", found),
+                )],
+            ),
+            TypeError::Unsupported { span: at } => (
+                "E009",
+                vec![primary(
+                    at,
+                    "help: check the program with `--typed=system-f`".to_owned(),
+                )],
+            ),
+            TypeError::MissingAnnotation { name, span: at } => (
+                "E006",
+                vec![primary(
+                    at,
+                    format!("help: annotate the parameter, e.g. `{}: A`", name),
+                )],
+            ),
+            TypeError::Unbound { span: at, .. } => (
+                "E007",
+                vec![primary(
+                    at,
+                    "not defined by any `let` or parameter".to_owned(),
+                )],
+            ),
+        };
+
+        Diagnostic {
+            level: Level::Error,
+            message: self.to_string(),
+            code: Some(code.to_owned()),
+            spans,
+        }
+    }
+}
+
+/// Whether two types are the same, regardless of where they were written.
+pub fn same(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Var { name: a, .. }, Type::Var { name: b, .. }) => a == b,
+        (
+            Type::Arrow {
+                lhs: a_lhs,
+                rhs: a_rhs,
+                ..
+            },
+            Type::Arrow {
+                lhs: b_lhs,
+                rhs: b_rhs,
+                ..
+            },
+        ) => same(a_lhs, b_lhs) && same(a_rhs, b_rhs),
+        (
+            Type::Forall {
+                name: a_name,
+                body: a_body,
+                ..
+            },
+            Type::Forall {
+                name: b_name,
+                body: b_body,
+                ..
+            },
+        ) => a_name == b_name && same(a_body, b_body),
+        _ => false,
+    }
+}
+
+/// Types of the top-level `let`s checked so far.
+#[derive(Clone, Debug, Default)]
+pub struct Checker {
+    globals: HashMap<String, Type>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives the variable `name` a type without checking a definition.
+    pub fn declare(&mut self, name: &str, ty: Type) {
+        self.globals.insert(name.to_owned(), ty);
+    }
+
+    pub fn type_of(&self, name: &str) -> Option<&Type> {
+        self.globals.get(name)
+    }
+
+    /// Checks a statement and returns its type. The variable defined by a
+    /// `let` is declared even if its value is ill-typed, as long as it is
+    /// annotated.
+    pub fn check_statement(&mut self, statement: &Statement) -> Result<Type, TypeError> {
+        match statement {
+            Statement::Expression { expression, .. } | Statement::Assert { expression, .. } => {
+                self.infer(expression)
+            }
+            Statement::AssertEq { lhs, rhs, .. } => {
+                self.infer(rhs)?;
+                self.infer(lhs)
+            }
+            Statement::Syntax { .. } => {
+                unreachable!("syntax rules are expanded before type checking")
+            }
+            Statement::Data { .. } => {
+                unreachable!("data types are defined by `let`s of their constructors")
+            }
+            Statement::Let {
+                name,
+                annotation,
+                expression,
+                expression_span,
+                ..
+            } => {
+                let result = self.infer(expression).and_then(|found| match annotation {
+                    Some(expected) if !same(expected, &found) => Err(TypeError::Mismatch {
+                        expected: Box::new(expected.clone()),
+                        found: Box::new(found),
+                        span: expression_span.clone(),
+                        reason: Some((
+                            expected.span(),
+                            "expected because of this annotation".to_owned(),
+                        )),
+                    }),
+                    _ => Ok(found),
+                });
+
+                if let Some(ty) = annotation.clone().or_else(|| result.clone().ok()) {
+                    self.declare(name, ty);
+                }
+
+                result
+            }
+        }
+    }
+
+    /// Computes the type of `expression`.
+    pub fn infer(&self, expression: &Expression) -> Result<Type, TypeError> {
+        self.infer_in(expression, &mut vec![])
+    }
+
+    fn infer_in(
+        &self,
+        expression: &Expression,
+        locals: &mut Vec<(String, Type)>,
+    ) -> Result<Type, TypeError> {
+        match expression {
+            Expression::Var { name, name_span } => locals
+                .iter()
+                .rev()
+                .find(|(local, _)| local == name)
+                .map(|(_, ty)| ty)
+                .or_else(|| self.globals.get(name))
+                .cloned()
+                .ok_or_else(|| TypeError::Unbound {
+                    name: name.clone(),
+                    span: name_span.clone(),
+                }),
+            Expression::Paren { expression, .. } => self.infer_in(expression, locals),
+            Expression::Apply {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            }
+            | Expression::Dollar {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => match self.infer_in(lhs, locals)? {
+                Type::Arrow {
+                    lhs: parameter,
+                    rhs: result,
+                    ..
+                } => {
+                    let argument = self.infer_in(rhs, locals)?;
+                    if !same(&parameter, &argument) {
+                        return Err(TypeError::Mismatch {
+                            expected: parameter.clone(),
+                            found: Box::new(argument),
+                            span: rhs_span.clone(),
+                            reason: Some((
+                                lhs_span.clone(),
+                                format!("this function takes `{}`", parameter),
+                            )),
+                        });
+                    }
+
+                    Ok(*result)
+                }
+                found => Err(TypeError::NotAFunction {
+                    found: Box::new(found),
+                    span: lhs_span.clone(),
+                }),
+            },
+            Expression::TypeAbstraction { name_span, .. } => Err(TypeError::Unsupported {
+                span: name_span.clone(),
+            }),
+            Expression::TypeApply { argument, .. } => Err(TypeError::Unsupported {
+                span: argument.span(),
+            }),
+            Expression::Abstraction {
+                name,
+                name_span,
+                annotation,
+                expression,
+                expression_span,
+            } => {
+                let parameter = annotation
+                    .clone()
+                    .ok_or_else(|| TypeError::MissingAnnotation {
+                        name: name.clone(),
+                        span: name_span.clone(),
+                    })?;
+
+                locals.push((name.clone(), parameter.clone()));
+                let result = self.infer_in(expression, locals);
+                locals.pop();
+
+                Ok(Type::Arrow {
+                    lhs: Box::new(parameter),
+                    rhs: Box::new(result?),
+                    span: name_span.start..expression_span.end,
+                })
+            }
+            Expression::If { .. } | Expression::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before type checking")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod stlc_tests {
+    use super::{Checker, TypeError};
+    use crate::parser::Parser;
+    use codemap::CodeMap;
+
+    fn check(source: &str) -> Vec<Result<String, TypeError>> {
+        let ast = Parser::new(source, "<stdin>", &mut CodeMap::new()).parse();
+        let mut checker = Checker::new();
+        ast.iter()
+            .map(|statement| checker.check_statement(statement).map(|ty| ty.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn well_typed() {
+        assert_eq!(
+            check(
+                "let id : A -> A = \\x: A => x;
+                 let compose = \\f: B -> C => \\g: A -> B => \\x: A => f (g x);
+                 \\x: A => id x;"
+            ),
+            vec![
+                Ok("A -> A".to_owned()),
+                Ok("(B -> C) -> (A -> B) -> A -> C".to_owned()),
+                Ok("A -> A".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ill_typed() {
+        let results = check(
+            "let id = \\x: A => x;
+             \\y: B => id y;
+             let k : A -> A = \\x: A => \\y: A => x;
+             \\x => x;
+             \\x: A => x x;
+             z;",
+        );
+
+        assert!(matches!(
+            &results[1],
+            Err(TypeError::Mismatch { span, .. }) if *span == (46..47)
+        ));
+        assert!(matches!(&results[2], Err(TypeError::Mismatch { .. })));
+        assert!(matches!(
+            &results[3],
+            Err(TypeError::MissingAnnotation { .. })
+        ));
+        assert!(matches!(&results[4], Err(TypeError::NotAFunction { .. })));
+        assert!(matches!(&results[5], Err(TypeError::Unbound { .. })));
+    }
+}
@@ -0,0 +1,512 @@
+//! Type checking for System F, the polymorphic lambda calculus.
+//!
+//! On top of [`crate::stlc`], `/\a => e` abstracts over the type variable
+//! `a` and has type `forall a. T`, and `e [T]` instantiates such a term. An
+//! identifier in a type is a type variable if a `forall` or a type
+//! abstraction binds it and a base type otherwise. Types are compared up to
+//! renaming of bound type variables.
+//!
+//! Types do not matter at run time: [`erase`] turns a checked term back into
+//! an untyped one for the reducer.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Expression, Statement, Type};
+use crate::reduce::fresh_name;
+use crate::stlc::TypeError;
+
+/// Types of the top-level `let`s checked so far.
+#[derive(Clone, Debug, Default)]
+pub struct Checker {
+    globals: HashMap<String, Type>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives the variable `name` a type without checking a definition.
+    pub fn declare(&mut self, name: &str, ty: Type) {
+        self.globals.insert(name.to_owned(), ty);
+    }
+
+    pub fn type_of(&self, name: &str) -> Option<&Type> {
+        self.globals.get(name)
+    }
+
+    /// Checks a statement and returns its type. The variable defined by a
+    /// `let` is declared even if its value is ill-typed, as long as it is
+    /// annotated.
+    pub fn check_statement(&mut self, statement: &Statement) -> Result<Type, TypeError> {
+        match statement {
+            Statement::Expression { expression, .. } | Statement::Assert { expression, .. } => {
+                self.infer(expression)
+            }
+            Statement::AssertEq { lhs, rhs, .. } => {
+                self.infer(rhs)?;
+                self.infer(lhs)
+            }
+            Statement::Syntax { .. } => {
+                unreachable!("syntax rules are expanded before type checking")
+            }
+            Statement::Data { .. } => {
+                unreachable!("data types are defined by `let`s of their constructors")
+            }
+            Statement::Let {
+                name,
+                annotation,
+                expression,
+                expression_span,
+                ..
+            } => {
+                let result = self.infer(expression).and_then(|found| match annotation {
+                    Some(expected) if !equal(expected, &found) => Err(TypeError::Mismatch {
+                        expected: Box::new(expected.clone()),
+                        found: Box::new(found),
+                        span: expression_span.clone(),
+                        reason: Some((
+                            expected.span(),
+                            "expected because of this annotation".to_owned(),
+                        )),
+                    }),
+                    _ => Ok(found),
+                });
+
+                if let Some(ty) = annotation.clone().or_else(|| result.clone().ok()) {
+                    self.declare(name, ty);
+                }
+
+                result
+            }
+        }
+    }
+
+    /// Computes the type of `expression`.
+    pub fn infer(&self, expression: &Expression) -> Result<Type, TypeError> {
+        self.infer_in(expression, &mut vec![], &mut vec![])
+    }
+
+    /// `locals` are the types of the parameters in scope, `types` maps the
+    /// type variables bound by type abstractions in scope to the names they
+    /// have in the computed types.
+    fn infer_in(
+        &self,
+        expression: &Expression,
+        locals: &mut Vec<(String, Type)>,
+        types: &mut Vec<(String, String)>,
+    ) -> Result<Type, TypeError> {
+        match expression {
+            Expression::Var { name, name_span } => locals
+                .iter()
+                .rev()
+                .find(|(local, _)| local == name)
+                .map(|(_, ty)| ty)
+                .or_else(|| self.globals.get(name))
+                .cloned()
+                .ok_or_else(|| TypeError::Unbound {
+                    name: name.clone(),
+                    span: name_span.clone(),
+                }),
+            Expression::Paren { expression, .. } => self.infer_in(expression, locals, types),
+            Expression::Apply {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            }
+            | Expression::Dollar {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+            } => match self.infer_in(lhs, locals, types)? {
+                Type::Arrow {
+                    lhs: parameter,
+                    rhs: result,
+                    ..
+                } => {
+                    let argument = self.infer_in(rhs, locals, types)?;
+                    if !equal(&parameter, &argument) {
+                        return Err(TypeError::Mismatch {
+                            expected: parameter.clone(),
+                            found: Box::new(argument),
+                            span: rhs_span.clone(),
+                            reason: Some((
+                                lhs_span.clone(),
+                                format!("this function takes `{}`", parameter),
+                            )),
+                        });
+                    }
+
+                    Ok(*result)
+                }
+                found => Err(TypeError::NotAFunction {
+                    found: Box::new(found),
+                    span: lhs_span.clone(),
+                }),
+            },
+            Expression::Abstraction {
+                name,
+                name_span,
+                annotation,
+                expression,
+                expression_span,
+            } => {
+                let parameter = match annotation {
+                    Some(annotation) => rename_bound(annotation, types),
+                    None => {
+                        return Err(TypeError::MissingAnnotation {
+                            name: name.clone(),
+                            span: name_span.clone(),
+                        })
+                    }
+                };
+
+                locals.push((name.clone(), parameter.clone()));
+                let result = self.infer_in(expression, locals, types);
+                locals.pop();
+
+                Ok(Type::Arrow {
+                    lhs: Box::new(parameter),
+                    rhs: Box::new(result?),
+                    span: name_span.start..expression_span.end,
+                })
+            }
+            Expression::TypeAbstraction {
+                name,
+                name_span,
+                expression,
+                expression_span,
+            } => {
+                // The new variable must not capture a type variable or a
+                // base type that parameters in scope or globals already use.
+                let taken: HashSet<String> = locals
+                    .iter()
+                    .map(|(_, ty)| ty)
+                    .chain(self.globals.values())
+                    .flat_map(free_type_variables)
+                    .collect();
+                let variable = match taken.contains(name) {
+                    true => fresh_name(name, |candidate| taken.contains(candidate)),
+                    false => name.clone(),
+                };
+
+                types.push((name.clone(), variable.clone()));
+                let body = self.infer_in(expression, locals, types);
+                types.pop();
+
+                Ok(Type::Forall {
+                    name: variable,
+                    name_span: name_span.clone(),
+                    body: Box::new(body?),
+                    span: name_span.start..expression_span.end,
+                })
+            }
+            Expression::TypeApply {
+                expression,
+                expression_span,
+                argument,
+            } => match self.infer_in(expression, locals, types)? {
+                Type::Forall { name, body, .. } => Ok(substitute_type(
+                    &body,
+                    &name,
+                    &rename_bound(argument, types),
+                )),
+                found => Err(TypeError::NotPolymorphic {
+                    found: Box::new(found),
+                    span: expression_span.clone(),
+                }),
+            },
+            Expression::If { .. } | Expression::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before type checking")
+            }
+        }
+    }
+}
+
+/// Whether two types are equal up to renaming of bound type variables.
+pub fn equal(a: &Type, b: &Type) -> bool {
+    equal_in(a, b, &mut vec![])
+}
+
+fn equal_in(a: &Type, b: &Type, bound: &mut Vec<(String, String)>) -> bool {
+    match (a, b) {
+        (Type::Var { name: a, .. }, Type::Var { name: b, .. }) => {
+            let a_binder = bound.iter().rposition(|(binder, _)| binder == a);
+            let b_binder = bound.iter().rposition(|(_, binder)| binder == b);
+            match (a_binder, b_binder) {
+                (None, None) => a == b,
+                (a_binder, b_binder) => a_binder == b_binder,
+            }
+        }
+        (
+            Type::Arrow {
+                lhs: a_lhs,
+                rhs: a_rhs,
+                ..
+            },
+            Type::Arrow {
+                lhs: b_lhs,
+                rhs: b_rhs,
+                ..
+            },
+        ) => equal_in(a_lhs, b_lhs, bound) && equal_in(a_rhs, b_rhs, bound),
+        (
+            Type::Forall {
+                name: a_name,
+                body: a_body,
+                ..
+            },
+            Type::Forall {
+                name: b_name,
+                body: b_body,
+                ..
+            },
+        ) => {
+            bound.push((a_name.clone(), b_name.clone()));
+            let result = equal_in(a_body, b_body, bound);
+            bound.pop();
+            result
+        }
+        _ => false,
+    }
+}
+
+pub fn free_type_variables(ty: &Type) -> HashSet<String> {
+    match ty {
+        Type::Var { name, .. } => HashSet::from([name.clone()]),
+        Type::Arrow { lhs, rhs, .. } => {
+            let mut variables = free_type_variables(lhs);
+            variables.extend(free_type_variables(rhs));
+            variables
+        }
+        Type::Forall { name, body, .. } => {
+            let mut variables = free_type_variables(body);
+            variables.remove(name);
+            variables
+        }
+    }
+}
+
+/// Replaces free occurrences of the type variable `name` in `ty` with
+/// `value`, renaming `forall`s that would capture free variables of `value`.
+pub fn substitute_type(ty: &Type, name: &str, value: &Type) -> Type {
+    match ty {
+        Type::Var { name: variable, .. } if variable == name => value.clone(),
+        Type::Var { .. } => ty.clone(),
+        Type::Arrow { lhs, rhs, span } => Type::Arrow {
+            lhs: Box::new(substitute_type(lhs, name, value)),
+            rhs: Box::new(substitute_type(rhs, name, value)),
+            span: span.clone(),
+        },
+        Type::Forall {
+            name: binder,
+            name_span,
+            body,
+            span,
+        } => {
+            if binder == name {
+                return ty.clone();
+            }
+
+            let value_variables = free_type_variables(value);
+            let (binder, body) = if value_variables.contains(binder) {
+                let body_variables = free_type_variables(body);
+                let fresh = fresh_name(binder, |candidate| {
+                    candidate == name
+                        || value_variables.contains(candidate)
+                        || body_variables.contains(candidate)
+                });
+                let renamed = Type::Var {
+                    name: fresh.clone(),
+                    span: name_span.clone(),
+                };
+                (fresh, substitute_type(body, binder, &renamed))
+            } else {
+                (binder.clone(), *body.clone())
+            };
+
+            Type::Forall {
+                name: binder,
+                name_span: name_span.clone(),
+                body: Box::new(substitute_type(&body, name, value)),
+                span: span.clone(),
+            }
+        }
+    }
+}
+
+/// Renames the type variables of `ty` that are bound by type abstractions
+/// in scope to the names they were given, see [`Checker::infer_in`].
+fn rename_bound(ty: &Type, types: &[(String, String)]) -> Type {
+    match ty {
+        Type::Var { name, span } => Type::Var {
+            name: types
+                .iter()
+                .rev()
+                .find(|(bound, _)| bound == name)
+                .map_or(name, |(_, variable)| variable)
+                .clone(),
+            span: span.clone(),
+        },
+        Type::Arrow { lhs, rhs, span } => Type::Arrow {
+            lhs: Box::new(rename_bound(lhs, types)),
+            rhs: Box::new(rename_bound(rhs, types)),
+            span: span.clone(),
+        },
+        Type::Forall {
+            name,
+            name_span,
+            body,
+            span,
+        } => {
+            let mut types = types.to_vec();
+            types.push((name.clone(), name.clone()));
+            Type::Forall {
+                name: name.clone(),
+                name_span: name_span.clone(),
+                body: Box::new(rename_bound(body, &types)),
+                span: span.clone(),
+            }
+        }
+    }
+}
+
+/// Removes type abstractions, type applications and annotations.
+pub fn erase(expression: &Expression) -> Expression {
+    match expression {
+        Expression::Var { .. } => expression.clone(),
+        Expression::Apply {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => Expression::Apply {
+            lhs: Box::new(erase(lhs)),
+            lhs_span: lhs_span.clone(),
+            rhs: Box::new(erase(rhs)),
+            rhs_span: rhs_span.clone(),
+        },
+        Expression::Dollar {
+            lhs,
+            lhs_span,
+            rhs,
+            rhs_span,
+        } => Expression::Dollar {
+            lhs: Box::new(erase(lhs)),
+            lhs_span: lhs_span.clone(),
+            rhs: Box::new(erase(rhs)),
+            rhs_span: rhs_span.clone(),
+        },
+        Expression::Paren {
+            expression,
+            expression_span,
+        } => Expression::Paren {
+            expression: Box::new(erase(expression)),
+            expression_span: expression_span.clone(),
+        },
+        Expression::Abstraction {
+            name,
+            name_span,
+            expression,
+            expression_span,
+            ..
+        } => Expression::Abstraction {
+            name: name.clone(),
+            name_span: name_span.clone(),
+            annotation: None,
+            expression: Box::new(erase(expression)),
+            expression_span: expression_span.clone(),
+        },
+        Expression::TypeAbstraction { expression, .. }
+        | Expression::TypeApply { expression, .. } => erase(expression),
+        Expression::If { .. } | Expression::Match { .. } => {
+            unreachable!("`if` and `match` are desugared before erasing types")
+        }
+    }
+}
+
+#[cfg(test)]
+mod system_f_tests {
+    use super::{erase, Checker};
+    use crate::ast::Type;
+    use crate::parser::Parser;
+    use crate::stlc::TypeError;
+    use codemap::CodeMap;
+
+    fn check(source: &str) -> Vec<Result<String, TypeError>> {
+        let ast = Parser::new(source, "<stdin>", &mut CodeMap::new()).parse();
+        let mut checker = Checker::new();
+        ast.iter()
+            .map(|statement| checker.check_statement(statement).map(|ty| ty.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn polymorphism() {
+        assert_eq!(
+            check(
+                "let id : forall b. b -> b = /\\a => \\x: a => x;
+                 id [Nat];
+                 id [forall a. a -> a] id;
+                 let zero = /\\a => \\f: a -> a => \\x: a => x;
+                 \\x: a => /\\a => x;"
+            ),
+            vec![
+                Ok("forall a. a -> a".to_owned()),
+                Ok("Nat -> Nat".to_owned()),
+                Ok("forall a. a -> a".to_owned()),
+                Ok("forall a. (a -> a) -> a -> a".to_owned()),
+                Ok("a -> forall a1. a".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn type_abstractions_do_not_capture_globals() {
+        let mut checker = Checker::new();
+        checker.declare(
+            "n",
+            Type::Var {
+                name: "Nat".to_owned(),
+                span: 0..0,
+            },
+        );
+        let ast = Parser::new("/\\Nat => n;", "<stdin>", &mut CodeMap::new()).parse();
+        assert_eq!(
+            checker.check_statement(&ast[0]).unwrap().to_string(),
+            "forall Nat1. Nat"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let results = check(
+            "let id = /\\a => \\x: a => x;
+             id id;
+             (\\x: A => x) [A];
+             let k : forall a. a -> a = /\\a => /\\b => \\x: a => x;",
+        );
+
+        assert!(matches!(&results[1], Err(TypeError::NotAFunction { .. })));
+        assert!(matches!(&results[2], Err(TypeError::NotPolymorphic { .. })));
+        assert!(matches!(&results[3], Err(TypeError::Mismatch { .. })));
+    }
+
+    #[test]
+    fn erasure() {
+        let ast = Parser::new(
+            "(/\\a => \\x: a => x) [Nat] y;",
+            "<stdin>",
+            &mut CodeMap::new(),
+        )
+        .parse();
+        match &ast[0] {
+            crate::ast::Statement::Expression { expression, .. } => {
+                assert_eq!(erase(expression).to_string(), "(\\x => x) y")
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+    }
+}
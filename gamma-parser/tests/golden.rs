@@ -0,0 +1,54 @@
+//! Parses the golden tests of the repository and checks that the parser
+//! reports the syntax errors and warnings their `.stderr` files expect.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use codemap::CodeMap;
+use gamma_parser::parser::Parser;
+
+/// Codes of the diagnostics the parser reports.
+const CODES: &[&str] = &["E001", "W002", "E013", "E014"];
+
+fn programs(directory: &Path, programs: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            self::programs(&path, programs);
+        } else if path.extension().is_some_and(|extension| extension == "gm") {
+            programs.push(path);
+        }
+    }
+}
+
+#[test]
+fn golden() {
+    let mut paths = vec![];
+    programs(
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests")),
+        &mut paths,
+    );
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let source = fs::read_to_string(&path).unwrap().replace("\r\n", "\n");
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(&source, "<test>", &mut codemap);
+        parser.parse();
+        let reported: Vec<_> = parser
+            .diagnostics
+            .iter()
+            .filter_map(|diagnostic| diagnostic.code.clone())
+            .collect();
+
+        let expected: Vec<_> = fs::read_to_string(path.with_extension("stderr"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let code = line.split_once('[')?.1.split_once(']')?.0;
+                CODES.contains(&code).then(|| code.to_owned())
+            })
+            .collect();
+        assert_eq!(reported, expected, "{}", path.display());
+    }
+}
@@ -0,0 +1,300 @@
+//! Property tests on generated terms and sources: printing and parsing back,
+//! agreement of the reduction strategies, and a parser that never panics.
+
+use codemap::CodeMap;
+use gamma_parser::ast::{Expression, Type};
+use gamma_parser::format::format_expression;
+use gamma_parser::parser::Parser;
+use gamma_parser::reduce::{self, alpha_equivalent, Reducer};
+use proptest::prelude::*;
+use std::collections::HashMap;
+
+/// Few names, so that generated terms shadow and capture often.
+const NAMES: &[&str] = &["x", "y", "f", "α", "f′", "x1", "succ?"];
+
+fn name() -> impl Strategy<Value = String> {
+    prop::sample::select(NAMES).prop_map(str::to_owned)
+}
+
+/// Operators without a fixity declaration, which are all `infixl 9`.
+fn operator() -> impl Strategy<Value = String> {
+    prop::sample::select(&["+", "<>"][..]).prop_map(str::to_owned)
+}
+
+fn var(name: String) -> Expression {
+    Expression::Var {
+        name,
+        name_span: 0..0,
+    }
+}
+
+fn apply(lhs: Expression, rhs: Expression) -> Expression {
+    Expression::Apply {
+        lhs: Box::new(lhs),
+        lhs_span: 0..0,
+        rhs: Box::new(rhs),
+        rhs_span: 0..0,
+    }
+}
+
+/// `lhs $ rhs`.
+fn dollar(lhs: Expression, rhs: Expression) -> Expression {
+    Expression::Dollar {
+        lhs: Box::new(lhs),
+        lhs_span: 0..0,
+        rhs: Box::new(rhs),
+        rhs_span: 0..0,
+    }
+}
+
+fn abstraction(name: String, annotation: Option<Type>, expression: Expression) -> Expression {
+    Expression::Abstraction {
+        name,
+        name_span: 0..0,
+        annotation,
+        expression: Box::new(expression),
+        expression_span: 0..0,
+    }
+}
+
+/// Untyped terms.
+fn term() -> impl Strategy<Value = Expression> {
+    name().prop_map(var).prop_recursive(6, 48, 2, |term| {
+        prop_oneof![
+            (term.clone(), term.clone()).prop_map(|(lhs, rhs)| apply(lhs, rhs)),
+            (term.clone(), term.clone()).prop_map(|(lhs, rhs)| dollar(lhs, rhs)),
+            (operator(), term.clone(), term.clone())
+                .prop_map(|(operator, lhs, rhs)| apply(apply(var(operator), lhs), rhs)),
+            (operator(), term.clone()).prop_map(|(operator, lhs)| apply(var(operator), lhs)),
+            (name(), term).prop_map(|(name, body)| abstraction(name, None, body)),
+        ]
+    })
+}
+
+fn ty() -> impl Strategy<Value = Type> {
+    prop::sample::select(&["A", "B", "a"][..])
+        .prop_map(|name| Type::Var {
+            name: name.to_owned(),
+            span: 0..0,
+        })
+        .prop_recursive(3, 8, 2, |ty| {
+            prop_oneof![
+                (ty.clone(), ty.clone()).prop_map(|(lhs, rhs)| Type::Arrow {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                    span: 0..0,
+                }),
+                ty.prop_map(|body| Type::Forall {
+                    name: "a".to_owned(),
+                    name_span: 0..0,
+                    body: Box::new(body),
+                    span: 0..0,
+                }),
+            ]
+        })
+}
+
+/// Terms with type annotations, type abstractions and type applications.
+fn typed_term() -> impl Strategy<Value = Expression> {
+    name().prop_map(var).prop_recursive(6, 48, 2, |term| {
+        prop_oneof![
+            (term.clone(), term.clone()).prop_map(|(lhs, rhs)| apply(lhs, rhs)),
+            (name(), prop::option::of(ty()), term.clone())
+                .prop_map(|(name, annotation, body)| abstraction(name, annotation, body)),
+            term.clone().prop_map(|body| Expression::TypeAbstraction {
+                name: "a".to_owned(),
+                name_span: 0..0,
+                expression: Box::new(body),
+                expression_span: 0..0,
+            }),
+            (term, ty()).prop_map(|(expression, argument)| Expression::TypeApply {
+                expression: Box::new(expression),
+                expression_span: 0..0,
+                argument,
+            }),
+        ]
+    })
+}
+
+/// Sources made of the tokens of the language, which get much further into
+/// the parser than arbitrary text does.
+fn tokens() -> impl Strategy<Value = String> {
+    let token = prop::sample::select(
+        &[
+            "let",
+            "assert",
+            "assert_eq",
+            "infixl",
+            "infixr",
+            "syntax",
+            "data",
+            "if",
+            "then",
+            "else",
+            "match",
+            "6",
+            "+",
+            "*",
+            "x",
+            "f",
+            "\\",
+            "λ",
+            "/\\",
+            "forall",
+            "=>",
+            "->",
+            ".",
+            ":",
+            "=",
+            ";",
+            "(",
+            ")",
+            "[",
+            "]",
+            "{",
+            "}",
+            "|",
+            "_",
+            "$",
+            "-- c\n",
+            "\n",
+            "é",
+            "#",
+        ][..],
+    );
+    prop::collection::vec(token, 0..32).prop_map(|tokens| tokens.join(" "))
+}
+
+fn parse(source: &str) -> Option<Expression> {
+    let mut codemap = CodeMap::new();
+    let mut parser = Parser::new(source, "<test>", &mut codemap);
+    let expression = parser.parse_single_expression();
+    match parser.has_errors() {
+        true => None,
+        false => expression,
+    }
+}
+
+/// Types are compared by their printed form, which the type printer has
+/// tests of its own for.
+fn same_types(a: &Expression, b: &Expression) -> bool {
+    match (a, b) {
+        (Expression::Paren { expression, .. }, b) | (b, Expression::Paren { expression, .. }) => {
+            same_types(expression, b)
+        }
+        (Expression::Var { .. }, Expression::Var { .. }) => true,
+        (
+            Expression::Apply { lhs, rhs, .. },
+            Expression::Apply {
+                lhs: other_lhs,
+                rhs: other_rhs,
+                ..
+            },
+        ) => same_types(lhs, other_lhs) && same_types(rhs, other_rhs),
+        (
+            Expression::Abstraction {
+                annotation,
+                expression,
+                ..
+            },
+            Expression::Abstraction {
+                annotation: other_annotation,
+                expression: other_expression,
+                ..
+            },
+        ) => {
+            annotation.as_ref().map(ToString::to_string)
+                == other_annotation.as_ref().map(ToString::to_string)
+                && same_types(expression, other_expression)
+        }
+        (
+            Expression::TypeAbstraction { expression, .. },
+            Expression::TypeAbstraction {
+                expression: other_expression,
+                ..
+            },
+        ) => same_types(expression, other_expression),
+        (
+            Expression::TypeApply {
+                expression,
+                argument,
+                ..
+            },
+            Expression::TypeApply {
+                expression: other_expression,
+                argument: other_argument,
+                ..
+            },
+        ) => {
+            argument.to_string() == other_argument.to_string()
+                && same_types(expression, other_expression)
+        }
+        _ => false,
+    }
+}
+
+fn normalize(term: &Expression, strategy: reduce::Strategy) -> Option<Expression> {
+    let bindings = HashMap::new();
+    let mut reducer = Reducer::new(&bindings, 200);
+    reducer.strategy = strategy;
+    reducer.normalize(term).ok()
+}
+
+proptest! {
+    #[test]
+    fn printed_terms_parse_back(term in term()) {
+        let printed = format_expression(&term);
+        let parsed = parse(&printed);
+        prop_assert!(parsed.is_some(), "{} does not parse", printed);
+        prop_assert!(alpha_equivalent(&term, &parsed.unwrap()), "{}", printed);
+    }
+
+    #[test]
+    fn printed_typed_terms_parse_back(term in typed_term()) {
+        let printed = format_expression(&term);
+        let parsed = parse(&printed);
+        prop_assert!(parsed.is_some(), "{} does not parse", printed);
+        prop_assert!(same_types(&term, &parsed.unwrap()), "{}", printed);
+    }
+
+    /// Normal order finds the normal form whenever there is one, and normal
+    /// forms are unique, so any strategy that terminates agrees with it.
+    #[test]
+    fn strategies_are_confluent(term in term()) {
+        if let Some(by_value) = normalize(&term, reduce::Strategy::CallByValue) {
+            let by_name = normalize(&term, reduce::Strategy::NormalOrder);
+            prop_assert!(by_name.is_some(), "{}", term);
+            let by_name = by_name.unwrap();
+            prop_assert!(
+                alpha_equivalent(&by_name, &by_value),
+                "{}: {} and {}",
+                term,
+                by_name,
+                by_value
+            );
+        }
+    }
+
+    #[test]
+    fn normal_forms_are_normal(term in term()) {
+        if let Some(normal) = normalize(&term, reduce::Strategy::NormalOrder) {
+            let again = normalize(&normal, reduce::Strategy::NormalOrder);
+            prop_assert!(again.is_some_and(|again| alpha_equivalent(&normal, &again)));
+        }
+    }
+
+    #[test]
+    fn parser_does_not_panic_on_text(source in any::<String>()) {
+        Parser::new(&source, "<test>", &mut CodeMap::new()).parse();
+    }
+
+    #[test]
+    fn parser_does_not_panic_on_tokens(source in tokens()) {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(&source, "<test>", &mut codemap);
+        prop_assert_eq!(parser.parse_cst().text(), source.clone());
+
+        Parser::new(&source, "<test>", &mut CodeMap::new()).parse();
+        Parser::new(&source, "<test>", &mut CodeMap::new()).parse_single_expression();
+    }
+}
@@ -0,0 +1,85 @@
+//! Compares the backends on arithmetic and sorting with the prelude:
+//! `cargo bench -p gamma`.
+
+use std::time::{Duration, Instant};
+
+use gamma::{Backend, Session};
+
+const PRELUDE: &str = "
+    let true = \\x => \\y => x; let false = \\x => \\y => y;
+    let zero = \\f => \\x => x; let succ = \\n => \\f => \\x => f (n f x);
+    let one = succ zero; let two = succ one; let three = succ two;
+    let four = succ three; let five = succ four;
+    let plus = \\m => \\n => \\f => \\x => m f (n f x);
+    let mult = \\m => \\n => \\f => m (n f);
+    let pred = \\n => \\f => \\x => n (\\g => \\h => h (g f)) (\\u => x) (\\u => u);
+    let minus = \\m => \\n => n pred m;
+    let is_zero = \\n => n (\\x => false) true;
+    let leq = \\m => \\n => is_zero (minus m n);
+    let fix = \\f => (\\x => f (x x)) (\\x => f (x x));
+    let fact = fix (\\r => \\n => is_zero n one (mult n (r (pred n))));
+    let nil = \\n => \\c => n; let cons = \\h => \\t => \\n => \\c => c h t;
+    let insert = fix (\\insert => \\x => \\l =>
+        l (cons x nil) (\\h => \\t => leq x h (cons x l) (cons h (insert x t))));
+    let sort = fix (\\sort => \\l => l nil (\\h => \\t => insert h (sort t)));
+";
+
+const WORKLOADS: &[(&str, &str)] = &[
+    ("fact four", "fact four;"),
+    ("fact five", "fact five;"),
+    ("mult", "mult (plus five five) (mult four five);"),
+    (
+        "sort",
+        "sort (cons three (cons one (cons five (cons two (cons four nil)))));",
+    ),
+];
+
+const BACKENDS: &[(&str, Backend)] = &[
+    ("substitution", Backend::Substitution),
+    ("krivine", Backend::Krivine),
+    ("vm", Backend::Vm),
+    ("graph", Backend::Graph),
+];
+
+fn main() {
+    for (name, source) in WORKLOADS {
+        for (backend_name, backend) in BACKENDS {
+            // Every run gets a new session, so that none of them measures
+            // the state that the runs before it left behind.
+            let (runs, elapsed) = measure(
+                || {
+                    let mut session = Session::new();
+                    session.set_backend(*backend);
+                    session.set_reduction_limit(usize::MAX);
+                    session.load("prelude.gm", PRELUDE).unwrap();
+                    session
+                },
+                |session| {
+                    session.load("bench.gm", source).unwrap();
+                },
+            );
+            println!(
+                "{:<10} {:<13} {:>10.3} ms/run ({} runs)",
+                name,
+                backend_name,
+                elapsed.as_secs_f64() * 1000.0 / runs as f64,
+                runs
+            );
+        }
+    }
+}
+
+/// Runs `f` on inputs made by `setup` until it has taken about a second.
+/// Only the time spent in `f` counts.
+fn measure<T>(mut setup: impl FnMut() -> T, mut f: impl FnMut(&mut T)) -> (u32, Duration) {
+    let mut elapsed = Duration::ZERO;
+    let mut runs = 0;
+    while runs == 0 || elapsed < Duration::from_secs(1) {
+        let mut input = setup();
+        let start = Instant::now();
+        f(&mut input);
+        elapsed += start.elapsed();
+        runs += 1;
+    }
+    (runs, elapsed)
+}
@@ -0,0 +1,387 @@
+//! CEK machine: call-by-value evaluation with environments.
+//!
+//! The state of the machine is a control, an environment and a
+//! continuation. The control is either a term to evaluate, whose free
+//! variables the environment binds, or a value to return to the
+//! continuation, which is the stack of work left to do with it. A function
+//! is evaluated before its argument and the argument before the call, so
+//! results agree with [`Strategy::CallByValue`] substitution.
+//!
+//! Normal forms are read back like in [`crate::krivine`], by evaluating
+//! under each binder with the bound variable left as an unknown.
+//!
+//! [`Strategy::CallByValue`]: gamma_parser::reduce::Strategy::CallByValue
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use gamma_parser::format::format_expression;
+use gamma_parser::reduce::{Bindings, Error};
+
+use crate::eval::Term;
+use crate::readback::{self, Head, Shape};
+
+/// A term without spans, parentheses and types.
+#[derive(Debug)]
+enum Code {
+    Var(String),
+    Abstraction(String, Rc<Code>),
+    Apply(Rc<Code>, Rc<Code>),
+}
+
+impl Code {
+    fn compile(term: &Term) -> Code {
+        match term {
+            Term::Var { name, .. } => Code::Var(name.clone()),
+            Term::Apply { lhs, rhs, .. } | Term::Dollar { lhs, rhs, .. } => {
+                Code::Apply(Rc::new(Code::compile(lhs)), Rc::new(Code::compile(rhs)))
+            }
+            Term::Paren { expression, .. }
+            | Term::TypeAbstraction { expression, .. }
+            | Term::TypeApply { expression, .. } => Code::compile(expression),
+            Term::Abstraction {
+                name, expression, ..
+            } => Code::Abstraction(name.clone(), Rc::new(Code::compile(expression))),
+            Term::If { .. } | Term::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before compiling")
+            }
+        }
+    }
+
+    fn to_term(&self) -> Term {
+        match self {
+            Code::Var(name) => Term::Var {
+                name: name.clone(),
+                name_span: 0..0,
+            },
+            Code::Abstraction(name, body) => Term::Abstraction {
+                name: name.clone(),
+                name_span: 0..0,
+                annotation: None,
+                expression: Box::new(body.to_term()),
+                expression_span: 0..0,
+            },
+            Code::Apply(function, argument) => Term::Apply {
+                lhs: Box::new(function.to_term()),
+                lhs_span: 0..0,
+                rhs: Box::new(argument.to_term()),
+                rhs_span: 0..0,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Value {
+    Closure {
+        parameter: String,
+        body: Rc<Code>,
+        environment: Environment,
+    },
+    /// An unknown variable, named `name`, applied to arguments.
+    Neutral {
+        head: Head,
+        name: String,
+        arguments: Vec<Rc<Value>>,
+    },
+}
+
+impl Value {
+    fn to_term(&self) -> Term {
+        match self {
+            Value::Closure {
+                parameter, body, ..
+            } => Code::Abstraction(parameter.clone(), body.clone()).to_term(),
+            Value::Neutral {
+                name, arguments, ..
+            } => {
+                let head = Term::Var {
+                    name: name.clone(),
+                    name_span: 0..0,
+                };
+                arguments
+                    .iter()
+                    .fold(head, |function, argument| Term::Apply {
+                        lhs: Box::new(function),
+                        lhs_span: 0..0,
+                        rhs: Box::new(argument.to_term()),
+                        rhs_span: 0..0,
+                    })
+            }
+        }
+    }
+}
+
+/// Values of the variables in scope, the innermost first.
+#[derive(Clone, Debug, Default)]
+struct Environment(Option<Rc<(String, Rc<Value>, Environment)>>);
+
+impl Environment {
+    fn push(&self, name: String, value: Rc<Value>) -> Environment {
+        Environment(Some(Rc::new((name, value, self.clone()))))
+    }
+
+    fn get(&self, name: &str) -> Option<&Rc<Value>> {
+        let mut environment = self;
+        while let Some(entry) = &environment.0 {
+            if entry.0 == name {
+                return Some(&entry.1);
+            }
+            environment = &entry.2;
+        }
+
+        None
+    }
+
+    /// The visible variables, the innermost first.
+    fn entries(&self) -> Vec<(&str, &Rc<Value>)> {
+        let mut entries: Vec<(&str, &Rc<Value>)> = vec![];
+        let mut environment = self;
+        while let Some(entry) = &environment.0 {
+            if !entries.iter().any(|(name, _)| *name == entry.0) {
+                entries.push((&entry.0, &entry.1));
+            }
+            environment = &entry.2;
+        }
+
+        entries
+    }
+}
+
+impl Drop for Environment {
+    /// Drops long chains of environments without recursion.
+    fn drop(&mut self) {
+        let mut pending = vec![self.0.take()];
+        while let Some(environment) = pending.pop() {
+            if let Some(Ok((_, value, mut rest))) = environment.map(Rc::try_unwrap) {
+                if let Ok(Value::Closure {
+                    mut environment, ..
+                }) = Rc::try_unwrap(value)
+                {
+                    pending.push(environment.0.take());
+                }
+                pending.push(rest.0.take());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Control {
+    Eval(Rc<Code>, Environment),
+    Return(Rc<Value>),
+}
+
+/// Work left to do with the value being computed.
+#[derive(Debug)]
+enum Frame {
+    /// The value is a function; evaluate this argument next.
+    Argument(Rc<Code>, Environment),
+    /// The value is the argument of this function.
+    Call(Rc<Value>),
+}
+
+/// A state of the machine, shown by `--trace`.
+#[derive(Debug)]
+pub struct State {
+    control: Control,
+    /// The innermost frame last.
+    continuation: Vec<Frame>,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: &Value| format_expression(&value.to_term());
+
+        match &self.control {
+            Control::Eval(code, environment) => {
+                write!(f, "C = {}", format_expression(&code.to_term()))?;
+                let entries = environment
+                    .entries()
+                    .iter()
+                    .map(|(name, v)| format!("{} = {}", name, value(v)))
+                    .collect::<Vec<_>>();
+                write!(f, "  E = {{{}}}", entries.join(", "))?;
+            }
+            Control::Return(v) => write!(f, "V = {}", value(v))?,
+        }
+
+        write!(f, "  K = ")?;
+        for frame in self.continuation.iter().rev() {
+            match frame {
+                Frame::Argument(code, _) => {
+                    write!(f, "arg({}) : ", format_expression(&code.to_term()))?
+                }
+                Frame::Call(function) => write!(f, "call({}) : ", value(function))?,
+            }
+        }
+        write!(f, "halt")
+    }
+}
+
+/// Callback that receives every state of the machine.
+pub type Trace = dyn Fn(&State);
+
+/// Evaluates expressions with a CEK machine. Variables that name a binding
+/// are replaced with the bound expression when evaluation needs them.
+pub struct Machine<'a> {
+    bindings: &'a dyn Bindings,
+    /// Compiled values of the bindings used so far.
+    globals: HashMap<String, Rc<Code>>,
+    trace: Option<&'a Trace>,
+    /// Number of beta reductions and unfoldings of bindings performed so far.
+    pub steps: usize,
+    /// Number of steps after which evaluation gives up.
+    pub limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(bindings: &'a dyn Bindings, limit: usize) -> Self {
+        Self {
+            bindings,
+            globals: HashMap::new(),
+            trace: None,
+            steps: 0,
+            limit,
+        }
+    }
+
+    /// Calls `trace` with every state the machine goes through.
+    pub fn trace(&mut self, trace: &'a Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Reduces `term` to its normal form. Fails if that takes more than
+    /// `limit` steps, or if the normal form is too large.
+    pub fn normalize(&mut self, term: &Term) -> Result<Term, Error> {
+        let value = self
+            .evaluate(Rc::new(Code::compile(term)), Environment::default())
+            .ok_or(Error::Limit)?;
+        let normal = readback::read_back(value, Error::TooLarge, |value, depth| {
+            self.shape(&value, depth)
+        })?;
+        Ok(normal.to_term(self.bindings))
+    }
+
+    /// Runs the machine until `code` is evaluated to a value.
+    fn evaluate(&mut self, code: Rc<Code>, environment: Environment) -> Option<Rc<Value>> {
+        let mut state = State {
+            control: Control::Eval(code, environment),
+            continuation: vec![],
+        };
+
+        loop {
+            if let Some(trace) = self.trace {
+                trace(&state);
+            }
+
+            state.control = match state.control {
+                Control::Eval(code, environment) => match &*code {
+                    Code::Var(name) => match environment.get(name) {
+                        Some(value) => Control::Return(value.clone()),
+                        None => match self.global(name) {
+                            Some(code) => {
+                                self.step()?;
+                                Control::Eval(code, Environment::default())
+                            }
+                            None => Control::Return(Rc::new(Value::Neutral {
+                                head: Head::Global(name.clone()),
+                                name: name.clone(),
+                                arguments: vec![],
+                            })),
+                        },
+                    },
+                    Code::Abstraction(parameter, body) => {
+                        Control::Return(Rc::new(Value::Closure {
+                            parameter: parameter.clone(),
+                            body: body.clone(),
+                            environment,
+                        }))
+                    }
+                    Code::Apply(function, argument) => {
+                        state
+                            .continuation
+                            .push(Frame::Argument(argument.clone(), environment.clone()));
+                        Control::Eval(function.clone(), environment)
+                    }
+                },
+                Control::Return(value) => match state.continuation.pop() {
+                    None => return Some(value),
+                    Some(Frame::Argument(argument, environment)) => {
+                        state.continuation.push(Frame::Call(value));
+                        Control::Eval(argument, environment)
+                    }
+                    Some(Frame::Call(function)) => match &*function {
+                        Value::Closure {
+                            parameter,
+                            body,
+                            environment,
+                        } => {
+                            self.step()?;
+                            Control::Eval(body.clone(), environment.push(parameter.clone(), value))
+                        }
+                        Value::Neutral {
+                            head,
+                            name,
+                            arguments,
+                        } => {
+                            let mut arguments = arguments.clone();
+                            arguments.push(value);
+                            Control::Return(Rc::new(Value::Neutral {
+                                head: head.clone(),
+                                name: name.clone(),
+                                arguments,
+                            }))
+                        }
+                    },
+                },
+            };
+        }
+    }
+
+    /// Evaluates the body of `value`, found under `depth` abstractions, if
+    /// it is a closure, for read-back.
+    fn shape(&mut self, value: &Value, depth: usize) -> Result<Shape<Rc<Value>>, Error> {
+        match value {
+            Value::Closure {
+                parameter,
+                body,
+                environment,
+            } => {
+                let variable = Rc::new(Value::Neutral {
+                    head: Head::Level(depth),
+                    name: parameter.clone(),
+                    arguments: vec![],
+                });
+                let body = self
+                    .evaluate(body.clone(), environment.push(parameter.clone(), variable))
+                    .ok_or(Error::Limit)?;
+
+                Ok(Shape::Abstraction(parameter.clone(), body))
+            }
+            Value::Neutral {
+                head, arguments, ..
+            } => Ok(Shape::Neutral(head.clone(), arguments.clone())),
+        }
+    }
+
+    fn global(&mut self, name: &str) -> Option<Rc<Code>> {
+        if let Some(code) = self.globals.get(name) {
+            return Some(code.clone());
+        }
+
+        let code = Rc::new(Code::compile(self.bindings.binding(name)?));
+        self.globals.insert(name.to_owned(), code.clone());
+        Some(code)
+    }
+
+    fn step(&mut self) -> Option<()> {
+        if self.steps >= self.limit {
+            return None;
+        }
+
+        self.steps += 1;
+        Some(())
+    }
+}
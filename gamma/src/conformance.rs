@@ -0,0 +1,142 @@
+//! Conformance suite for the backends: each of them has to find the normal
+//! forms that substitution finds with the same strategy, up to the names of
+//! bound variables, and give up where substitution does. Optimal reduction
+//! may also refuse a case it does not handle, but never get it wrong.
+
+use codemap::CodeMap;
+use gamma_parser::parser::Parser;
+use gamma_parser::reduce::{alpha_equivalent, Reducer, Strategy};
+
+use crate::eval::{Backend, Evaluator};
+
+/// Definitions used by the cases.
+const PRELUDE: &str = "
+    let true = \\x => \\y => x; let false = \\x => \\y => y;
+    let not = \\b => b false true; let and = \\a => \\b => a b a;
+    let zero = \\f => \\x => x; let succ = \\n => \\f => \\x => f (n f x);
+    let one = succ zero; let two = succ one; let three = succ two;
+    let plus = \\m => \\n => \\f => \\x => m f (n f x);
+    let mult = \\m => \\n => \\f => m (n f);
+    let pow = \\b => \\e => e b;
+    let pred = \\n => \\f => \\x => n (\\g => \\h => h (g f)) (\\u => x) (\\u => u);
+    let is_zero = \\n => n (\\x => false) true;
+    let pair = \\a => \\b => \\s => s a b; let first = \\p => p true;
+    let fix = \\f => (\\x => f (x x)) (\\x => f (x x));
+    let fact = fix (\\r => \\n => is_zero n one (mult n (r (pred n))));
+    let z = \\f => (\\x => f (\\v => x x v)) (\\x => f (\\v => x x v));
+    let fact_z = z (\\r => \\n =>
+        (if is_zero n then \\u => one else \\u => mult n (r (pred n))) (\\x => x));
+    let omega = (\\x => x x) (\\x => x x);
+";
+
+/// Expressions, and whether they have a normal form in normal order and
+/// in call-by-value.
+const CASES: &[(&str, bool, bool)] = &[
+    ("\\x => x", true, true),
+    ("free variables", true, true),
+    ("not true", true, true),
+    ("and true (not false)", true, true),
+    ("plus two three", true, true),
+    ("mult three three", true, true),
+    ("pow two three", true, true),
+    ("pred (pred three)", true, true),
+    ("first (pair one omega)", true, false),
+    ("true one omega", true, false),
+    ("fact three", true, false),
+    ("fact_z three", true, true),
+    ("(\\x => \\y => x) y", true, true),
+    ("(\\f => \\x => f x) x", true, true),
+    ("\\true => not true", true, true),
+    ("\\x => (\\y => \\x => y) x", true, true),
+    ("\\x => \\y => (\\z => z x y) (\\a => \\b => b)", true, true),
+    ("two two", true, true),
+    ("/\\a => \\x: a => x", true, true),
+    ("omega", false, false),
+    ("fix (\\x => x)", false, false),
+    ("\\x => omega", false, false),
+    ("free omega", false, false),
+];
+
+const LIMIT: usize = 10_000;
+
+/// Checks that `backend` agrees with substitution on every case.
+fn conforms(backend: Backend) {
+    let mut codemap = CodeMap::new();
+    let mut evaluator = Evaluator::new();
+    evaluator.backend = backend;
+    evaluator.limit = LIMIT;
+
+    let mut parser = Parser::new(PRELUDE, "prelude.gm", &mut codemap);
+    let (prelude, file_span) = (parser.parse(), parser.file_span);
+    assert!(!parser.has_errors(), "{:?}", parser.diagnostics);
+    drop(parser);
+    for statement in &prelude {
+        evaluator.eval_statement(statement, file_span).unwrap();
+    }
+
+    for &(source, by_name, by_value) in CASES {
+        let mut parser = Parser::new(source, "<case>", &mut codemap);
+        let file_span = parser.file_span;
+        let expression = parser.parse_single_expression().unwrap();
+        drop(parser);
+
+        let mut reducer = Reducer::new(&evaluator.context, LIMIT);
+        reducer.strategy = backend.strategy();
+        let expected = reducer.normalize(&expression);
+        let normalizes = match backend.strategy() {
+            Strategy::NormalOrder => by_name,
+            Strategy::CallByValue => by_value,
+        };
+        assert_eq!(expected.is_ok(), normalizes, "{}", source);
+
+        match (expected, evaluator.eval_expression(&expression, file_span)) {
+            (Ok(expected), Ok(actual)) => assert!(
+                alpha_equivalent(&expected, &actual),
+                "{}: expected {}, found {}",
+                source,
+                expected,
+                actual
+            ),
+            (Err(_), Err(diagnostics)) if diagnostics[0].code.as_deref() == Some("E004") => {}
+            (_, Err(diagnostics))
+                if matches!(backend, Backend::Optimal)
+                    && diagnostics[0].code.as_deref() == Some("E011") => {}
+            (expected, actual) => panic!(
+                "{}: expected {:?}, found {:?}",
+                source,
+                expected.map(|term| term.to_string()),
+                actual.map(|term| term.to_string())
+            ),
+        }
+    }
+}
+
+#[test]
+fn substitution() {
+    conforms(Backend::Substitution);
+}
+
+#[test]
+fn krivine() {
+    conforms(Backend::Krivine);
+}
+
+#[test]
+fn cek() {
+    conforms(Backend::Cek);
+}
+
+#[test]
+fn vm() {
+    conforms(Backend::Vm);
+}
+
+#[test]
+fn graph() {
+    conforms(Backend::Graph);
+}
+
+#[test]
+fn optimal() {
+    conforms(Backend::Optimal);
+}
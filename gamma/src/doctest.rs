@@ -0,0 +1,224 @@
+//! Doctests: evaluates the Gamma code blocks of Markdown files, like the
+//! tutorials, and checks the results they claim.
+//!
+//! Code blocks fenced with ```` ```gamma ```` are evaluated in order, so a
+//! block can use the bindings of the blocks before it. An expression
+//! statement followed by a `-- expect: <normal form>` comment on the same
+//! line must reduce to that normal form. Everything else in the file is
+//! blanked before it is parsed, so diagnostics point to lines of the Markdown
+//! file.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use codemap::CodeMap;
+use codemap_diagnostic::{Diagnostic, Emitter, Level, SpanLabel, SpanStyle};
+use gamma_parser::ast::Statement;
+use gamma_parser::parser::Parser;
+
+use crate::eval::Evaluator;
+use crate::golden::{expectation, find_files, normalize_newlines, Outcome};
+
+/// Runs the doctests of every Markdown file in `directory` and its
+/// subdirectories, sorted by path, or of `directory` itself if it is a file.
+pub fn test_path(path: &Path) -> io::Result<Vec<(PathBuf, Outcome)>> {
+    let mut files = vec![];
+    match path.is_dir() {
+        true => find_files(path, "md", &mut files)?,
+        false => files.push(path.to_owned()),
+    }
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|file| {
+            let source = fs::read_to_string(&file)?;
+            let outcome = test_markdown(&file.to_string_lossy(), &source);
+            Ok((file, outcome))
+        })
+        .collect()
+}
+
+/// Evaluates the code blocks of the Markdown file `source`, registered under
+/// `name`. Failures are reported with the rendered diagnostics.
+pub fn test_markdown(name: &str, source: &str) -> Outcome {
+    let code = code_blocks(source);
+    let mut codemap = CodeMap::new();
+    let mut parser = Parser::new(&code, name, &mut codemap);
+    let ast = parser.parse();
+    let file_span = parser.file_span;
+    let mut diagnostics = std::mem::take(&mut parser.diagnostics);
+    let comments = std::mem::take(&mut parser.comments);
+    drop(parser);
+
+    let span =
+        |span: &std::ops::Range<usize>| file_span.subspan(span.start as u64, span.end as u64);
+    let mut expectations: Vec<_> = comments
+        .iter()
+        .filter_map(|comment| Some((comment, expectation(&comment.literal)?)))
+        .collect();
+
+    if !diagnostics.iter().any(is_error) {
+        let mut evaluator = Evaluator::new();
+        for statement in &ast {
+            let term = match evaluator.eval_statement(statement, file_span) {
+                Ok(term) => term,
+                Err(errors) => {
+                    diagnostics.extend(errors);
+                    break;
+                }
+            };
+
+            let (term, expression_span, statement_span) = match (term, statement) {
+                (
+                    Some(term),
+                    Statement::Expression {
+                        expression_span,
+                        span,
+                        ..
+                    },
+                ) => (term, expression_span, span),
+                _ => continue,
+            };
+
+            // The comment has to be on the line the statement ends on.
+            let index = expectations.iter().position(|(comment, _)| {
+                comment.span.start >= statement_span.end
+                    && !code[statement_span.end..comment.span.start].contains('\n')
+            });
+            let Some((comment, expected)) = index.map(|index| expectations.remove(index)) else {
+                continue;
+            };
+
+            let found = term.to_string();
+            if found != expected {
+                diagnostics.push(Diagnostic {
+                    level: Level::Error,
+                    message: "unexpected normal form".to_owned(),
+                    code: None,
+                    spans: vec![
+                        SpanLabel {
+                            span: span(expression_span),
+                            style: SpanStyle::Primary,
+                            label: Some(format!("reduces to `{}`", found)),
+                        },
+                        SpanLabel {
+                            span: span(&comment.span),
+                            style: SpanStyle::Secondary,
+                            label: Some(format!("expected `{}`", expected)),
+                        },
+                    ],
+                });
+            }
+        }
+
+        // Comments after a failure were never reached, the others do not
+        // follow an expression.
+        if !diagnostics.iter().any(is_error) {
+            diagnostics.extend(expectations.iter().map(|(comment, _)| Diagnostic {
+                level: Level::Error,
+                message: "expectation without an expression".to_owned(),
+                code: None,
+                spans: vec![SpanLabel {
+                    span: span(&comment.span),
+                    style: SpanStyle::Primary,
+                    label: Some("put this after an expression statement".to_owned()),
+                }],
+            }));
+        }
+    }
+
+    if diagnostics.is_empty() {
+        return Outcome::Passed;
+    }
+
+    let mut report = vec![];
+    Emitter::vec(&mut report, Some(&codemap)).emit(&diagnostics);
+    Outcome::Failed(String::from_utf8_lossy(&report).into_owned())
+}
+
+fn is_error(diagnostic: &Diagnostic) -> bool {
+    matches!(diagnostic.level, Level::Bug | Level::Error)
+}
+
+/// `source` with every line outside of ```` ```gamma ```` blocks emptied, so
+/// that the code keeps its line numbers.
+fn code_blocks(source: &str) -> String {
+    let mut code = String::new();
+    let mut in_block = false;
+    for line in normalize_newlines(source).split_inclusive('\n') {
+        let text = line.trim_end_matches('\n');
+        let fence = text.trim_start().strip_prefix("```");
+        match (in_block, fence) {
+            (false, Some(info)) => {
+                in_block = info.split([' ', ',']).next() == Some("gamma");
+            }
+            (true, Some("")) => in_block = false,
+            (true, _) => code += text,
+            (false, None) => {}
+        }
+
+        if line.ends_with('\n') {
+            code.push('\n');
+        }
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod doctest_tests {
+    use super::*;
+
+    const BOOLEANS: &str = "# Booleans
+
+```gamma
+let true = \\x => \\y => x;
+let false = \\x => \\y => y;
+```
+
+Negation:
+```gamma
+let not = \\b => b false true;
+not true; -- expect: \\x => \\y => y
+```
+";
+
+    #[test]
+    fn blocks_keep_their_lines() {
+        assert_eq!(
+            code_blocks("text\n```gamma\na;\n```\n```ocaml\nb;\n```\n"),
+            "\n\na;\n\n\n\n\n"
+        );
+    }
+
+    #[test]
+    fn expectations() {
+        assert_eq!(test_markdown("booleans.md", BOOLEANS), Outcome::Passed);
+
+        let wrong = BOOLEANS.replace("b false true", "b true false");
+        match test_markdown("booleans.md", &wrong) {
+            Outcome::Failed(report) => {
+                assert!(report.contains("--> booleans.md:11:1"), "{}", report);
+                assert!(
+                    report.contains("reduces to `\\x => \\y => x`"),
+                    "{}",
+                    report
+                );
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn stray_expectations() {
+        let source = "```gamma\nlet a = \\x => x; -- expect: a\n```\n";
+        match test_markdown("stray.md", source) {
+            Outcome::Failed(report) => {
+                assert!(report.contains("expectation without an expression"))
+            }
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+}
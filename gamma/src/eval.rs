@@ -1,122 +1,633 @@
-use codemap::CodeMap;
-use codemap_diagnostic::{ColorConfig, Diagnostic, Emitter, Level, SpanLabel, SpanStyle};
-use gamma_parser::{ast, parser::Parser};
-use std::io::Write;
-use std::{collections::HashMap, process::exit};
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
-type Context = HashMap<String, (ast::Span, ast::Expression, ast::Span)>;
-
-pub struct Evaluator<'a> {
-    file_span: codemap::Span,
-    context: Context,
-    ast: ast::AST,
-    emitter: Emitter<'a>,
-}
-
-impl<'a> Evaluator<'a> {
-    pub fn new(source: &'a str, filename: &'a str, codemap: &'a mut CodeMap) -> Self {
-        let context = HashMap::new();
-        let mut parser = Parser::new(source, filename, codemap);
-        let ast = parser.parse();
-
-        let file_span = parser.file_span;
-        let emitter = Emitter::stderr(ColorConfig::Always, Some(parser.codemap));
+use codemap_diagnostic::{Diagnostic, Level, SpanLabel, SpanStyle};
+use gamma_parser::data::{self, DataTypes};
+use gamma_parser::macros::{self, Macros};
+use gamma_parser::{ast, infer, reduce, resolve, stlc, system_f};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
+use crate::{cek, graph, krivine, optimal, vm};
+
+/// A lambda term, e.g. the value of a binding or a normal form.
+pub type Term = ast::Expression;
+
+/// Default number of beta reductions after which evaluation of an expression
+/// gives up.
+pub const REDUCTION_LIMIT: usize = 100_000;
+
+struct Binding {
+    term: Term,
+    /// Spans of the name and the value in the source. Bindings defined by the
+    /// host program have none.
+    spans: Option<(codemap::Span, codemap::Span)>,
+}
+
+/// Variables defined with `let` or by the host program.
+#[derive(Default)]
+pub struct Context {
+    bindings: HashMap<String, Binding>,
+}
+
+impl Context {
+    pub fn get(&self, name: &str) -> Option<&Term> {
+        self.bindings.get(name).map(|binding| &binding.term)
+    }
+
+    /// `term` with every variable defined in the context replaced by its
+    /// value, recursively. A variable whose value refers back to it stays.
+    pub fn expand(&self, term: &Term) -> Term {
+        self.expand_avoiding(term, &mut vec![])
+    }
+
+    fn expand_avoiding(&self, term: &Term, expanding: &mut Vec<String>) -> Term {
+        let mut names: Vec<_> = reduce::free_variables(term).into_iter().collect();
+        names.sort();
+
+        names
+            .into_iter()
+            .fold(term.clone(), |term, name| match self.get(&name) {
+                Some(value) if !expanding.contains(&name) => {
+                    expanding.push(name);
+                    let value = self.expand_avoiding(value, expanding);
+                    let name = expanding.pop().unwrap();
+                    reduce::substitute(&term, &name, &value)
+                }
+                _ => term,
+            })
+    }
+}
+
+impl reduce::Bindings for Context {
+    fn binding(&self, name: &str) -> Option<&ast::Expression> {
+        self.get(name)
+    }
+}
+
+/// Type discipline that statements are checked against before evaluation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Typing {
+    Untyped,
+    /// Simply typed lambda calculus, see [`gamma_parser::stlc`].
+    Stlc,
+    /// Hindley–Milner type inference, see [`gamma_parser::infer`].
+    Hm,
+    /// Polymorphic lambda calculus, see [`gamma_parser::system_f`]. Terms
+    /// are evaluated after erasing their types.
+    SystemF,
+}
+
+impl FromStr for Typing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "untyped" => Ok(Typing::Untyped),
+            "stlc" => Ok(Typing::Stlc),
+            "hm" => Ok(Typing::Hm),
+            "system-f" => Ok(Typing::SystemF),
+            _ => Err(format!("unknown type system `{}`", s)),
+        }
+    }
+}
+
+/// Machine that reduces expressions to their normal forms. All of them
+/// find the same normal forms, up to the names of bound variables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Normal-order reduction by substitution, see [`gamma_parser::reduce`].
+    Substitution,
+    /// Krivine machine, see [`crate::krivine`].
+    Krivine,
+    /// CEK machine, see [`crate::cek`]. Arguments are evaluated before the
+    /// call, so some expressions with a normal form have none here.
+    Cek,
+    /// Bytecode virtual machine, see [`crate::vm`].
+    Vm,
+    /// Graph reduction, see [`crate::graph`].
+    Graph,
+    /// Optimal reduction with interaction nets, see [`crate::optimal`].
+    /// Only handles some terms.
+    Optimal,
+}
+
+impl Backend {
+    /// Strategy of substitution that finds the same normal forms.
+    pub fn strategy(self) -> reduce::Strategy {
+        match self {
+            Backend::Substitution
+            | Backend::Krivine
+            | Backend::Vm
+            | Backend::Graph
+            | Backend::Optimal => reduce::Strategy::NormalOrder,
+            Backend::Cek => reduce::Strategy::CallByValue,
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "substitution" => Ok(Backend::Substitution),
+            "krivine" => Ok(Backend::Krivine),
+            "cek" => Ok(Backend::Cek),
+            "vm" => Ok(Backend::Vm),
+            "graph" => Ok(Backend::Graph),
+            "optimal" => Ok(Backend::Optimal),
+            _ => Err(format!("unknown backend `{}`", s)),
+        }
+    }
+}
+
+/// Work a backend did to reduce an expression, shown by `--stats`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Statistics {
+    Substitution { beta_steps: usize },
+    Graph(graph::Statistics),
+    Optimal(optimal::Statistics),
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statistics::Substitution { beta_steps } => write!(f, "{} beta steps", beta_steps),
+            Statistics::Graph(statistics) => statistics.fmt(f),
+            Statistics::Optimal(statistics) => statistics.fmt(f),
+        }
+    }
+}
+
+/// Callback that receives the statistics of every expression.
+pub type Report = dyn Fn(&Statistics);
+
+/// Evaluates statements one by one, keeping the variables they define.
+pub struct Evaluator {
+    pub context: Context,
+    /// Syntax rules, expanded in every statement before it is checked.
+    pub macros: Macros,
+    /// Data types, whose `match`es are desugared after syntax rules are
+    /// expanded.
+    pub data: DataTypes,
+    /// Number of beta reductions after which evaluation of an expression
+    /// gives up.
+    pub limit: usize,
+    pub typing: Typing,
+    pub backend: Backend,
+    /// Called with every state of the CEK machine.
+    pub trace: Option<Box<cek::Trace>>,
+    /// Called with the work done for every expression by the backends that
+    /// count it.
+    pub statistics: Option<Box<Report>>,
+    stlc: stlc::Checker,
+    system_f: system_f::Checker,
+    /// Inferred types of the bindings, kept in every mode for
+    /// [`Evaluator::type_of`].
+    hm: infer::Inference,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
         Self {
-            file_span,
-            context,
-            ast,
-            emitter,
+            context: Context::default(),
+            macros: Macros::new(),
+            data: DataTypes::new(),
+            limit: REDUCTION_LIMIT,
+            typing: Typing::Untyped,
+            backend: Backend::Substitution,
+            trace: None,
+            statistics: None,
+            stlc: stlc::Checker::new(),
+            system_f: system_f::Checker::new(),
+            hm: infer::Inference::new(),
         }
     }
 
-    pub fn eval(&mut self) -> bool {
-        for statement in self.ast.to_owned() {
-            if !self.eval_statement(statement) {
-                let mut stdout = StandardStream::stdout(ColorChoice::Always);
-                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true));
-                writeln!(
-                    &mut stdout,
-                    "error: aborting due to error occured in execution process"
-                );
-                stdout.set_color(ColorSpec::new().set_fg(None));
-                return false;
+    /// Defines `name` without a `let` statement, replacing and returning its
+    /// previous value. A `let` of the same name is then a redefinition. In
+    /// typed modes the term keeps its type, if it has one.
+    pub fn define(&mut self, name: &str, term: Term) -> Option<Term> {
+        if let Ok(ty) = self.stlc.infer(&term) {
+            self.stlc.declare(name, ty);
+        }
+        if let Ok(ty) = self.system_f.infer(&term) {
+            self.system_f.declare(name, ty);
+        }
+        self.hm.declare(name, &term);
+
+        self.context
+            .bindings
+            .insert(name.to_owned(), Binding { term, spans: None })
+            .map(|binding| binding.term)
+    }
+
+    /// Type-checks a statement of the file at `file_span`, whose syntax rules
+    /// are expanded, according to [`Evaluator::typing`].
+    pub fn check_statement(
+        &mut self,
+        statement: &ast::Statement,
+        file_span: codemap::Span,
+    ) -> Result<(), Vec<Diagnostic>> {
+        // Syntax rules have no type, only their uses do. Data types are
+        // checked as the `let`s of their constructors, which only
+        // Hindley–Milner can type without annotations.
+        if let ast::Statement::Syntax { .. } = statement {
+            return Ok(());
+        }
+        if let ast::Statement::Data { span, .. } = statement {
+            let typing = match self.typing {
+                Typing::Stlc => "stlc",
+                Typing::SystemF => "system-f",
+                Typing::Untyped | Typing::Hm => return Ok(()),
+            };
+            let error = data::DataError::Typed {
+                span: span.clone(),
+                typing: typing.to_owned(),
+            };
+            return Err(vec![error.diagnostic(file_span)]);
+        }
+
+        match self.typing {
+            Typing::Untyped => Ok(()),
+            Typing::Stlc => self
+                .stlc
+                .check_statement(statement)
+                .map(|_| ())
+                .map_err(|error| vec![error.diagnostic(file_span)]),
+            Typing::Hm => self
+                .hm
+                .check_statement(statement)
+                .map(|_| ())
+                .map_err(|error| vec![error.diagnostic(file_span)]),
+            Typing::SystemF => self
+                .system_f
+                .check_statement(statement)
+                .map(|_| ())
+                .map_err(|error| vec![error.diagnostic(file_span)]),
+        }
+    }
+
+    /// `expression`, found at `span` in the file at `file_span`, with its
+    /// syntax rules expanded and its `if`s and `match`es desugared.
+    pub fn expand(
+        &self,
+        expression: &Term,
+        span: &ast::Span,
+        file_span: codemap::Span,
+    ) -> Result<Term, Vec<Diagnostic>> {
+        let (expanded, expansions) = self
+            .macros
+            .expand(expression, span)
+            .map_err(|error| error.diagnostics(file_span))?;
+
+        self.data.desugar(&expanded).map_err(|error| {
+            macros::annotate(vec![error.diagnostic(file_span)], &expansions, file_span)
+        })
+    }
+
+    /// Type of `expression`, found at `span` in the file at `file_span`, in
+    /// the type system of [`Evaluator::typing`]. Untyped code gets its
+    /// principal type from Hindley–Milner inference.
+    pub fn type_of(
+        &self,
+        expression: &Term,
+        span: &ast::Span,
+        file_span: codemap::Span,
+    ) -> Result<ast::Type, Vec<Diagnostic>> {
+        let (expression, expansions) = self
+            .macros
+            .expand(expression, span)
+            .map_err(|error| error.diagnostics(file_span))?;
+
+        let result = self
+            .data
+            .desugar(&expression)
+            .map_err(|error| vec![error.diagnostic(file_span)])
+            .and_then(|expression| match self.typing {
+                Typing::Untyped | Typing::Hm => self
+                    .hm
+                    .infer(&expression)
+                    .map_err(|error| vec![error.diagnostic(file_span)]),
+                Typing::Stlc => self
+                    .stlc
+                    .infer(&expression)
+                    .map_err(|error| vec![error.diagnostic(file_span)]),
+                Typing::SystemF => self
+                    .system_f
+                    .infer(&expression)
+                    .map_err(|error| vec![error.diagnostic(file_span)]),
+            });
+
+        result.map_err(|diagnostics| macros::annotate(diagnostics, &expansions, file_span))
+    }
+
+    /// Type-checks and evaluates a statement of the file at `file_span`.
+    /// Returns the normal form of expression statements and `None` for
+    /// `let`s, syntax rules, data types and assertions that hold.
+    pub fn eval_statement(
+        &mut self,
+        statement: &ast::Statement,
+        file_span: codemap::Span,
+    ) -> Result<Option<Term>, Vec<Diagnostic>> {
+        if let ast::Statement::Syntax { .. } = statement {
+            self.macros
+                .define(statement, file_span)
+                .map_err(|error| error.diagnostics(file_span))?;
+            return Ok(None);
+        }
+
+        // A data type is defined by `let`s of its constructors, and `match`
+        // knows about it once they all are.
+        if let ast::Statement::Data { .. } = statement {
+            self.check_statement(statement, file_span)?;
+            let definitions =
+                data::definitions(statement).map_err(|error| vec![error.diagnostic(file_span)])?;
+            for definition in &definitions {
+                self.eval_expanded(definition, file_span)?;
             }
+
+            self.data.declare(statement);
+            return Ok(None);
         }
 
-        true
+        let (statement, expansions) = self
+            .macros
+            .expand_statement(statement)
+            .map_err(|error| error.diagnostics(file_span))?;
+
+        self.data
+            .desugar_statement(&statement)
+            .map_err(|error| vec![error.diagnostic(file_span)])
+            .and_then(|statement| self.eval_expanded(&statement, file_span))
+            .map_err(|diagnostics| macros::annotate(diagnostics, &expansions, file_span))
     }
 
-    fn eval_statement(&mut self, statement: ast::Statement) -> bool {
+    fn eval_expanded(
+        &mut self,
+        statement: &ast::Statement,
+        file_span: codemap::Span,
+    ) -> Result<Option<Term>, Vec<Diagnostic>> {
+        let span = |span: &ast::Span| file_span.subspan(span.start as u64, span.end as u64);
+
+        if let ast::Statement::Let {
+            name,
+            name_span,
+            expression_span,
+            ..
+        } = statement
+        {
+            if let Some(previous) = self.context.bindings.get(name) {
+                return Err(resolve::redefinition(
+                    name,
+                    span(name_span),
+                    span(expression_span),
+                    previous.spans.map(|(_, expression_span)| expression_span),
+                ));
+            }
+        }
+
+        self.check_statement(statement, file_span)?;
+
+        let term = |expression: &Term| match self.typing {
+            Typing::SystemF => system_f::erase(expression),
+            _ => expression.clone(),
+        };
+
         match statement {
             ast::Statement::Let {
                 name,
                 name_span,
                 expression,
                 expression_span,
-                span: _,
+                ..
             } => {
-                if self.context.contains_key(&name) {
-                    self.emitter.emit(&[
-                        Diagnostic {
-                            level: Level::Error,
-                            message: "trying to redefine existing variable".to_owned(),
-                            spans: vec![
-                                SpanLabel {
-                                    span: self.logos_to_codemap_span(&name_span),
-                                    style: SpanStyle::Primary,
-                                    label: Some(
-                                        format!("trying to overwrite `{}`", name).to_owned(),
-                                    ),
-                                },
-                                SpanLabel {
-                                    span: self.logos_to_codemap_span(&expression_span),
-                                    style: SpanStyle::Secondary,
-                                    label: Some("new value".to_owned()),
-                                },
-                            ],
-
-                            code: Some("E003".to_owned()),
-                        },
-                        Diagnostic {
-                            level: Level::Note,
-                            message: format!("variable `{}` was firstly defined here", name)
-                                .to_owned(),
-                            spans: vec![SpanLabel {
-                                span: self
-                                    .logos_to_codemap_span(&self.context.get(&name).unwrap().2),
+                if self.typing != Typing::Hm {
+                    let _ = self.hm.check_statement(statement);
+                }
+
+                let term = term(expression);
+                self.context.bindings.insert(
+                    name.clone(),
+                    Binding {
+                        term,
+                        spans: Some((span(name_span), span(expression_span))),
+                    },
+                );
+
+                Ok(None)
+            }
+            ast::Statement::Expression {
+                expression,
+                expression_span,
+                ..
+            } => self
+                .eval_expression(&term(expression), span(expression_span))
+                .map(Some),
+            ast::Statement::Assert {
+                expression,
+                expression_span,
+                ..
+            } => {
+                let normal = self.eval_expression(&term(expression), span(expression_span))?;
+                match reduce::alpha_equivalent(&normal, &church_true()) {
+                    true => Ok(None),
+                    false => Err(vec![assertion_failed(
+                        "assertion failed: expression is not `true`",
+                        vec![SpanLabel {
+                            span: span(expression_span),
+                            style: SpanStyle::Primary,
+                            label: Some(format!(
+                                "reduces to `{}` instead of `{}`",
+                                normal,
+                                church_true()
+                            )),
+                        }],
+                    )]),
+                }
+            }
+            ast::Statement::AssertEq {
+                lhs,
+                lhs_span,
+                rhs,
+                rhs_span,
+                ..
+            } => {
+                let lhs_normal = self.eval_expression(&term(lhs), span(lhs_span))?;
+                let rhs_normal = self.eval_expression(&term(rhs), span(rhs_span))?;
+                match reduce::alpha_equivalent(&lhs_normal, &rhs_normal) {
+                    true => Ok(None),
+                    false => Err(vec![assertion_failed(
+                        "assertion failed: normal forms differ",
+                        vec![
+                            SpanLabel {
+                                span: span(lhs_span),
                                 style: SpanStyle::Primary,
-                                label: Some("previous value".to_owned()),
-                            }],
-                            code: Some("N003".to_owned()),
-                        },
-                        Diagnostic {
-                            level: Level::Note,
-                            message: "consider renaming the variable".to_owned(),
-                            spans: vec![SpanLabel {
-                                span: self.logos_to_codemap_span(&name_span),
+                                label: Some(format!("reduces to `{}`", lhs_normal)),
+                            },
+                            SpanLabel {
+                                span: span(rhs_span),
                                 style: SpanStyle::Primary,
-                                label: Some(format!("rename `{}` here", name).to_owned()),
-                            }],
-                            code: Some("N003".to_owned()),
-                        },
-                    ]);
-
-                    return false;
+                                label: Some(format!("reduces to `{}`", rhs_normal)),
+                            },
+                        ],
+                    )]),
                 }
+            }
+            ast::Statement::Syntax { .. } | ast::Statement::Data { .. } => Ok(None),
+        }
+    }
 
-                self.context
-                    .insert(name, (name_span, expression, expression_span));
+    /// Reduces `expression`, found at `span`, to its normal form.
+    pub fn eval_expression(
+        &self,
+        expression: &Term,
+        span: codemap::Span,
+    ) -> Result<Term, Vec<Diagnostic>> {
+        match self.backend {
+            Backend::Substitution => {
+                let mut reducer = reduce::Reducer::new(&self.context, self.limit);
+                let normal = reducer.normalize(expression);
+                self.report(Statistics::Substitution {
+                    beta_steps: reducer.beta_steps,
+                });
+                normal
+            }
+            Backend::Krivine => {
+                krivine::Machine::new(&self.context, self.limit).normalize(expression)
+            }
+            Backend::Cek => {
+                let mut machine = cek::Machine::new(&self.context, self.limit);
+                if let Some(trace) = &self.trace {
+                    machine.trace(trace.as_ref());
+                }
+                machine.normalize(expression)
+            }
+            Backend::Vm => {
+                let normal = vm::Machine::new(&self.context, self.limit).normalize(expression);
+                return normal.map_err(|error| match error {
+                    vm::Error::Limit => vec![no_normal_form(self.limit, span)],
+                    vm::Error::Loop => vec![infinite_loop(span)],
+                    vm::Error::TooLarge => vec![too_large(span)],
+                });
+            }
+            Backend::Graph => {
+                let mut machine = graph::Machine::new(&self.context, self.limit);
+                let normal = machine.normalize(expression);
+                self.report(Statistics::Graph(machine.statistics));
+                normal
+            }
+            Backend::Optimal => {
+                let mut machine = optimal::Machine::new(&self.context, self.limit);
+                let normal = machine.normalize(expression);
+                self.report(Statistics::Optimal(machine.statistics));
+                return normal.map_err(|error| match error {
+                    optimal::Error::Limit => vec![no_normal_form(self.limit, span)],
+                    optimal::Error::TooLarge => vec![too_large(span)],
+                    error => vec![unsupported_by_optimal(&error, span)],
+                });
             }
-            _ => {}
         }
+        .map_err(|error| match error {
+            reduce::Error::Limit => vec![no_normal_form(self.limit, span)],
+            reduce::Error::TooLarge => vec![too_large(span)],
+        })
+    }
 
-        true
+    fn report(&self, statistics: Statistics) {
+        if let Some(report) = &self.statistics {
+            report(&statistics);
+        }
     }
+}
+
+/// Error for an expression at `span` that did not reach a normal form within
+/// `limit` steps.
+pub fn no_normal_form(limit: usize, span: codemap::Span) -> Diagnostic {
+    Diagnostic {
+        level: Level::Error,
+        message: format!("no normal form found within {} reduction steps", limit),
+        code: Some("E004".to_owned()),
+        spans: vec![SpanLabel {
+            span,
+            style: SpanStyle::Primary,
+            label: Some("while reducing this expression".to_owned()),
+        }],
+    }
+}
+
+/// Error for an expression at `span` that needs its own value to reduce.
+pub fn infinite_loop(span: codemap::Span) -> Diagnostic {
+    Diagnostic {
+        level: Level::Error,
+        message: "infinite loop: the expression needs its own value".to_owned(),
+        code: Some("E017".to_owned()),
+        spans: vec![SpanLabel {
+            span,
+            style: SpanStyle::Primary,
+            label: Some("while reducing this expression".to_owned()),
+        }],
+    }
+}
+
+/// Error for an expression at `span` whose normal form, or a term on the way
+/// to it, is too large.
+pub fn too_large(span: codemap::Span) -> Diagnostic {
+    Diagnostic {
+        level: Level::Error,
+        message: "term too large: reduction gave up on it".to_owned(),
+        code: Some("E018".to_owned()),
+        spans: vec![SpanLabel {
+            span,
+            style: SpanStyle::Primary,
+            label: Some("while reducing this expression".to_owned()),
+        }],
+    }
+}
+
+/// `\x => \y => x`, the Church boolean `assert` expects.
+fn church_true() -> Term {
+    let var = |name: &str| Term::Var {
+        name: name.to_owned(),
+        name_span: 0..0,
+    };
+    let abstraction = |name: &str, expression| Term::Abstraction {
+        name: name.to_owned(),
+        name_span: 0..0,
+        annotation: None,
+        expression: Box::new(expression),
+        expression_span: 0..0,
+    };
+
+    abstraction("x", abstraction("y", var("x")))
+}
+
+/// Error for an `assert` or `assert_eq` that does not hold, with the normal
+/// forms in `labels`.
+fn assertion_failed(message: &str, labels: Vec<SpanLabel>) -> Diagnostic {
+    Diagnostic {
+        level: Level::Error,
+        message: message.to_owned(),
+        code: Some("E012".to_owned()),
+        spans: labels,
+    }
+}
 
-    fn logos_to_codemap_span(&self, logos_span: &ast::Span) -> codemap::Span {
-        return self
-            .file_span
-            .subspan(logos_span.start as u64, logos_span.end as u64);
+/// Error for an expression at `span` that optimal reduction gave up on.
+pub fn unsupported_by_optimal(error: &optimal::Error, span: codemap::Span) -> Diagnostic {
+    Diagnostic {
+        level: Level::Error,
+        message: error.to_string(),
+        code: Some("E011".to_owned()),
+        spans: vec![SpanLabel {
+            span,
+            style: SpanStyle::Primary,
+            label: Some("while reducing this expression".to_owned()),
+        }],
     }
 }
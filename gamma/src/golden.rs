@@ -0,0 +1,301 @@
+//! Golden tests: runs `.gm` programs and compares what they print with the
+//! expectations kept next to them.
+//!
+//! The normal forms that a program prints are expected in a sibling `.out`
+//! file or, without one, in `-- expect: <normal form>` comments, one per
+//! expression statement in order. Its diagnostics are expected in a sibling
+//! `.stderr` file; without one, there must be none. A `-- typed: <system>`
+//! comment checks the program like `--typed=<system>` does.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use codemap_diagnostic::Emitter;
+
+use crate::session::Session;
+
+const EXPECT: &str = "-- expect:";
+const TYPED: &str = "-- typed:";
+
+/// What a program printed, without colors.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Output {
+    /// Normal forms of the expression statements, one per line.
+    pub stdout: String,
+    /// Warnings and errors.
+    pub stderr: String,
+}
+
+/// Evaluates the program `source`, registered under `name`.
+pub fn evaluate(name: &str, source: &str) -> Output {
+    let mut session = Session::new();
+    if let Some(typing) = source.lines().find_map(|line| line.strip_prefix(TYPED)) {
+        match typing.trim().parse() {
+            Ok(typing) => session.set_typing(typing),
+            Err(error) => {
+                return Output {
+                    stdout: String::new(),
+                    stderr: format!("error: {}\n", error),
+                }
+            }
+        }
+    }
+    let result = session.load(name, &normalize_newlines(source));
+    let mut diagnostics = session.take_warnings();
+    let mut stdout = String::new();
+    match result {
+        Ok(terms) => {
+            for term in terms {
+                stdout += &format!("{}\n", term);
+            }
+        }
+        Err(errors) => diagnostics.extend(errors),
+    }
+
+    let mut stderr = vec![];
+    if !diagnostics.is_empty() {
+        Emitter::vec(&mut stderr, Some(session.codemap())).emit(&diagnostics);
+    }
+
+    Output {
+        stdout,
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+    }
+}
+
+/// Result of a golden test.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The expectations were updated to what the program printed.
+    Blessed,
+    /// What was expected and what was printed differ, as explained by this
+    /// report.
+    Failed(String),
+}
+
+/// Runs every `.gm` program in `directory` and its subdirectories, sorted by
+/// path. With `bless`, expectations that are not met are updated instead of
+/// failing.
+pub fn test_directory(directory: &Path, bless: bool) -> io::Result<Vec<(PathBuf, Outcome)>> {
+    let mut programs = vec![];
+    find_files(directory, "gm", &mut programs)?;
+    programs.sort();
+
+    programs
+        .into_iter()
+        .map(|path| {
+            let outcome = test_program(directory, &path, bless)?;
+            Ok((path, outcome))
+        })
+        .collect()
+}
+
+/// Adds the files of `directory` and its subdirectories with `extension` to
+/// `files`.
+pub(crate) fn find_files(
+    directory: &Path,
+    extension: &str,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, extension, files)?;
+        } else if path.extension().is_some_and(|other| other == extension) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the program at `path` and checks its output. Diagnostics name it
+/// relative to `directory`, so expectations do not depend on where the tests
+/// run from.
+pub fn test_program(directory: &Path, path: &Path, bless: bool) -> io::Result<Outcome> {
+    let source = normalize_newlines(&fs::read_to_string(path)?);
+    let name = path
+        .strip_prefix(directory)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let output = evaluate(&name, &source);
+
+    let out_path = path.with_extension("out");
+    let stderr_path = path.with_extension("stderr");
+    let comments = expectations(&source);
+    let expected_stdout = match read_optional(&out_path)? {
+        Some(stdout) => stdout,
+        None => comments.iter().map(|line| format!("{}\n", line)).collect(),
+    };
+    let expected_stderr = read_optional(&stderr_path)?.unwrap_or_default();
+
+    if output.stdout == expected_stdout && output.stderr == expected_stderr {
+        return Ok(Outcome::Passed);
+    }
+
+    if !bless {
+        let mut report = String::new();
+        if output.stdout != expected_stdout {
+            report += &format!("normal forms:\n{}", diff(&expected_stdout, &output.stdout));
+        }
+        if output.stderr != expected_stderr {
+            report += &format!("diagnostics:\n{}", diff(&expected_stderr, &output.stderr));
+        }
+        return Ok(Outcome::Failed(report));
+    }
+
+    if output.stdout != expected_stdout {
+        if comments.is_empty() || out_path.exists() {
+            write_optional(&out_path, &output.stdout)?;
+        } else {
+            let results: Vec<_> = output.stdout.lines().collect();
+            if results.len() != comments.len() {
+                return Ok(Outcome::Failed(format!(
+                    "cannot bless {} `{}` comments with {} normal forms, use a .out file instead\n",
+                    comments.len(),
+                    EXPECT,
+                    results.len()
+                )));
+            }
+            fs::write(path, replace_expectations(&source, &results))?;
+        }
+    }
+    if output.stderr != expected_stderr {
+        write_optional(&stderr_path, &output.stderr)?;
+    }
+
+    Ok(Outcome::Blessed)
+}
+
+/// Diagnostics render source lines as they are, so carriage returns would
+/// make them depend on how the files were checked out.
+pub(crate) fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(normalize_newlines(&text))),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Writes `text` to `path`, or removes `path` if `text` is empty.
+fn write_optional(path: &Path, text: &str) -> io::Result<()> {
+    match (text.is_empty(), path.exists()) {
+        (false, _) => fs::write(path, text),
+        (true, true) => fs::remove_file(path),
+        (true, false) => Ok(()),
+    }
+}
+
+/// Normal forms of the `-- expect:` comments of `source`, in order.
+fn expectations(source: &str) -> Vec<&str> {
+    source.lines().filter_map(expectation).collect()
+}
+
+/// Normal form of the `-- expect:` comment in `line`, if there is one.
+pub(crate) fn expectation(line: &str) -> Option<&str> {
+    Some(line[line.find(EXPECT)? + EXPECT.len()..].trim())
+}
+
+/// `source` with the normal forms of its `-- expect:` comments replaced with
+/// `results`.
+fn replace_expectations(source: &str, results: &[&str]) -> String {
+    let mut results = results.iter();
+    let mut replaced = String::new();
+    for line in source.split_inclusive('\n') {
+        match line.find(EXPECT) {
+            Some(start) => {
+                let end = start + EXPECT.len();
+                let newline = &line[line.trim_end_matches('\n').len()..];
+                let result = results.next().expect("one result per comment");
+                replaced += &format!("{} {}{}", &line[..end], result, newline);
+            }
+            None => replaced += line,
+        }
+    }
+
+    replaced
+}
+
+/// Lines of `expected` missing from `actual`, prefixed with `-`, and lines
+/// of `actual` not in `expected`, prefixed with `+`, among the common ones.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+
+    // Lengths of the longest common subsequences of the suffixes.
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = match expected[i] == actual[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = String::new();
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines += &format!(" {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            lines += &format!("-{}\n", expected[i]);
+            i += 1;
+        } else {
+            lines += &format!("+{}\n", actual[j]);
+            j += 1;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use super::*;
+
+    #[test]
+    fn comments() {
+        let source = "id; -- expect: a\n-- expect:b\nother -- note\n";
+        assert_eq!(expectations(source), vec!["a", "b"]);
+        assert_eq!(
+            replace_expectations(source, &["c", "d"]),
+            "id; -- expect: c\n-- expect: d\nother -- note\n"
+        );
+    }
+
+    #[test]
+    fn diffs() {
+        assert_eq!(diff("a\nb\nc\n", "a\nx\nc\n"), " a\n-b\n+x\n c\n");
+        assert_eq!(diff("", "a\n"), "+a\n");
+        assert_eq!(diff("a\n", "a\n"), " a\n");
+    }
+
+    #[test]
+    fn outputs() {
+        let output = evaluate("test.gm", "let id = \\x => x;\nid id;\n\\x . x;");
+        assert_eq!(output.stdout, "\\x => x\n\\x => x\n");
+        assert!(
+            output.stderr.starts_with("warning[W002]"),
+            "{}",
+            output.stderr
+        );
+        assert!(
+            output.stderr.contains("--> test.gm:3:"),
+            "{}",
+            output.stderr
+        );
+    }
+}
@@ -0,0 +1,399 @@
+//! Graph reduction: normal-order evaluation that shares arguments.
+//!
+//! Terms are graphs of nodes in an arena. Beta reduction copies the body of
+//! the abstraction with the parameter replaced by a pointer to the argument
+//! node, so an argument used several times is a single node, and overwrites
+//! the application with an indirection to the result. Whatever refers to the
+//! application afterwards, like the other uses of an argument, finds the
+//! result instead of reducing it again. Parts of a body that the parameter
+//! does not occur in are shared rather than copied.
+//!
+//! Bindings are unfolded into one graph per expression, so they are reduced
+//! at most once too.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use gamma_parser::reduce::{Bindings, Error};
+
+use crate::eval::Term;
+use crate::readback::{self, Head, Shape};
+
+type NodeId = usize;
+
+#[derive(Clone, Copy, Debug)]
+enum Node {
+    /// The variable of the abstraction with this binder.
+    Var(usize),
+    /// A variable bound outside of the term, by a binding or not at all. Its
+    /// name is this entry of the names table.
+    Global(usize),
+    /// Binders are unique, so copies of an abstraction get a new one. Its
+    /// name is this entry of the names table.
+    Abstraction {
+        name: usize,
+        binder: usize,
+        body: NodeId,
+    },
+    Apply(NodeId, NodeId),
+    /// An application that was reduced to this node.
+    Indirection(NodeId),
+}
+
+/// A weak head normal form.
+enum Whnf {
+    Abstraction {
+        name: usize,
+        binder: usize,
+        body: NodeId,
+    },
+    /// An unknown variable applied to arguments, the first one first.
+    Neutral {
+        head: NodeId,
+        arguments: Vec<NodeId>,
+    },
+}
+
+/// What a variable is replaced with while copying a body.
+enum Replacement {
+    Node(NodeId),
+    /// The binder of the copy of the abstraction, and the node of its
+    /// variable once one is needed.
+    Binder(usize, Option<NodeId>),
+}
+
+/// Work done by a [`Machine`], shown by `--stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Number of beta reductions.
+    pub beta_steps: usize,
+    /// Number of nodes created, for the expression and the bindings it uses
+    /// as well as while reducing.
+    pub allocations: usize,
+    /// Number of times reduction reached an application that was already
+    /// reduced, and took its result instead of reducing it again.
+    pub shared_reuses: usize,
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} beta steps, {} node allocations, {} shared reuses",
+            self.beta_steps, self.allocations, self.shared_reuses
+        )
+    }
+}
+
+/// Evaluates expressions by graph reduction. Variables that name a binding
+/// are replaced with the graph of the bound expression when evaluation needs
+/// them.
+pub struct Machine<'a> {
+    bindings: &'a dyn Bindings,
+    nodes: Vec<Node>,
+    names: Vec<String>,
+    name_ids: HashMap<String, usize>,
+    /// Graphs of the bindings used so far, by name.
+    globals: HashMap<usize, NodeId>,
+    binders: usize,
+    pub statistics: Statistics,
+    /// Number of beta reductions and unfoldings of bindings performed so far.
+    pub steps: usize,
+    /// Number of steps after which evaluation gives up.
+    pub limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(bindings: &'a dyn Bindings, limit: usize) -> Self {
+        Self {
+            bindings,
+            nodes: vec![],
+            names: vec![],
+            name_ids: HashMap::new(),
+            globals: HashMap::new(),
+            binders: 0,
+            statistics: Statistics::default(),
+            steps: 0,
+            limit,
+        }
+    }
+
+    /// Reduces `term` to its normal form. Fails if that takes more than
+    /// `limit` steps, or if the normal form is too large.
+    pub fn normalize(&mut self, term: &Term) -> Result<Term, Error> {
+        let node = self.build(term, &mut vec![]);
+        let mut scope = vec![];
+        let normal = readback::read_back(node, Error::TooLarge, |node, depth| {
+            scope.truncate(depth);
+            self.shape(node, &mut scope)
+        })?;
+        Ok(normal.to_term(self.bindings))
+    }
+
+    fn allocate(&mut self, node: Node) -> NodeId {
+        self.statistics.allocations += 1;
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.name_ids.get(name) {
+            return id;
+        }
+
+        self.names.push(name.to_owned());
+        self.name_ids.insert(name.to_owned(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    fn fresh_binder(&mut self) -> usize {
+        self.binders += 1;
+        self.binders
+    }
+
+    /// Creates the graph of `term` with the binders of `scope` around it.
+    /// Types are erased.
+    fn build<'t>(&mut self, term: &'t Term, scope: &mut Vec<(&'t str, usize)>) -> NodeId {
+        match term {
+            Term::Var { name, .. } => {
+                let node = match scope.iter().rev().find(|(binder, _)| binder == name) {
+                    Some(&(_, binder)) => Node::Var(binder),
+                    None => Node::Global(self.name(name)),
+                };
+                self.allocate(node)
+            }
+            Term::Apply { lhs, rhs, .. } | Term::Dollar { lhs, rhs, .. } => {
+                let function = self.build(lhs, scope);
+                let argument = self.build(rhs, scope);
+                self.allocate(Node::Apply(function, argument))
+            }
+            Term::Paren { expression, .. }
+            | Term::TypeAbstraction { expression, .. }
+            | Term::TypeApply { expression, .. } => self.build(expression, scope),
+            Term::Abstraction {
+                name, expression, ..
+            } => {
+                let binder = self.fresh_binder();
+                scope.push((name, binder));
+                let body = self.build(expression, scope);
+                scope.pop();
+
+                let name = self.name(name);
+                self.allocate(Node::Abstraction { name, binder, body })
+            }
+            Term::If { .. } | Term::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before building graphs")
+            }
+        }
+    }
+
+    /// Reduces the spine of `node` until it is an abstraction or an unknown
+    /// variable applied to arguments.
+    fn whnf(&mut self, node: NodeId) -> Option<Whnf> {
+        // Applications whose function is being reduced, the innermost last.
+        let mut spine: Vec<NodeId> = vec![];
+        let mut current = node;
+        loop {
+            current = match self.nodes[current] {
+                Node::Indirection(result) => {
+                    self.statistics.shared_reuses += 1;
+                    result
+                }
+                Node::Apply(function, _) => {
+                    spine.push(current);
+                    function
+                }
+                Node::Abstraction { name, binder, body } => match spine.pop() {
+                    Some(application) => {
+                        let argument = match self.nodes[application] {
+                            Node::Apply(_, argument) => argument,
+                            _ => unreachable!("the spine is made of applications"),
+                        };
+                        self.step()?;
+                        self.statistics.beta_steps += 1;
+
+                        let mut substitution = vec![(binder, Replacement::Node(argument))];
+                        let result = self.instantiate(body, &mut substitution);
+                        self.nodes[application] = Node::Indirection(result);
+                        result
+                    }
+                    None => return Some(Whnf::Abstraction { name, binder, body }),
+                },
+                Node::Global(name) => match self.global(name) {
+                    Some(graph) => {
+                        self.step()?;
+                        graph
+                    }
+                    None => return Some(neutral(&self.nodes, current, spine)),
+                },
+                Node::Var(_) => return Some(neutral(&self.nodes, current, spine)),
+            }
+        }
+    }
+
+    /// Copies `node` with the variables replaced according to
+    /// `substitution`, the innermost binder last. Nodes that do not change
+    /// are shared.
+    fn instantiate(
+        &mut self,
+        node: NodeId,
+        substitution: &mut Vec<(usize, Replacement)>,
+    ) -> NodeId {
+        match self.nodes[node] {
+            Node::Var(binder) => {
+                match substitution
+                    .iter_mut()
+                    .rev()
+                    .find(|(other, _)| *other == binder)
+                {
+                    Some((_, Replacement::Node(replacement))) => *replacement,
+                    Some((_, Replacement::Binder(_, Some(variable)))) => *variable,
+                    Some((_, Replacement::Binder(binder, variable))) => {
+                        let binder = *binder;
+                        self.statistics.allocations += 1;
+                        self.nodes.push(Node::Var(binder));
+                        *variable = Some(self.nodes.len() - 1);
+                        self.nodes.len() - 1
+                    }
+                    None => node,
+                }
+            }
+            Node::Global(_) => node,
+            Node::Indirection(result) => self.instantiate(result, substitution),
+            Node::Apply(function, argument) => {
+                let new_function = self.instantiate(function, substitution);
+                let new_argument = self.instantiate(argument, substitution);
+                match new_function == function && new_argument == argument {
+                    true => node,
+                    false => self.allocate(Node::Apply(new_function, new_argument)),
+                }
+            }
+            Node::Abstraction { name, binder, body } => {
+                let new_binder = self.fresh_binder();
+                substitution.push((binder, Replacement::Binder(new_binder, None)));
+                let new_body = self.instantiate(body, substitution);
+                substitution.pop();
+
+                match new_body == body {
+                    true => node,
+                    false => self.allocate(Node::Abstraction {
+                        name,
+                        binder: new_binder,
+                        body: new_body,
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Reduces `node` to weak head normal form, in place, for read-back.
+    /// `scope` holds the binders around it, the outermost first, and gets
+    /// the binder of an abstraction.
+    fn shape(&mut self, node: NodeId, scope: &mut Vec<usize>) -> Result<Shape<NodeId>, Error> {
+        match self.whnf(node).ok_or(Error::Limit)? {
+            Whnf::Abstraction { name, binder, body } => {
+                scope.push(binder);
+                Ok(Shape::Abstraction(self.names[name].clone(), body))
+            }
+            Whnf::Neutral { head, arguments } => {
+                let head = match self.nodes[head] {
+                    Node::Var(binder) => Head::Level(
+                        scope
+                            .iter()
+                            .rposition(|other| *other == binder)
+                            .expect("variables are bound"),
+                    ),
+                    Node::Global(name) => Head::Global(self.names[name].clone()),
+                    _ => unreachable!("heads are variables"),
+                };
+
+                Ok(Shape::Neutral(head, arguments))
+            }
+        }
+    }
+
+    fn global(&mut self, name: usize) -> Option<NodeId> {
+        if let Some(&graph) = self.globals.get(&name) {
+            return Some(graph);
+        }
+
+        let graph = self.build(
+            self.bindings.binding(&self.names[name].clone())?,
+            &mut vec![],
+        );
+        self.globals.insert(name, graph);
+        Some(graph)
+    }
+
+    fn step(&mut self) -> Option<()> {
+        if self.steps >= self.limit {
+            return None;
+        }
+
+        self.steps += 1;
+        Some(())
+    }
+}
+
+fn neutral(nodes: &[Node], head: NodeId, spine: Vec<NodeId>) -> Whnf {
+    let arguments = spine
+        .into_iter()
+        .rev()
+        .map(|application| match nodes[application] {
+            Node::Apply(_, argument) => argument,
+            _ => unreachable!("the spine is made of applications"),
+        })
+        .collect();
+
+    Whnf::Neutral { head, arguments }
+}
+
+#[cfg(test)]
+mod graph_tests {
+    use codemap::CodeMap;
+    use gamma_parser::parser::Parser;
+
+    use super::*;
+    use crate::eval::Context;
+
+    fn statistics(source: &str) -> Statistics {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<test>", &mut codemap);
+        let expression = parser.parse_single_expression().unwrap();
+        drop(parser);
+
+        let context = Context::default();
+        let mut machine = Machine::new(&context, 1000);
+        machine.normalize(&expression).unwrap();
+        machine.statistics
+    }
+
+    #[test]
+    fn arguments_are_reduced_once() {
+        // The argument reduces in one step, and the second use of it finds
+        // the result.
+        let shared = statistics("(\\x => x x) ((\\y => y) (\\z => z))");
+        assert_eq!(shared.beta_steps, 3);
+        assert_eq!(shared.shared_reuses, 1);
+
+        let unshared = statistics("((\\y => y) (\\z => z)) ((\\y => y) (\\z => z))");
+        assert_eq!(unshared.beta_steps, 3);
+        assert_eq!(unshared.shared_reuses, 0);
+    }
+
+    #[test]
+    fn copies_get_their_own_binders() {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(
+            "(\\k => k k) (\\x => \\y => x (\\z => y))",
+            "<test>",
+            &mut codemap,
+        );
+        let expression = parser.parse_single_expression().unwrap();
+        drop(parser);
+
+        let context = Context::default();
+        let normal = Machine::new(&context, 1000).normalize(&expression).unwrap();
+        assert_eq!(normal.to_string(), "\\y => \\y1 => y");
+    }
+}
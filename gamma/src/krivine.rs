@@ -0,0 +1,247 @@
+//! Krivine machine: call-by-name evaluation with closures instead of
+//! substitution.
+//!
+//! Terms are compiled to de Bruijn indices. The machine state is the closure
+//! being evaluated and a stack of argument closures; an abstraction pops an
+//! argument into its environment instead of copying it into its body. This
+//! computes the weak head normal form. Normal forms are read back by
+//! evaluating under each binder with the bound variable left as an unknown.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use gamma_parser::reduce::{Bindings, Error};
+
+use crate::eval::Term;
+use crate::readback::{self, Head, Shape};
+
+/// A term with de Bruijn indices, starting at 1 for the innermost binder.
+#[derive(Debug)]
+enum Code {
+    Var(usize),
+    /// A variable bound outside of the term, by a binding or not at all.
+    Global(String),
+    /// The variable of the binder at this depth during read-back.
+    Level(usize),
+    /// Keeps the name of the parameter for read-back.
+    Abstraction(String, Rc<Code>),
+    Apply(Rc<Code>, Rc<Code>),
+}
+
+#[derive(Clone, Debug)]
+struct Closure {
+    code: Rc<Code>,
+    environment: Environment,
+}
+
+/// Values of the variables bound around a closure, the innermost first.
+#[derive(Clone, Debug, Default)]
+struct Environment(Option<Rc<(Closure, Environment)>>);
+
+impl Environment {
+    fn push(&self, closure: Closure) -> Environment {
+        Environment(Some(Rc::new((closure, self.clone()))))
+    }
+
+    fn get(&self, index: usize) -> &Closure {
+        let mut environment = self;
+        for _ in 1..index {
+            environment = &environment.0.as_ref().expect("indices are bound").1;
+        }
+
+        &environment.0.as_ref().expect("indices are bound").0
+    }
+}
+
+impl Drop for Environment {
+    /// Drops long chains of environments without recursion.
+    fn drop(&mut self) {
+        let mut pending = vec![self.0.take()];
+        while let Some(environment) = pending.pop() {
+            if let Some(Ok((mut closure, mut rest))) = environment.map(Rc::try_unwrap) {
+                pending.push(closure.environment.0.take());
+                pending.push(rest.0.take());
+            }
+        }
+    }
+}
+
+/// A weak head normal form.
+enum Whnf {
+    Abstraction {
+        name: String,
+        body: Rc<Code>,
+        environment: Environment,
+    },
+    /// An unknown variable applied to arguments, the first one first.
+    Neutral { head: Head, arguments: Vec<Closure> },
+}
+
+/// Evaluates expressions with a Krivine machine. Variables that name a
+/// binding are replaced with the bound expression when evaluation needs
+/// them.
+pub struct Machine<'a> {
+    bindings: &'a dyn Bindings,
+    /// Compiled values of the bindings used so far.
+    globals: HashMap<String, Rc<Code>>,
+    /// Number of beta reductions and unfoldings of bindings performed so far.
+    pub steps: usize,
+    /// Number of steps after which evaluation gives up.
+    pub limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(bindings: &'a dyn Bindings, limit: usize) -> Self {
+        Self {
+            bindings,
+            globals: HashMap::new(),
+            steps: 0,
+            limit,
+        }
+    }
+
+    /// Reduces `term` to its normal form. Fails if that takes more than
+    /// `limit` steps, or if the normal form is too large.
+    pub fn normalize(&mut self, term: &Term) -> Result<Term, Error> {
+        let closure = Closure {
+            code: Rc::new(compile(term, &mut vec![])),
+            environment: Environment::default(),
+        };
+        let normal = readback::read_back(closure, Error::TooLarge, |closure, depth| {
+            self.shape(closure, depth)
+        })?;
+        Ok(normal.to_term(self.bindings))
+    }
+
+    /// Runs the machine until `closure` applied to nothing is in weak head
+    /// normal form.
+    fn whnf(&mut self, mut closure: Closure) -> Option<Whnf> {
+        let mut stack: Vec<Closure> = vec![];
+        loop {
+            let code = closure.code.clone();
+            closure = match &*code {
+                Code::Apply(function, argument) => {
+                    // A variable is passed on as its value rather than as a
+                    // closure that looks it up.
+                    stack.push(match &**argument {
+                        Code::Var(index) => closure.environment.get(*index).clone(),
+                        _ => Closure {
+                            code: argument.clone(),
+                            environment: closure.environment.clone(),
+                        },
+                    });
+                    Closure {
+                        code: function.clone(),
+                        environment: closure.environment,
+                    }
+                }
+                Code::Abstraction(name, body) => match stack.pop() {
+                    Some(argument) => {
+                        self.step()?;
+                        Closure {
+                            code: body.clone(),
+                            environment: closure.environment.push(argument),
+                        }
+                    }
+                    None => {
+                        return Some(Whnf::Abstraction {
+                            name: name.clone(),
+                            body: body.clone(),
+                            environment: closure.environment,
+                        })
+                    }
+                },
+                Code::Var(index) => closure.environment.get(*index).clone(),
+                Code::Global(name) => match self.global(name) {
+                    Some(code) => {
+                        self.step()?;
+                        Closure {
+                            code,
+                            environment: Environment::default(),
+                        }
+                    }
+                    None => return Some(neutral(Head::Global(name.clone()), stack)),
+                },
+                Code::Level(level) => return Some(neutral(Head::Level(*level), stack)),
+            }
+        }
+    }
+
+    /// Reduces `closure`, found under `depth` abstractions, for read-back.
+    fn shape(&mut self, closure: Closure, depth: usize) -> Result<Shape<Closure>, Error> {
+        match self.whnf(closure).ok_or(Error::Limit)? {
+            Whnf::Abstraction {
+                name,
+                body,
+                environment,
+            } => {
+                let variable = Closure {
+                    code: Rc::new(Code::Level(depth)),
+                    environment: Environment::default(),
+                };
+                let body = Closure {
+                    code: body,
+                    environment: environment.push(variable),
+                };
+
+                Ok(Shape::Abstraction(name, body))
+            }
+            Whnf::Neutral { head, arguments } => Ok(Shape::Neutral(head, arguments)),
+        }
+    }
+
+    fn global(&mut self, name: &str) -> Option<Rc<Code>> {
+        if let Some(code) = self.globals.get(name) {
+            return Some(code.clone());
+        }
+
+        let code = Rc::new(compile(self.bindings.binding(name)?, &mut vec![]));
+        self.globals.insert(name.to_owned(), code.clone());
+        Some(code)
+    }
+
+    fn step(&mut self) -> Option<()> {
+        if self.steps >= self.limit {
+            return None;
+        }
+
+        self.steps += 1;
+        Some(())
+    }
+}
+
+fn neutral(head: Head, mut stack: Vec<Closure>) -> Whnf {
+    stack.reverse();
+    Whnf::Neutral {
+        head,
+        arguments: stack,
+    }
+}
+
+/// Translates `term` with the binders of `scope` around it. Types are
+/// erased.
+fn compile<'t>(term: &'t Term, scope: &mut Vec<&'t str>) -> Code {
+    match term {
+        Term::Var { name, .. } => match scope.iter().rev().position(|binder| binder == name) {
+            Some(index) => Code::Var(index + 1),
+            None => Code::Global(name.clone()),
+        },
+        Term::Apply { lhs, rhs, .. } | Term::Dollar { lhs, rhs, .. } => {
+            Code::Apply(Rc::new(compile(lhs, scope)), Rc::new(compile(rhs, scope)))
+        }
+        Term::Paren { expression, .. }
+        | Term::TypeAbstraction { expression, .. }
+        | Term::TypeApply { expression, .. } => compile(expression, scope),
+        Term::Abstraction {
+            name, expression, ..
+        } => {
+            scope.push(name);
+            let body = compile(expression, scope);
+            scope.pop();
+            Code::Abstraction(name.clone(), Rc::new(body))
+        }
+        Term::If { .. } | Term::Match { .. } => {
+            unreachable!("`if` and `match` are desugared before compiling")
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Gamma interpreter. [`Session`] evaluates Gamma code from a host program.
+
+pub mod cek;
+#[cfg(test)]
+mod conformance;
+pub mod doctest;
+pub mod eval;
+pub mod golden;
+pub mod graph;
+pub mod krivine;
+pub mod optimal;
+mod readback;
+pub mod session;
+pub mod vm;
+
+pub use eval::{Backend, Statistics, Term, Typing};
+pub use session::Session;
@@ -1,29 +1,768 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{env, fs, process::exit};
 
-mod eval;
-
 use codemap::CodeMap;
-use eval::Evaluator;
+use codemap_diagnostic::{ColorConfig, Emitter};
+use gamma::doctest;
+use gamma::eval::{no_normal_form, Evaluator, REDUCTION_LIMIT};
+use gamma::golden::{self, Outcome};
+use gamma::{Backend, Session, Typing};
+use gamma_parser::{ast, blc, dot, format, parser::Parser, reduce, rename, resolve, ski};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+const USAGE: &str = "usage: gamma [run] [--typed=<untyped|stlc|hm|system-f>]
+                   [--backend=<substitution|krivine|cek|vm|graph|optimal> | --vm]
+                   [--trace] [--stats] [<filename>]
+       gamma run --blc [--bytes] <filename>
+       gamma fmt [--check] [--width <columns>] <filename>...
+       gamma rename <filename> <old name> <new name> [--at <line>:<column>]
+       gamma compile [--target=<ski|bckw>] [--reduce] <filename>
+       gamma encode --blc [--bytes] <filename>
+       gamma decode --blc [--bytes] <filename>
+       gamma dot --binding <name> [--reduction-graph [--limit <terms>]] <filename>
+       gamma test [--bless] [<directory>]
+       gamma doctest <path>...
+       gamma lsp";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("usage: gamma <filename>");
-        exit(1);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("fmt") => exit(fmt(&args[1..])),
+        Some("rename") => exit(rename(&args[1..])),
+        Some("compile") => exit(compile(&args[1..])),
+        Some("encode") => exit(encode(&args[1..])),
+        Some("decode") => exit(decode(&args[1..])),
+        Some("dot") => exit(dot(&args[1..])),
+        Some("test") => exit(test(&args[1..])),
+        Some("doctest") => exit(doctest(&args[1..])),
+        Some("run") => run(&args[1..]),
+        Some("lsp") => lsp(&args[1..]),
+        _ => run(&args),
     }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(1);
+}
 
-    let filename = args[1].as_str();
+/// Parses the value of an `--option=value` argument.
+fn option_value<T: FromStr<Err = String>>(arg: &str) -> T {
+    let (_, value) = arg.split_once('=').unwrap_or_else(|| usage());
+    value.parse().unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        usage();
+    })
+}
+
+fn read(filename: &str) -> String {
     match fs::read_to_string(filename) {
-        Ok(content) => {
-            let mut codemap = CodeMap::new();
-            let mut exec = Evaluator::new(content.as_str(), filename, &mut codemap);
-            exec.eval();
-            //
-            // }
-        }
+        Ok(content) => content,
         Err(_) => {
             eprintln!("unable to read file");
             exit(1);
         }
     }
 }
+
+/// `gamma <filename>`: evaluates the file and prints the normal forms of its
+/// expression statements. With `--typed` every statement is type-checked
+/// before it is evaluated. Without a file it starts the REPL.
+fn run(args: &[String]) {
+    let mut typing = Typing::Untyped;
+    let mut backend = Backend::Substitution;
+    let mut binary = false;
+    let mut bytes = false;
+    let mut trace = false;
+    let mut stats = false;
+    let mut filename = None;
+    for arg in args {
+        match arg.as_str() {
+            "--blc" => binary = true,
+            "--bytes" => bytes = true,
+            "--trace" => trace = true,
+            "--stats" => stats = true,
+            "--vm" => backend = Backend::Vm,
+            _ if arg.starts_with("--typed=") => typing = option_value(arg),
+            _ if arg.starts_with("--backend=") => backend = option_value(arg),
+            _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    if bytes && !binary {
+        eprintln!("error: --bytes needs --blc");
+        usage();
+    }
+    if trace && backend != Backend::Cek {
+        eprintln!("error: --trace needs --backend=cek");
+        usage();
+    }
+    if stats && ![Backend::Substitution, Backend::Graph, Backend::Optimal].contains(&backend) {
+        eprintln!("error: --stats needs --backend=substitution, graph or optimal");
+        usage();
+    }
+
+    let filename = match (filename, binary) {
+        (Some(filename), true) => return run_blc(filename, bytes),
+        (_, true) => usage(),
+        (Some(filename), false) => filename,
+        (None, false) => return repl(typing, backend, trace, stats),
+    };
+
+    let content = read(filename);
+    let mut session = Session::new();
+    session.set_typing(typing);
+    session.set_backend(backend);
+    if trace {
+        session.set_trace(|state| eprintln!("{}", state));
+    }
+    if stats {
+        session.set_statistics(|statistics| eprintln!("{}", statistics));
+    }
+    let result = session.load(filename, &content);
+    let warnings = session.take_warnings();
+    let mut emitter = Emitter::stderr(ColorConfig::Always, Some(session.codemap()));
+    if !warnings.is_empty() {
+        emitter.emit(&warnings);
+    }
+
+    match result {
+        Ok(terms) => {
+            for term in terms {
+                println!("{}", term);
+            }
+        }
+        Err(diagnostics) => {
+            emitter.emit(&diagnostics);
+
+            let mut stdout = StandardStream::stdout(ColorChoice::Always);
+            let _ = stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true));
+            let _ = writeln!(
+                &mut stdout,
+                "error: aborting due to error occured in execution process"
+            );
+            let _ = stdout.set_color(ColorSpec::new().set_fg(None));
+            exit(1);
+        }
+    }
+}
+
+/// Reads statements from the terminal and prints the normal forms of
+/// expressions. `:type <expression>` prints the inferred type instead.
+fn repl(typing: Typing, backend: Backend, trace: bool, stats: bool) {
+    let mut editor = match rustyline::Editor::<()>::new() {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("unable to start the REPL: {}", error);
+            exit(1);
+        }
+    };
+
+    let mut session = Session::new();
+    session.set_typing(typing);
+    session.set_backend(backend);
+    if trace {
+        session.set_trace(|state| eprintln!("{}", state));
+    }
+    if stats {
+        session.set_statistics(|statistics| eprintln!("{}", statistics));
+    }
+    let mut inputs = 0;
+
+    while let Ok(line) = editor.readline("λ> ") {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(line);
+
+        let result = match line.strip_prefix(':') {
+            Some("q" | "quit") => break,
+            Some(command) => match command.split_once(char::is_whitespace) {
+                Some(("t" | "type", expression)) => session
+                    .type_of(expression.trim())
+                    .map(|ty| vec![format!("{} : {}", expression.trim(), ty)]),
+                _ => {
+                    eprintln!("commands: :type <expression>, :quit");
+                    continue;
+                }
+            },
+            None => {
+                inputs += 1;
+                let source = match line.ends_with(';') {
+                    true => line.to_owned(),
+                    false => format!("{};", line),
+                };
+
+                session
+                    .load(&format!("<repl {}>", inputs), &source)
+                    .map(|terms| terms.iter().map(ToString::to_string).collect())
+            }
+        };
+
+        let mut diagnostics = session.take_warnings();
+        match result {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(errors) => diagnostics.extend(errors),
+        }
+
+        if !diagnostics.is_empty() {
+            Emitter::stderr(ColorConfig::Always, Some(session.codemap())).emit(&diagnostics);
+        }
+    }
+}
+
+/// `gamma lsp`: runs the language server over stdio.
+fn lsp(args: &[String]) {
+    if !args.is_empty() {
+        usage();
+    }
+
+    if let Err(error) = gamma_lsp::run() {
+        eprintln!("language server failed: {}", error);
+        exit(1);
+    }
+}
+
+/// `gamma fmt`: rewrites files in place, or with `--check` only reports the
+/// files that are not formatted. Returns the process exit code.
+fn fmt(args: &[String]) -> i32 {
+    let mut check = false;
+    let mut config = format::Config::default();
+    let mut filenames = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--width" => match args.next().and_then(|width| width.parse().ok()) {
+                Some(width) => config.width = width,
+                None => usage(),
+            },
+            _ if !arg.starts_with('-') => filenames.push(arg.as_str()),
+            _ => usage(),
+        }
+    }
+
+    if filenames.is_empty() {
+        usage();
+    }
+
+    let mut code = 0;
+    for filename in filenames {
+        let content = read(filename);
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(content.as_str(), filename, &mut codemap);
+        let ast = parser.parse();
+
+        if parser.has_errors() {
+            Emitter::stderr(ColorConfig::Always, Some(parser.codemap)).emit(&parser.diagnostics);
+            code = 1;
+            continue;
+        }
+
+        let formatted = format::format(&content, &ast, &parser.comments, &config);
+        if formatted == content {
+            continue;
+        }
+
+        if check {
+            println!("{} is not formatted", filename);
+            code = 1;
+        } else if fs::write(filename, formatted).is_err() {
+            eprintln!("unable to write file");
+            code = 1;
+        }
+    }
+
+    code
+}
+
+/// `gamma test`: runs the golden tests in a directory, `tests` by default,
+/// and with `--bless` updates the expectations that are not met. Returns the
+/// process exit code.
+fn test(args: &[String]) -> i32 {
+    let mut bless = false;
+    let mut directory = None;
+    for arg in args {
+        match arg.as_str() {
+            "--bless" => bless = true,
+            _ if directory.is_none() && !arg.starts_with("--") => directory = Some(arg.as_str()),
+            _ => usage(),
+        }
+    }
+
+    let directory = Path::new(directory.unwrap_or("tests"));
+    match golden::test_directory(directory, bless) {
+        Ok(outcomes) => report(&outcomes),
+        Err(error) => {
+            eprintln!("unable to run tests: {}", error);
+            1
+        }
+    }
+}
+
+/// `gamma doctest`: evaluates the Gamma code blocks of Markdown files, or of
+/// the Markdown files in directories. Returns the process exit code.
+fn doctest(args: &[String]) -> i32 {
+    if args.is_empty() {
+        usage();
+    }
+
+    let mut outcomes = vec![];
+    for path in args {
+        match doctest::test_path(Path::new(path)) {
+            Ok(more) => outcomes.extend(more),
+            Err(error) => {
+                eprintln!("unable to run doctests of {}: {}", path, error);
+                return 1;
+            }
+        }
+    }
+
+    report(&outcomes)
+}
+
+/// Prints the outcome of every test, the reports of the failed ones and a
+/// summary. Returns the process exit code.
+fn report(outcomes: &[(PathBuf, Outcome)]) -> i32 {
+    let mut failures = vec![];
+    for (path, outcome) in outcomes {
+        let status = match outcome {
+            Outcome::Passed => "ok",
+            Outcome::Blessed => "blessed",
+            Outcome::Failed(report) => {
+                failures.push((path, report));
+                "FAILED"
+            }
+        };
+        println!("test {} ... {}", path.display(), status);
+    }
+
+    for (path, report) in &failures {
+        println!("\n---- {} ----\n{}", path.display(), report);
+    }
+
+    let passed = outcomes.len() - failures.len();
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len()
+    );
+
+    match failures.is_empty() {
+        true => 0,
+        false => 1,
+    }
+}
+
+/// `gamma rename`: renames the binder named `old` at the given position (by
+/// default the top-level `let` named `old`) and its references in place.
+fn rename(args: &[String]) -> i32 {
+    let (filename, old, new, at) = match args {
+        [filename, old, new] => (filename, old, new, None),
+        [filename, old, new, flag, at] if flag == "--at" => (filename, old, new, Some(at)),
+        _ => usage(),
+    };
+
+    let content = read(filename);
+    let mut codemap = CodeMap::new();
+    let mut parser = Parser::new(content.as_str(), filename, &mut codemap);
+    let ast = parser.parse();
+    let mut emitter = Emitter::stderr(ColorConfig::Always, Some(parser.codemap));
+
+    if parser.has_errors() {
+        emitter.emit(&parser.diagnostics);
+        return 1;
+    }
+
+    let offset = match at {
+        Some(at) => position_offset(&content, at).unwrap_or_else(|| usage()),
+        None => match ast.iter().find_map(|statement| match statement {
+            ast::Statement::Let {
+                name, name_span, ..
+            } if name == old => Some(name_span.start),
+            _ => None,
+        }) {
+            Some(offset) => offset,
+            None => {
+                eprintln!("error: there is no top-level `{}`", old);
+                return 1;
+            }
+        },
+    };
+
+    match resolve::resolve(&ast).symbol_at(offset) {
+        Some(symbol) if symbol.name == *old => {}
+        _ => {
+            eprintln!(
+                "error: there is no `{}` at {}",
+                old,
+                at.map_or("", String::as_str)
+            );
+            return 1;
+        }
+    }
+
+    match rename::rename(&ast, offset, new) {
+        Ok(edits) => {
+            if fs::write(filename, rename::apply(&content, &edits)).is_err() {
+                eprintln!("unable to write file");
+                return 1;
+            }
+
+            0
+        }
+        Err(error) => {
+            emitter.emit(&[error.diagnostic(parser.file_span)]);
+            1
+        }
+    }
+}
+
+/// Expression statements and their spans.
+type Expressions = Vec<(ast::Expression, ast::Span)>;
+
+/// Parses a file and returns the span of its source, the evaluator its other
+/// statements were evaluated with and its expression statements with its
+/// syntax rules and the variables defined by its `let`s expanded. Errors are
+/// reported here.
+fn expanded_expressions(
+    filename: &str,
+    codemap: &mut CodeMap,
+) -> Option<(codemap::Span, Evaluator, Expressions)> {
+    let content = read(filename);
+    let mut parser = Parser::new(content.as_str(), filename, codemap);
+    let ast = parser.parse();
+    let file_span = parser.file_span;
+    let mut emitter = Emitter::stderr(ColorConfig::Always, Some(parser.codemap));
+
+    if parser.has_errors() {
+        emitter.emit(&parser.diagnostics);
+        return None;
+    }
+
+    let mut evaluator = Evaluator::new();
+    let mut expressions = vec![];
+    for statement in &ast {
+        match statement {
+            ast::Statement::Expression {
+                expression,
+                expression_span,
+                ..
+            } => match evaluator.expand(expression, expression_span, file_span) {
+                Ok(expression) => expressions.push((
+                    evaluator.context.expand(&expression),
+                    expression_span.clone(),
+                )),
+                Err(diagnostics) => {
+                    emitter.emit(&diagnostics);
+                    return None;
+                }
+            },
+            _ => {
+                if let Err(diagnostics) = evaluator.eval_statement(statement, file_span) {
+                    emitter.emit(&diagnostics);
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some((file_span, evaluator, expressions))
+}
+
+/// `gamma compile`: translates the expression statements of a file into
+/// combinators, after expanding the `let`s they use. With `--reduce` it
+/// prints the normal forms of the combinator terms instead.
+fn compile(args: &[String]) -> i32 {
+    let mut target = ski::Target::Ski;
+    let mut reduce = false;
+    let mut filename = None;
+    for arg in args {
+        match arg.as_str() {
+            "--reduce" => reduce = true,
+            _ if arg.starts_with("--target=") => target = option_value(arg),
+            _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let filename = filename.unwrap_or_else(|| usage());
+    let mut codemap = CodeMap::new();
+    let (file_span, _, expressions) = match expanded_expressions(filename, &mut codemap) {
+        Some(expressions) => expressions,
+        None => return 1,
+    };
+
+    for (expression, expression_span) in expressions {
+        let term = ski::compile(&expression, target);
+        if !reduce {
+            println!("{}", term);
+            continue;
+        }
+
+        match ski::Reducer::new(REDUCTION_LIMIT).normalize(&term) {
+            Some(normal_form) => println!("{}", normal_form),
+            None => {
+                let span =
+                    file_span.subspan(expression_span.start as u64, expression_span.end as u64);
+                Emitter::stderr(ColorConfig::Always, Some(&codemap))
+                    .emit(&[no_normal_form(REDUCTION_LIMIT, span)]);
+                return 1;
+            }
+        }
+    }
+
+    0
+}
+
+/// `gamma dot`: prints the tree of the value of a `let` as a Graphviz graph.
+/// With `--reduction-graph` it prints the terms that the value reduces to
+/// instead, after expanding the `let`s it uses, at most `--limit` of them.
+fn dot(args: &[String]) -> i32 {
+    let mut binding = None;
+    let mut reduction_graph = false;
+    let mut limit = 100;
+    let mut filename = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--binding" => binding = Some(args.next().unwrap_or_else(|| usage())),
+            "--reduction-graph" => reduction_graph = true,
+            "--limit" => match args.next().and_then(|limit| limit.parse().ok()) {
+                Some(terms) => limit = terms,
+                None => usage(),
+            },
+            _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let (filename, binding) = match (filename, binding) {
+        (Some(filename), Some(binding)) => (filename, binding),
+        _ => usage(),
+    };
+    let mut codemap = CodeMap::new();
+    let (_, evaluator, _) = match expanded_expressions(filename, &mut codemap) {
+        Some(expressions) => expressions,
+        None => return 1,
+    };
+
+    let value = match evaluator.context.get(binding) {
+        Some(value) => value,
+        None => {
+            eprintln!("error: there is no top-level `{}`", binding);
+            return 1;
+        }
+    };
+
+    match reduction_graph {
+        true => print!(
+            "{}",
+            dot::reduction_graph(&evaluator.context.expand(value), limit)
+        ),
+        false => print!("{}", dot::term_tree(value)),
+    }
+
+    0
+}
+
+/// Options of the BLC subcommands: whether bits are packed into bytes and
+/// the file name. `--blc` is required.
+fn blc_options(args: &[String]) -> (bool, &str) {
+    let mut binary = false;
+    let mut bytes = false;
+    let mut filename = None;
+    for arg in args {
+        match arg.as_str() {
+            "--blc" => binary = true,
+            "--bytes" => bytes = true,
+            _ if filename.is_none() && !arg.starts_with("--") => filename = Some(arg.as_str()),
+            _ => usage(),
+        }
+    }
+
+    match (binary, filename) {
+        (true, Some(filename)) => (bytes, filename),
+        _ => usage(),
+    }
+}
+
+/// Reads a file of `0` and `1` characters, or of bytes.
+fn read_bits(filename: &str, bytes: bool) -> Vec<bool> {
+    if bytes {
+        return match fs::read(filename) {
+            Ok(content) => blc::unpack(&content),
+            Err(_) => {
+                eprintln!("unable to read file");
+                exit(1);
+            }
+        };
+    }
+
+    blc::parse_bits(&read(filename)).unwrap_or_else(|error| {
+        eprintln!("error: {}", error);
+        exit(1);
+    })
+}
+
+/// Writes bits as a line of `0` and `1` characters, or packed into bytes.
+fn write_bits(bits: &[bool], bytes: bool) {
+    let result = match bytes {
+        true => io::stdout().write_all(&blc::pack(bits)),
+        false => writeln!(io::stdout(), "{}", blc::format_bits(bits)),
+    };
+
+    if result.is_err() {
+        eprintln!("unable to write output");
+        exit(1);
+    }
+}
+
+/// `gamma encode --blc`: prints the binary lambda calculus encoding of every
+/// expression statement of a file, after expanding the `let`s it uses.
+fn encode(args: &[String]) -> i32 {
+    let (bytes, filename) = blc_options(args);
+    let mut codemap = CodeMap::new();
+    let (file_span, _, expressions) = match expanded_expressions(filename, &mut codemap) {
+        Some(expressions) => expressions,
+        None => return 1,
+    };
+
+    for (expression, _) in expressions {
+        match blc::encode(&expression) {
+            Ok(bits) => write_bits(&bits, bytes),
+            Err(error) => {
+                Emitter::stderr(ColorConfig::Always, Some(&codemap))
+                    .emit(&[error.diagnostic(file_span)]);
+                return 1;
+            }
+        }
+    }
+
+    0
+}
+
+/// `gamma decode --blc`: prints the terms encoded in a file. With `--bytes`
+/// every term starts at a new byte.
+fn decode(args: &[String]) -> i32 {
+    let (bytes, filename) = blc_options(args);
+    let bits = read_bits(filename, bytes);
+
+    let mut offset = 0;
+    while offset < bits.len() {
+        match blc::decode(&bits[offset..]) {
+            Ok((term, length)) => {
+                println!("{}", term);
+                offset += length;
+                if bytes {
+                    offset = offset.next_multiple_of(8);
+                }
+            }
+            Err(blc::DecodeError::UnboundIndex { index, position }) => {
+                let position = offset + position;
+                eprintln!(
+                    "error: {}",
+                    blc::DecodeError::UnboundIndex { index, position }
+                );
+                return 1;
+            }
+            Err(error) => {
+                eprintln!("error: {}", error);
+                return 1;
+            }
+        }
+    }
+
+    0
+}
+
+/// `gamma run --blc`: applies the program encoded in a file to the bits read
+/// from stdin and prints the list of bits it returns.
+fn run_blc(filename: &str, bytes: bool) {
+    let bits = read_bits(filename, bytes);
+    let program = match blc::decode(&bits) {
+        Ok((program, length)) if bytes || length == bits.len() => program,
+        Ok(_) => {
+            eprintln!("error: unexpected bits after the program");
+            exit(1);
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            exit(1);
+        }
+    };
+
+    let mut input = vec![];
+    if io::stdin().read_to_end(&mut input).is_err() {
+        eprintln!("unable to read input");
+        exit(1);
+    }
+
+    let input = match bytes {
+        true => blc::unpack(&input),
+        false => blc::parse_bits(&String::from_utf8_lossy(&input)).unwrap_or_else(|error| {
+            eprintln!("error: {} of the input", error);
+            exit(1);
+        }),
+    };
+
+    let expression = ast::Expression::Apply {
+        lhs: Box::new(program),
+        lhs_span: 0..0,
+        rhs: Box::new(blc::bit_list(&input)),
+        rhs_span: 0..0,
+    };
+    let output = match reduce::Reducer::new(&HashMap::new(), REDUCTION_LIMIT).normalize(&expression)
+    {
+        Ok(output) => output,
+        Err(reduce::Error::Limit) => {
+            eprintln!(
+                "error: no normal form found within {} reduction steps",
+                REDUCTION_LIMIT
+            );
+            exit(1);
+        }
+        Err(reduce::Error::TooLarge) => {
+            eprintln!("error: the program made a term too large");
+            exit(1);
+        }
+    };
+
+    match blc::read_bit_list(&output) {
+        Some(bits) => write_bits(&bits, bytes),
+        None => {
+            eprintln!(
+                "error: the program did not return a list of bits: {}",
+                output
+            );
+            exit(1);
+        }
+    }
+}
+
+/// Byte offset of a 1-based `line:column` position, columns counted in
+/// characters.
+fn position_offset(source: &str, position: &str) -> Option<usize> {
+    let (line, column) = position.split_once(':')?;
+    let (line, column): (usize, usize) = (line.parse().ok()?, column.parse().ok()?);
+
+    let line_start = match line {
+        0 => return None,
+        1 => 0,
+        _ => source.match_indices('\n').nth(line - 2)?.0 + 1,
+    };
+
+    source[line_start..]
+        .char_indices()
+        .map(|(offset, _)| line_start + offset)
+        .chain(std::iter::once(source.len()))
+        .nth(column.checked_sub(1)?)
+}
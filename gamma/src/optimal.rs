@@ -0,0 +1,730 @@
+//! Optimal reduction: the abstract algorithm on interaction nets.
+//!
+//! A term is compiled to a net of nodes with three ports each, the first
+//! one principal: an abstraction's principal port is its value, the others
+//! its variable and body; an application's principal port is its function,
+//! the others its argument and result. A variable used several times is
+//! shared with a tree of duplicators, fans whose principal port takes the
+//! value and whose other ports give the copies, and an unused one is
+//! connected to an eraser. Every fan gets the label of the abstraction that
+//! introduced it.
+//!
+//! Reduction rewrites pairs of nodes connected by their principal ports:
+//! an application and an abstraction perform a beta step, fans with the
+//! same label annihilate, fans with different labels and other nodes
+//! commute by copying each other, and erasers delete what they meet. Only
+//! pairs that read-back of the normal form needs are rewritten, leftmost
+//! outermost first, so a duplicator copies an abstraction incrementally and
+//! redexes inside it are shared between the copies.
+//!
+//! Without Lamping's brackets, fan labels are only right as long as a
+//! duplicator never has to copy a fan with its own label. Fans are only made
+//! for abstractions whose variable is used several times, so as long as no
+//! such abstraction is copied, all the fans with a label come from the one
+//! tree of its abstraction and those that meet are twins. The machine gives
+//! up with [`Error::Duplication`] instead of copying such an abstraction,
+//! e.g. in `(\x => x x) two`, and with [`Error::Recursion`] on bindings that
+//! refer to themselves, which cannot be compiled into a finite net. It
+//! handles terms typeable in elementary affine logic, like arithmetic on
+//! Church numerals, but never returns a wrong normal form.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use gamma_parser::reduce::{Bindings, SIZE_LIMIT};
+
+use crate::eval::Term;
+use crate::readback::{Head, Normal};
+
+/// Number of frames that `whnf` or `read_back` waits on after which
+/// read-back gives up. Nets that the abstract algorithm gets wrong can make
+/// them go deeper without end.
+const MAX_DEPTH: usize = 100_000;
+
+type NodeId = usize;
+/// A node and one of its ports, `node * 3 + slot`.
+type Port = usize;
+
+fn port(node: NodeId, slot: usize) -> Port {
+    node * 3 + slot
+}
+
+fn node(port: Port) -> NodeId {
+    port / 3
+}
+
+fn slot(port: Port) -> usize {
+    port % 3
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    /// Its second port observes the whole term.
+    Root,
+    /// Named with this entry of the names table for read-back, and whether
+    /// its variable is shared by fans.
+    Abstraction(usize, bool),
+    Apply,
+    Fan(usize),
+    Eraser,
+    /// A variable bound outside of the term, named with this entry of the
+    /// names table. Only its principal port is used.
+    Free(usize),
+    /// Rewritten away.
+    Deleted,
+}
+
+/// Number of each kind of interaction performed by a [`Machine`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Applications meeting abstractions.
+    pub beta: usize,
+    /// Fans meeting fans with the same label.
+    pub annihilations: usize,
+    /// Nodes copying each other.
+    pub commutations: usize,
+    /// Erasers deleting nodes.
+    pub erasures: usize,
+}
+
+impl Statistics {
+    pub fn interactions(&self) -> usize {
+        self.beta + self.annihilations + self.commutations + self.erasures
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} interactions: {} beta, {} annihilations, {} commutations, {} erasures",
+            self.interactions(),
+            self.beta,
+            self.annihilations,
+            self.commutations,
+            self.erasures
+        )
+    }
+}
+
+/// What [`Machine::whnf`] does once the term that the observer it went on
+/// with sees is in weak head normal form.
+enum Resume {
+    /// It is the function of this application, which the observer sees.
+    Apply(Port, NodeId),
+    /// It is the term behind this fan, which the observer sees through the
+    /// auxiliary port with this label.
+    Fan(Port, NodeId, usize),
+    /// It was reached by leaving a fan with this label through this port.
+    Exit(usize, usize),
+}
+
+/// What [`Machine::read_back`] does with the next normal form.
+enum Reading {
+    /// It is the body of an abstraction with this name.
+    Body(usize),
+    /// It is the function of this application.
+    Function(NodeId),
+    /// It is the argument of an application with this function.
+    Argument(Normal),
+    /// It was reached by entering a fan with this label.
+    Fan(usize),
+    /// It was reached by leaving a fan with this label through this port.
+    Exit(usize, usize),
+}
+
+/// Why a [`Machine`] did not find a normal form.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Reduction took more than `limit` interactions.
+    Limit,
+    /// The net does not read back to a term, because its normal form is
+    /// too deep.
+    ReadBack,
+    /// A fan would have had to copy an abstraction that shares its variable,
+    /// which fan labels cannot tell apart from its copies.
+    Duplication,
+    /// The term uses this binding, which refers to itself.
+    Recursion(String),
+    /// The normal form is larger than [`SIZE_LIMIT`].
+    TooLarge,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Limit => write!(f, "optimal reduction gave up"),
+            Error::ReadBack => write!(f, "optimal reduction cannot read back the normal form"),
+            Error::Duplication => write!(
+                f,
+                "optimal reduction cannot copy a function that uses its parameter several times"
+            ),
+            Error::Recursion(name) => write!(
+                f,
+                "optimal reduction cannot unfold the recursive binding `{}`",
+                name
+            ),
+            Error::TooLarge => write!(f, "the normal form is too large"),
+        }
+    }
+}
+
+/// Reduces expressions with interaction nets. Bindings are compiled into
+/// the net of every expression that uses them.
+pub struct Machine<'a> {
+    bindings: &'a dyn Bindings,
+    kinds: Vec<Kind>,
+    /// The port each port is connected to.
+    ports: Vec<Port>,
+    names: Vec<String>,
+    name_ids: HashMap<String, usize>,
+    labels: usize,
+    /// For every fan label, the ports that read-back left fans through, the
+    /// innermost last.
+    exits: HashMap<usize, Vec<usize>>,
+    pub statistics: Statistics,
+    /// Number of interactions after which reduction gives up.
+    pub limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(bindings: &'a dyn Bindings, limit: usize) -> Self {
+        Self {
+            bindings,
+            kinds: vec![],
+            ports: vec![],
+            names: vec![],
+            name_ids: HashMap::new(),
+            labels: 0,
+            exits: HashMap::new(),
+            statistics: Statistics::default(),
+            limit,
+        }
+    }
+
+    /// Reduces `term` to its normal form.
+    pub fn normalize(&mut self, term: &Term) -> Result<Term, Error> {
+        let root = self.allocate(Kind::Root);
+        self.link(port(root, 0), port(root, 0));
+        self.link(port(root, 2), port(root, 2));
+        self.compile(term, port(root, 1), &mut vec![], &mut vec![])?;
+
+        let normal = self.read_back(port(root, 1))?;
+        Ok(normal.to_term(self.bindings))
+    }
+
+    fn allocate(&mut self, kind: Kind) -> NodeId {
+        self.kinds.push(kind);
+        let node = self.kinds.len() - 1;
+        self.ports
+            .extend([port(node, 0), port(node, 1), port(node, 2)]);
+        node
+    }
+
+    fn link(&mut self, a: Port, b: Port) {
+        self.ports[a] = b;
+        self.ports[b] = a;
+    }
+
+    fn peer(&self, port: Port) -> Port {
+        self.ports[port]
+    }
+
+    fn kind(&self, port: Port) -> Kind {
+        self.kinds[node(port)]
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.name_ids.get(name) {
+            return id;
+        }
+
+        self.names.push(name.to_owned());
+        self.name_ids.insert(name.to_owned(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    /// Builds the net of `term` with its value connected to `observer`.
+    /// `scope` holds the abstractions around it and the ports that observe
+    /// their variables, `expanding` the bindings being compiled.
+    fn compile<'t>(
+        &mut self,
+        term: &'t Term,
+        observer: Port,
+        scope: &mut Vec<(&'t str, Vec<Port>)>,
+        expanding: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        match term {
+            Term::Var { name, .. } => {
+                if let Some((_, uses)) = scope.iter_mut().rev().find(|(other, _)| other == name) {
+                    uses.push(observer);
+                    return Ok(());
+                }
+
+                match self.bindings.binding(name) {
+                    // A binding is compiled without the abstractions around
+                    // the variable.
+                    Some(_) if expanding.contains(name) => {
+                        return Err(Error::Recursion(name.clone()))
+                    }
+                    Some(value) => {
+                        expanding.push(name.clone());
+                        self.compile(value, observer, &mut vec![], expanding)?;
+                        expanding.pop();
+                    }
+                    None => {
+                        let name = self.name(name);
+                        let free = self.allocate(Kind::Free(name));
+                        self.link(port(free, 0), observer);
+                    }
+                }
+            }
+            Term::Apply { lhs, rhs, .. } | Term::Dollar { lhs, rhs, .. } => {
+                let apply = self.allocate(Kind::Apply);
+                self.link(port(apply, 2), observer);
+                self.compile(lhs, port(apply, 0), scope, expanding)?;
+                self.compile(rhs, port(apply, 1), scope, expanding)?;
+            }
+            Term::Paren { expression, .. }
+            | Term::TypeAbstraction { expression, .. }
+            | Term::TypeApply { expression, .. } => {
+                self.compile(expression, observer, scope, expanding)?
+            }
+            Term::Abstraction {
+                name, expression, ..
+            } => {
+                let name_id = self.name(name);
+                let abstraction = self.allocate(Kind::Abstraction(name_id, false));
+                self.link(port(abstraction, 0), observer);
+
+                scope.push((name, vec![]));
+                self.compile(expression, port(abstraction, 2), scope, expanding)?;
+                let (_, uses) = scope.pop().unwrap();
+                self.kinds[abstraction] = Kind::Abstraction(name_id, uses.len() > 1);
+                self.share(port(abstraction, 1), &uses);
+            }
+            Term::If { .. } | Term::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before building nets")
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Connects the variable port `variable` to every one of `uses`, through
+    /// a chain of fans if there are several.
+    fn share(&mut self, variable: Port, uses: &[Port]) {
+        let Some((last, others)) = uses.split_last() else {
+            let eraser = self.allocate(Kind::Eraser);
+            self.link(variable, port(eraser, 0));
+            return;
+        };
+
+        self.labels += 1;
+        let mut value = variable;
+        for observer in others {
+            let fan = self.allocate(Kind::Fan(self.labels));
+            self.link(port(fan, 0), value);
+            self.link(port(fan, 1), *observer);
+            value = port(fan, 2);
+        }
+        self.link(value, *last);
+    }
+
+    /// Rewrites the pair of nodes connected by their principal ports.
+    fn interact(&mut self, a: NodeId, b: NodeId) -> Result<(), Error> {
+        if self.statistics.interactions() >= self.limit {
+            return Err(Error::Limit);
+        }
+
+        match (self.kinds[a], self.kinds[b]) {
+            (Kind::Apply, Kind::Abstraction(..)) => self.beta(a, b),
+            (Kind::Abstraction(..), Kind::Apply) => self.beta(b, a),
+            (Kind::Fan(left), Kind::Fan(right)) if left == right => self.annihilate(a, b),
+            (Kind::Eraser, _) => self.erase(a, b),
+            (_, Kind::Eraser) => self.erase(b, a),
+            (Kind::Fan(_), Kind::Free(_)) => self.copy_free(a, b),
+            (Kind::Free(_), Kind::Fan(_)) => self.copy_free(b, a),
+            // The copies of the abstraction would share their variables with
+            // fans of the same label.
+            (Kind::Abstraction(_, true), Kind::Fan(_))
+            | (Kind::Fan(_), Kind::Abstraction(_, true)) => return Err(Error::Duplication),
+            (Kind::Abstraction(..) | Kind::Apply | Kind::Fan(_), Kind::Fan(_))
+            | (Kind::Fan(_), Kind::Abstraction(..) | Kind::Apply) => self.commute(a, b),
+            _ => return Err(Error::ReadBack),
+        }
+
+        Ok(())
+    }
+
+    fn delete(&mut self, node: NodeId) {
+        self.kinds[node] = Kind::Deleted;
+    }
+
+    /// Connects the argument to the variable and the body to the result.
+    fn beta(&mut self, apply: NodeId, abstraction: NodeId) {
+        self.statistics.beta += 1;
+        self.link(self.peer(port(apply, 1)), self.peer(port(abstraction, 1)));
+        self.link(self.peer(port(apply, 2)), self.peer(port(abstraction, 2)));
+        self.delete(apply);
+        self.delete(abstraction);
+    }
+
+    fn annihilate(&mut self, a: NodeId, b: NodeId) {
+        self.statistics.annihilations += 1;
+        self.link(self.peer(port(a, 1)), self.peer(port(b, 1)));
+        self.link(self.peer(port(a, 2)), self.peer(port(b, 2)));
+        self.delete(a);
+        self.delete(b);
+    }
+
+    /// Copies `a` onto the other ports of `b` and `b` onto those of `a`.
+    fn commute(&mut self, a: NodeId, b: NodeId) {
+        self.statistics.commutations += 1;
+        let a1 = self.allocate(self.kinds[a]);
+        let a2 = self.allocate(self.kinds[a]);
+        let b1 = self.allocate(self.kinds[b]);
+        let b2 = self.allocate(self.kinds[b]);
+
+        self.link(port(b1, 0), self.peer(port(a, 1)));
+        self.link(port(b2, 0), self.peer(port(a, 2)));
+        self.link(port(a1, 0), self.peer(port(b, 1)));
+        self.link(port(a2, 0), self.peer(port(b, 2)));
+        self.link(port(a1, 1), port(b1, 1));
+        self.link(port(a1, 2), port(b2, 1));
+        self.link(port(a2, 1), port(b1, 2));
+        self.link(port(a2, 2), port(b2, 2));
+        self.delete(a);
+        self.delete(b);
+    }
+
+    /// Connects erasers to the other ports of `node`.
+    fn erase(&mut self, eraser: NodeId, node: NodeId) {
+        self.statistics.erasures += 1;
+        if let Kind::Abstraction(..) | Kind::Apply | Kind::Fan(_) = self.kinds[node] {
+            for slot in 1..3 {
+                let copy = self.allocate(Kind::Eraser);
+                self.link(port(copy, 0), self.peer(port(node, slot)));
+            }
+        }
+        self.delete(eraser);
+        self.delete(node);
+    }
+
+    /// Gives each copy of `fan` its own free variable.
+    fn copy_free(&mut self, fan: NodeId, free: NodeId) {
+        self.statistics.commutations += 1;
+        for slot in 1..3 {
+            let copy = self.allocate(self.kinds[free]);
+            self.link(port(copy, 0), self.peer(port(fan, slot)));
+        }
+        self.delete(fan);
+        self.delete(free);
+    }
+
+    /// Rewrites the net until the term that `observer` sees is an
+    /// abstraction or an unknown variable applied to arguments. A principal
+    /// `observer` facing a principal port is left for the caller to rewrite.
+    ///
+    /// The observers of the terms that need to be rewritten first wait on a
+    /// stack of frames rather than on the call stack.
+    fn whnf(&mut self, mut observer: Port) -> Result<(), Error> {
+        let mut frames = vec![];
+        loop {
+            if frames.len() >= MAX_DEPTH {
+                return Err(Error::ReadBack);
+            }
+
+            let value = self.peer(observer);
+            match (self.kind(value), slot(value)) {
+                (Kind::Apply, 2) => {
+                    let apply = node(value);
+                    if slot(self.peer(port(apply, 0))) != 0 {
+                        frames.push(Resume::Apply(observer, apply));
+                        observer = port(apply, 0);
+                        continue;
+                    }
+                    if self.rewrite_apply(apply)? {
+                        continue;
+                    }
+                }
+                (Kind::Fan(label), 1 | 2) => {
+                    let fan = node(value);
+                    if slot(self.peer(port(fan, 0))) != 0 {
+                        self.push_exit(label, slot(value));
+                        frames.push(Resume::Fan(observer, fan, label));
+                        observer = port(fan, 0);
+                        continue;
+                    }
+                    if self.rewrite_fan(fan)? {
+                        continue;
+                    }
+                }
+                (Kind::Fan(label), 0) if slot(observer) != 0 => {
+                    let Some(exit) = self.pop_exit(label) else {
+                        return Err(Error::ReadBack);
+                    };
+                    frames.push(Resume::Exit(label, exit));
+                    observer = port(node(value), exit);
+                    continue;
+                }
+                _ => {}
+            }
+
+            // `observer` sees a weak head normal form: the innermost frame
+            // goes on, until one of them has an interaction to rewrite.
+            observer = loop {
+                match frames.pop() {
+                    None => return Ok(()),
+                    Some(Resume::Apply(observer, apply)) => {
+                        if self.rewrite_apply(apply)? {
+                            break observer;
+                        }
+                    }
+                    Some(Resume::Fan(observer, fan, label)) => {
+                        self.pop_exit(label);
+                        if self.rewrite_fan(fan)? {
+                            break observer;
+                        }
+                    }
+                    Some(Resume::Exit(label, exit)) => self.push_exit(label, exit),
+                }
+            };
+        }
+    }
+
+    /// Rewrites `apply` with its function, unless the function is an
+    /// unknown variable. Returns whether it did.
+    fn rewrite_apply(&mut self, apply: NodeId) -> Result<bool, Error> {
+        let function = self.peer(port(apply, 0));
+        match (self.kind(function), slot(function)) {
+            (Kind::Free(_), _) | (_, 1 | 2) => Ok(false),
+            _ => {
+                self.interact(apply, node(function))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Rewrites `fan` with the node its principal port faces, if that is a
+    /// principal port too. Returns whether it did.
+    fn rewrite_fan(&mut self, fan: NodeId) -> Result<bool, Error> {
+        let target = self.peer(port(fan, 0));
+        match slot(target) {
+            0 => {
+                self.interact(fan, node(target))?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Reads back the normal form of the term that `observer` sees,
+    /// rewriting the net as needed. Like in [`Machine::whnf`], the parts of
+    /// the normal form wait on a stack of frames.
+    fn read_back(&mut self, mut observer: Port) -> Result<Normal, Error> {
+        // The abstractions around `observer`, the outermost first.
+        let mut scope = vec![];
+        let mut frames = vec![];
+        let mut size = 0;
+        loop {
+            if frames.len() >= MAX_DEPTH {
+                return Err(Error::ReadBack);
+            }
+
+            self.whnf(observer)?;
+            let value = self.peer(observer);
+            if let Kind::Abstraction(..) | Kind::Free(_) | Kind::Apply = self.kind(value) {
+                size += 1;
+                if size > SIZE_LIMIT {
+                    return Err(Error::TooLarge);
+                }
+            }
+
+            let mut normal = match (self.kind(value), slot(value)) {
+                (Kind::Abstraction(name, _), 0) => {
+                    scope.push(node(value));
+                    frames.push(Reading::Body(name));
+                    observer = port(node(value), 2);
+                    continue;
+                }
+                (Kind::Abstraction(..), 1) => {
+                    let level = scope
+                        .iter()
+                        .rposition(|abstraction| *abstraction == node(value));
+                    Normal::Neutral(Head::Level(level.ok_or(Error::ReadBack)?), vec![])
+                }
+                (Kind::Free(name), 0) => {
+                    Normal::Neutral(Head::Global(self.names[name].clone()), vec![])
+                }
+                (Kind::Apply, 2) => {
+                    frames.push(Reading::Function(node(value)));
+                    observer = port(node(value), 0);
+                    continue;
+                }
+                (Kind::Fan(label), 1 | 2) => {
+                    self.push_exit(label, slot(value));
+                    frames.push(Reading::Fan(label));
+                    observer = port(node(value), 0);
+                    continue;
+                }
+                (Kind::Fan(label), 0) if slot(observer) != 0 => {
+                    let Some(exit) = self.pop_exit(label) else {
+                        return Err(Error::ReadBack);
+                    };
+                    frames.push(Reading::Exit(label, exit));
+                    observer = port(node(value), exit);
+                    continue;
+                }
+                _ => return Err(Error::ReadBack),
+            };
+
+            // `normal` is complete: the innermost frame takes it, until one
+            // of them has an argument left to read back.
+            observer = loop {
+                match frames.pop() {
+                    None => return Ok(normal),
+                    Some(Reading::Body(name)) => {
+                        scope.pop();
+                        normal = Normal::Abstraction(self.names[name].clone(), Box::new(normal));
+                    }
+                    Some(Reading::Function(apply)) => {
+                        frames.push(Reading::Argument(normal));
+                        break port(apply, 1);
+                    }
+                    Some(Reading::Argument(function)) => match function {
+                        Normal::Neutral(head, mut arguments) => {
+                            arguments.push(normal);
+                            normal = Normal::Neutral(head, arguments);
+                        }
+                        Normal::Abstraction(..) => return Err(Error::ReadBack),
+                    },
+                    Some(Reading::Fan(label)) => {
+                        self.pop_exit(label);
+                    }
+                    Some(Reading::Exit(label, exit)) => self.push_exit(label, exit),
+                }
+            };
+        }
+    }
+
+    /// Records that read-back entered a fan with `label` through `exit`, the
+    /// port it leaves the next fan with the same label through.
+    fn push_exit(&mut self, label: usize, exit: usize) {
+        self.exits.entry(label).or_default().push(exit);
+    }
+
+    fn pop_exit(&mut self, label: usize) -> Option<usize> {
+        self.exits.get_mut(&label).and_then(Vec::pop)
+    }
+}
+
+#[cfg(test)]
+mod optimal_tests {
+    use codemap::CodeMap;
+    use gamma_parser::parser::Parser;
+    use gamma_parser::reduce::{alpha_equivalent, Reducer};
+
+    use super::*;
+    use crate::eval::{Context, Evaluator};
+
+    const PRELUDE: &str = "
+        let true = \\x => \\y => x; let false = \\x => \\y => y;
+        let not = \\b => b false true;
+        let zero = \\f => \\x => x; let succ = \\n => \\f => \\x => f (n f x);
+        let two = succ (succ zero); let three = succ two;
+        let plus = \\m => \\n => \\f => \\x => m f (n f x);
+        let mult = \\m => \\n => \\f => m (n f);
+        let is_zero = \\n => n (\\x => false) true;
+        let pred = \\n => \\f => \\x => n (\\g => \\h => h (g f)) (\\u => x) (\\u => u);
+        let down = \\n => is_zero n zero (down (pred n));
+    ";
+
+    fn context() -> Context {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(PRELUDE, "prelude.gm", &mut codemap);
+        let (prelude, file_span) = (parser.parse(), parser.file_span);
+        drop(parser);
+
+        let mut evaluator = Evaluator::new();
+        for statement in &prelude {
+            evaluator.eval_statement(statement, file_span).unwrap();
+        }
+        evaluator.context
+    }
+
+    fn expression(source: &str) -> Term {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<test>", &mut codemap);
+        let expression = parser.parse_single_expression().unwrap();
+        drop(parser);
+        expression
+    }
+
+    #[test]
+    fn agrees_with_substitution() {
+        let context = context();
+        for source in [
+            "\\x => x",
+            "free (\\x => x) variables",
+            "not true",
+            "plus two three",
+            "mult three (mult three two)",
+            "pred three",
+            "(\\x => \\y => y) ((\\x => x x) (\\x => x x))",
+            "(\\x => x x) ((\\y => y) (\\z => z))",
+            "\\x => (\\y => \\x => y) x",
+        ] {
+            let expression = expression(source);
+            let expected = Reducer::new(&context, 10_000)
+                .normalize(&expression)
+                .unwrap();
+            let actual = Machine::new(&context, 10_000)
+                .normalize(&expression)
+                .unwrap();
+            assert!(
+                alpha_equivalent(&expected, &actual),
+                "{}: expected {}, found {}",
+                source,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn shares_work() {
+        let context = context();
+        let expression = expression("mult three (mult three (mult three three))");
+
+        let mut reducer = Reducer::new(&context, 10_000);
+        reducer.normalize(&expression).unwrap();
+        let mut machine = Machine::new(&context, 10_000);
+        machine.normalize(&expression).unwrap();
+        assert!(machine.statistics.beta < reducer.beta_steps / 2);
+    }
+
+    #[test]
+    fn limits() {
+        let context = context();
+        let nine = expression("mult three three");
+        assert_eq!(
+            Machine::new(&context, 10).normalize(&nine),
+            Err(Error::Limit)
+        );
+
+        for source in ["(\\x => x x) two", "two two", "three two"] {
+            assert_eq!(
+                Machine::new(&context, 100).normalize(&expression(source)),
+                Err(Error::Duplication),
+                "{}",
+                source
+            );
+        }
+
+        assert_eq!(
+            Machine::new(&context, 100).normalize(&expression("down two")),
+            Err(Error::Recursion("down".to_owned()))
+        );
+    }
+}
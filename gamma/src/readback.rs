@@ -0,0 +1,255 @@
+//! Normal forms computed by the abstract machines, before their bound
+//! variables get names.
+
+use std::collections::HashSet;
+
+use gamma_parser::reduce::{fresh_name, Bindings, SIZE_LIMIT};
+
+use crate::eval::Term;
+
+/// Head of a normal form that is not an abstraction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Head {
+    /// A variable that is neither bound in the term nor by a binding.
+    Global(String),
+    /// The variable of the enclosing abstraction at this depth, counted from
+    /// the outermost one.
+    Level(usize),
+}
+
+/// A normal form with nameless bound variables.
+#[derive(Clone, Debug)]
+pub enum Normal {
+    /// Keeps the name of the parameter as a suggestion.
+    Abstraction(String, Box<Normal>),
+    /// An unknown variable applied to arguments, the first one first.
+    Neutral(Head, Vec<Normal>),
+}
+
+/// A value of a machine in weak head normal form.
+pub enum Shape<V> {
+    /// An abstraction that keeps the name of its parameter, and its body
+    /// with the parameter bound to the variable of its level.
+    Abstraction(String, V),
+    /// An unknown variable applied to arguments, the first one first.
+    Neutral(Head, Vec<V>),
+}
+
+/// What [`read_back`] does with the next normal form.
+enum Frame<V> {
+    /// It is the body of an abstraction with this parameter.
+    Body(String),
+    /// It is an argument of `head`, after those in `normal` and before those
+    /// in `pending`, under `depth` abstractions.
+    Argument {
+        head: Head,
+        normal: Vec<Normal>,
+        pending: std::vec::IntoIter<V>,
+        depth: usize,
+    },
+}
+
+/// Reads back the normal form of `value`, which `whnf` reduces to weak head
+/// normal form under a number of abstractions, from the outermost value to
+/// the innermost, and from the first argument of a neutral value to the
+/// last. Gives up with `too_large` once the normal form has more than
+/// [`SIZE_LIMIT`] nodes.
+///
+/// The parts of the normal form that are being read back wait on a stack
+/// of frames rather than on the call stack, which deep normal forms would
+/// overflow.
+pub fn read_back<V, E>(
+    value: V,
+    too_large: E,
+    mut whnf: impl FnMut(V, usize) -> Result<Shape<V>, E>,
+) -> Result<Normal, E> {
+    let mut frames = vec![];
+    let mut size = 0;
+    let (mut value, mut depth) = (value, 0);
+    loop {
+        let shape = whnf(value, depth)?;
+        size += match &shape {
+            Shape::Abstraction(..) => 1,
+            Shape::Neutral(_, arguments) => 1 + arguments.len(),
+        };
+        if size > SIZE_LIMIT {
+            return Err(too_large);
+        }
+
+        let mut normal = match shape {
+            Shape::Abstraction(name, body) => {
+                frames.push(Frame::Body(name));
+                (value, depth) = (body, depth + 1);
+                continue;
+            }
+            Shape::Neutral(head, arguments) => {
+                let mut pending = arguments.into_iter();
+                match pending.next() {
+                    Some(argument) => {
+                        frames.push(Frame::Argument {
+                            head,
+                            normal: vec![],
+                            pending,
+                            depth,
+                        });
+                        value = argument;
+                        continue;
+                    }
+                    None => Normal::Neutral(head, vec![]),
+                }
+            }
+        };
+
+        // `normal` is complete: the innermost frame takes it, until one of
+        // them has an argument left to read back.
+        (value, depth) = loop {
+            match frames.pop() {
+                None => return Ok(normal),
+                Some(Frame::Body(name)) => normal = Normal::Abstraction(name, Box::new(normal)),
+                Some(Frame::Argument {
+                    head,
+                    normal: mut arguments,
+                    mut pending,
+                    depth,
+                }) => {
+                    arguments.push(normal);
+                    match pending.next() {
+                        Some(argument) => {
+                            frames.push(Frame::Argument {
+                                head,
+                                normal: arguments,
+                                pending,
+                                depth,
+                            });
+                            break (argument, depth);
+                        }
+                        None => normal = Normal::Neutral(head, arguments),
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// What [`Normal::to_term`] does with the next term.
+enum Naming<'n> {
+    /// It is the body of the innermost abstraction in scope.
+    Body,
+    /// It is an argument of `function`, before those in `pending`.
+    Argument {
+        function: Term,
+        pending: std::slice::Iter<'n, Normal>,
+    },
+}
+
+impl Normal {
+    /// Names the binders after their parameters, renaming those that would
+    /// capture a variable or shadow a binding.
+    ///
+    /// Like [`read_back`], this keeps the parts of the term on a stack of
+    /// frames rather than on the call stack.
+    pub fn to_term(&self, bindings: &dyn Bindings) -> Term {
+        let globals = self.globals();
+        let mut scope: Vec<String> = vec![];
+        let mut frames = vec![];
+        let mut normal = self;
+        loop {
+            let mut term = match normal {
+                Normal::Abstraction(name, body) => {
+                    let taken = |candidate: &str| {
+                        scope.iter().any(|name| name == candidate)
+                            || globals.contains(candidate)
+                            || bindings.binding(candidate).is_some()
+                    };
+                    let name = match taken(name) {
+                        true => fresh_name(name, taken),
+                        false => name.clone(),
+                    };
+
+                    scope.push(name);
+                    frames.push(Naming::Body);
+                    normal = body;
+                    continue;
+                }
+                Normal::Neutral(head, arguments) => {
+                    let name = match head {
+                        Head::Global(name) => name.clone(),
+                        Head::Level(level) => scope[*level].clone(),
+                    };
+                    let head = Term::Var {
+                        name,
+                        name_span: 0..0,
+                    };
+
+                    let mut pending = arguments.iter();
+                    match pending.next() {
+                        Some(argument) => {
+                            frames.push(Naming::Argument {
+                                function: head,
+                                pending,
+                            });
+                            normal = argument;
+                            continue;
+                        }
+                        None => head,
+                    }
+                }
+            };
+
+            normal = loop {
+                match frames.pop() {
+                    None => return term,
+                    Some(Naming::Body) => {
+                        term = Term::Abstraction {
+                            name: scope.pop().expect("bodies are in scope"),
+                            name_span: 0..0,
+                            annotation: None,
+                            expression: Box::new(term),
+                            expression_span: 0..0,
+                        }
+                    }
+                    Some(Naming::Argument {
+                        function,
+                        mut pending,
+                    }) => {
+                        let application = Term::Apply {
+                            lhs: Box::new(function),
+                            lhs_span: 0..0,
+                            rhs: Box::new(term),
+                            rhs_span: 0..0,
+                        };
+                        match pending.next() {
+                            Some(argument) => {
+                                frames.push(Naming::Argument {
+                                    function: application,
+                                    pending,
+                                });
+                                break argument;
+                            }
+                            None => term = application,
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    /// Names of the variables that are not bound in the normal form.
+    fn globals(&self) -> HashSet<String> {
+        let mut globals = HashSet::new();
+        let mut pending = vec![self];
+        while let Some(normal) = pending.pop() {
+            match normal {
+                Normal::Abstraction(_, body) => pending.push(body),
+                Normal::Neutral(head, arguments) => {
+                    if let Head::Global(name) = head {
+                        globals.insert(name.clone());
+                    }
+                    pending.extend(arguments);
+                }
+            }
+        }
+
+        globals
+    }
+}
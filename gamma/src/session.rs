@@ -0,0 +1,443 @@
+//! Embedding API: evaluates Gamma code on behalf of a host program without
+//! printing anything or exiting the process.
+
+use std::collections::HashMap;
+
+use codemap::CodeMap;
+use codemap_diagnostic::{Diagnostic, Level};
+use gamma_parser::ast::{self, Fixity, Statement, Type};
+use gamma_parser::parser::Parser;
+
+use crate::cek;
+use crate::eval::{Backend, Evaluator, Statistics, Term, Typing};
+
+/// Bindings and sources shared by everything evaluated in it.
+///
+/// Diagnostics refer to spans of [`Session::codemap`], so a host that wants
+/// to show them can pass it to a `codemap_diagnostic::Emitter`.
+pub struct Session {
+    codemap: CodeMap,
+    evaluator: Evaluator,
+    /// Operators declared by the programs loaded so far.
+    fixities: HashMap<String, Fixity>,
+    warnings: Vec<Diagnostic>,
+    expressions: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            codemap: CodeMap::new(),
+            evaluator: Evaluator::new(),
+            fixities: HashMap::new(),
+            warnings: vec![],
+            expressions: 0,
+        }
+    }
+
+    pub fn codemap(&self) -> &CodeMap {
+        &self.codemap
+    }
+
+    /// Sets the number of beta reductions after which evaluation of an
+    /// expression gives up.
+    pub fn set_reduction_limit(&mut self, limit: usize) {
+        self.evaluator.limit = limit;
+    }
+
+    /// Sets the machine that reduces expressions.
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.evaluator.backend = backend;
+    }
+
+    /// Calls `trace` with every state of the CEK machine.
+    pub fn set_trace(&mut self, trace: impl Fn(&cek::State) + 'static) {
+        self.evaluator.trace = Some(Box::new(trace));
+    }
+
+    /// Calls `statistics` with the work done for every expression, if the
+    /// backend counts it.
+    pub fn set_statistics(&mut self, statistics: impl Fn(&Statistics) + 'static) {
+        self.evaluator.statistics = Some(Box::new(statistics));
+    }
+
+    /// Sets the type system that code is checked against before it is
+    /// evaluated.
+    pub fn set_typing(&mut self, typing: Typing) {
+        self.evaluator.typing = typing;
+    }
+
+    /// Evaluates the program `source`, registered under `name`, and returns
+    /// the normal forms of its expression statements.
+    ///
+    /// Nothing is evaluated if the program has syntax errors. Otherwise
+    /// evaluation stops at the first failing statement; `let`s before it stay
+    /// defined.
+    pub fn load(&mut self, name: &str, source: &str) -> Result<Vec<Term>, Vec<Diagnostic>> {
+        let mut parser = Parser::new(source, name, &mut self.codemap);
+        parser.fixities = self.fixities.clone();
+        let ast = parser.parse();
+        let file_span = parser.file_span;
+        let diagnostics = std::mem::take(&mut parser.diagnostics);
+        let fixities = std::mem::take(&mut parser.fixities);
+        drop(parser);
+
+        self.check(diagnostics)?;
+        self.fixities = fixities;
+
+        let mut terms = vec![];
+        for statement in &ast {
+            if let Some(term) = self.evaluator.eval_statement(statement, file_span)? {
+                terms.push(term);
+            }
+        }
+
+        Ok(terms)
+    }
+
+    /// Reduces the single expression `source` to its normal form.
+    pub fn eval_expr(&mut self, source: &str) -> Result<Term, Vec<Diagnostic>> {
+        let (expression, expression_span, file_span) = self.parse_expression(source)?;
+
+        let statement = Statement::Expression {
+            span: expression_span.start..source.trim_end().len(),
+            expression,
+            expression_span,
+        };
+
+        self.evaluator
+            .eval_statement(&statement, file_span)
+            .map(|term| term.expect("expression statements have a normal form"))
+    }
+
+    /// Infers the principal type of the single expression `source`, whatever
+    /// type system the session uses.
+    pub fn type_of(&mut self, source: &str) -> Result<Type, Vec<Diagnostic>> {
+        let (expression, span, file_span) = self.parse_expression(source)?;
+        self.evaluator.type_of(&expression, &span, file_span)
+    }
+
+    /// Defines `name` for code evaluated afterwards, replacing and returning
+    /// its previous value. Redefining it with `let` is an error.
+    pub fn define(&mut self, name: &str, term: Term) -> Option<Term> {
+        self.evaluator.define(name, term)
+    }
+
+    /// Value of the variable `name`, as written in its definition.
+    pub fn get(&self, name: &str) -> Option<&Term> {
+        self.evaluator.context.get(name)
+    }
+
+    /// Warnings reported since the last call.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Parses the single expression `source`, and returns it with its span
+    /// and the span of the source.
+    fn parse_expression(
+        &mut self,
+        source: &str,
+    ) -> Result<(Term, ast::Span, codemap::Span), Vec<Diagnostic>> {
+        self.expressions += 1;
+        let name = format!("<expression {}>", self.expressions);
+
+        let mut parser = Parser::new(source, &name, &mut self.codemap);
+        parser.fixities = self.fixities.clone();
+        let expression = parser.parse_single_expression();
+        if expression.is_none() && !parser.has_errors() {
+            parser.unexpected_eof();
+        }
+        let file_span = parser.file_span;
+        let diagnostics = std::mem::take(&mut parser.diagnostics);
+        drop(parser);
+
+        self.check(diagnostics)?;
+
+        let start = source.len() - source.trim_start().len();
+        let end = source.trim_end().trim_end_matches(';').trim_end().len();
+        Ok((expression.unwrap(), start..end, file_span))
+    }
+
+    /// Keeps the warnings and fails with all diagnostics if there is an error.
+    fn check(&mut self, diagnostics: Vec<Diagnostic>) -> Result<(), Vec<Diagnostic>> {
+        if diagnostics
+            .iter()
+            .any(|diagnostic| matches!(diagnostic.level, Level::Bug | Level::Error))
+        {
+            return Err(diagnostics);
+        }
+
+        self.warnings.extend(diagnostics);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::Session;
+    use crate::eval::{Backend, Typing};
+
+    fn normal_form(session: &mut Session, source: &str) -> String {
+        session.eval_expr(source).unwrap().to_string()
+    }
+
+    #[test]
+    fn load_and_eval() {
+        let mut session = Session::new();
+        let terms = session
+            .load(
+                "booleans.gm",
+                "let true = \\x => \\y => x; let false = \\x => \\y => y;
+                 let not = \\b => b false true; not true;",
+            )
+            .unwrap();
+        assert_eq!(
+            terms.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["\\x => \\y => y"]
+        );
+
+        assert_eq!(
+            normal_form(&mut session, "not (not true)"),
+            "\\x => \\y => x"
+        );
+        assert_eq!(normal_form(&mut session, "not false;"), "\\x => \\y => x");
+    }
+
+    #[test]
+    fn missing_expressions_are_errors() {
+        let mut session = Session::new();
+        for source in ["", "  ;", "-- nothing\n"] {
+            let diagnostics = session.eval_expr(source).unwrap_err();
+            assert_eq!(diagnostics.len(), 1, "{:?}", source);
+            assert_eq!(diagnostics[0].code.as_deref(), Some("E001"));
+        }
+    }
+
+    #[test]
+    fn operators() {
+        let mut session = Session::new();
+        session
+            .load(
+                "booleans.gm",
+                "let true = \\x => \\y => x; let false = \\x => \\y => y;
+                 infixr 3 && = \\a => \\b => a b false;
+                 infixr 2 || = \\a => \\b => a true b;",
+            )
+            .unwrap();
+        assert_eq!(
+            normal_form(&mut session, "false && true || true"),
+            "\\x => \\y => x"
+        );
+        assert_eq!(
+            normal_form(&mut session, "(&& false) true"),
+            "\\x => \\y => y"
+        );
+    }
+
+    #[test]
+    fn syntax_rules() {
+        let mut session = Session::new();
+        session
+            .load(
+                "when.gm",
+                "let true = \\x => \\y => x;
+                 syntax [do otherwise] when c do a otherwise b = c a b;
+                 syntax apply x = f x;",
+            )
+            .unwrap();
+        assert_eq!(normal_form(&mut session, "when true do a otherwise b"), "a");
+        assert_eq!(
+            session
+                .type_of("when true do true otherwise true")
+                .unwrap()
+                .to_string(),
+            "a -> b -> a"
+        );
+
+        let diagnostics = session.eval_expr("when true do a").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E015"));
+        assert_eq!(diagnostics[1].code.as_deref(), Some("N015"));
+
+        // `f` is not defined, and comes from the template.
+        session.set_typing(Typing::Stlc);
+        let diagnostics = session.eval_expr("apply (\\y: A => y)").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E007"));
+        assert_eq!(diagnostics[1].code.as_deref(), Some("N015"));
+    }
+
+    #[test]
+    fn data_types() {
+        let mut session = Session::new();
+        session
+            .load(
+                "list.gm",
+                "data List = Nil | Cons h t;
+                 let null = \\xs => match xs { Nil => \\x => \\y => x; _ => \\x => \\y => y };",
+            )
+            .unwrap();
+        assert_eq!(normal_form(&mut session, "if null Nil then a else b"), "a");
+        assert_eq!(
+            session
+                .type_of("match Cons a Nil { Cons h t => h; Nil => h }")
+                .unwrap_err()[0]
+                .code
+                .as_deref(),
+            Some("E007")
+        );
+        assert_eq!(
+            session
+                .type_of("\\xs => match xs { Cons h t => h; Nil => \\x => x }")
+                .unwrap()
+                .to_string(),
+            "((a -> a) -> (b -> c -> b) -> d) -> d"
+        );
+
+        let diagnostics = session.eval_expr("match Nil { Nil => a }").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E016"));
+        let diagnostics = session.load("again.gm", "data List = Nil;").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E003"));
+    }
+
+    #[test]
+    fn host_bindings() {
+        let mut session = Session::new();
+        let id = session.eval_expr("\\x => x").unwrap();
+        assert!(session.define("id", id).is_none());
+        assert_eq!(normal_form(&mut session, "id id"), "\\x => x");
+
+        let diagnostics = session
+            .load("redefine.gm", "let id = \\y => y;")
+            .unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E003"));
+        assert_eq!(
+            diagnostics[1].message,
+            "variable `id` is defined by the host"
+        );
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let mut session = Session::new();
+        let diagnostics = session.eval_expr("\\x =>").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E001"));
+        assert!(session.eval_expr("a b; c").is_err());
+
+        session.set_reduction_limit(100);
+        let diagnostics = session.eval_expr("(\\x => x x) (\\x => x x)").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E004"));
+
+        assert!(session.load("syntax.gm", "let a = ;").is_err());
+        assert!(session.get("a").is_none());
+
+        assert!(session.load("warning.gm", "let a = \\x . x;").is_ok());
+        assert_eq!(session.take_warnings().len(), 1);
+        assert!(session.get("a").is_some());
+    }
+
+    #[test]
+    fn large_terms_are_errors() {
+        let church = "let mult = \\m => \\n => \\f => m (n f);
+                      let ten = \\f => \\x => f (f (f (f (f (f (f (f (f (f x)))))))));
+                      let hundred = mult ten ten;
+                      mult hundred hundred;";
+        let growing = "(\\w => w ((\\z => w) w)) (\\w => w ((\\z => w) w));";
+        for backend in [
+            Backend::Substitution,
+            Backend::Krivine,
+            Backend::Cek,
+            Backend::Vm,
+            Backend::Graph,
+            Backend::Optimal,
+        ] {
+            let mut session = Session::new();
+            session.set_backend(backend);
+            let diagnostics = session.load("church.gm", church).unwrap_err();
+            assert_eq!(
+                diagnostics[0].code.as_deref(),
+                Some("E018"),
+                "{:?}",
+                backend
+            );
+            let diagnostics = session.eval_expr("mult hundred hundred").unwrap_err();
+            assert_eq!(
+                diagnostics[0].code.as_deref(),
+                Some("E018"),
+                "{:?}",
+                backend
+            );
+            assert!(
+                session.load("growing.gm", growing).is_err(),
+                "{:?}",
+                backend
+            );
+            assert!(session.eval_expr(growing).is_err(), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn stlc() {
+        let mut session = Session::new();
+        session.set_typing(Typing::Stlc);
+        session
+            .load("id.gm", "let id : A -> A = \\x: A => x;")
+            .unwrap();
+        assert_eq!(normal_form(&mut session, "\\y: A => id y"), "\\y: A => y");
+
+        let diagnostics = session.eval_expr("\\y: B => id y").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E005"));
+        let diagnostics = session.load("untyped.gm", "let k = \\x => x;").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E006"));
+    }
+
+    #[test]
+    fn system_f() {
+        let mut session = Session::new();
+        session.set_typing(Typing::SystemF);
+        session
+            .load("id.gm", "let id : forall a. a -> a = /\\a => \\x: a => x;")
+            .unwrap();
+        assert_eq!(
+            session.type_of("id [Nat]").unwrap().to_string(),
+            "Nat -> Nat"
+        );
+        assert_eq!(normal_form(&mut session, "id [Nat]"), "\\x => x");
+
+        let diagnostics = session.eval_expr("id id").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E005"));
+
+        session.set_typing(Typing::Stlc);
+        let diagnostics = session.eval_expr("/\\a => \\x: a => x").unwrap_err();
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E009"));
+    }
+
+    #[test]
+    fn inferred_types() {
+        let mut session = Session::new();
+        session
+            .load(
+                "booleans.gm",
+                "let true = \\x => \\y => x; let omega = \\x => x x;",
+            )
+            .unwrap();
+        assert_eq!(session.type_of("true").unwrap().to_string(), "a -> b -> a");
+        assert_eq!(
+            session.type_of("true true").unwrap().to_string(),
+            "a -> b -> c -> b"
+        );
+        assert_eq!(
+            session.type_of("omega").unwrap_err()[0].code.as_deref(),
+            Some("E008")
+        );
+
+        session.set_typing(Typing::Hm);
+        let diagnostics = session.load("omega.gm", "\\x => x x;").unwrap_err();
+        assert_eq!(diagnostics[0].message, "the term is not simply typeable");
+    }
+}
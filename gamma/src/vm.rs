@@ -0,0 +1,487 @@
+//! Bytecode virtual machine: lazy evaluation of compiled terms.
+//!
+//! Terms are compiled to instructions for a stack machine. An application
+//! pushes its arguments as thunks, closures of code and environment, and
+//! continues with the function without saving anything to return to, so
+//! every call is a tail call. An abstraction pops an argument into its
+//! environment. A thunk is evaluated at most once: entering it pushes an
+//! update marker, and the value it reaches replaces it, so bindings like
+//! `let three = succ two;` are also computed once per expression.
+//!
+//! Normal forms are read back like in [`crate::krivine`], by evaluating
+//! under each binder with the bound variable left as an unknown.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use gamma_parser::reduce::Bindings;
+
+use crate::eval::Term;
+use crate::readback::{self, Head, Shape};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instruction {
+    /// Pushes a thunk of the code at this address in the current
+    /// environment.
+    Push(usize),
+    /// Pushes the thunk of the variable with this de Bruijn index.
+    PushVar(usize),
+    /// Pushes the thunk of this global.
+    PushGlobal(usize),
+    /// Pops an argument into the environment. Names the parameter with this
+    /// entry of the names table for read-back.
+    Grab(usize),
+    /// Continues with the variable with this de Bruijn index.
+    Access(usize),
+    /// Continues with this global.
+    Global(usize),
+}
+
+type Thunk = Rc<RefCell<State>>;
+
+#[derive(Debug)]
+enum State {
+    Delayed {
+        address: usize,
+        environment: Environment,
+    },
+    /// Being evaluated: a thunk entered again before it has a value needs
+    /// its own value to get one.
+    BlackHole,
+    Evaluated(Value),
+}
+
+/// A weak head normal form.
+#[derive(Clone, Debug)]
+enum Value {
+    /// The code at `address` starts with a `Grab`.
+    Function {
+        address: usize,
+        environment: Environment,
+    },
+    /// An unknown variable applied to arguments, the first one first.
+    Neutral { head: Head, arguments: Vec<Thunk> },
+}
+
+/// Thunks of the variables in scope, the innermost first.
+#[derive(Clone, Debug, Default)]
+struct Environment(Option<Rc<(Thunk, Environment)>>);
+
+impl Environment {
+    fn push(&self, thunk: Thunk) -> Environment {
+        Environment(Some(Rc::new((thunk, self.clone()))))
+    }
+
+    fn get(&self, index: usize) -> &Thunk {
+        let mut environment = self;
+        for _ in 1..index {
+            environment = &environment.0.as_ref().expect("indices are bound").1;
+        }
+
+        &environment.0.as_ref().expect("indices are bound").0
+    }
+}
+
+impl Drop for Environment {
+    /// Drops long chains of environments without recursion.
+    fn drop(&mut self) {
+        let mut pending = vec![self.0.take()];
+        while let Some(environment) = pending.pop() {
+            if let Some(Ok((thunk, mut rest))) = environment.map(Rc::try_unwrap) {
+                if let Ok(thunk) = Rc::try_unwrap(thunk) {
+                    match thunk.into_inner() {
+                        State::Delayed {
+                            mut environment, ..
+                        }
+                        | State::Evaluated(Value::Function {
+                            mut environment, ..
+                        }) => pending.push(environment.0.take()),
+                        State::BlackHole | State::Evaluated(Value::Neutral { .. }) => {}
+                    }
+                }
+                pending.push(rest.0.take());
+            }
+        }
+    }
+}
+
+enum Entry {
+    Argument(Thunk),
+    /// The thunk whose value is being computed.
+    Update(Thunk),
+}
+
+/// What the machine does after entering a thunk.
+enum Next {
+    /// Runs the code at this address.
+    Run(usize, Environment),
+    /// Stops with this value.
+    Done(Value),
+}
+
+/// Why a [`Machine`] did not find a normal form.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// It takes more than `limit` steps.
+    Limit,
+    /// A thunk needs its own value to get one, like `loop` in
+    /// `let loop = loop;`, so evaluation would never end.
+    Loop,
+    /// The normal form is larger than [`SIZE_LIMIT`].
+    ///
+    /// [`SIZE_LIMIT`]: gamma_parser::reduce::SIZE_LIMIT
+    TooLarge,
+}
+
+/// Compiles expressions to bytecode and evaluates them. Variables that name
+/// a binding are compiled the first time an expression refers to them.
+pub struct Machine<'a> {
+    bindings: &'a dyn Bindings,
+    code: Vec<Instruction>,
+    names: Vec<String>,
+    /// Thunks of the variables bound outside of the compiled terms, by a
+    /// binding or not at all.
+    globals: Vec<Thunk>,
+    global_ids: HashMap<String, usize>,
+    /// Number of beta reductions performed so far. Thunks, bindings included,
+    /// are only evaluated once, so this is usually fewer than the other
+    /// backends need.
+    pub steps: usize,
+    /// Number of steps after which evaluation gives up.
+    pub limit: usize,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(bindings: &'a dyn Bindings, limit: usize) -> Self {
+        Self {
+            bindings,
+            code: vec![],
+            names: vec![],
+            globals: vec![],
+            global_ids: HashMap::new(),
+            steps: 0,
+            limit,
+        }
+    }
+
+    /// Reduces `term` to its normal form.
+    pub fn normalize(&mut self, term: &Term) -> Result<Term, Error> {
+        let address = self.compile(term, &mut vec![]);
+        let thunk = Rc::new(RefCell::new(State::Delayed {
+            address,
+            environment: Environment::default(),
+        }));
+        let normal = readback::read_back(thunk, Error::TooLarge, |thunk, depth| {
+            self.shape(&thunk, depth)
+        })?;
+        Ok(normal.to_term(self.bindings))
+    }
+
+    /// Compiles `term` with the binders of `scope` around it and returns the
+    /// address of its code. Types are erased.
+    fn compile<'t>(&mut self, term: &'t Term, scope: &mut Vec<&'t str>) -> usize {
+        let mut block = vec![];
+        self.emit(term, scope, &mut block);
+        let address = self.code.len();
+        self.code.extend(block);
+        address
+    }
+
+    /// Appends the code of `term` to `block`. Arguments are compiled to
+    /// blocks of their own first.
+    fn emit<'t>(&mut self, term: &'t Term, scope: &mut Vec<&'t str>, block: &mut Vec<Instruction>) {
+        match term {
+            Term::Var { name, .. } => block.push(match index(scope, name) {
+                Some(index) => Instruction::Access(index),
+                None => Instruction::Global(self.global_id(name)),
+            }),
+            Term::Apply { lhs, rhs, .. } | Term::Dollar { lhs, rhs, .. } => {
+                block.push(match skip_types(rhs) {
+                    Term::Var { name, .. } => match index(scope, name) {
+                        Some(index) => Instruction::PushVar(index),
+                        None => Instruction::PushGlobal(self.global_id(name)),
+                    },
+                    _ => Instruction::Push(self.compile(rhs, scope)),
+                });
+                self.emit(lhs, scope, block);
+            }
+            Term::Paren { expression, .. }
+            | Term::TypeAbstraction { expression, .. }
+            | Term::TypeApply { expression, .. } => self.emit(expression, scope, block),
+            Term::Abstraction {
+                name, expression, ..
+            } => {
+                block.push(Instruction::Grab(self.names.len()));
+                self.names.push(name.clone());
+                scope.push(name);
+                self.emit(expression, scope, block);
+                scope.pop();
+            }
+            Term::If { .. } | Term::Match { .. } => {
+                unreachable!("`if` and `match` are desugared before compiling")
+            }
+        }
+    }
+
+    fn global_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.global_ids.get(name) {
+            return id;
+        }
+
+        let id = self.globals.len();
+        self.global_ids.insert(name.to_owned(), id);
+        self.globals
+            .push(Rc::new(RefCell::new(State::Evaluated(Value::Neutral {
+                head: Head::Global(name.to_owned()),
+                arguments: vec![],
+            }))));
+
+        // The binding may refer to itself, so it is compiled once the global
+        // has an id.
+        if let Some(value) = self.bindings.binding(name) {
+            let address = self.compile(value, &mut vec![]);
+            *self.globals[id].borrow_mut() = State::Delayed {
+                address,
+                environment: Environment::default(),
+            };
+        }
+
+        id
+    }
+
+    /// Runs the machine until `thunk` is evaluated.
+    fn evaluate(&mut self, thunk: &Thunk) -> Result<Value, Error> {
+        let mut stack: Vec<Entry> = vec![];
+        let (mut address, mut environment) = match self.enter(thunk, &mut stack)? {
+            Next::Run(address, environment) => (address, environment),
+            Next::Done(value) => return Ok(value),
+        };
+
+        loop {
+            let thunk = match self.code[address] {
+                Instruction::Push(code) => {
+                    stack.push(Entry::Argument(Rc::new(RefCell::new(State::Delayed {
+                        address: code,
+                        environment: environment.clone(),
+                    }))));
+                    address += 1;
+                    continue;
+                }
+                Instruction::PushVar(index) => {
+                    stack.push(Entry::Argument(environment.get(index).clone()));
+                    address += 1;
+                    continue;
+                }
+                Instruction::PushGlobal(id) => {
+                    stack.push(Entry::Argument(self.globals[id].clone()));
+                    address += 1;
+                    continue;
+                }
+                Instruction::Grab(_) => match stack.pop() {
+                    Some(Entry::Argument(argument)) => {
+                        self.step()?;
+                        environment = environment.push(argument);
+                        address += 1;
+                        continue;
+                    }
+                    Some(Entry::Update(thunk)) => {
+                        *thunk.borrow_mut() = State::Evaluated(Value::Function {
+                            address,
+                            environment: environment.clone(),
+                        });
+                        continue;
+                    }
+                    None => {
+                        return Ok(Value::Function {
+                            address,
+                            environment,
+                        })
+                    }
+                },
+                Instruction::Access(index) => environment.get(index).clone(),
+                Instruction::Global(id) => self.globals[id].clone(),
+            };
+
+            (address, environment) = match self.enter(&thunk, &mut stack)? {
+                Next::Run(address, environment) => (address, environment),
+                Next::Done(value) => return Ok(value),
+            };
+        }
+    }
+
+    /// Continues with `thunk` applied to the arguments on `stack`. Returns the
+    /// code to run next, or the result if `thunk` is an unknown variable.
+    fn enter(&mut self, thunk: &Thunk, stack: &mut Vec<Entry>) -> Result<Next, Error> {
+        let state = match &*thunk.borrow() {
+            State::Delayed {
+                address,
+                environment,
+            } => Next::Run(*address, environment.clone()),
+            State::BlackHole => return Err(Error::Loop),
+            State::Evaluated(value) => Next::Done(value.clone()),
+        };
+
+        match state {
+            Next::Run(address, environment) => {
+                *thunk.borrow_mut() = State::BlackHole;
+                stack.push(Entry::Update(thunk.clone()));
+                Ok(Next::Run(address, environment))
+            }
+            Next::Done(Value::Function {
+                address,
+                environment,
+            }) => Ok(Next::Run(address, environment)),
+            Next::Done(Value::Neutral {
+                head,
+                mut arguments,
+            }) => {
+                while let Some(entry) = stack.pop() {
+                    match entry {
+                        Entry::Argument(argument) => arguments.push(argument),
+                        Entry::Update(thunk) => {
+                            *thunk.borrow_mut() = State::Evaluated(Value::Neutral {
+                                head: head.clone(),
+                                arguments: arguments.clone(),
+                            })
+                        }
+                    }
+                }
+
+                Ok(Next::Done(Value::Neutral { head, arguments }))
+            }
+        }
+    }
+
+    /// Evaluates `thunk`, found under `depth` abstractions, for read-back.
+    fn shape(&mut self, thunk: &Thunk, depth: usize) -> Result<Shape<Thunk>, Error> {
+        match self.evaluate(thunk)? {
+            Value::Function {
+                address,
+                environment,
+            } => {
+                let name = match self.code[address] {
+                    Instruction::Grab(name) => self.names[name].clone(),
+                    _ => unreachable!("functions start with a grab"),
+                };
+                let variable = Rc::new(RefCell::new(State::Evaluated(Value::Neutral {
+                    head: Head::Level(depth),
+                    arguments: vec![],
+                })));
+                let body = Rc::new(RefCell::new(State::Delayed {
+                    address: address + 1,
+                    environment: environment.push(variable),
+                }));
+
+                Ok(Shape::Abstraction(name, body))
+            }
+            Value::Neutral { head, arguments } => Ok(Shape::Neutral(head, arguments)),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        if self.steps >= self.limit {
+            return Err(Error::Limit);
+        }
+
+        self.steps += 1;
+        Ok(())
+    }
+}
+
+fn index(scope: &[&str], name: &str) -> Option<usize> {
+    scope
+        .iter()
+        .rev()
+        .position(|binder| *binder == name)
+        .map(|index| index + 1)
+}
+
+fn skip_types(term: &Term) -> &Term {
+    match term {
+        Term::Paren { expression, .. }
+        | Term::TypeAbstraction { expression, .. }
+        | Term::TypeApply { expression, .. } => skip_types(expression),
+        term => term,
+    }
+}
+
+#[cfg(test)]
+mod vm_tests {
+    use codemap::CodeMap;
+    use gamma_parser::parser::Parser;
+    use gamma_parser::reduce::{alpha_equivalent, Reducer};
+
+    use super::*;
+    use crate::eval::{Context, Evaluator};
+
+    const PRELUDE: &str = "
+        let zero = \\f => \\x => x; let succ = \\n => \\f => \\x => f (n f x);
+        let two = succ (succ zero); let three = succ two;
+        let mult = \\m => \\n => \\f => m (n f);
+        let loop = loop;
+        let omega = (\\x => x x) (\\x => x x);
+    ";
+
+    fn context() -> Context {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(PRELUDE, "prelude.gm", &mut codemap);
+        let (prelude, file_span) = (parser.parse(), parser.file_span);
+        drop(parser);
+
+        let mut evaluator = Evaluator::new();
+        for statement in &prelude {
+            evaluator.eval_statement(statement, file_span).unwrap();
+        }
+        evaluator.context
+    }
+
+    fn expression(source: &str) -> Term {
+        let mut codemap = CodeMap::new();
+        let mut parser = Parser::new(source, "<test>", &mut codemap);
+        let expression = parser.parse_single_expression().unwrap();
+        drop(parser);
+        expression
+    }
+
+    #[test]
+    fn agrees_with_substitution() {
+        let context = context();
+        for source in [
+            "\\x => x",
+            "free (\\x => x) variables",
+            "mult three (mult three two)",
+            "two two",
+            "(\\x => \\y => y) loop",
+            "\\x => (\\y => \\x => y) x",
+        ] {
+            let expression = expression(source);
+            let expected = Reducer::new(&context, 10_000)
+                .normalize(&expression)
+                .unwrap();
+            let actual = Machine::new(&context, 10_000)
+                .normalize(&expression)
+                .unwrap();
+            assert!(
+                alpha_equivalent(&expected, &actual),
+                "{}: expected {}, found {}",
+                source,
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn loops() {
+        let context = context();
+        assert_eq!(
+            Machine::new(&context, 10_000).normalize(&expression("loop a")),
+            Err(Error::Loop)
+        );
+        assert_eq!(
+            Machine::new(&context, 10_000).normalize(&expression("omega")),
+            Err(Error::Limit)
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Checks the code blocks of the tutorials and of the README, like
+//! `gamma doctest` does.
+
+use std::path::Path;
+
+use gamma::doctest;
+use gamma::golden::Outcome;
+
+#[test]
+fn doctest() {
+    let mut outcomes = vec![];
+    for path in ["/../tutorials", "/../README.md"] {
+        let path = format!("{}{}", env!("CARGO_MANIFEST_DIR"), path);
+        outcomes.extend(doctest::test_path(Path::new(&path)).unwrap());
+    }
+    assert!(!outcomes.is_empty());
+
+    let failures: Vec<_> = outcomes
+        .iter()
+        .filter_map(|(path, outcome)| match outcome {
+            Outcome::Passed => None,
+            outcome => Some(format!("---- {} ----\n{:?}", path.display(), outcome)),
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,22 @@
+//! Runs the golden tests of the repository, like `gamma test` does. Run
+//! `gamma test --bless` to update their expectations.
+
+use std::path::Path;
+
+use gamma::golden::{self, Outcome};
+
+#[test]
+fn golden() {
+    let directory = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../tests"));
+    let outcomes = golden::test_directory(directory, false).unwrap();
+    assert!(!outcomes.is_empty());
+
+    let failures: Vec<_> = outcomes
+        .iter()
+        .filter_map(|(path, outcome)| match outcome {
+            Outcome::Failed(report) => Some(format!("---- {} ----\n{}", path.display(), report)),
+            _ => None,
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}